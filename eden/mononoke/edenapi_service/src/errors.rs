@@ -58,6 +58,8 @@ pub enum ErrorKind {
     FileAuxDataFetchFailed(Key),
     #[error("Failed to fetch tree for key: {0:?}")]
     TreeFetchFailed(Key),
+    #[error("Augmented manifest is not derived for key: {0:?}")]
+    AugmentedTreeNotDerived(Key),
     #[error("Failed to fetch history for key: {0:?}")]
     HistoryFetchFailed(Key),
     #[error("Failed to fetch HgId for bookmark: {0:?}")]
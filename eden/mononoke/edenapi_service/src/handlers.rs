@@ -77,6 +77,7 @@ mod trees;
 pub(crate) use handler::HandlerResult;
 pub(crate) use handler::PathExtractorWithRepo;
 pub(crate) use handler::SaplingRemoteApiHandler;
+pub(crate) use trees::TreeFetchCounters;
 
 use self::handler::SaplingRemoteApiContext;
 
@@ -118,6 +119,7 @@ pub enum SaplingRemoteApiMethod {
     SetBookmark,
     SuffixQuery,
     Trees,
+    TreesExists,
     UploadBonsaiChangeset,
     UploadFile,
     UploadHgChangesets,
@@ -160,6 +162,7 @@ impl fmt::Display for SaplingRemoteApiMethod {
             Self::SetBookmark => "set_bookmark",
             Self::SuffixQuery => "suffix_query",
             Self::Trees => "trees",
+            Self::TreesExists => "trees_exists",
             Self::UploadBonsaiChangeset => "upload_bonsai_changeset",
             Self::UploadFile => "upload_file",
             Self::UploadHgChangesets => "upload_hg_changesets",
@@ -460,6 +463,7 @@ pub fn build_router<R: Send + Sync + Clone + 'static>(ctx: ServerContext<R>) ->
         Handlers::setup::<land::LandStackHandler>(route);
         Handlers::setup::<lookup::LookupHandler>(route);
         Handlers::setup::<suffix_query::SuffixQueryHandler>(route);
+        Handlers::setup::<trees::ExistsTreesHandler>(route);
         Handlers::setup::<trees::UploadTreesHandler>(route);
         route.get("/:repo/health_check").to(health_handler);
         route
@@ -5,6 +5,13 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+
 use anyhow::Context;
 use anyhow::Error;
 use async_trait::async_trait;
@@ -23,6 +30,7 @@ use edenapi_types::TreeRequest;
 use edenapi_types::UploadToken;
 use edenapi_types::UploadTreeRequest;
 use edenapi_types::UploadTreeResponse;
+use futures::future;
 use futures::stream;
 use futures::Future;
 use futures::FutureExt;
@@ -53,6 +61,8 @@ use mononoke_api_hg::HgTreeContext;
 use rate_limiting::Metric;
 use repo_blobstore::RepoBlobstoreRef;
 use serde::Deserialize;
+use tracing::field;
+use tracing::Instrument;
 use types::Key;
 use types::RepoPathBuf;
 
@@ -70,15 +80,36 @@ use crate::utils::parse_wire_request;
 
 // The size is optimized for the batching settings in EdenFs.
 const MAX_CONCURRENT_TREE_FETCHES_PER_REQUEST: usize = 128;
+// Requests at or above this priority (e.g. interactive checkouts) are served
+// with a higher concurrency limit than background prefetches.
+const INTERACTIVE_TREE_FETCH_PRIORITY: u8 = 128;
+const MAX_CONCURRENT_TREE_FETCHES_PER_INTERACTIVE_REQUEST: usize = 512;
 const MAX_CONCURRENT_METADATA_FETCHES_PER_TREE_FETCH: usize = 100;
 const MAX_CONCURRENT_UPLOAD_TREES_PER_REQUEST: usize = 100;
 const LARGE_TREE_METADATA_LIMIT: usize = 25000;
+// Children larger than this are served without file header metadata even
+// when the client asked for it, to keep legacy child metadata responses
+// bounded.
+const MAX_CHILD_FILE_HEADER_METADATA_FILE_SIZE: u64 = 10 * 1024 * 1024;
+// Rough per-child wire overhead (key hash, aux hashes, sizes, framing) used
+// when charging child metadata against the response byte budget.
+const TREE_CHILD_METADATA_SIZE_ESTIMATE: u64 = 128;
 
 #[derive(Debug, Deserialize, StateData, StaticResponseExtender)]
 pub struct TreeParams {
     repo: String,
 }
 
+/// Counters behind the `X-EdenAPI-Trees-*` response headers. The handler
+/// resolves all tree fetches before constructing the response, then puts the
+/// final counters into the request state; `TreeFetchCountersMiddleware` turns
+/// them into headers on the way out.
+#[derive(Clone, Debug, Default, StateData)]
+pub struct TreeFetchCounters {
+    pub fetched: Arc<AtomicU64>,
+    pub from_cache: Arc<AtomicU64>,
+}
+
 /// Fetch the tree nodes requested by the client.
 pub async fn trees(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
     let params = TreeParams::take_from(state);
@@ -100,29 +131,166 @@ pub async fn trees(state: &mut State) -> Result<impl TryIntoResponse, HttpError>
 
     ScubaMiddlewareState::try_set_sampling_rate(state, nonzero_ext::nonzero!(256_u64));
 
-    Ok(custom_cbor_stream(
-        super::monitor_request(state, fetch_all_trees(repo, request)),
-        |tree_entry| tree_entry.as_ref().err(),
-    ))
+    let counters = TreeFetchCounters::default();
+
+    // Resolve the fetches before building the response: the counters must be
+    // final when the middleware emits the headers, as they cannot be amended
+    // once the body starts streaming.
+    let entries = super::monitor_request(state, fetch_all_trees(repo, request, counters.clone()))
+        .collect::<Vec<_>>()
+        .await;
+
+    state.put(counters);
+
+    Ok(custom_cbor_stream(stream::iter(entries), |tree_entry| {
+        tree_entry.as_ref().err()
+    }))
 }
 
 /// Fetch trees for all of the requested keys concurrently.
 fn fetch_all_trees<R: MononokeRepo>(
     repo: HgRepoContext<R>,
     request: TreeRequest,
+    counters: TreeFetchCounters,
 ) -> impl Stream<Item = Result<TreeEntry, SaplingRemoteApiServerError>> {
     let ctx = repo.ctx().clone();
+    let span = request_span(repo.repo_ctx().name());
+    let budget = response_byte_budget(repo.repo_ctx().name());
+
+    let max_concurrent_fetches = if request.priority >= INTERACTIVE_TREE_FETCH_PRIORITY {
+        MAX_CONCURRENT_TREE_FETCHES_PER_INTERACTIVE_REQUEST
+    } else {
+        MAX_CONCURRENT_TREE_FETCHES_PER_REQUEST
+    };
 
-    let fetches = request.keys.into_iter().map(move |key| {
-        fetch_tree(repo.clone(), key.clone(), request.attributes)
+    let keys = request.keys;
+    let fetches = keys.clone().into_iter().map(move |key| {
+        fetch_tree(repo.clone(), key.clone(), request.attributes, counters.clone())
             .map(|r| r.map_err(|e| SaplingRemoteApiServerError::with_key(key, e)))
+            .instrument(span.clone())
     });
 
-    stream::iter(fetches)
-        .buffer_unordered(MAX_CONCURRENT_TREE_FETCHES_PER_REQUEST)
+    let entries = stream::iter(fetches)
+        .buffer_unordered(max_concurrent_fetches)
         .inspect_ok(move |_| {
             ctx.session().bump_load(Metric::TotalManifests, 1.0);
+        });
+
+    match budget {
+        Some(budget) => apply_response_budget(entries, budget, keys).left_stream(),
+        None => entries.right_stream(),
+    }
+}
+
+/// Span under which every fetch for a trees request runs. The repo name is
+/// recorded as a structured `repo.name` field so that traces from different
+/// repos can be told apart in a shared tracing backend; the per-phase child
+/// spans (`parents_fetch`, `manifest_blob_fetch`, ...) inherit it through the
+/// subscriber context.
+fn request_span(repo_name: &str) -> tracing::Span {
+    tracing::info_span!("fetch_all_trees", repo.name = %repo_name)
+}
+
+/// Whether a tree requested as an augmented manifest may be served via the
+/// plain hg manifest when its augmented manifest hasn't been derived yet.
+/// Useful mid-rollout, when some commits predate augmented manifest
+/// derivation and would otherwise fail per key.
+fn augmented_trees_fallback_enabled(repo_name: &str) -> bool {
+    justknobs::eval(
+        "scm/mononoke:edenapi_augmented_trees_fallback",
+        None,
+        Some(repo_name),
+    )
+    .unwrap_or(false)
+}
+
+/// Whether augmented tree responses carry the blobstore ctime of the served
+/// augmented manifest, so clients debugging digest mismatches can tell
+/// freshly derived data apart from backfills.
+fn derivation_ctime_enabled(repo_name: &str) -> bool {
+    justknobs::eval(
+        "scm/mononoke:edenapi_trees_derivation_ctime",
+        None,
+        Some(repo_name),
+    )
+    .unwrap_or(false)
+}
+
+/// Response byte budget for a single trees request, if one is configured for
+/// the repo. A request for a few thousand large manifests can produce a
+/// multi-GB response that slow clients never finish reading, so repos can cap
+/// how much a single request is allowed to serve.
+fn response_byte_budget(repo_name: &str) -> Option<u64> {
+    match justknobs::get_as::<u64>(
+        "scm/mononoke:edenapi_trees_response_byte_budget",
+        Some(repo_name),
+    ) {
+        Ok(0) | Err(_) => None,
+        Ok(budget) => Some(budget),
+    }
+}
+
+/// Cap the response at roughly `budget` bytes. Entries are charged against
+/// the budget as they are produced; once it is exceeded the remaining fetches
+/// are dropped and a final marker entry listing the unserved keys is emitted
+/// so the client can re-request them.
+fn apply_response_budget(
+    entries: impl Stream<Item = Result<TreeEntry, SaplingRemoteApiServerError>>,
+    budget: u64,
+    keys: Vec<Key>,
+) -> impl Stream<Item = Result<TreeEntry, SaplingRemoteApiServerError>> {
+    let not_served = Arc::new(Mutex::new(keys.into_iter().collect::<HashSet<Key>>()));
+    let marker_keys = not_served.clone();
+
+    entries
+        .scan(0u64, move |served_bytes, entry| {
+            if *served_bytes > budget {
+                // Ending the stream drops fetches that are still in flight;
+                // their keys stay in `not_served`.
+                return future::ready(None);
+            }
+            match &entry {
+                Ok(tree) => {
+                    *served_bytes += tree_entry_size(tree);
+                    not_served.lock().expect("poisoned lock").remove(&tree.key);
+                }
+                Err(e) => {
+                    // Errors are reported to the client; don't re-list them.
+                    if let Some(key) = &e.key {
+                        not_served.lock().expect("poisoned lock").remove(key);
+                    }
+                }
+            }
+            future::ready(Some(entry))
         })
+        .chain(
+            stream::once(async move {
+                let mut not_served: Vec<Key> =
+                    marker_keys.lock().expect("poisoned lock").drain().collect();
+                // Deterministic ordering for clients (and tests).
+                not_served.sort();
+                not_served
+            })
+            .filter(|not_served| future::ready(!not_served.is_empty()))
+            .map(|not_served| Ok(TreeEntry::new_truncated(not_served))),
+        )
+}
+
+/// Approximate serialized size of a tree entry, counting both the manifest
+/// blob and any child metadata.
+fn tree_entry_size(entry: &TreeEntry) -> u64 {
+    let mut size = entry.data.as_ref().map_or(0, |data| data.len() as u64);
+    if let Some(children) = &entry.children {
+        for child in children {
+            size += TREE_CHILD_METADATA_SIZE_ESTIMATE;
+            match child {
+                Ok(TreeChildEntry::File(file)) => size += file.key.path.len() as u64,
+                Ok(TreeChildEntry::Directory(dir)) => size += dir.key.path.len() as u64,
+                Err(_) => {}
+            }
+        }
+    }
+    size
 }
 
 /// Fetch requested tree for a single key.
@@ -132,6 +300,7 @@ async fn fetch_tree<R: MononokeRepo>(
     repo: HgRepoContext<R>,
     key: Key,
     attributes: TreeAttributes,
+    counters: TreeFetchCounters,
 ) -> Result<TreeEntry, Error> {
     let mut entry = TreeEntry::new(key.clone());
 
@@ -152,8 +321,15 @@ async fn fetch_tree<R: MononokeRepo>(
                 augmented_manifest_size: ctx.augmented_manifest_size(),
             });
 
+            if derivation_ctime_enabled(repo.repo_ctx().name()) {
+                entry.with_derivation_ctime(ctx.derivation_ctime());
+            }
+
             if attributes.parents {
+                let span = tracing::debug_span!("parents_fetch", duration_ms = field::Empty);
+                let start = Instant::now();
                 entry.with_parents(Some(ctx.hg_parents().into()));
+                span.record("duration_ms", start.elapsed().as_millis() as u64);
             }
 
             if attributes.child_metadata {
@@ -161,6 +337,8 @@ async fn fetch_tree<R: MononokeRepo>(
                     .perf_counters()
                     .increment_counter(PerfCounterType::EdenapiTreesAuxData);
 
+                let span = tracing::debug_span!("child_metadata_fetch", duration_ms = field::Empty);
+                let start = Instant::now();
                 entry.with_children(Some(
                     ctx.augmented_children_entries()
                         .map(|(path, augmented_entry)| match augmented_entry {
@@ -218,24 +396,39 @@ async fn fetch_tree<R: MononokeRepo>(
                         })
                         .collect(),
                 ));
+                span.record("duration_ms", start.elapsed().as_millis() as u64);
             }
 
             if attributes.manifest_blob {
+                let span = tracing::debug_span!("manifest_blob_fetch", duration_ms = field::Empty);
+                let start = Instant::now();
                 let (data, _) = ctx
                     .content()
                     .await
                     .with_context(|| ErrorKind::TreeFetchFailed(key.clone()))?;
+                span.record("duration_ms", start.elapsed().as_millis() as u64);
 
                 entry.with_data(Some(data.into()));
             }
 
+            // Trees served from the pre-derived augmented manifests count as
+            // cache hits; the fallback below fetches from the blobstore.
+            counters.fetched.fetch_add(1, Ordering::Relaxed);
+            counters.from_cache.fetch_add(1, Ordering::Relaxed);
+
             return Ok(entry);
         } else {
-            // If we don't have an augmented tree, fallback to the old way of fetching trees
-            // Log the fallback to scuba
+            if !augmented_trees_fallback_enabled(repo.repo_ctx().name()) {
+                return Err(ErrorKind::AugmentedTreeNotDerived(key.clone()).into());
+            }
+            // If we don't have an augmented tree, fallback to the old way of
+            // fetching trees (without child metadata), flagging the downgrade
+            // on the entry so mid-rollout clients can tell the modes apart.
+            // Log the fallback to scuba.
             repo.ctx()
                 .perf_counters()
                 .increment_counter(PerfCounterType::EdenapiAugmentedTreesFallback);
+            entry.with_augmented_fallback();
         }
     }
 
@@ -252,24 +445,35 @@ async fn fetch_tree<R: MononokeRepo>(
             .perf_counters()
             .increment_counter(PerfCounterType::EdenapiTrees);
 
+        let span = tracing::debug_span!("manifest_blob_fetch", duration_ms = field::Empty);
+        let start = Instant::now();
         let (data, _) = ctx
             .content()
             .await
             .with_context(|| ErrorKind::TreeFetchFailed(key.clone()))?;
+        span.record("duration_ms", start.elapsed().as_millis() as u64);
 
         entry.with_data(Some(data.into()));
     }
 
     if attributes.parents {
+        let span = tracing::debug_span!("parents_fetch", duration_ms = field::Empty);
+        let start = Instant::now();
         entry.with_parents(Some(ctx.hg_parents().into()));
+        span.record("duration_ms", start.elapsed().as_millis() as u64);
     }
 
-    if attributes.child_metadata {
+    if attributes.child_metadata && !entry.augmented_fallback {
         repo.ctx()
             .perf_counters()
             .increment_counter(PerfCounterType::EdenapiTreesAuxData);
 
-        if let Some(entries) = fetch_child_file_metadata_entries(&repo, &ctx).await? {
+        let span = tracing::debug_span!("child_metadata_fetch", duration_ms = field::Empty);
+        let start = Instant::now();
+        if let Some(entries) =
+            fetch_child_file_metadata_entries(&repo, &ctx, attributes.child_file_header_metadata)
+                .await?
+        {
             let children: Vec<Result<TreeChildEntry, SaplingRemoteApiServerError>> = entries
                 .buffer_unordered(MAX_CONCURRENT_METADATA_FETCHES_PER_TREE_FETCH)
                 .map(|r| r.map_err(|e| SaplingRemoteApiServerError::with_key(key.clone(), e)))
@@ -278,14 +482,18 @@ async fn fetch_tree<R: MononokeRepo>(
 
             entry.with_children(Some(children));
         }
+        span.record("duration_ms", start.elapsed().as_millis() as u64);
     }
 
+    counters.fetched.fetch_add(1, Ordering::Relaxed);
+
     Ok(entry)
 }
 
 async fn fetch_child_file_metadata_entries<'a, R: MononokeRepo>(
     repo: &'a HgRepoContext<R>,
     ctx: &'a HgTreeContext<R>,
+    include_file_header_metadata: bool,
 ) -> Result<
     Option<impl Stream<Item = impl Future<Output = Result<TreeChildEntry, Error>> + 'a> + 'a>,
     Error,
@@ -315,7 +523,8 @@ async fn fetch_child_file_metadata_entries<'a, R: MononokeRepo>(
                 move |(name, child_id)| async move {
                     let name = RepoPathBuf::from_string(name.to_string())?;
                     let child_key = Key::new(name, child_id.into_nodehash().into());
-                    fetch_child_file_metadata(repo, child_key.clone()).await
+                    fetch_child_file_metadata(repo, child_key.clone(), include_file_header_metadata)
+                        .await
                 }
             }),
     ))
@@ -324,6 +533,7 @@ async fn fetch_child_file_metadata_entries<'a, R: MononokeRepo>(
 async fn fetch_child_file_metadata<R: MononokeRepo>(
     repo: &HgRepoContext<R>,
     child_key: Key,
+    include_file_header_metadata: bool,
 ) -> Result<TreeChildEntry, Error> {
     let ctx = repo
         .file(HgFileNodeId::new(child_key.hgid.into()))
@@ -331,18 +541,31 @@ async fn fetch_child_file_metadata<R: MononokeRepo>(
         .ok_or_else(|| ErrorKind::FileFetchFailed(child_key.clone()))?;
 
     let metadata = ctx.content_metadata().await?;
+    let file_header_metadata =
+        if should_include_file_header_metadata(include_file_header_metadata, metadata.total_size) {
+            Some(ctx.file_header_metadata().into())
+        } else {
+            None
+        };
     Ok(TreeChildEntry::new_file_entry(
         child_key,
         FileAuxData {
             total_size: metadata.total_size,
             sha1: metadata.sha1.into(),
             blake3: metadata.seeded_blake3.into(),
-            file_header_metadata: Some(ctx.file_header_metadata().into()),
+            file_header_metadata,
         }
         .into(),
     ))
 }
 
+/// Whether a child file entry in the legacy child metadata path gets the hg
+/// file header bytes: only when the client requested them and the child is
+/// small enough.
+fn should_include_file_header_metadata(requested: bool, total_size: u64) -> bool {
+    requested && total_size <= MAX_CHILD_FILE_HEADER_METADATA_FILE_SIZE
+}
+
 /// Store the content of a single tree
 async fn store_tree<R: MononokeRepo>(
     repo: HgRepoContext<R>,
@@ -359,6 +582,41 @@ async fn store_tree<R: MononokeRepo>(
     })
 }
 
+/// Check which tree keys exist in the blobstore without returning content.
+///
+/// This allows clients to decide what to upload without first fetching
+/// manifest content.
+pub struct ExistsTreesHandler;
+
+#[async_trait]
+impl SaplingRemoteApiHandler for ExistsTreesHandler {
+    type Request = Batch<Key>;
+    type Response = (Key, bool);
+
+    const HTTP_METHOD: hyper::Method = hyper::Method::POST;
+    const API_METHOD: SaplingRemoteApiMethod = SaplingRemoteApiMethod::TreesExists;
+    const ENDPOINT: &'static str = "/trees/exists";
+
+    async fn handler(
+        ectx: SaplingRemoteApiContext<Self::PathExtractor, Self::QueryStringExtractor, Repo>,
+        request: Self::Request,
+    ) -> HandlerResult<'async_trait, Self::Response> {
+        let repo = ectx.repo();
+        let checks = request.batch.into_iter().map(move |key| {
+            let repo = repo.clone();
+            async move {
+                let id = HgManifestId::new(HgNodeHash::from(key.hgid));
+                let exists = repo.tree_exists(id).await?;
+                Ok((key, exists))
+            }
+        });
+
+        Ok(stream::iter(checks)
+            .buffer_unordered(MAX_CONCURRENT_TREE_FETCHES_PER_REQUEST)
+            .boxed())
+    }
+}
+
 /// Upload list of trees requested by the client (batch request).
 pub struct UploadTreesHandler;
 
@@ -386,3 +644,138 @@ impl SaplingRemoteApiHandler for UploadTreesHandler {
             .boxed())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use types::HgId;
+
+    use super::*;
+
+    fn test_key(path: &str, id: u8) -> Key {
+        Key::new(
+            RepoPathBuf::from_string(path.to_string()).unwrap(),
+            HgId::from_byte_array([id; HgId::len()]),
+        )
+    }
+
+    fn entry_with_data(key: &Key, size: usize) -> Result<TreeEntry, SaplingRemoteApiServerError> {
+        let mut entry = TreeEntry::new(key.clone());
+        entry.with_data(Some(vec![0u8; size].into()));
+        Ok(entry)
+    }
+
+    #[tokio::test]
+    async fn test_response_budget_truncates() {
+        let keys: Vec<Key> = (0..4u8).map(|i| test_key(&format!("d{}", i), i)).collect();
+        let entries = stream::iter(
+            keys.iter()
+                .map(|key| entry_with_data(key, 100))
+                .collect::<Vec<_>>(),
+        );
+
+        // 100 bytes per entry: the second entry exceeds the budget, so the
+        // third and fourth are never served.
+        let served: Vec<_> = apply_response_budget(entries, 150, keys.clone())
+            .collect()
+            .await;
+
+        assert_eq!(served.len(), 3);
+        assert_eq!(served[0].as_ref().unwrap().key, keys[0]);
+        assert_eq!(served[1].as_ref().unwrap().key, keys[1]);
+
+        let marker = served[2].as_ref().unwrap();
+        assert!(marker.is_truncation_marker());
+        let mut expected = vec![keys[2].clone(), keys[3].clone()];
+        expected.sort();
+        assert_eq!(marker.not_served, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_response_budget_not_exceeded() {
+        let keys: Vec<Key> = (0..2u8).map(|i| test_key(&format!("d{}", i), i)).collect();
+        let entries = stream::iter(
+            keys.iter()
+                .map(|key| entry_with_data(key, 10))
+                .collect::<Vec<_>>(),
+        );
+
+        let served: Vec<_> = apply_response_budget(entries, 1000, keys.clone())
+            .collect()
+            .await;
+
+        assert_eq!(served.len(), 2);
+        assert!(served.iter().all(|entry| {
+            !entry.as_ref().unwrap().is_truncation_marker()
+        }));
+    }
+
+    #[test]
+    fn test_child_file_header_metadata_gating() {
+        // Off by default: no header regardless of size.
+        assert!(!should_include_file_header_metadata(false, 0));
+        assert!(!should_include_file_header_metadata(false, 100));
+
+        // On: the header is served for small children only.
+        assert!(should_include_file_header_metadata(true, 0));
+        assert!(should_include_file_header_metadata(
+            true,
+            MAX_CHILD_FILE_HEADER_METADATA_FILE_SIZE
+        ));
+        assert!(!should_include_file_header_metadata(
+            true,
+            MAX_CHILD_FILE_HEADER_METADATA_FILE_SIZE + 1
+        ));
+    }
+
+    /// `std::io::Write` sink that collects formatted tracing output so the
+    /// test can assert on it.
+    #[derive(Clone, Default)]
+    struct CapturedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedOutput {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_span_records_repo_name() {
+        let output = CapturedOutput::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(output.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = request_span("test-repo");
+            let _guard = span.enter();
+            // Events inside child spans are emitted with the full span
+            // context, so the repo name tags everything under the request.
+            let child = tracing::debug_span!("parents_fetch", duration_ms = field::Empty);
+            let _child_guard = child.enter();
+            tracing::debug!("fetching");
+        });
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.contains("repo.name=test-repo"),
+            "missing repo.name field in: {}",
+            output
+        );
+        assert!(output.contains("fetch_all_trees"));
+        assert!(output.contains("parents_fetch"));
+    }
+}
@@ -46,6 +46,7 @@ use crate::context::ServerContext;
 use crate::handlers::build_router;
 use crate::middleware::OdsMiddleware;
 use crate::middleware::RequestDumperMiddleware;
+use crate::middleware::TreeFetchCountersMiddleware;
 use crate::scuba::SaplingRemoteApiScubaHandler;
 
 pub type SaplingRemoteApi = MononokeHttpHandler<Router>;
@@ -104,6 +105,7 @@ pub fn build<R: Send + Sync + Clone + 'static>(
             common_config.edenapi_dumper_scuba_table.clone(),
         ))
         .add(LoadMiddleware::new())
+        .add(TreeFetchCountersMiddleware::new())
         .add(log_middleware)
         .add(OdsMiddleware::new())
         .add(<ScubaMiddleware<SaplingRemoteApiScubaHandler>>::new(scuba))
@@ -7,6 +7,8 @@
 
 pub mod ods;
 pub mod request_dumper;
+pub mod tree_fetch_counters;
 
 pub use self::ods::OdsMiddleware;
 pub use self::request_dumper::RequestDumperMiddleware;
+pub use self::tree_fetch_counters::TreeFetchCountersMiddleware;
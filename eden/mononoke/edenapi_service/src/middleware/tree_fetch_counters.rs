@@ -0,0 +1,45 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::atomic::Ordering;
+
+use gotham::state::State;
+use gotham_ext::middleware::Middleware;
+use hyper::Body;
+use hyper::Response;
+
+use crate::handlers::TreeFetchCounters;
+
+/// Exposes the tree fetch counters put into the request state by the trees
+/// handler as `X-EdenAPI-Trees-Fetched` and `X-EdenAPI-Trees-From-Cache`
+/// response headers, so CDN-level monitoring can track blobstore vs. cache
+/// traffic without parsing the CBOR response body.
+#[derive(Clone)]
+pub struct TreeFetchCountersMiddleware;
+
+impl TreeFetchCountersMiddleware {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for TreeFetchCountersMiddleware {
+    async fn outbound(&self, state: &mut State, response: &mut Response<Body>) {
+        if let Some(counters) = state.try_take::<TreeFetchCounters>() {
+            let headers = response.headers_mut();
+            headers.insert(
+                "X-EdenAPI-Trees-Fetched",
+                counters.fetched.load(Ordering::Relaxed).into(),
+            );
+            headers.insert(
+                "X-EdenAPI-Trees-From-Cache",
+                counters.from_cache.load(Ordering::Relaxed).into(),
+            );
+        }
+    }
+}
@@ -8,6 +8,7 @@
 //! For Facebook hooks check the src/facebook/ folder
 
 mod always_fail_changeset;
+mod author_blocklist;
 pub(crate) mod block_accidental_new_bookmark_creation;
 mod block_commit_message_pattern;
 mod block_content_pattern;
@@ -16,23 +17,39 @@ mod block_files;
 pub(crate) mod block_invalid_symlinks;
 pub(crate) mod block_merge_commits;
 pub(crate) mod block_new_bookmark_creations_by_name;
+mod block_tombstoned_paths;
 pub(crate) mod block_unannotated_tags;
 pub(crate) mod block_unclean_merge_commits;
+mod block_unreviewable_archives;
+mod blocked_author_patterns;
 pub(crate) mod deny_files;
+mod deny_renames_of_protected_directories;
+mod enforce_ascii_or_nfc_normalized_filenames;
+mod enforce_max_file_count_per_directory_addition;
+mod forbid_byte_order_mark;
 mod limit_commit_message_length;
 pub(crate) mod limit_commit_size;
 mod limit_directory_size;
 pub(crate) mod limit_filesize;
+mod limit_new_top_level_entries;
 mod limit_path_length;
 pub(crate) mod limit_submodule_edits;
+mod limit_symlink_count_per_changeset;
 pub(crate) mod limit_tag_updates;
+mod no_absolute_symlink_targets;
 pub(crate) mod no_bad_extensions;
 pub(crate) mod no_bad_filenames;
 mod no_executable_binaries;
 mod no_insecure_filenames;
+mod no_merge_commits_with_file_changes;
+mod no_path_traversal;
 pub(crate) mod no_questionable_filenames;
+mod no_secrets_in_files;
 pub(crate) mod no_windows_filenames;
+mod require_breaking_change_documentation;
+mod require_codeowners_entry;
 pub(crate) mod require_commit_message_pattern;
+mod require_paired_generated_files;
 
 use anyhow::Result;
 use fbinit::FacebookInit;
@@ -87,6 +104,9 @@ pub async fn make_changeset_hook(
 
     Ok(match params.implementation.as_str() {
         "always_fail_changeset" => Some(b(always_fail_changeset::AlwaysFailChangeset::new())),
+        "author_blocklist" => Some(b(author_blocklist::AuthorBlocklistHook::new(
+            &params.config,
+        )?)),
         "block_merge_commits" => Some(b(block_merge_commits::BlockMergeCommitsHook::new(
             &params.config,
         )?)),
@@ -100,6 +120,19 @@ pub async fn make_changeset_hook(
             block_commit_message_pattern::BlockCommitMessagePatternHook::new(&params.config)?,
         )),
         "block_empty_commit" => Some(b(block_empty_commit::BlockEmptyCommit::new())),
+        "blocked_author_patterns" => Some(b(
+            blocked_author_patterns::BlockedAuthorPatternsHook::new(&params.config)?,
+        )),
+        "deny_renames_of_protected_directories" => Some(b(
+            deny_renames_of_protected_directories::DenyRenamesOfProtectedDirectoriesHook::new(
+                &params.config,
+            )?,
+        )),
+        "enforce_max_file_count_per_directory_addition" => Some(b(
+            enforce_max_file_count_per_directory_addition::EnforceMaxFileCountPerDirectoryAdditionHook::new(
+                &params.config,
+            )?,
+        )),
         "limit_commit_message_length" => {
             let hook =
                 limit_commit_message_length::LimitCommitMessageLengthHook::new(&params.config)?;
@@ -111,9 +144,33 @@ pub async fn make_changeset_hook(
         "limit_directory_size" => Some(b(limit_directory_size::LimitDirectorySizeHook::new(
             &params.config,
         )?)),
+        "limit_new_top_level_entries" => Some(b(
+            limit_new_top_level_entries::LimitNewTopLevelEntriesHook::new(&params.config)?,
+        )),
+        "limit_symlink_count_per_changeset" => Some(b(
+            limit_symlink_count_per_changeset::LimitSymlinkCountPerChangesetHook::new(
+                &params.config,
+            )?,
+        )),
+        "no_merge_commits_with_file_changes" => Some(b(
+            no_merge_commits_with_file_changes::NoMergeCommitsWithFileChangesHook::new(
+                &params.config,
+            )?,
+        )),
+        "require_breaking_change_documentation" => Some(b(
+            require_breaking_change_documentation::RequireBreakingChangeDocumentationHook::new(
+                &params.config,
+            )?,
+        )),
+        "require_codeowners_entry" => Some(b(
+            require_codeowners_entry::RequireCodeownersEntryHook::new(&params.config)?,
+        )),
         "require_commit_message_pattern" => Some(b(
             require_commit_message_pattern::RequireCommitMessagePatternHook::new(&params.config)?,
         )),
+        "require_paired_generated_files" => Some(b(
+            require_paired_generated_files::RequirePairedGeneratedFilesHook::new(&params.config)?,
+        )),
         _ => None,
     })
 }
@@ -130,6 +187,12 @@ pub fn make_file_hook(
         "block_invalid_symlinks" => Some(Box::new(
             block_invalid_symlinks::BlockInvalidSymlinksHook::new(&params.config)?,
         )),
+        "block_tombstoned_paths" => Some(Box::new(
+            block_tombstoned_paths::BlockTombstonedPathsHook::new(&params.config)?,
+        )),
+        "block_unreviewable_archives" => Some(Box::new(
+            block_unreviewable_archives::BlockUnreviewableArchivesHook::new(&params.config)?,
+        )),
         "deny_files" => Some(Box::new(
             deny_files::DenyFiles::builder()
                 .set_from_config(&params.config)
@@ -140,10 +203,21 @@ pub fn make_file_hook(
                 .set_from_config(&params.config)
                 .build()?,
         )),
+        "enforce_ascii_or_nfc_normalized_filenames" => Some(Box::new(
+            enforce_ascii_or_nfc_normalized_filenames::EnforceAsciiOrNfcNormalizedFilenamesHook::new(
+                &params.config,
+            )?,
+        )),
+        "forbid_byte_order_mark" => Some(Box::new(
+            forbid_byte_order_mark::ForbidByteOrderMarkHook::new(&params.config)?,
+        )),
         "limit_path_length" => {
             let hook = limit_path_length::LimitPathLengthHook::new(&params.config)?;
             Some(Box::new(hook))
         }
+        "no_absolute_symlink_targets" => Some(Box::new(
+            no_absolute_symlink_targets::NoAbsoluteSymlinkTargetsHook::new(&params.config)?,
+        )),
         "no_bad_filenames" => Some(Box::new(no_bad_filenames::NoBadFilenamesHook::new(
             &params.config,
         )?)),
@@ -158,11 +232,17 @@ pub fn make_file_hook(
         "no_insecure_filenames" => {
             Some(Box::new(no_insecure_filenames::NoInsecureFilenames::new()?))
         }
+        "no_path_traversal" => Some(Box::new(no_path_traversal::NoPathTraversalHook::new(
+            &params.config,
+        )?)),
         "no_questionable_filenames" => Some(Box::new(
             no_questionable_filenames::NoQuestionableFilenames::builder()
                 .set_from_config(&params.config)
                 .build()?,
         )),
+        "no_secrets_in_files" => Some(Box::new(no_secrets_in_files::NoSecretsInFilesHook::new(
+            &params.config,
+        )?)),
         "no_windows_filenames" => Some(Box::new(
             no_windows_filenames::NoWindowsFilenamesHook::new(&params.config)?,
         )),
@@ -0,0 +1,265 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use mononoke_types::BonsaiChangeset;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthorBlocklistEntry {
+    /// Exact unixname to block.  The author is blocked if the unixname
+    /// appears as the local part of the author email, or matches the whole
+    /// author string.
+    #[serde(default)]
+    unixname: Option<String>,
+
+    /// Regular expression matched against the full author string.
+    #[serde(default, with = "serde_regex")]
+    pattern: Option<Regex>,
+
+    /// Optional expiry for this entry, in seconds since the unix epoch.
+    /// Expired entries are ignored, allowing blocks to be time-bounded.
+    #[serde(default)]
+    expiry_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthorBlocklistConfig {
+    /// Identities that are not permitted to land commits.
+    entries: Vec<AuthorBlocklistEntry>,
+
+    /// Message to include in the hook rejection.  The string is expanded
+    /// with `${author}` replaced by the blocked author and `${contact}`
+    /// replaced by the configured contact point.
+    message: String,
+
+    /// Contact point to include in the rejection message, e.g. an oncall
+    /// or support group.
+    contact: String,
+}
+
+/// Hook to block changesets authored by compromised or deprecated
+/// automation accounts, even if their certs are still valid at the
+/// transport layer.
+#[derive(Clone, Debug)]
+pub struct AuthorBlocklistHook {
+    config: AuthorBlocklistConfig,
+}
+
+impl AuthorBlocklistHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: AuthorBlocklistConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+impl AuthorBlocklistEntry {
+    fn is_expired(&self, now_secs: u64) -> bool {
+        match self.expiry_secs {
+            Some(expiry) => expiry <= now_secs,
+            None => false,
+        }
+    }
+
+    fn matches(&self, author: &str) -> bool {
+        if let Some(unixname) = &self.unixname {
+            if author == unixname {
+                return true;
+            }
+            // Match the local part of "Name <unixname@domain>" style authors.
+            if let Some(email_start) = author.find('<') {
+                let email = author[email_start + 1..].trim_end_matches('>');
+                if let Some(local) = email.split('@').next() {
+                    if local == unixname {
+                        return true;
+                    }
+                }
+            }
+        }
+        if let Some(pattern) = &self.pattern {
+            if pattern.is_match(author) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for AuthorBlocklistHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let author = changeset.author();
+        for entry in &self.config.entries {
+            if entry.is_expired(now_secs) {
+                continue;
+            }
+            if entry.matches(author) {
+                let message = self
+                    .config
+                    .message
+                    .replace("${author}", author)
+                    .replace("${contact}", &self.config.contact);
+                return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "Author is blocked",
+                    message,
+                )));
+            }
+        }
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::bookmark;
+    use tests_utils::drawdag::changes;
+    use tests_utils::drawdag::create_from_dag_with_changes;
+    use tests_utils::BasicTestRepo;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> AuthorBlocklistConfig {
+        AuthorBlocklistConfig {
+            entries: vec![
+                AuthorBlocklistEntry {
+                    unixname: Some("badbot".to_string()),
+                    pattern: None,
+                    expiry_secs: None,
+                },
+                AuthorBlocklistEntry {
+                    unixname: None,
+                    pattern: Some(Regex::new(r"deprecated-\w+@example\.com").unwrap()),
+                    expiry_secs: None,
+                },
+                AuthorBlocklistEntry {
+                    unixname: Some("formerbot".to_string()),
+                    pattern: None,
+                    // Expired long ago, so this entry should be ignored.
+                    expiry_secs: Some(1),
+                },
+            ],
+            message: String::from("Commits by ${author} are blocked. Contact ${contact}."),
+            contact: String::from("Source Control oncall"),
+        }
+    }
+
+    async fn run_hook_for_author(
+        fb: FacebookInit,
+        author: &str,
+    ) -> Result<HookExecution> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let author = author.to_string();
+        let changesets = create_from_dag_with_changes(
+            &ctx,
+            &repo,
+            r##"
+                Z-A
+            "##,
+            changes! {
+                "A" => |c| c.set_author(author),
+            },
+        )
+        .await?;
+        bookmark(&ctx, &repo, "main")
+            .create_publishing(changesets["Z"])
+            .await?;
+
+        let hook = AuthorBlocklistHook::with_config(make_test_config())?;
+
+        test_changeset_hook(
+            &ctx,
+            &repo,
+            &hook,
+            "main",
+            changesets["A"],
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_exact_unixname_is_blocked(fb: FacebookInit) -> Result<()> {
+        assert_eq!(
+            run_hook_for_author(fb, "Bad Bot <badbot@example.com>").await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "Author is blocked".into(),
+                long_description:
+                    "Commits by Bad Bot <badbot@example.com> are blocked. Contact Source Control oncall."
+                        .into(),
+            }),
+        );
+        Ok(())
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_regex_match_is_blocked(fb: FacebookInit) -> Result<()> {
+        assert_eq!(
+            run_hook_for_author(fb, "Old Bot <deprecated-sync@example.com>").await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "Author is blocked".into(),
+                long_description:
+                    "Commits by Old Bot <deprecated-sync@example.com> are blocked. Contact Source Control oncall."
+                        .into(),
+            }),
+        );
+        Ok(())
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_expired_entry_is_ignored(fb: FacebookInit) -> Result<()> {
+        assert_eq!(
+            run_hook_for_author(fb, "Former Bot <formerbot@example.com>").await?,
+            HookExecution::Accepted,
+        );
+        Ok(())
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_clean_author_is_accepted(fb: FacebookInit) -> Result<()> {
+        assert_eq!(
+            run_hook_for_author(fb, "Good User <gooduser@example.com>").await?,
+            HookExecution::Accepted,
+        );
+        Ok(())
+    }
+}
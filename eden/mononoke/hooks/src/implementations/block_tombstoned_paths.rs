@@ -0,0 +1,209 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use metaconfig_types::HookConfig;
+use mononoke_types::BasicFileChange;
+use mononoke_types::NonRootMPath;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::CrossRepoPushSource;
+use crate::FileHook;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TombstoneEnforcement {
+    /// Log a warning and accept the change. Intended for modifications to
+    /// tombstoned paths that somehow still exist, while they are being
+    /// cleaned up.
+    Warn,
+    /// Reject the change.
+    Reject,
+}
+
+impl Default for TombstoneEnforcement {
+    fn default() -> Self {
+        TombstoneEnforcement::Reject
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockTombstonedPathsConfig {
+    /// Exact paths that have been deleted for compliance reasons and must
+    /// never be re-added.
+    #[serde(default)]
+    paths: Vec<String>,
+
+    /// Regexes matching paths that must never be re-added.
+    #[serde(default, with = "serde_regex")]
+    path_regexes: Vec<Regex>,
+
+    /// The compliance ticket that mandated the deletion, referenced in the
+    /// rejection message.
+    compliance_ticket: String,
+
+    /// What to do when a tombstoned path that somehow still exists is
+    /// modified. A file hook cannot see the parent manifest, so this
+    /// applies to every non-deletion change to a tombstoned path: the
+    /// default rejects, `"warn"` only logs while the stragglers are being
+    /// cleaned up.
+    #[serde(default)]
+    enforcement: TombstoneEnforcement,
+}
+
+/// Hook to prevent paths that were deleted for compliance reasons from ever
+/// being re-added. Any addition, copy destination, or modification whose
+/// path matches the tombstone list is blocked; deleting a tombstoned path
+/// is always allowed.
+#[derive(Clone, Debug)]
+pub struct BlockTombstonedPathsHook {
+    config: BlockTombstonedPathsConfig,
+}
+
+impl BlockTombstonedPathsHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: BlockTombstonedPathsConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+/// Easily-testable business logic of the `BlockTombstonedPathsHook`:
+/// returns the rejection message for a change that re-adds a tombstoned
+/// path, or `None` if the change is acceptable.
+fn check_tombstoned_path(
+    config: &BlockTombstonedPathsConfig,
+    path: &NonRootMPath,
+    change: Option<&BasicFileChange>,
+) -> Option<String> {
+    if change.is_none() {
+        // It is acceptable to delete a tombstoned path.
+        return None;
+    }
+
+    let path = path.to_string();
+    let matches = config.paths.iter().any(|tombstoned| tombstoned == &path)
+        || config
+            .path_regexes
+            .iter()
+            .any(|regex| regex.is_match(&path));
+
+    if matches {
+        Some(format!(
+            "Path '{}' was deleted for compliance reasons ({}) and must not be re-added.",
+            path, config.compliance_ticket,
+        ))
+    } else {
+        None
+    }
+}
+
+#[async_trait]
+impl FileHook for BlockTombstonedPathsHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        change: Option<&'change BasicFileChange>,
+        path: &'path NonRootMPath,
+        _cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        match check_tombstoned_path(&self.config, path, change) {
+            Some(message) => match self.config.enforcement {
+                TombstoneEnforcement::Warn => {
+                    slog::warn!(ctx.logger(), "block_tombstoned_paths: {}", message);
+                    Ok(HookExecution::Accepted)
+                }
+                TombstoneEnforcement::Reject => Ok(HookExecution::Rejected(
+                    HookRejectionInfo::new_long("Tombstoned path", message),
+                )),
+            },
+            None => Ok(HookExecution::Accepted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mononoke_macros::mononoke;
+    use mononoke_types::FileType;
+    use mononoke_types::GitLfs;
+    use mononoke_types_mocks::contentid::TWOS_CTID;
+
+    use super::*;
+
+    fn make_test_config() -> BlockTombstonedPathsConfig {
+        BlockTombstonedPathsConfig {
+            paths: vec!["secrets/leaked.txt".to_string()],
+            path_regexes: vec![Regex::new("^userdata/.*\\.dump$").unwrap()],
+            compliance_ticket: "COMP-1234".to_string(),
+            enforcement: TombstoneEnforcement::default(),
+        }
+    }
+
+    fn basic_change() -> BasicFileChange {
+        BasicFileChange::new(TWOS_CTID, FileType::Regular, 10, GitLfs::FullContent)
+    }
+
+    fn mpath(s: &str) -> NonRootMPath {
+        NonRootMPath::new(s).unwrap()
+    }
+
+    #[mononoke::test]
+    fn test_exact_path_readd_is_blocked() {
+        let config = make_test_config();
+        let message = check_tombstoned_path(
+            &config,
+            &mpath("secrets/leaked.txt"),
+            Some(&basic_change()),
+        )
+        .expect("re-add should be blocked");
+        assert!(message.contains("secrets/leaked.txt"));
+        assert!(message.contains("COMP-1234"));
+    }
+
+    #[mononoke::test]
+    fn test_regex_match_is_blocked() {
+        let config = make_test_config();
+        assert!(
+            check_tombstoned_path(
+                &config,
+                &mpath("userdata/2021-01-01.dump"),
+                Some(&basic_change()),
+            )
+            .is_some()
+        );
+    }
+
+    #[mononoke::test]
+    fn test_unrelated_path_passes() {
+        let config = make_test_config();
+        assert!(
+            check_tombstoned_path(&config, &mpath("src/lib.rs"), Some(&basic_change())).is_none()
+        );
+    }
+
+    #[mononoke::test]
+    fn test_deletion_passes() {
+        let config = make_test_config();
+        assert!(check_tombstoned_path(&config, &mpath("secrets/leaked.txt"), None).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use mononoke_types::BasicFileChange;
+use mononoke_types::NonRootMPath;
+use serde::Deserialize;
+
+use crate::CrossRepoPushSource;
+use crate::FileHook;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ArchiveSignature {
+    /// Human-readable format name included in the rejection, e.g. "zip".
+    format: String,
+    /// Bytes the file must start with to be detected as this format,
+    /// e.g. `[80, 75, 3, 4]` for zip.
+    magic: Vec<u8>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockUnreviewableArchivesConfig {
+    /// Archive formats to detect, by the magic bytes at the start of the
+    /// file.
+    signatures: Vec<ArchiveSignature>,
+
+    /// Allow archives under any of these paths, e.g. vendored code under
+    /// `third-party/`.
+    #[serde(default)]
+    allowed_paths: Vec<String>,
+
+    /// Number of leading bytes to scan for a magic signature. Files larger
+    /// than this still have their leading bytes checked, since the magic is
+    /// at the start; the cutoff only bounds how much of the file is
+    /// examined.
+    scan_cutoff: u64,
+
+    /// Message to include in the hook rejection.
+    /// ${filename} => The path of the file along with the filename
+    /// ${format} => The detected archive format
+    message: String,
+}
+
+/// Hook to block opaque archive files (zip, tar.gz, 7z, jar, ...) outside of
+/// allowed directories, since their contents bypass code review. Archives
+/// are detected by their leading magic bytes rather than their extension.
+#[derive(Clone, Debug)]
+pub struct BlockUnreviewableArchivesHook {
+    config: BlockUnreviewableArchivesConfig,
+}
+
+impl BlockUnreviewableArchivesHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: BlockUnreviewableArchivesConfig) -> Result<Self> {
+        for signature in &config.signatures {
+            if signature.magic.is_empty() {
+                return Err(anyhow!(
+                    "magic signature for format {} must not be empty",
+                    signature.format
+                ));
+            }
+        }
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl FileHook for BlockUnreviewableArchivesHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        content_manager: &'fetcher dyn HookStateProvider,
+        change: Option<&'change BasicFileChange>,
+        path: &'path NonRootMPath,
+        _cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        for allowed_path in &self.config.allowed_paths {
+            let allowed_mpath = NonRootMPath::new(allowed_path)
+                .with_context(|| anyhow!("{allowed_path} is an invalid path"))?;
+            if allowed_mpath.is_prefix_of(path) {
+                return Ok(HookExecution::Accepted);
+            }
+        }
+
+        let change = match change {
+            Some(change) => change,
+            None => return Ok(HookExecution::Accepted),
+        };
+
+        let text = match content_manager
+            .get_file_text(ctx, change.content_id())
+            .await?
+        {
+            Some(text) => text,
+            None => return Ok(HookExecution::Accepted),
+        };
+
+        // The magic is at the start, so even files above the scan cutoff
+        // have their leading bytes checked.
+        let scan = &text[..text.len().min(self.config.scan_cutoff as usize)];
+        for signature in &self.config.signatures {
+            if scan.starts_with(&signature.magic) {
+                return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "Non-reviewable archive file",
+                    self.config
+                        .message
+                        .replace("${filename}", &path.to_string())
+                        .replace("${format}", &signature.format),
+                )));
+            }
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::bookmark;
+    use tests_utils::drawdag::changes;
+    use tests_utils::drawdag::create_from_dag_with_changes;
+    use tests_utils::BasicTestRepo;
+
+    use super::*;
+    use crate::testlib::test_file_hook;
+
+    #[mononoke::fbinit_test]
+    async fn test_block_unreviewable_archives(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let changesets = create_from_dag_with_changes(
+            &ctx,
+            &repo,
+            r##"
+                Z-A-B-C-D
+            "##,
+            changes! {
+                "A" => |c| c.add_file("fixtures/data.zip", b"PK\x03\x04rest of the zip".to_vec()),
+                "B" => |c| c.add_file("fixtures/data.bin", b"\x1f\x8b\x08rest of the gzip".to_vec()),
+                "C" => |c| c.add_file("third-party/lib/data.zip", b"PK\x03\x04vendored zip".to_vec()),
+                "D" => |c| c.add_file("fixtures/data.txt", "plain text\n"),
+            },
+        )
+        .await?;
+        bookmark(&ctx, &repo, "main")
+            .create_publishing(changesets["Z"])
+            .await?;
+
+        let hook = BlockUnreviewableArchivesHook::with_config(BlockUnreviewableArchivesConfig {
+            signatures: vec![
+                ArchiveSignature {
+                    format: "zip".to_string(),
+                    magic: vec![0x50, 0x4B, 0x03, 0x04],
+                },
+                ArchiveSignature {
+                    format: "gzip".to_string(),
+                    magic: vec![0x1F, 0x8B],
+                },
+            ],
+            allowed_paths: vec!["third-party/".to_string()],
+            scan_cutoff: 64,
+            message: String::from(
+                "${filename} is a ${format} archive. Archives bypass code review; commit the expanded contents instead.",
+            ),
+        })?;
+
+        // A zip outside the allowed paths is rejected with its format.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["A"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("A".try_into()?, HookExecution::Accepted),
+                (
+                    "fixtures/data.zip".try_into()?,
+                    HookExecution::Rejected(HookRejectionInfo {
+                        description: "Non-reviewable archive file".into(),
+                        long_description:
+                            "fixtures/data.zip is a zip archive. Archives bypass code review; commit the expanded contents instead."
+                                .into(),
+                    })
+                )
+            ],
+        );
+
+        // Magic detection doesn't depend on the extension.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["B"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("B".try_into()?, HookExecution::Accepted),
+                (
+                    "fixtures/data.bin".try_into()?,
+                    HookExecution::Rejected(HookRejectionInfo {
+                        description: "Non-reviewable archive file".into(),
+                        long_description:
+                            "fixtures/data.bin is a gzip archive. Archives bypass code review; commit the expanded contents instead."
+                                .into(),
+                    })
+                )
+            ],
+        );
+
+        // An archive under an allowed path is accepted.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["C"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("C".try_into()?, HookExecution::Accepted),
+                (
+                    "third-party/lib/data.zip".try_into()?,
+                    HookExecution::Accepted
+                ),
+            ],
+        );
+
+        // A plain text file is accepted.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["D"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("D".try_into()?, HookExecution::Accepted),
+                ("fixtures/data.txt".try_into()?, HookExecution::Accepted),
+            ],
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use mononoke_types::BonsaiChangeset;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockedAuthorPatternsConfig {
+    /// Regular expressions matched against the full author string.  The
+    /// changeset is rejected if any pattern matches.
+    blocked_patterns: Vec<String>,
+
+    /// Message to include in the hook rejection.  The string is expanded
+    /// with `${author}` replaced by the rejected author.
+    rejection_message: String,
+}
+
+/// Hook to reject changesets whose author string matches any of a list of
+/// regular expressions, e.g. `user@localhost` authors or auto-generated bot
+/// emails that were never meant to land commits.
+#[derive(Clone, Debug)]
+pub struct BlockedAuthorPatternsHook {
+    blocked_patterns: Vec<Regex>,
+    rejection_message: String,
+}
+
+impl BlockedAuthorPatternsHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: BlockedAuthorPatternsConfig) -> Result<Self> {
+        let blocked_patterns = config
+            .blocked_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .with_context(|| format!("invalid blocked author pattern: {}", pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            blocked_patterns,
+            rejection_message: config.rejection_message,
+        })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for BlockedAuthorPatternsHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        let author = changeset.author();
+        for pattern in &self.blocked_patterns {
+            if pattern.is_match(author) {
+                let message = self.rejection_message.replace("${author}", author);
+                return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "Author matches a blocked pattern",
+                    message,
+                )));
+            }
+        }
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::bookmark;
+    use tests_utils::drawdag::changes;
+    use tests_utils::drawdag::create_from_dag_with_changes;
+    use tests_utils::BasicTestRepo;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> BlockedAuthorPatternsConfig {
+        BlockedAuthorPatternsConfig {
+            blocked_patterns: vec![
+                String::from(r"@localhost>?$"),
+                String::from(r"autogen-\w+@example\.com"),
+            ],
+            rejection_message: String::from("Author ${author} is not allowed to land commits."),
+        }
+    }
+
+    async fn run_hook_for_author(fb: FacebookInit, author: &str) -> Result<HookExecution> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let author = author.to_string();
+        let changesets = create_from_dag_with_changes(
+            &ctx,
+            &repo,
+            r##"
+                Z-A
+            "##,
+            changes! {
+                "A" => |c| c.set_author(author),
+            },
+        )
+        .await?;
+        bookmark(&ctx, &repo, "main")
+            .create_publishing(changesets["Z"])
+            .await?;
+
+        let hook = BlockedAuthorPatternsHook::with_config(make_test_config())?;
+
+        test_changeset_hook(
+            &ctx,
+            &repo,
+            &hook,
+            "main",
+            changesets["A"],
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_matching_author_is_rejected(fb: FacebookInit) -> Result<()> {
+        assert_eq!(
+            run_hook_for_author(fb, "Some User <user@localhost>").await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "Author matches a blocked pattern".into(),
+                long_description:
+                    "Author Some User <user@localhost> is not allowed to land commits.".into(),
+            }),
+        );
+        assert_eq!(
+            run_hook_for_author(fb, "Auto Gen <autogen-sync@example.com>").await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "Author matches a blocked pattern".into(),
+                long_description:
+                    "Author Auto Gen <autogen-sync@example.com> is not allowed to land commits."
+                        .into(),
+            }),
+        );
+        Ok(())
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_non_matching_author_is_accepted(fb: FacebookInit) -> Result<()> {
+        assert_eq!(
+            run_hook_for_author(fb, "Good User <gooduser@example.com>").await?,
+            HookExecution::Accepted,
+        );
+        Ok(())
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_invalid_regex_in_config_is_an_error(_fb: FacebookInit) -> Result<()> {
+        let config = BlockedAuthorPatternsConfig {
+            blocked_patterns: vec![String::from(r"(unclosed")],
+            rejection_message: String::from("unused"),
+        };
+        let err = BlockedAuthorPatternsHook::with_config(config).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("invalid blocked author pattern: (unclosed")
+        );
+        Ok(())
+    }
+}
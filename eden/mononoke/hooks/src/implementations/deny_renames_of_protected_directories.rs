@@ -0,0 +1,261 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use mononoke_types::BonsaiChangeset;
+use mononoke_types::NonRootMPath;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DenyRenamesOfProtectedDirectoriesConfig {
+    /// Directory prefixes that must not be renamed away from.  Moving files
+    /// from under one of these prefixes to a different prefix is rejected.
+    /// Renames that stay within the same protected prefix are allowed.
+    protected_prefixes: Vec<String>,
+
+    /// Authors that are exempt from this restriction, e.g. migration
+    /// automation.
+    #[serde(default)]
+    allow_list_authors: Vec<String>,
+}
+
+/// Hook to reject mass renames of protected directories, which break
+/// downstream path-based ACLs and sync mappings.  Detection is based on
+/// bonsai copy-from information: a file whose copy source is under a
+/// protected prefix but whose destination is not counts as a move.
+#[derive(Clone, Debug)]
+pub struct DenyRenamesOfProtectedDirectoriesHook {
+    config: DenyRenamesOfProtectedDirectoriesConfig,
+}
+
+impl DenyRenamesOfProtectedDirectoriesHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: DenyRenamesOfProtectedDirectoriesConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for DenyRenamesOfProtectedDirectoriesHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if self
+            .config
+            .allow_list_authors
+            .iter()
+            .any(|author| changeset.author() == author)
+        {
+            return Ok(HookExecution::Accepted);
+        }
+
+        // Map of (old prefix, new prefix) -> number of files moved.
+        let mut moves: BTreeMap<(String, String), usize> = BTreeMap::new();
+
+        for (path, change) in changeset.file_changes() {
+            let (copy_src, _) = match change.copy_from() {
+                Some(copy_from) => copy_from,
+                None => continue,
+            };
+            for prefix in &self.config.protected_prefixes {
+                let protected_mpath = NonRootMPath::new(prefix)
+                    .with_context(|| anyhow!("{prefix} is an invalid path"))?;
+                if protected_mpath.is_prefix_of(copy_src) && !protected_mpath.is_prefix_of(path) {
+                    let new_prefix = path
+                        .iter()
+                        .next()
+                        .map_or_else(String::new, |elem| elem.to_string());
+                    *moves.entry((prefix.clone(), new_prefix)).or_default() += 1;
+                }
+            }
+        }
+
+        if !moves.is_empty() {
+            let mut message =
+                String::from("Renaming files out of a protected directory is not allowed:\n");
+            for ((old_prefix, new_prefix), count) in moves {
+                writeln!(
+                    message,
+                    "  {} file(s) moved from '{}' to '{}'",
+                    count, old_prefix, new_prefix
+                )?;
+            }
+            return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "Protected directory was renamed",
+                message,
+            )));
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> DenyRenamesOfProtectedDirectoriesConfig {
+        DenyRenamesOfProtectedDirectoriesConfig {
+            protected_prefixes: vec!["protected".to_string()],
+            allow_list_authors: vec!["migration-bot".to_string()],
+        }
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_rejects_move_out_of_protected_prefix(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("protected/a", "a")
+            .add_file("protected/b", "b")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        let moved = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file_with_copy_info("elsewhere/a", "a", (root, "protected/a"))
+            .add_file_with_copy_info("elsewhere/b", "b", (root, "protected/b"))
+            .delete_file("protected/a")
+            .delete_file("protected/b")
+            .commit()
+            .await?;
+
+        let hook = DenyRenamesOfProtectedDirectoriesHook::with_config(make_test_config())?;
+
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                moved,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "Protected directory was renamed".into(),
+                long_description:
+                    "Renaming files out of a protected directory is not allowed:\n  2 file(s) moved from 'protected' to 'elsewhere'\n"
+                        .into(),
+            }),
+        );
+
+        Ok(())
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_allows_rename_within_protected_prefix(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("protected/a", "a")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        let renamed = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file_with_copy_info("protected/subdir/a", "a", (root, "protected/a"))
+            .delete_file("protected/a")
+            .commit()
+            .await?;
+
+        let hook = DenyRenamesOfProtectedDirectoriesHook::with_config(make_test_config())?;
+
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                renamed,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        Ok(())
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_allow_listed_author_can_move(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("protected/a", "a")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        let moved = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .set_author("migration-bot")
+            .add_file_with_copy_info("elsewhere/a", "a", (root, "protected/a"))
+            .delete_file("protected/a")
+            .commit()
+            .await?;
+
+        let hook = DenyRenamesOfProtectedDirectoriesHook::with_config(make_test_config())?;
+
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                moved,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        Ok(())
+    }
+}
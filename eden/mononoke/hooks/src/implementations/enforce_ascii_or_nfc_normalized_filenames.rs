@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use mononoke_types::BasicFileChange;
+use mononoke_types::NonRootMPath;
+use serde::Deserialize;
+use unicode_normalization::is_nfc;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::CrossRepoPushSource;
+use crate::FileHook;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct EnforceAsciiOrNfcNormalizedFilenamesConfig {
+    /// Paths under these prefixes must use ASCII-only filenames.
+    #[serde(default)]
+    ascii_only_prefixes: Vec<String>,
+}
+
+/// Hook to reject filenames that are not in NFC normalized form.  Filenames
+/// in NFD form (typically produced on macOS) collide with their NFC
+/// counterparts on Linux and confuse dedup tooling.  Specific path prefixes
+/// can additionally be restricted to ASCII-only filenames.
+#[derive(Clone, Debug)]
+pub struct EnforceAsciiOrNfcNormalizedFilenamesHook {
+    config: EnforceAsciiOrNfcNormalizedFilenamesConfig,
+}
+
+impl EnforceAsciiOrNfcNormalizedFilenamesHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: EnforceAsciiOrNfcNormalizedFilenamesConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl FileHook for EnforceAsciiOrNfcNormalizedFilenamesHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        change: Option<&'change BasicFileChange>,
+        path: &'path NonRootMPath,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if change.is_none() {
+            // Deletions of existing files must pass.
+            return Ok(HookExecution::Accepted);
+        }
+
+        let path_str = path.to_string();
+        let ascii_only = self
+            .config
+            .ascii_only_prefixes
+            .iter()
+            .any(|prefix| match NonRootMPath::new(prefix) {
+                Ok(prefix) => prefix.is_prefix_of(path),
+                Err(_) => false,
+            });
+
+        for component in path.iter() {
+            let component = match std::str::from_utf8(component.as_ref()) {
+                Ok(component) => component,
+                // Non-UTF8 filenames are left for other hooks to police.
+                Err(_) => continue,
+            };
+
+            if ascii_only && !component.is_ascii() {
+                return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "Filename must be ASCII",
+                    format!(
+                        "component '{component}' in path '{path_str}' contains non-ASCII characters, which are not permitted in this directory"
+                    ),
+                )));
+            }
+
+            if !is_nfc(component) {
+                let nfc: String = component.nfc().collect();
+                return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "Filename is not NFC normalized",
+                    format!(
+                        "component '{component}' in path '{path_str}' is not in NFC normalized form; rename it to its NFC equivalent '{nfc}'"
+                    ),
+                )));
+            }
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::bookmark;
+    use tests_utils::drawdag::changes;
+    use tests_utils::drawdag::create_from_dag_with_changes;
+    use tests_utils::BasicTestRepo;
+
+    use super::*;
+    use crate::testlib::test_file_hook;
+
+    #[mononoke::fbinit_test]
+    async fn test_enforce_ascii_or_nfc_normalized_filenames(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let changesets = create_from_dag_with_changes(
+            &ctx,
+            &repo,
+            r##"
+                A-B-C
+            "##,
+            changes! {
+                // U+0065 U+0301: NFD form of "café".
+                "A" => |c| c.add_file("dir/cafe\u{301}.txt", "nfd"),
+                // U+00E9: NFC form of "café".
+                "B" => |c| c.add_file("dir/caf\u{e9}.txt", "nfc"),
+                "C" => |c| c.add_file("ascii/caf\u{e9}.txt", "ascii"),
+            },
+        )
+        .await?;
+        bookmark(&ctx, &repo, "main")
+            .create_publishing(changesets["C"])
+            .await?;
+
+        let hook = EnforceAsciiOrNfcNormalizedFilenamesHook::with_config(
+            EnforceAsciiOrNfcNormalizedFilenamesConfig {
+                ascii_only_prefixes: vec!["ascii".to_string()],
+            },
+        )?;
+
+        // NFD filename is rejected.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["A"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("A".try_into()?, HookExecution::Accepted),
+                (
+                    "dir/cafe\u{301}.txt".try_into()?,
+                    HookExecution::Rejected(HookRejectionInfo {
+                        description: "Filename is not NFC normalized".into(),
+                        long_description:
+                            "component 'cafe\u{301}.txt' in path 'dir/cafe\u{301}.txt' is not in NFC normalized form; rename it to its NFC equivalent 'caf\u{e9}.txt'"
+                                .into(),
+                    }),
+                )
+            ],
+        );
+
+        // The NFC counterpart passes.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["B"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("B".try_into()?, HookExecution::Accepted),
+                ("dir/caf\u{e9}.txt".try_into()?, HookExecution::Accepted),
+            ],
+        );
+
+        // Non-ASCII filenames under an ASCII-only prefix are rejected, even
+        // in NFC form.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["C"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("C".try_into()?, HookExecution::Accepted),
+                (
+                    "ascii/caf\u{e9}.txt".try_into()?,
+                    HookExecution::Rejected(HookRejectionInfo {
+                        description: "Filename must be ASCII".into(),
+                        long_description:
+                            "component 'caf\u{e9}.txt' in path 'ascii/caf\u{e9}.txt' contains non-ASCII characters, which are not permitted in this directory"
+                                .into(),
+                    }),
+                )
+            ],
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,420 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use itertools::Itertools;
+use mononoke_types::BonsaiChangeset;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::FileChangeType;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+/// How many of the offending directories to list in the rejection message.
+const MAX_REPORTED_DIRECTORIES: usize = 10;
+
+/// Whether `directory` is the directory named by `prefix` or lies under it.
+/// Matches whole path components, so prefix `dir1` does not match `dir10`.
+fn prefix_matches(prefix: &str, directory: &str) -> bool {
+    match directory.strip_prefix(prefix.trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct EnforceMaxFileCountPerDirectoryAdditionConfig {
+    /// Maximum number of files a single commit may add to any one directory.
+    max_files_added_per_directory: u64,
+
+    /// Per-prefix overrides.  Directories at or under a prefix use the
+    /// override limit instead of the default; when several prefixes match,
+    /// the largest override wins.  Prefixes match whole path components.
+    #[serde(default)]
+    path_overrides: Vec<MaxFileCountPerDirectoryOverride>,
+
+    /// Directory prefixes excluded from the check, e.g. for generated code
+    /// where large additions are expected.  Prefixes match whole path
+    /// components.
+    #[serde(default)]
+    exclude_prefixes: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MaxFileCountPerDirectoryOverride {
+    /// This override applies to directories whose path starts with this
+    /// prefix.
+    prefix: String,
+
+    /// The limit to use for matching directories.
+    max_files_added_per_directory: u64,
+}
+
+/// Hook to guard against directory blowup: no single commit may add more
+/// than a configured number of files to any one directory.  Only direct
+/// children count; adding files to `dir/a` and `dir/b` counts against those
+/// directories, not against `dir`.
+///
+/// Only additions are counted, so commits that modify or remove many files
+/// in an already-large directory are unaffected.
+///
+/// Roots and merges are ignored.
+#[derive(Clone, Debug)]
+pub struct EnforceMaxFileCountPerDirectoryAdditionHook {
+    config: EnforceMaxFileCountPerDirectoryAdditionConfig,
+}
+
+impl EnforceMaxFileCountPerDirectoryAdditionHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: EnforceMaxFileCountPerDirectoryAdditionConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+
+    fn limit_for_directory(&self, directory: &str) -> u64 {
+        self.config
+            .path_overrides
+            .iter()
+            .filter_map(|path_override| {
+                prefix_matches(&path_override.prefix, directory)
+                    .then_some(path_override.max_files_added_per_directory)
+            })
+            .max()
+            .unwrap_or(self.config.max_files_added_per_directory)
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for EnforceMaxFileCountPerDirectoryAdditionHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        content_manager: &'fetcher dyn HookStateProvider,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        if cross_repo_push_source == CrossRepoPushSource::PushRedirected {
+            // For push-redirected commits, we rely on running source-repo hooks
+            return Ok(HookExecution::Accepted);
+        }
+
+        let parent_changeset_id = match changeset.parents().exactly_one() {
+            Ok(changeset_id) => changeset_id,
+            _ => {
+                // Ignore roots and merges
+                return Ok(HookExecution::Accepted);
+            }
+        };
+
+        let file_changes = content_manager
+            .file_changes(ctx, changeset.get_changeset_id(), parent_changeset_id)
+            .await?;
+
+        // Count of added files per parent directory.
+        let mut added_per_directory: HashMap<String, u64> = HashMap::new();
+
+        for (path, change) in file_changes {
+            if !matches!(change, FileChangeType::Added(_)) {
+                continue;
+            }
+            let directory = match path.split_dirname().0 {
+                Some(dirname) => dirname.to_string(),
+                None => String::new(),
+            };
+            if self
+                .config
+                .exclude_prefixes
+                .iter()
+                .any(|prefix| prefix_matches(prefix, &directory))
+            {
+                continue;
+            }
+            *added_per_directory.entry(directory).or_default() += 1;
+        }
+
+        let mut violations: Vec<(String, u64, u64)> = added_per_directory
+            .into_iter()
+            .filter_map(|(directory, count)| {
+                let limit = self.limit_for_directory(&directory);
+                (count > limit).then_some((directory, count, limit))
+            })
+            .collect();
+
+        if violations.is_empty() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        violations.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let listing = violations
+            .iter()
+            .take(MAX_REPORTED_DIRECTORIES)
+            .map(|(directory, count, limit)| {
+                let directory = if directory.is_empty() {
+                    "(root)"
+                } else {
+                    directory
+                };
+                format!("  {}: {} files added (limit {})", directory, count, limit)
+            })
+            .join("\n");
+
+        Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+            "Too many files added to one directory",
+            format!(
+                "This commit adds more files to a single directory than allowed. \
+                 Split the addition into several commits, or ask the repository \
+                 administrators for an exemption. Top directories:\n{}",
+                listing
+            ),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> EnforceMaxFileCountPerDirectoryAdditionConfig {
+        EnforceMaxFileCountPerDirectoryAdditionConfig {
+            max_files_added_per_directory: 2,
+            path_overrides: Vec::new(),
+            exclude_prefixes: Vec::new(),
+        }
+    }
+
+    fn assert_rejected_listing(hook_execution: HookExecution, listing: &str) {
+        match hook_execution {
+            HookExecution::Rejected(info) => {
+                assert!(
+                    info.long_description.ends_with(listing),
+                    "unexpected rejection: {}",
+                    info.long_description
+                );
+            }
+            HookExecution::Accepted => panic!("should be rejected"),
+        }
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_enforce_max_file_count_per_directory_addition(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("dir1/file1", "a")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        // Many files added to one directory.
+        let one_dir = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir1/file2", "b")
+            .add_file("dir1/file3", "b")
+            .add_file("dir1/file4", "b")
+            .commit()
+            .await?;
+
+        // The same number of files spread across directories.
+        let spread = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir1/file2", "b")
+            .add_file("dir2/file1", "b")
+            .add_file("dir3/file1", "b")
+            .commit()
+            .await?;
+
+        // Many files added under a generated prefix.
+        let generated = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("generated/thrift/file1", "b")
+            .add_file("generated/thrift/file2", "b")
+            .add_file("generated/thrift/file3", "b")
+            .commit()
+            .await?;
+
+        // Modifying an existing file doesn't count as an addition.
+        let modified = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir1/file1", "changed")
+            .add_file("dir1/file2", "b")
+            .add_file("dir1/file3", "b")
+            .commit()
+            .await?;
+
+        let hook = EnforceMaxFileCountPerDirectoryAdditionHook::with_config(make_test_config())?;
+
+        assert_rejected_listing(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                one_dir,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            "Top directories:\n  dir1: 3 files added (limit 2)",
+        );
+
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                spread,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                modified,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // Generated prefixes are checked unless excluded.
+        assert_rejected_listing(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                generated,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            "Top directories:\n  generated/thrift: 3 files added (limit 2)",
+        );
+
+        let mut config = make_test_config();
+        config.exclude_prefixes = vec!["generated/".to_string()];
+        let hook = EnforceMaxFileCountPerDirectoryAdditionHook::with_config(config)?;
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                generated,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // A per-prefix override raises the limit.
+        let mut config = make_test_config();
+        config.path_overrides = vec![MaxFileCountPerDirectoryOverride {
+            prefix: "dir1".to_string(),
+            max_files_added_per_directory: 5,
+        }];
+        let hook = EnforceMaxFileCountPerDirectoryAdditionHook::with_config(config)?;
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                one_dir,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // An override lower than the default also takes effect.
+        let mut config = make_test_config();
+        config.path_overrides = vec![MaxFileCountPerDirectoryOverride {
+            prefix: "dir1".to_string(),
+            max_files_added_per_directory: 1,
+        }];
+        let hook = EnforceMaxFileCountPerDirectoryAdditionHook::with_config(config)?;
+        assert_rejected_listing(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                one_dir,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            "Top directories:\n  dir1: 3 files added (limit 1)",
+        );
+
+        // Prefixes match whole path components: neither an override nor an
+        // exclusion for `dir1` applies to the sibling directory `dir10`.
+        let sibling = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir10/file1", "b")
+            .add_file("dir10/file2", "b")
+            .add_file("dir10/file3", "b")
+            .commit()
+            .await?;
+
+        let mut config = make_test_config();
+        config.path_overrides = vec![MaxFileCountPerDirectoryOverride {
+            prefix: "dir1".to_string(),
+            max_files_added_per_directory: 5,
+        }];
+        config.exclude_prefixes = vec!["dir1".to_string()];
+        let hook = EnforceMaxFileCountPerDirectoryAdditionHook::with_config(config)?;
+        assert_rejected_listing(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                sibling,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            "Top directories:\n  dir10: 3 files added (limit 2)",
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use mononoke_types::BasicFileChange;
+use mononoke_types::NonRootMPath;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::CrossRepoPushSource;
+use crate::FileHook;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ForbidByteOrderMarkConfig {
+    /// File extensions (without the leading dot) to check for a byte order
+    /// mark.  Files with other extensions are not checked.
+    extensions: Vec<String>,
+
+    /// Ignore paths.  Files whose paths match any of these regexes are not
+    /// checked, e.g. vendored code.
+    #[serde(default, with = "serde_regex")]
+    ignore_path_regexes: Vec<Regex>,
+
+    /// Message to include in the hook rejection.
+    /// ${filename} => The path of the file along with the filename
+    message: String,
+}
+
+/// Hook to reject source files that start with a UTF-8 byte order mark
+/// (`EF BB BF`), which breaks several build tools.
+#[derive(Clone, Debug)]
+pub struct ForbidByteOrderMarkHook {
+    config: ForbidByteOrderMarkConfig,
+}
+
+impl ForbidByteOrderMarkHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: ForbidByteOrderMarkConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl FileHook for ForbidByteOrderMarkHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        content_manager: &'fetcher dyn HookStateProvider,
+        change: Option<&'change BasicFileChange>,
+        path: &'path NonRootMPath,
+        _cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        let path = path.to_string();
+
+        if !self
+            .config
+            .extensions
+            .iter()
+            .any(|ext| path.ends_with(&format!(".{}", ext)))
+        {
+            return Ok(HookExecution::Accepted);
+        }
+
+        if self
+            .config
+            .ignore_path_regexes
+            .iter()
+            .any(|regex| regex.is_match(&path))
+        {
+            return Ok(HookExecution::Accepted);
+        }
+
+        if let Some(change) = change {
+            if let Some(text) = content_manager
+                .get_file_text(ctx, change.content_id())
+                .await?
+            {
+                if text.starts_with(&UTF8_BOM) {
+                    // Ignore binary files: only reject when the remainder of
+                    // the file is valid UTF-8, i.e. an actual text file that
+                    // happens to start with a BOM.
+                    if std::str::from_utf8(&text[UTF8_BOM.len()..]).is_ok() {
+                        return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                            "File starts with a byte order mark",
+                            self.config.message.replace("${filename}", &path),
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::bookmark;
+    use tests_utils::drawdag::changes;
+    use tests_utils::drawdag::create_from_dag_with_changes;
+    use tests_utils::BasicTestRepo;
+
+    use super::*;
+    use crate::testlib::test_file_hook;
+
+    #[mononoke::fbinit_test]
+    async fn test_forbid_byte_order_mark(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let changesets = create_from_dag_with_changes(
+            &ctx,
+            &repo,
+            r##"
+                Z-A-B-C
+            "##,
+            changes! {
+                "A" => |c| c.add_file("foo.py", b"\xEF\xBB\xBFprint('hello')\n".to_vec()),
+                "B" => |c| c.add_file("third-party/vendored/bar.py", b"\xEF\xBB\xBFprint('vendored')\n".to_vec()),
+                "C" => |c| c.add_file("baz.py", "print('no bom')\n"),
+            },
+        )
+        .await?;
+        bookmark(&ctx, &repo, "main")
+            .create_publishing(changesets["Z"])
+            .await?;
+
+        let hook = ForbidByteOrderMarkHook::with_config(ForbidByteOrderMarkConfig {
+            extensions: vec!["py".to_string()],
+            ignore_path_regexes: vec![Regex::new(r"^third-party/")?],
+            message: String::from(
+                "${filename} starts with a UTF-8 BOM. Strip it with `sed -i '1s/^\\xEF\\xBB\\xBF//'`.",
+            ),
+        })?;
+
+        // A BOM in a checked extension is rejected.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["A"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("A".try_into()?, HookExecution::Accepted),
+                (
+                    "foo.py".try_into()?,
+                    HookExecution::Rejected(HookRejectionInfo {
+                        description: "File starts with a byte order mark".into(),
+                        long_description:
+                            "foo.py starts with a UTF-8 BOM. Strip it with `sed -i '1s/^\\xEF\\xBB\\xBF//'`."
+                                .into(),
+                    })
+                )
+            ],
+        );
+
+        // A BOM in an excluded vendored path is accepted.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["B"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("B".try_into()?, HookExecution::Accepted),
+                (
+                    "third-party/vendored/bar.py".try_into()?,
+                    HookExecution::Accepted
+                ),
+            ],
+        );
+
+        // A BOM-less file is accepted.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["C"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("C".try_into()?, HookExecution::Accepted),
+                ("baz.py".try_into()?, HookExecution::Accepted),
+            ],
+        );
+
+        Ok(())
+    }
+}
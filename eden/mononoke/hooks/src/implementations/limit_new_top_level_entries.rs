@@ -0,0 +1,254 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use itertools::Itertools;
+use mononoke_types::BonsaiChangeset;
+use mononoke_types::MPath;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LimitNewTopLevelEntriesConfig {
+    /// New top-level entries whose names match any of these regexes may be
+    /// created without approval.
+    #[serde(default, with = "serde_regex")]
+    allowed_entry_regexes: Vec<Regex>,
+
+    /// Authors that are exempt from this restriction, e.g. migration
+    /// automation.
+    #[serde(default)]
+    allow_list_authors: Vec<String>,
+
+    /// Message to include in the hook rejection.  This should point at the
+    /// directory-ownership process.
+    ///
+    /// The following variables used in the message will be expanded:
+    ///    ${entry} => the name of the new top-level entry
+    message: String,
+}
+
+/// Hook to prevent the accidental creation of new top-level directories,
+/// e.g. because of a typo in an existing directory name.  A commit that adds
+/// files under a first path component that does not exist in the parent
+/// manifest is rejected unless the component is allowlisted or the author is
+/// exempt.
+///
+/// Roots and merges are ignored.
+#[derive(Clone, Debug)]
+pub struct LimitNewTopLevelEntriesHook {
+    config: LimitNewTopLevelEntriesConfig,
+}
+
+impl LimitNewTopLevelEntriesHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: LimitNewTopLevelEntriesConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for LimitNewTopLevelEntriesHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        content_manager: &'fetcher dyn HookStateProvider,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        if cross_repo_push_source == CrossRepoPushSource::PushRedirected {
+            // For push-redirected commits, we rely on running source-repo hooks
+            return Ok(HookExecution::Accepted);
+        }
+        if self
+            .config
+            .allow_list_authors
+            .iter()
+            .any(|author| changeset.author() == author)
+        {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let parent_changeset_id = match changeset.parents().exactly_one() {
+            Ok(changeset_id) => changeset_id,
+            _ => {
+                // Ignore roots and merges
+                return Ok(HookExecution::Accepted);
+            }
+        };
+
+        // Map of top-level component name to its path, for all components
+        // that files are being added under.
+        let mut top_level_dirs: BTreeMap<String, MPath> = BTreeMap::new();
+
+        for (path, file_change) in changeset.file_changes() {
+            if !file_change.is_changed() || path.num_components() < 2 {
+                continue;
+            }
+            let entry = match path.iter().next() {
+                Some(element) => element.to_string(),
+                None => continue,
+            };
+            if self
+                .config
+                .allowed_entry_regexes
+                .iter()
+                .any(|regex| regex.is_match(&entry))
+            {
+                continue;
+            }
+            let entry_path = MPath::new(&entry)?;
+            top_level_dirs.insert(entry, entry_path);
+        }
+
+        if top_level_dirs.is_empty() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let parent_directory_sizes = content_manager
+            .directory_sizes(
+                ctx,
+                parent_changeset_id,
+                top_level_dirs.values().cloned().collect(),
+            )
+            .await?;
+
+        for (entry, entry_path) in top_level_dirs {
+            if !parent_directory_sizes.contains_key(&entry_path) {
+                return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "New top-level entry",
+                    self.config.message.replace("${entry}", &entry),
+                )));
+            }
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> LimitNewTopLevelEntriesConfig {
+        LimitNewTopLevelEntriesConfig {
+            allowed_entry_regexes: Vec::new(),
+            allow_list_authors: vec!["migration-bot".to_string()],
+            message: String::from(
+                "New top-level entry '${entry}' requires approval; see the directory ownership process.",
+            ),
+        }
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_limit_new_top_level_entries(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("dir1/file1", "a")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        let existing_dir = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir1/file2", "b")
+            .commit()
+            .await?;
+
+        let new_dir = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("newdir/file1", "c")
+            .commit()
+            .await?;
+
+        let hook = LimitNewTopLevelEntriesHook::with_config(make_test_config())?;
+
+        // Adding under an existing top-level directory passes.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                existing_dir,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // Creating a new top-level directory is rejected.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                new_dir,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "New top-level entry".into(),
+                long_description:
+                    "New top-level entry 'newdir' requires approval; see the directory ownership process."
+                        .into(),
+            }),
+        );
+
+        // Allowlisted entries may be created.
+        let mut config = make_test_config();
+        config.allowed_entry_regexes = vec![Regex::new("^newdir$")?];
+        let hook = LimitNewTopLevelEntriesHook::with_config(config)?;
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                new_dir,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        Ok(())
+    }
+}
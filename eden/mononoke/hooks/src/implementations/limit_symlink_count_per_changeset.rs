@@ -0,0 +1,241 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use mononoke_types::BonsaiChangeset;
+use mononoke_types::FileType;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LimitSymlinkCountPerChangesetConfig {
+    /// Maximum number of symlinks a changeset may add or modify. Deletions
+    /// of symlinks don't count towards the limit.
+    symlink_count_limit: u64,
+
+    /// Users that are allowed to exceed the limit, e.g. known migration
+    /// tools that legitimately convert many files at once.
+    #[serde(default)]
+    allowed_users: Vec<String>,
+
+    /// Message to include in the hook rejection, with the following
+    /// replacements:
+    ///    ${count} => the number of symlinks added or modified
+    ///    ${limit} => the limit used
+    too_many_symlinks_message: String,
+}
+
+/// Hook to block changesets that add or modify more symlinks than a
+/// configured limit, to catch misbehaving tools that convert swathes of
+/// regular files into symlinks.
+#[derive(Clone, Debug)]
+pub struct LimitSymlinkCountPerChangesetHook {
+    config: LimitSymlinkCountPerChangesetConfig,
+}
+
+impl LimitSymlinkCountPerChangesetHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: LimitSymlinkCountPerChangesetConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for LimitSymlinkCountPerChangesetHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        _cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        if !self.config.allowed_users.is_empty() {
+            if let Some(username) = ctx.metadata().unix_name() {
+                if self
+                    .config
+                    .allowed_users
+                    .iter()
+                    .any(|allowed_user| allowed_user == username)
+                {
+                    // Allowed user, so passes hook without counting symlinks
+                    return Ok(HookExecution::Accepted);
+                }
+            }
+        }
+
+        // Deletions simplify to `None`, so only added or modified symlinks
+        // are counted.
+        let symlink_count = changeset
+            .file_changes()
+            .filter_map(|(_path, change)| change.simplify())
+            .filter(|change| change.file_type() == FileType::Symlink)
+            .count() as u64;
+
+        if symlink_count > self.config.symlink_count_limit {
+            return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "Too many symlinks in changeset",
+                self.config
+                    .too_many_symlinks_message
+                    .replace("${count}", &symlink_count.to_string())
+                    .replace("${limit}", &self.config.symlink_count_limit.to_string()),
+            )));
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use anyhow::Error;
+    use blobstore::Loadable;
+    use borrowed::borrowed;
+    use context::SessionContainer;
+    use fbinit::FacebookInit;
+    use metadata::Metadata;
+    use mononoke_macros::mononoke;
+    use permission_checker::MononokeIdentity;
+    use repo_hook_file_content_provider::RepoHookStateProvider;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+
+    /// Create default test config that each test can customize.
+    fn make_test_config() -> LimitSymlinkCountPerChangesetConfig {
+        LimitSymlinkCountPerChangesetConfig {
+            symlink_count_limit: 2,
+            allowed_users: Vec::new(),
+            too_many_symlinks_message: String::from("Too many symlinks: ${count} > ${limit}."),
+        }
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_limit_symlink_count_per_changeset(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(ctx.fb).await?;
+        borrowed!(ctx, repo);
+
+        let content_manager = RepoHookStateProvider::new(&repo);
+        let hook = LimitSymlinkCountPerChangesetHook::with_config(make_test_config())?;
+
+        // Two symlinks alongside regular files are within the limit.
+        let parent_cs_id = CreateCommitContext::new_root(ctx, repo)
+            .add_file_with_type("dir/link_a", "target_a", FileType::Symlink)
+            .add_file_with_type("dir/link_b", "target_b", FileType::Symlink)
+            .add_file("dir/regular", "contents")
+            .commit()
+            .await?;
+
+        let bcs = parent_cs_id.load(ctx, &repo.repo_blobstore).await?;
+        let hook_execution = hook
+            .run(
+                ctx,
+                &BookmarkKey::new("book")?,
+                &bcs,
+                &content_manager,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?;
+        assert_eq!(hook_execution, HookExecution::Accepted);
+
+        // Deleting a symlink doesn't count, so two new symlinks plus a
+        // deletion still pass.
+        let cs_id = CreateCommitContext::new(ctx, repo, vec![parent_cs_id])
+            .delete_file("dir/link_a")
+            .add_file_with_type("dir/link_c", "target_c", FileType::Symlink)
+            .add_file_with_type("dir/link_d", "target_d", FileType::Symlink)
+            .commit()
+            .await?;
+
+        let bcs = cs_id.load(ctx, &repo.repo_blobstore).await?;
+        let hook_execution = hook
+            .run(
+                ctx,
+                &BookmarkKey::new("book")?,
+                &bcs,
+                &content_manager,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?;
+        assert_eq!(hook_execution, HookExecution::Accepted);
+
+        // One symlink over the limit is rejected with the count and limit.
+        let cs_id = CreateCommitContext::new_root(ctx, repo)
+            .add_file_with_type("dir/link_a", "target_a", FileType::Symlink)
+            .add_file_with_type("dir/link_b", "target_b", FileType::Symlink)
+            .add_file_with_type("dir/link_c", "target_c", FileType::Symlink)
+            .commit()
+            .await?;
+
+        let bcs = cs_id.load(ctx, &repo.repo_blobstore).await?;
+        let hook_execution = hook
+            .run(
+                ctx,
+                &BookmarkKey::new("book")?,
+                &bcs,
+                &content_manager,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?;
+        match hook_execution {
+            HookExecution::Rejected(info) => {
+                assert_eq!(info.long_description, "Too many symlinks: 3 > 2.");
+            }
+            HookExecution::Accepted => return Err(anyhow::anyhow!("should be rejected")),
+        };
+
+        // An allowlisted pusher may exceed the limit.
+        let metadata = Metadata::default()
+            .set_identities([MononokeIdentity::new("USER", "migration_bot")].into());
+        let session = SessionContainer::builder(fb)
+            .metadata(Arc::new(metadata))
+            .build();
+        let allowed_ctx = CoreContext::test_mock_session(session);
+
+        let mut config = make_test_config();
+        config.allowed_users = vec![String::from("migration_bot")];
+        let hook = LimitSymlinkCountPerChangesetHook::with_config(config)?;
+        let hook_execution = hook
+            .run(
+                &allowed_ctx,
+                &BookmarkKey::new("book")?,
+                &bcs,
+                &content_manager,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?;
+        assert_eq!(hook_execution, HookExecution::Accepted);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use mononoke_types::BasicFileChange;
+use mononoke_types::FileType;
+use mononoke_types::NonRootMPath;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::CrossRepoPushSource;
+use crate::FileHook;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct NoAbsoluteSymlinkTargetsConfig {
+    /// Symlinks at paths matching these regexes may have absolute targets,
+    /// e.g. generated configuration trees.
+    #[serde(default, with = "serde_regex")]
+    allow_absolute_path_regexes: Vec<Regex>,
+}
+
+/// Hook to block symlinks whose targets are absolute paths, or escape the
+/// repo root via `..` components.  Such symlinks behave differently across
+/// checkout machines and are a security smell.
+#[derive(Clone, Debug)]
+pub struct NoAbsoluteSymlinkTargetsHook {
+    config: NoAbsoluteSymlinkTargetsConfig,
+}
+
+impl NoAbsoluteSymlinkTargetsHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: NoAbsoluteSymlinkTargetsConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+/// Check whether a relative symlink target resolved against the symlink's
+/// parent directory traverses above the repo root.
+fn escapes_repo_root(symlink_path: &NonRootMPath, target: &str) -> bool {
+    // Depth of the directory containing the symlink.
+    let mut depth = symlink_path.num_components() as i64 - 1;
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                depth -= 1;
+                if depth < 0 {
+                    return true;
+                }
+            }
+            _ => depth += 1,
+        }
+    }
+    false
+}
+
+#[async_trait]
+impl FileHook for NoAbsoluteSymlinkTargetsHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        content_manager: &'fetcher dyn HookStateProvider,
+        change: Option<&'change BasicFileChange>,
+        path: &'path NonRootMPath,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        let change = match change {
+            Some(change) if change.file_type() == FileType::Symlink => change,
+            _ => return Ok(HookExecution::Accepted),
+        };
+
+        let target = match content_manager
+            .get_file_text(ctx, change.content_id())
+            .await?
+        {
+            Some(text) => match String::from_utf8(text.to_vec()) {
+                Ok(target) => target,
+                // Non-UTF8 targets are left for other hooks to police.
+                Err(_) => return Ok(HookExecution::Accepted),
+            },
+            None => return Ok(HookExecution::Accepted),
+        };
+
+        let path_str = path.to_string();
+
+        if target.starts_with('/') {
+            if self
+                .config
+                .allow_absolute_path_regexes
+                .iter()
+                .any(|regex| regex.is_match(&path_str))
+            {
+                return Ok(HookExecution::Accepted);
+            }
+            return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "Symlink target is an absolute path",
+                format!("symlink '{path_str}' points to absolute path '{target}'"),
+            )));
+        }
+
+        if escapes_repo_root(path, &target) {
+            return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "Symlink target escapes the repo root",
+                format!("symlink '{path_str}' target '{target}' traverses above the repo root"),
+            )));
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::bookmark;
+    use tests_utils::drawdag::changes;
+    use tests_utils::drawdag::create_from_dag_with_changes;
+    use tests_utils::BasicTestRepo;
+
+    use super::*;
+    use crate::testlib::test_file_hook;
+
+    #[mononoke::fbinit_test]
+    async fn test_no_absolute_symlink_targets(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let changesets = create_from_dag_with_changes(
+            &ctx,
+            &repo,
+            r##"
+                A-B-C-D
+            "##,
+            changes! {
+                "A" => |c| c.add_file_with_type("dir/absolute", "/etc/hosts", FileType::Symlink),
+                "B" => |c| c.add_file_with_type("dir/escape", "../../../etc/passwd", FileType::Symlink),
+                "C" => |c| c.add_file_with_type("dir/safe", "../other/file", FileType::Symlink),
+                "D" => |c| c.add_file_with_type("generated/allowed", "/usr/bin/true", FileType::Symlink),
+            },
+        )
+        .await?;
+        bookmark(&ctx, &repo, "main")
+            .create_publishing(changesets["D"])
+            .await?;
+
+        let hook = NoAbsoluteSymlinkTargetsHook::with_config(NoAbsoluteSymlinkTargetsConfig {
+            allow_absolute_path_regexes: vec![Regex::new(r"^generated/")?],
+        })?;
+
+        // Absolute target is rejected.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["A"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("A".try_into()?, HookExecution::Accepted),
+                (
+                    "dir/absolute".try_into()?,
+                    HookExecution::Rejected(HookRejectionInfo {
+                        description: "Symlink target is an absolute path".into(),
+                        long_description:
+                            "symlink 'dir/absolute' points to absolute path '/etc/hosts'".into(),
+                    }),
+                )
+            ],
+        );
+
+        // Target escaping the repo root is rejected.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["B"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("B".try_into()?, HookExecution::Accepted),
+                (
+                    "dir/escape".try_into()?,
+                    HookExecution::Rejected(HookRejectionInfo {
+                        description: "Symlink target escapes the repo root".into(),
+                        long_description:
+                            "symlink 'dir/escape' target '../../../etc/passwd' traverses above the repo root"
+                                .into(),
+                    }),
+                )
+            ],
+        );
+
+        // Safe relative target passes.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["C"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("C".try_into()?, HookExecution::Accepted),
+                ("dir/safe".try_into()?, HookExecution::Accepted),
+            ],
+        );
+
+        // Absolute target in an allowlisted path passes.
+        assert_eq!(
+            test_file_hook(
+                &ctx,
+                &repo,
+                &hook,
+                changesets["D"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            vec![
+                ("D".try_into()?, HookExecution::Accepted),
+                ("generated/allowed".try_into()?, HookExecution::Accepted),
+            ],
+        );
+
+        Ok(())
+    }
+}
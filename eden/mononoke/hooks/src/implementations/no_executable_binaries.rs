@@ -34,6 +34,10 @@ pub struct NoExecutableBinariesConfig {
     /// Allow-list specific files that might be present in multiple paths
     /// by adding their Sha256 digest and size to this list.
     allow_list_files: Option<Vec<(String, u64)>>,
+    /// Users that are allowed to commit executable binaries, e.g. build bots
+    /// that legitimately need to land compiled artifacts.
+    #[serde(default)]
+    allowed_users: Vec<String>,
 }
 
 /// Hook to block commits containing files with illegal name patterns
@@ -66,6 +70,19 @@ impl FileHook for NoExecutableBinariesHook {
         _cross_repo_push_source: CrossRepoPushSource,
         _push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution> {
+        if !self.config.allowed_users.is_empty() {
+            if let Some(username) = ctx.metadata().unix_name() {
+                if self
+                    .config
+                    .allowed_users
+                    .iter()
+                    .any(|allowed_user| allowed_user == username)
+                {
+                    // Allowed user, so passes hook without checking content
+                    return Ok(HookExecution::Accepted);
+                }
+            }
+        }
         if let Some(allow_list_paths) = &self.config.allow_list_paths {
             for allowed_path in allow_list_paths {
                 let allowed_mpath = NonRootMPath::new(allowed_path)
@@ -122,15 +139,19 @@ mod test {
 
     use std::collections::HashMap;
     use std::collections::HashSet;
+    use std::sync::Arc;
 
     use anyhow::anyhow;
     use blobstore::Loadable;
     use borrowed::borrowed;
+    use context::SessionContainer;
     use fbinit::FacebookInit;
     use maplit::hashmap;
     use maplit::hashset;
+    use metadata::Metadata;
     use mononoke_macros::mononoke;
     use mononoke_types::BonsaiChangeset;
+    use permission_checker::MononokeIdentity;
     use repo_hook_file_content_provider::RepoHookStateProvider;
     use tests_utils::BasicTestRepo;
     use tests_utils::CreateCommitContext;
@@ -147,6 +168,7 @@ mod test {
                 "560a153deec1d4cda8481e96756e53c466f3c8eb2dabaf93f9e167c986bb77c4".to_string(),
                 3,
             )]),
+            allowed_users: Vec::new(),
         }
     }
 
@@ -396,4 +418,75 @@ mod test {
 
         assert_hook_execution(ctx, content_manager, bcs, hook, valid_files, illegal_files).await
     }
+
+    /// Test that allow-listed users may commit executable binaries, while
+    /// everyone else is still rejected.
+    #[mononoke::fbinit_test]
+    async fn test_allowed_users_may_commit_executable_binaries(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(ctx.fb)
+            .await
+            .expect("Failed to create test repo");
+
+        borrowed!(repo);
+
+        let cs_id = CreateCommitContext::new_root(&ctx, repo)
+            .add_file_with_type(
+                "foo/bar/exec",
+                vec![b'\0', 0x4D, 0x5A],
+                FileType::Executable,
+            )
+            .add_file("bar/baz/hoo.txt", "a")
+            .commit()
+            .await?;
+
+        let bcs = cs_id.load(&ctx, &repo.repo_blobstore).await?;
+
+        let mut config = make_test_config();
+        config.allowed_users = vec!["build_bot".to_string()];
+        let hook = NoExecutableBinariesHook::with_config(config);
+
+        // An allow-listed pusher may commit executable binaries.
+        let metadata =
+            Metadata::default().set_identities([MononokeIdentity::new("USER", "build_bot")].into());
+        let session = SessionContainer::builder(fb)
+            .metadata(Arc::new(metadata))
+            .build();
+        let allowed_ctx = CoreContext::test_mock_session(session);
+
+        let valid_files: HashSet<&str> = hashset! {"foo/bar/exec", "bar/baz/hoo.txt"};
+        let illegal_files: HashMap<&str, &str> = hashmap! {};
+
+        assert_hook_execution(
+            &allowed_ctx,
+            RepoHookStateProvider::new(repo),
+            bcs.clone(),
+            hook.clone(),
+            valid_files,
+            illegal_files,
+        )
+        .await?;
+
+        // Any other pusher is still rejected.
+        let metadata = Metadata::default()
+            .set_identities([MononokeIdentity::new("USER", "someone_else")].into());
+        let session = SessionContainer::builder(fb)
+            .metadata(Arc::new(metadata))
+            .build();
+        let other_ctx = CoreContext::test_mock_session(session);
+
+        let valid_files: HashSet<&str> = hashset! {"bar/baz/hoo.txt"};
+        let illegal_files: HashMap<&str, &str> =
+            hashmap! {"foo/bar/exec" => "Executable file 'foo/bar/exec' can't be committed."};
+
+        assert_hook_execution(
+            &other_ctx,
+            RepoHookStateProvider::new(repo),
+            bcs,
+            hook,
+            valid_files,
+            illegal_files,
+        )
+        .await
+    }
 }
@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use metaconfig_types::HookConfig;
+use mononoke_types::BonsaiChangeset;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NoMergeCommitsWithFileChangesConfig {
+    rejection_message: String,
+}
+
+/// Hook to block merge commits that also contain file changes, so that all
+/// changes land in regular commits and merges carry only merge metadata.
+#[derive(Clone, Debug)]
+pub struct NoMergeCommitsWithFileChangesHook {
+    config: NoMergeCommitsWithFileChangesConfig,
+}
+
+impl NoMergeCommitsWithFileChangesHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: NoMergeCommitsWithFileChangesConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for NoMergeCommitsWithFileChangesHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        if !changeset.is_merge() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        if changeset.file_changes().next().is_none() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+            "Merge commits must not contain file changes",
+            self.config.rejection_message.clone(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> NoMergeCommitsWithFileChangesConfig {
+        NoMergeCommitsWithFileChangesConfig {
+            rejection_message: String::from(
+                "Land your changes in regular commits and keep merges empty.",
+            ),
+        }
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_no_merge_commits_with_file_changes(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("dir1/file1", "a")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        let branch = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir2/file1", "b")
+            .commit()
+            .await?;
+
+        let non_merge = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir1/file2", "c")
+            .commit()
+            .await?;
+
+        let clean_merge = CreateCommitContext::new(&ctx, &repo, vec![non_merge, branch])
+            .commit()
+            .await?;
+
+        let dirty_merge = CreateCommitContext::new(&ctx, &repo, vec![non_merge, branch])
+            .add_file("dir1/file3", "d")
+            .commit()
+            .await?;
+
+        let hook = NoMergeCommitsWithFileChangesHook::with_config(make_test_config())?;
+
+        // Non-merge commits pass regardless of their file changes.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                non_merge,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // A merge without file changes passes.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                clean_merge,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // A merge with file changes is rejected with the configured message.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                dirty_merge,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "Merge commits must not contain file changes".into(),
+                long_description: "Land your changes in regular commits and keep merges empty."
+                    .into(),
+            }),
+        );
+
+        Ok(())
+    }
+}
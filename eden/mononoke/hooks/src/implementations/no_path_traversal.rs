@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use metaconfig_types::HookConfig;
+use mononoke_types::BasicFileChange;
+use mononoke_types::NonRootMPath;
+use serde::Deserialize;
+
+use crate::CrossRepoPushSource;
+use crate::FileHook;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NoPathTraversalConfig {
+    /// Message to include in the hook rejection if the file path contains a
+    /// traversal component, with the following replacements
+    /// ${filename} => The path of the file along with the filename
+    illegal_path_message: String,
+}
+
+/// Hook to reject file paths containing directory traversal components.
+///
+/// Path validation should already make such paths unrepresentable, but a
+/// crafted bundle could smuggle `../../etc/passwd`-style entries past a
+/// client that doesn't normalize. This hook is defense in depth: it rejects
+/// any path with a `..`, `.`, or NUL-containing component.
+#[derive(Clone, Debug)]
+pub struct NoPathTraversalHook {
+    config: NoPathTraversalConfig,
+}
+
+impl NoPathTraversalHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        config.parse_options().map(Self::with_config)
+    }
+
+    pub fn with_config(config: NoPathTraversalConfig) -> Self {
+        Self { config }
+    }
+
+    /// Whether `element` is a traversal component. This works on raw bytes so
+    /// the check is independent of platform path separators.
+    fn is_traversal_element(element: &[u8]) -> bool {
+        element == b".." || element == b"." || element.contains(&0)
+    }
+
+    fn check_path(&self, path: &NonRootMPath) -> HookExecution {
+        for element in path {
+            if Self::is_traversal_element(element.as_ref()) {
+                return HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "Illegal path traversal component",
+                    self.config
+                        .illegal_path_message
+                        .replace("${filename}", &path.to_string()),
+                ));
+            }
+        }
+        HookExecution::Accepted
+    }
+}
+
+#[async_trait]
+impl FileHook for NoPathTraversalHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _context_fetcher: &'fetcher dyn HookStateProvider,
+        change: Option<&'change BasicFileChange>,
+        path: &'path NonRootMPath,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        if cross_repo_push_source == CrossRepoPushSource::PushRedirected {
+            // For push-redirected pushes we rely on the hook
+            // running in the original repo
+            return Ok(HookExecution::Accepted);
+        }
+
+        if change.is_none() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        Ok(self.check_path(path))
+    }
+}
+
+/// `NonRootMPath` already rejects traversal components at parse time, so the
+/// element check is tested directly on raw bytes.
+#[cfg(test)]
+mod test {
+    use mononoke_macros::mononoke;
+
+    use super::*;
+
+    fn check_path(path: &str) -> bool {
+        let hook = NoPathTraversalHook::with_config(NoPathTraversalConfig {
+            illegal_path_message: "hook failed".to_string(),
+        });
+        match hook.check_path(&NonRootMPath::new(path).unwrap()) {
+            HookExecution::Accepted => true,
+            HookExecution::Rejected(_) => false,
+        }
+    }
+
+    #[mononoke::test]
+    fn test_good_paths() {
+        assert!(check_path("dir/some_filename.txt"));
+        assert!(check_path("dir/..twodots"));
+        assert!(check_path("dir/twodots.."));
+        assert!(check_path("...three/dots..."));
+        assert!(check_path(".hidden/.gitattributes"));
+    }
+
+    #[mononoke::test]
+    fn test_traversal_elements() {
+        assert!(NoPathTraversalHook::is_traversal_element(b".."));
+        assert!(NoPathTraversalHook::is_traversal_element(b"."));
+        assert!(NoPathTraversalHook::is_traversal_element(b"with\0nul"));
+        assert!(!NoPathTraversalHook::is_traversal_element(b"..."));
+        assert!(!NoPathTraversalHook::is_traversal_element(b"..a"));
+        assert!(!NoPathTraversalHook::is_traversal_element(b"a.."));
+        assert!(!NoPathTraversalHook::is_traversal_element(b"etc"));
+        assert!(!NoPathTraversalHook::is_traversal_element(b"passwd"));
+    }
+}
@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use metaconfig_types::HookConfig;
+use mononoke_types::BonsaiChangeset;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RequireBreakingChangeDocumentationConfig {
+    /// Pattern that marks a breaking change in the commit message, e.g.
+    /// `^BREAKING CHANGE:` (conventional commits style).
+    #[serde(with = "serde_regex")]
+    pub(crate) breaking_change_marker: Regex,
+
+    /// When set, a commit message containing the marker must describe the
+    /// breaking change: the line immediately following the marker must be
+    /// non-empty.
+    #[serde(default)]
+    pub(crate) requires_description_on_match: bool,
+}
+
+/// Hook that requires breaking changes to be documented.  Commits whose
+/// message contains the breaking-change marker are rejected unless the line
+/// after the marker describes the change.
+#[derive(Clone, Debug)]
+pub struct RequireBreakingChangeDocumentationHook {
+    config: RequireBreakingChangeDocumentationConfig,
+}
+
+impl RequireBreakingChangeDocumentationHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: RequireBreakingChangeDocumentationConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for RequireBreakingChangeDocumentationHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        _cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        if !self.config.requires_description_on_match {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let mut lines = changeset.message().lines();
+        while let Some(line) = lines.next() {
+            if self.config.breaking_change_marker.is_match(line) {
+                let described = lines
+                    .next()
+                    .is_some_and(|next_line| !next_line.trim().is_empty());
+                if !described {
+                    return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                        "Breaking change is not documented",
+                        format!(
+                            "The commit message marks a breaking change (matched `{}`), \
+                             but the line after the marker does not describe it.  Add a \
+                             description of the breaking change on the following line.",
+                            self.config.breaking_change_marker,
+                        ),
+                    )));
+                }
+            }
+        }
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::bookmark;
+    use tests_utils::drawdag::changes;
+    use tests_utils::drawdag::create_from_dag_with_changes;
+    use tests_utils::BasicTestRepo;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    #[mononoke::fbinit_test]
+    async fn test_requires_description_after_marker(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let changesets = create_from_dag_with_changes(
+            &ctx,
+            &repo,
+            r##"
+                Z-A-B-C-D
+            "##,
+            changes! {
+                "A" => |c| c.set_message("A\nno breaking changes here"),
+                "B" => |c| c.set_message("B\nBREAKING CHANGE:\nremoved the frob API"),
+                "C" => |c| c.set_message("C\nBREAKING CHANGE:\n\nremoved the frob API"),
+                "D" => |c| c.set_message("D\nBREAKING CHANGE:"),
+            },
+        )
+        .await?;
+        bookmark(&ctx, &repo, "main")
+            .create_publishing(changesets["Z"])
+            .await?;
+
+        let hook = RequireBreakingChangeDocumentationHook::with_config(
+            RequireBreakingChangeDocumentationConfig {
+                breaking_change_marker: Regex::new(r"^BREAKING CHANGE:")?,
+                requires_description_on_match: true,
+            },
+        )?;
+
+        // No marker: accepted.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                changesets["A"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+        // Marker followed by a description: accepted.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                changesets["B"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+        // Marker followed by an empty line: rejected.
+        assert!(matches!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                changesets["C"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Rejected(_),
+        ));
+        // Marker on the last line: rejected.
+        assert!(matches!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                changesets["D"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Rejected(_),
+        ));
+
+        // Without `requires_description_on_match` the hook never rejects.
+        let hook = RequireBreakingChangeDocumentationHook::with_config(
+            RequireBreakingChangeDocumentationConfig {
+                breaking_change_marker: Regex::new(r"^BREAKING CHANGE:")?,
+                requires_description_on_match: false,
+            },
+        )?;
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                changesets["D"],
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        Ok(())
+    }
+}
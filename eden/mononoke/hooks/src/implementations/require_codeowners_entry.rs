@@ -0,0 +1,252 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use itertools::Itertools;
+use mononoke_types::BonsaiChangeset;
+use mononoke_types::MPath;
+use mononoke_types::NonRootMPath;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RequireCodeownersEntryConfig {
+    /// Path of the ownership metadata file that must be modified whenever a
+    /// new top-level directory is created, e.g. `.github/CODEOWNERS` or
+    /// `tools/owners.toml`.
+    codeowners_path: String,
+
+    /// Message to include in the hook rejection.
+    ///
+    /// The following variables used in the message will be expanded:
+    ///    ${entries} => the names of the new top-level directories
+    ///    ${codeowners_path} => the configured metadata file path
+    message: String,
+}
+
+/// Hook to require that a changeset creating a new top-level directory also
+/// updates the ownership metadata file, so that every top-level directory
+/// has an owner.  A commit that adds files under a first path component that
+/// does not exist in the parent manifest is rejected unless the same commit
+/// also modifies the configured metadata file.
+///
+/// Roots and merges are ignored.
+#[derive(Clone, Debug)]
+pub struct RequireCodeownersEntryHook {
+    config: RequireCodeownersEntryConfig,
+}
+
+impl RequireCodeownersEntryHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: RequireCodeownersEntryConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for RequireCodeownersEntryHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        content_manager: &'fetcher dyn HookStateProvider,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        if cross_repo_push_source == CrossRepoPushSource::PushRedirected {
+            // For push-redirected commits, we rely on running source-repo hooks
+            return Ok(HookExecution::Accepted);
+        }
+
+        let parent_changeset_id = match changeset.parents().exactly_one() {
+            Ok(changeset_id) => changeset_id,
+            _ => {
+                // Ignore roots and merges
+                return Ok(HookExecution::Accepted);
+            }
+        };
+
+        let codeowners_path = NonRootMPath::new(&self.config.codeowners_path)?;
+
+        // Map of top-level component name to its path, for all components
+        // that files are being added under.
+        let mut top_level_dirs: BTreeMap<String, MPath> = BTreeMap::new();
+
+        for (path, file_change) in changeset.file_changes() {
+            if file_change.is_changed() && *path == codeowners_path {
+                // The ownership metadata is being updated, so any new
+                // top-level directory is accounted for.
+                return Ok(HookExecution::Accepted);
+            }
+            if !file_change.is_changed() || path.num_components() < 2 {
+                continue;
+            }
+            let entry = match path.iter().next() {
+                Some(element) => element.to_string(),
+                None => continue,
+            };
+            let entry_path = MPath::new(&entry)?;
+            top_level_dirs.insert(entry, entry_path);
+        }
+
+        if top_level_dirs.is_empty() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let parent_directory_sizes = content_manager
+            .directory_sizes(
+                ctx,
+                parent_changeset_id,
+                top_level_dirs.values().cloned().collect(),
+            )
+            .await?;
+
+        let new_dirs = top_level_dirs
+            .into_iter()
+            .filter(|(_entry, entry_path)| !parent_directory_sizes.contains_key(entry_path))
+            .map(|(entry, _entry_path)| entry)
+            .collect::<Vec<_>>();
+
+        if !new_dirs.is_empty() {
+            return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "New top-level directory without ownership entry",
+                self.config
+                    .message
+                    .replace("${entries}", &new_dirs.join(", "))
+                    .replace("${codeowners_path}", &self.config.codeowners_path),
+            )));
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> RequireCodeownersEntryConfig {
+        RequireCodeownersEntryConfig {
+            codeowners_path: String::from(".github/CODEOWNERS"),
+            message: String::from(
+                "New top-level directories (${entries}) must have an entry in ${codeowners_path}.",
+            ),
+        }
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_require_codeowners_entry(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("dir1/file1", "a")
+            .add_file(".github/CODEOWNERS", "dir1 @someone\n")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        let existing_dir = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir1/file2", "b")
+            .commit()
+            .await?;
+
+        let violating = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("newdir/file1", "c")
+            .add_file("otherdir/file1", "d")
+            .commit()
+            .await?;
+
+        let compliant = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("newdir/file1", "c")
+            .add_file(".github/CODEOWNERS", "dir1 @someone\nnewdir @someone\n")
+            .commit()
+            .await?;
+
+        let hook = RequireCodeownersEntryHook::with_config(make_test_config())?;
+
+        // Adding under an existing top-level directory passes.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                existing_dir,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // New top-level directories without a metadata update are rejected,
+        // all listed in the message.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                violating,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "New top-level directory without ownership entry".into(),
+                long_description:
+                    "New top-level directories (newdir, otherdir) must have an entry in .github/CODEOWNERS."
+                        .into(),
+            }),
+        );
+
+        // Touching the metadata file in the same changeset passes.
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                compliant,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        Ok(())
+    }
+}
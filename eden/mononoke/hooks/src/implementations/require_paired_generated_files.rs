@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use mononoke_types::BonsaiChangeset;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::HookStateProvider;
+use crate::PushAuthoredBy;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PairedFileMapping {
+    /// Regex matching source files that have a generated counterpart,
+    /// e.g. `^thrift/(.*)\.thrift$`.
+    #[serde(with = "serde_regex")]
+    source_regex: Regex,
+
+    /// Template producing the counterpart path from the source regex's
+    /// capture groups, e.g. `gen/$1.rs`.
+    target_template: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RequirePairedGeneratedFilesConfig {
+    /// Source-to-counterpart mappings to enforce.
+    mappings: Vec<PairedFileMapping>,
+
+    /// Including this marker in the commit message bypasses the hook, for
+    /// intentional desyncs.
+    bypass_marker: String,
+
+    /// Message to include in the hook rejection.
+    ///
+    /// The following variables used in the message will be expanded:
+    ///    ${paths} => the missing counterpart paths
+    ///    ${bypass_marker} => the configured bypass marker
+    message: String,
+}
+
+/// Hook to keep generated files in sync with their sources: a changeset
+/// that modifies a file matching a source regex must also touch the
+/// counterpart path derived from it, e.g. editing `foo.thrift` must come
+/// with the regenerated code under the mapped directory.
+#[derive(Clone, Debug)]
+pub struct RequirePairedGeneratedFilesHook {
+    config: RequirePairedGeneratedFilesConfig,
+}
+
+impl RequirePairedGeneratedFilesHook {
+    pub fn new(config: &HookConfig) -> Result<Self> {
+        Self::with_config(config.parse_options()?)
+    }
+
+    pub fn with_config(config: RequirePairedGeneratedFilesConfig) -> Result<Self> {
+        Ok(Self { config })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for RequirePairedGeneratedFilesHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn HookStateProvider,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        if cross_repo_push_source == CrossRepoPushSource::PushRedirected {
+            // For push-redirected commits, we rely on running source-repo hooks
+            return Ok(HookExecution::Accepted);
+        }
+        if changeset.message().contains(&self.config.bypass_marker) {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let touched_paths = changeset
+            .file_changes()
+            .map(|(path, _change)| path.to_string())
+            .collect::<BTreeSet<_>>();
+
+        let mut missing = BTreeSet::new();
+        for path in &touched_paths {
+            for mapping in &self.config.mappings {
+                if let Some(captures) = mapping.source_regex.captures(path) {
+                    let mut counterpart = String::new();
+                    captures.expand(&mapping.target_template, &mut counterpart);
+                    if !touched_paths.contains(&counterpart) {
+                        missing.insert(counterpart);
+                    }
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "Generated files out of sync",
+                self.config
+                    .message
+                    .replace(
+                        "${paths}",
+                        &missing.into_iter().collect::<Vec<_>>().join(", "),
+                    )
+                    .replace("${bypass_marker}", &self.config.bypass_marker),
+            )));
+        }
+
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use mononoke_macros::mononoke;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+    use crate::testlib::test_changeset_hook;
+
+    fn make_test_config() -> RequirePairedGeneratedFilesConfig {
+        RequirePairedGeneratedFilesConfig {
+            mappings: vec![PairedFileMapping {
+                source_regex: Regex::new(r"^thrift/(.*)\.thrift$").unwrap(),
+                target_template: String::from("gen/$1.rs"),
+            }],
+            bypass_marker: String::from("@allow-generated-desync"),
+            message: String::from(
+                "Changed sources require regenerated counterparts: ${paths}. Use ${bypass_marker} in the commit message to bypass.",
+            ),
+        }
+    }
+
+    #[mononoke::fbinit_test]
+    async fn test_require_paired_generated_files(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(fb).await?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("thrift/foo.thrift", "struct Foo {}\n")
+            .add_file("gen/foo.rs", "struct Foo;\n")
+            .commit()
+            .await?;
+        tests_utils::bookmark(&ctx, &repo, "main")
+            .create_publishing(root)
+            .await?;
+
+        let hook = RequirePairedGeneratedFilesHook::with_config(make_test_config())?;
+
+        // Both the source and its counterpart are touched.
+        let satisfied = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("thrift/foo.thrift", "struct Foo { 1: i64 id }\n")
+            .add_file("gen/foo.rs", "struct Foo { id: i64 }\n")
+            .commit()
+            .await?;
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                satisfied,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        // The source is changed without its counterpart.
+        let desynced = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("thrift/foo.thrift", "struct Foo { 1: i64 id }\n")
+            .commit()
+            .await?;
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                desynced,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Rejected(HookRejectionInfo {
+                description: "Generated files out of sync".into(),
+                long_description:
+                    "Changed sources require regenerated counterparts: gen/foo.rs. Use @allow-generated-desync in the commit message to bypass."
+                        .into(),
+            }),
+        );
+
+        // The bypass marker in the commit message allows the desync.
+        let bypassed = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("thrift/foo.thrift", "struct Foo { 1: i64 id }\n")
+            .set_message("Update thrift only\n\n@allow-generated-desync")
+            .commit()
+            .await?;
+        assert_eq!(
+            test_changeset_hook(
+                &ctx,
+                &repo,
+                &hook,
+                "main",
+                bypassed,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await?,
+            HookExecution::Accepted,
+        );
+
+        Ok(())
+    }
+}
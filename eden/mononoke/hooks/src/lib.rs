@@ -21,6 +21,7 @@ mod testlib;
 pub use hook_manager::BookmarkHook;
 pub use hook_manager::ChangesetHook;
 pub use hook_manager::CrossRepoPushSource;
+pub use hook_manager::FileChangeType;
 pub use hook_manager::FileHook;
 pub use hook_manager::HookExecution;
 pub use hook_manager::HookManager;
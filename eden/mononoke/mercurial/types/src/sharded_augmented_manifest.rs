@@ -634,6 +634,23 @@ pub async fn fetch_augmented_manifest_envelope_opt<B: Blobstore>(
     blobstore: &B,
     augmented_node_id: HgAugmentedManifestId,
 ) -> Result<Option<HgAugmentedManifestEnvelope>> {
+    Ok(
+        fetch_augmented_manifest_envelope_opt_with_ctime(ctx, blobstore, augmented_node_id)
+            .await?
+            .map(|(envelope, _ctime)| envelope),
+    )
+}
+
+/// Like `fetch_augmented_manifest_envelope_opt`, but also returns the
+/// blobstore ctime of the envelope blob when the blobstore records one.
+/// The envelope blob is written once, when the augmented manifest is
+/// derived, so the ctime tells freshly derived manifests apart from
+/// backfilled ones.
+pub async fn fetch_augmented_manifest_envelope_opt_with_ctime<B: Blobstore>(
+    ctx: &CoreContext,
+    blobstore: &B,
+    augmented_node_id: HgAugmentedManifestId,
+) -> Result<Option<(HgAugmentedManifestEnvelope, Option<i64>)>> {
     if augmented_node_id == HgAugmentedManifestId::new(NULL_HASH) {
         return Ok(None);
     }
@@ -646,6 +663,7 @@ pub async fn fetch_augmented_manifest_envelope_opt<B: Blobstore>(
         Some(bytes) => bytes,
         None => return Ok(None),
     };
+    let ctime = blobstore_bytes.as_meta().ctime();
     let envelope = HgAugmentedManifestEnvelope::from_blob(blobstore_bytes.into_raw_bytes())?;
     if augmented_node_id.into_nodehash() != envelope.augmented_manifest.hg_node_id() {
         bail!(
@@ -654,7 +672,7 @@ pub async fn fetch_augmented_manifest_envelope_opt<B: Blobstore>(
             envelope.augmented_manifest.hg_node_id()
         );
     }
-    Ok(Some(envelope))
+    Ok(Some((envelope, ctime)))
 }
 
 #[async_trait]
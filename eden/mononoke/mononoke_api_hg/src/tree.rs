@@ -8,7 +8,7 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
-use mercurial_types::fetch_augmented_manifest_envelope_opt;
+use mercurial_types::fetch_augmented_manifest_envelope_opt_with_ctime;
 use mercurial_types::fetch_manifest_envelope;
 use mercurial_types::fetch_manifest_envelope_opt;
 use mercurial_types::HgAugmentedManifestEntry;
@@ -78,6 +78,7 @@ pub struct HgAugmentedTreeContext<R> {
     #[allow(dead_code)]
     repo_ctx: HgRepoContext<R>,
     preloaded_manifest: HgPreloadedAugmentedManifest,
+    derivation_ctime: Option<i64>,
 }
 
 impl<R: MononokeRepo> HgAugmentedTreeContext<R> {
@@ -89,13 +90,15 @@ impl<R: MononokeRepo> HgAugmentedTreeContext<R> {
         let ctx = repo_ctx.ctx();
         let blobstore = repo_ctx.repo().repo_blobstore();
         let envelope =
-            fetch_augmented_manifest_envelope_opt(ctx, blobstore, augmented_manifest_id).await?;
-        if let Some(envelope) = envelope {
+            fetch_augmented_manifest_envelope_opt_with_ctime(ctx, blobstore, augmented_manifest_id)
+                .await?;
+        if let Some((envelope, derivation_ctime)) = envelope {
             let preloaded_manifest =
                 HgPreloadedAugmentedManifest::load_from_sharded(envelope, ctx, blobstore).await?;
             Ok(Some(Self {
                 repo_ctx,
                 preloaded_manifest,
+                derivation_ctime,
             }))
         } else {
             Ok(None)
@@ -110,6 +113,14 @@ impl<R: MononokeRepo> HgAugmentedTreeContext<R> {
         self.preloaded_manifest.augmented_manifest_size
     }
 
+    /// When the augmented manifest envelope blob was written, if the
+    /// blobstore records creation times. The blob is written at derivation
+    /// time, so this tells freshly derived manifests apart from backfilled
+    /// ones.
+    pub fn derivation_ctime(&self) -> Option<i64> {
+        self.derivation_ctime
+    }
+
     pub fn augmented_children_entries(
         &self,
     ) -> impl Iterator<Item = &(MPathElement, HgAugmentedManifestEntry)> {
@@ -175,6 +175,7 @@ fn fetch_files(
             pure_content: !aux_only,
             content_header: !aux_only && !pure_content,
             aux_data: true,
+            lfs_pointer: false,
         },
     );
 
@@ -187,6 +188,7 @@ fn fetch_files(
                 pure_content: true,
                 content_header: !pure_content,
                 aux_data: false,
+                lfs_pointer: false,
             },
         );
         missing = fetch_and_display_successes(
@@ -195,6 +197,7 @@ fn fetch_files(
                 pure_content: false,
                 content_header: false,
                 aux_data: true,
+                lfs_pointer: false,
             },
         );
     }
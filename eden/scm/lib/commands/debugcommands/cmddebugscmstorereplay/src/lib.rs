@@ -36,21 +36,18 @@ pub fn run(ctx: ReqCtx<DebugScmStoreReplayOpts>, repo: &Repo) -> Result<u8> {
     let (mut key_count, mut fetch_count) = (0, 0);
     let start_instant = Instant::now();
     for log in activitylogger::log_iter(ctx.opts.path)? {
-        let log = log?;
-        match log.op {
-            activitylogger::ActivityType::FileFetch => {
-                key_count += log.keys.len();
-                fetch_count += 1;
-                let result = store.fetch(log.keys, log.attrs, FetchMode::AllowRemote);
-                match result.missing() {
-                    Ok(failed) => {
-                        if !failed.is_empty() {
-                            write!(stderr, "Failed to fetch keys {:?}\n", failed)?;
-                        }
+        if let activitylogger::ActivityRecord::FileFetch { keys, attrs, .. } = log? {
+            key_count += keys.len();
+            fetch_count += 1;
+            let result = store.fetch(keys, attrs, FetchMode::AllowRemote);
+            match result.missing() {
+                Ok(failed) => {
+                    if !failed.is_empty() {
+                        write!(stderr, "Failed to fetch keys {:?}\n", failed)?;
                     }
-                    Err(err) => write!(stderr, "Fetch error: {:#?}\n", err)?,
-                };
-            }
+                }
+                Err(err) => write!(stderr, "Fetch error: {:#?}\n", err)?,
+            };
         }
     }
 
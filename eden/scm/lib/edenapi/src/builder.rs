@@ -49,6 +49,7 @@ static CUSTOM_BUILD_FUNCS: Lazy<
 pub struct Builder<'a> {
     config: &'a dyn configmodel::Config,
     repo_name: Option<String>,
+    timeout: Option<Duration>,
 }
 
 impl<'a> Builder<'a> {
@@ -57,6 +58,7 @@ impl<'a> Builder<'a> {
         let builder = Self {
             config,
             repo_name: None,
+            timeout: None,
         };
         Ok(builder)
     }
@@ -67,6 +69,12 @@ impl<'a> Builder<'a> {
         self
     }
 
+    /// Override the request timeout. This is only used by the Http Client.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     /// Build the client.
     pub fn build(self) -> Result<Arc<dyn SaplingRemoteApi>, SaplingRemoteApiError> {
         {
@@ -85,6 +93,10 @@ impl<'a> Builder<'a> {
             builder = builder.repo_name(repo_name);
         }
 
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
         Ok(Arc::new(builder.build()?))
     }
 
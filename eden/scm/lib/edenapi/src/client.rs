@@ -503,6 +503,7 @@ impl Client {
             manifest_blob: attrs.manifest_blob,
             parents: attrs.parents,
             child_metadata: attrs.child_metadata,
+            child_file_header_metadata: attrs.child_file_header_metadata,
             augmented_trees: attrs.augmented_trees || self.config().augmented_trees,
         };
 
@@ -518,6 +519,7 @@ impl Client {
                 let req = TreeRequest {
                     keys,
                     attributes: attrs,
+                    priority: 0,
                 };
                 self.log_request(&req, "trees");
                 req
@@ -31,11 +31,27 @@ use thiserror::Error;
 ///    situation.
 /// 2: HexError
 ///    Failed to convert hex to binary hash.
+/// 3: KeyNotFound
+///    The requested key does not exist on the server. Retrying will not help;
+///    the client should report the key as missing.
+/// 4: Retriable
+///    A transient server-side failure (e.g. an overloaded backend). The client
+///    may re-request the affected key.
 pub struct ServerError {
     pub message: String,
     pub code: u64,
 }
 
+/// How a client should react to a [`ServerError`], derived from its `code`.
+/// Unknown codes are treated as fatal since the client can't know whether
+/// retrying is safe.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServerErrorKind {
+    Retriable,
+    NotFound,
+    Fatal,
+}
+
 impl ServerError {
     pub fn new<M: Into<String>>(m: M, code: u64) -> Self {
         Self {
@@ -47,6 +63,14 @@ impl ServerError {
     pub fn generic<M: Into<String>>(m: M) -> Self {
         Self::new(m, 0)
     }
+
+    pub fn kind(&self) -> ServerErrorKind {
+        match self.code {
+            3 => ServerErrorKind::NotFound,
+            4 => ServerErrorKind::Retriable,
+            _ => ServerErrorKind::Fatal,
+        }
+    }
 }
 
 impl From<types::hash::HexError> for ServerError {
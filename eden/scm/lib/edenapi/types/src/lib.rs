@@ -141,6 +141,7 @@ pub use crate::commitid::CommitId;
 pub use crate::commitid::CommitIdScheme;
 pub use crate::commitid::GitSha1;
 pub use crate::errors::ServerError;
+pub use crate::errors::ServerErrorKind;
 pub use crate::file::FileAttributes;
 pub use crate::file::FileAuxData;
 pub use crate::file::FileContent;
@@ -245,6 +246,22 @@ impl SaplingRemoteApiServerError {
             }),
         }
     }
+
+    pub fn with_key_and_code(key: Key, err: ServerError) -> SaplingRemoteApiServerError {
+        SaplingRemoteApiServerError {
+            err: SaplingRemoteApiServerErrorKind::Coded(err),
+            key: Some(key),
+        }
+    }
+
+    /// How the client should react to this error. Opaque errors predate the
+    /// error-code classification and are treated as fatal.
+    pub fn kind(&self) -> ServerErrorKind {
+        match &self.err {
+            SaplingRemoteApiServerErrorKind::Coded(err) => err.kind(),
+            SaplingRemoteApiServerErrorKind::OpaqueError(_) => ServerErrorKind::Fatal,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Error, Eq, PartialEq, Serialize)]
@@ -252,4 +269,7 @@ impl SaplingRemoteApiServerError {
 pub enum SaplingRemoteApiServerErrorKind {
     #[error("SaplingRemoteAPI server returned an error with message: {0}")]
     OpaqueError(String),
+
+    #[error("{0}")]
+    Coded(ServerError),
 }
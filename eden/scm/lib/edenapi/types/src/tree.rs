@@ -75,6 +75,19 @@ pub struct TreeEntry {
     pub parents: Option<Parents>,
     pub children: Option<Vec<Result<TreeChildEntry, SaplingRemoteApiServerError>>>,
     pub tree_aux_data: Option<TreeAuxData>,
+    /// Set on the final marker entry of a truncated response: the requested
+    /// keys the server did not serve because its response byte budget was
+    /// exhausted. Clients should re-request these keys.
+    pub not_served: Option<Vec<Key>>,
+    /// Set when the tree was requested as an augmented manifest but its
+    /// augmented manifest was not derived yet, so the server downgraded to
+    /// the plain hg manifest (without children or aux data).
+    pub augmented_fallback: bool,
+    /// When the augmented manifest this entry was served from was derived
+    /// (unix seconds), useful for telling freshly derived data apart from
+    /// backfills when debugging digest mismatches. Only populated on repos
+    /// that opt in.
+    pub derivation_ctime: Option<i64>,
 }
 
 impl TreeEntry {
@@ -108,6 +121,32 @@ impl TreeEntry {
         self
     }
 
+    /// Mark this entry as downgraded to the plain hg manifest because its
+    /// augmented manifest was not derived yet.
+    pub fn with_augmented_fallback<'a>(&'a mut self) -> &'a mut Self {
+        self.augmented_fallback = true;
+        self
+    }
+
+    /// Record when the augmented manifest backing this entry was derived.
+    pub fn with_derivation_ctime<'a>(&'a mut self, ctime: Option<i64>) -> &'a mut Self {
+        self.derivation_ctime = ctime;
+        self
+    }
+
+    /// Create the marker entry terminating a truncated response. `not_served`
+    /// must be non-empty; the first key doubles as the marker's key.
+    pub fn new_truncated(not_served: Vec<Key>) -> Self {
+        let mut entry = Self::new(not_served[0].clone());
+        entry.not_served = Some(not_served);
+        entry
+    }
+
+    /// Whether this entry is a truncation marker rather than a tree.
+    pub fn is_truncation_marker(&self) -> bool {
+        self.not_served.is_some()
+    }
+
     pub fn key(&self) -> &Key {
         &self.key
     }
@@ -287,6 +326,9 @@ impl Arbitrary for TreeEntry {
             // Recursive TreeEntry in children causes stack overflow in QuickCheck
             children: None,
             tree_aux_data: None,
+            not_served: None,
+            augmented_fallback: Arbitrary::arbitrary(g),
+            derivation_ctime: Arbitrary::arbitrary(g),
         }
     }
 }
@@ -296,6 +338,11 @@ impl Arbitrary for TreeEntry {
 pub struct TreeRequest {
     pub keys: Vec<Key>,
     pub attributes: TreeAttributes,
+    /// Scheduling priority of the request, from 0 (background prefetch) to
+    /// 255 (interactive). The server may serve high-priority requests with
+    /// more concurrency than background ones.
+    #[serde(default)]
+    pub priority: u8,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
@@ -307,6 +354,11 @@ pub struct TreeAttributes {
     pub parents: bool,
     #[serde(default = "get_true")]
     pub child_metadata: bool,
+    /// Include the hg file header (copy metadata) bytes in each child file
+    /// entry's aux data. Only consulted when `child_metadata` is set; the
+    /// augmented trees path always includes the header.
+    #[serde(default = "get_false")]
+    pub child_file_header_metadata: bool,
     #[serde(default = "get_false")]
     pub augmented_trees: bool,
 }
@@ -325,15 +377,17 @@ impl TreeAttributes {
             manifest_blob: true,
             parents: true,
             child_metadata: true,
+            child_file_header_metadata: true,
             augmented_trees: false,
         }
     }
 
     pub fn augmented_trees() -> Self {
         TreeAttributes {
-            manifest_blob: false,  // not used
-            parents: false,        // not used
-            child_metadata: false, // not used
+            manifest_blob: false,              // not used
+            parents: false,                    // not used
+            child_metadata: false,             // not used
+            child_file_header_metadata: false, // not used
             augmented_trees: true,
         }
     }
@@ -345,6 +399,7 @@ impl Default for TreeAttributes {
             manifest_blob: true,
             parents: true,
             child_metadata: false,
+            child_file_header_metadata: false,
             augmented_trees: false,
         }
     }
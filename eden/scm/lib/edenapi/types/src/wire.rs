@@ -366,6 +366,9 @@ pub enum WireSaplingRemoteApiServerError {
     #[serde(rename = "1")]
     OpaqueError(String),
 
+    #[serde(rename = "2")]
+    Coded(WireError),
+
     #[serde(other, rename = "0")]
     Unknown,
 }
@@ -377,6 +380,7 @@ impl ToWire for SaplingRemoteApiServerErrorKind {
         use SaplingRemoteApiServerErrorKind::*;
         match self {
             OpaqueError(s) => WireSaplingRemoteApiServerError::OpaqueError(s),
+            Coded(e) => WireSaplingRemoteApiServerError::Coded(e.to_wire()),
         }
     }
 }
@@ -394,6 +398,7 @@ impl ToApi for WireSaplingRemoteApiServerError {
                 ));
             }
             OpaqueError(s) => SaplingRemoteApiServerErrorKind::OpaqueError(s),
+            Coded(e) => SaplingRemoteApiServerErrorKind::Coded(e.to_api()?),
         })
     }
 }
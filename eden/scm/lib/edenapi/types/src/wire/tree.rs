@@ -52,6 +52,15 @@ pub struct WireTreeEntry {
 
     #[serde(rename = "5", default, skip_serializing_if = "is_default")]
     pub tree_aux_data: Option<WireTreeAuxData>,
+
+    #[serde(rename = "6", default, skip_serializing_if = "is_default")]
+    pub not_served: Option<Vec<WireKey>>,
+
+    #[serde(rename = "7", default, skip_serializing_if = "is_default")]
+    pub augmented_fallback: bool,
+
+    #[serde(rename = "8", default, skip_serializing_if = "is_default")]
+    pub derivation_ctime: Option<i64>,
 }
 
 impl ToWire for Result<TreeEntry, SaplingRemoteApiServerError> {
@@ -66,6 +75,9 @@ impl ToWire for Result<TreeEntry, SaplingRemoteApiServerError> {
                 children: t.children.to_wire(),
                 error: None,
                 tree_aux_data: t.tree_aux_data.to_wire(),
+                not_served: t.not_served.to_wire(),
+                augmented_fallback: t.augmented_fallback,
+                derivation_ctime: t.derivation_ctime,
             },
             Err(e) => WireTreeEntry {
                 key: e.key.to_wire(),
@@ -96,6 +108,9 @@ impl ToApi for WireTreeEntry {
                 parents: self.parents.to_api()?,
                 children: self.children.to_api()?,
                 tree_aux_data: self.tree_aux_data.to_api()?,
+                not_served: self.not_served.to_api()?,
+                augmented_fallback: self.augmented_fallback,
+                derivation_ctime: self.derivation_ctime,
             })
         })
     }
@@ -205,6 +220,9 @@ pub struct WireTreeAttributesRequest {
 
     #[serde(rename = "5", default, skip_serializing_if = "is_default")]
     with_augmented_trees: bool,
+
+    #[serde(rename = "6", default, skip_serializing_if = "is_default")]
+    with_child_file_header_metadata: bool,
 }
 
 impl ToWire for TreeAttributes {
@@ -216,6 +234,7 @@ impl ToWire for TreeAttributes {
             with_parents: self.parents,
             with_child_metadata: self.child_metadata,
             with_augmented_trees: self.augmented_trees,
+            with_child_file_header_metadata: self.child_file_header_metadata,
         }
     }
 }
@@ -227,6 +246,7 @@ impl ToApi for WireTreeAttributesRequest {
     fn to_api(self) -> Result<Self::Api, Self::Error> {
         Ok(TreeAttributes {
             child_metadata: self.with_child_metadata,
+            child_file_header_metadata: self.with_child_file_header_metadata,
             parents: self.with_parents,
             manifest_blob: self.with_data,
             augmented_trees: self.with_augmented_trees,
@@ -242,6 +262,9 @@ pub struct WireTreeRequest {
 
     #[serde(rename = "1", default, skip_serializing_if = "is_default")]
     attributes: Option<WireTreeAttributesRequest>,
+
+    #[serde(rename = "2", default, skip_serializing_if = "is_default")]
+    priority: u8,
 }
 
 impl ToWire for TreeRequest {
@@ -254,6 +277,8 @@ impl ToWire for TreeRequest {
             })),
 
             attributes: Some(self.attributes.to_wire()),
+
+            priority: self.priority,
         }
     }
 }
@@ -278,6 +303,7 @@ impl ToApi for WireTreeRequest {
                 }
             },
             attributes: self.attributes.to_api()?.unwrap_or_default(),
+            priority: self.priority,
         })
     }
 }
@@ -295,6 +321,9 @@ impl Arbitrary for WireTreeEntry {
             // TODO
             error: None,
             tree_aux_data: Arbitrary::arbitrary(g),
+            not_served: Arbitrary::arbitrary(g),
+            augmented_fallback: Arbitrary::arbitrary(g),
+            derivation_ctime: Arbitrary::arbitrary(g),
         }
     }
 }
@@ -28,6 +28,16 @@ impl Stats {
     }
 }
 
+impl std::ops::AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.downloaded += other.downloaded;
+        self.uploaded += other.uploaded;
+        self.requests += other.requests;
+        self.time += other.time;
+        self.latency += other.latency;
+    }
+}
+
 impl fmt::Display for Stats {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -52,11 +52,28 @@ pub struct RotateLog {
     // Indicate an active reader. Destrictive writes (repair) are unsafe.
     reader_lock: Option<ScopedDirLock>,
     change_detector: Option<SharedChangeDetector>,
+    // Details about the most recent log deleted by rotation. Taken by
+    // `take_last_dropped`.
+    last_dropped: Option<RotationDropInfo>,
     // Run after log.sync(). For testing purpose only.
     #[cfg(test)]
     hook_after_log_sync: Option<Box<dyn Fn()>>,
 }
 
+/// Details about a [`Log`] that rotation deleted because the `max_log_count`
+/// limit was reached.
+#[derive(Clone, Debug)]
+pub struct RotationDropInfo {
+    /// Number of entries in the deleted log.
+    pub entries: u64,
+    /// Size of the deleted log's primary data, in bytes.
+    pub bytes: u64,
+    /// The `max_log_count` limit that triggered the deletion.
+    pub max_log_count: u8,
+    /// The configured `max_bytes_per_log`.
+    pub max_bytes_per_log: u64,
+}
+
 // On disk, a RotateLog is a directory containing:
 // - 0/, 1/, 2/, 3/, ...: one Log per directory.
 // - latest: a file, the name of the directory that is considered "active".
@@ -242,6 +259,7 @@ impl OpenOptions {
                 latest,
                 reader_lock: Some(reader_lock),
                 change_detector: Some(change_detector),
+                last_dropped: None,
                 #[cfg(test)]
                 hook_after_log_sync: None,
             };
@@ -267,6 +285,7 @@ impl OpenOptions {
                 latest: 0,
                 reader_lock: None,
                 change_detector: None,
+                last_dropped: None,
                 #[cfg(test)]
                 hook_after_log_sync: None,
             })
@@ -601,7 +620,25 @@ impl RotateLog {
             lock,
         )?;
         if self.logs.len() >= self.open_options.max_log_count as usize {
-            self.logs.pop();
+            // Load the oldest log (if it isn't already) so its size can be
+            // reported before it is deleted.
+            let _ = self.load_log(self.logs.len() - 1);
+            if let Some(dropped) = self.logs.pop().and_then(|cell| cell.into_inner()) {
+                let entries = dropped.iter().filter(|entry| entry.is_ok()).count() as u64;
+                let bytes = dropped.meta.primary_len;
+                debug!(
+                    entries,
+                    bytes,
+                    max_log_count = self.open_options.max_log_count,
+                    "rotation deleted the oldest log"
+                );
+                self.last_dropped = Some(RotationDropInfo {
+                    entries,
+                    bytes,
+                    max_log_count: self.open_options.max_log_count,
+                    max_bytes_per_log: self.open_options.max_bytes_per_log,
+                });
+            }
         }
         self.logs.insert(0, create_log_cell(log));
         self.logs_len = AtomicUsize::new(self.logs.len());
@@ -610,6 +647,12 @@ impl RotateLog {
         Ok(())
     }
 
+    /// Take details about the most recent log deleted by rotation, or `None`
+    /// if no log was deleted since the last call.
+    pub fn take_last_dropped(&mut self) -> Option<RotationDropInfo> {
+        self.last_dropped.take()
+    }
+
     /// Renamed. Use [`RotateLog::sync`] instead.
     pub fn flush(&mut self) -> crate::Result<u8> {
         self.sync()
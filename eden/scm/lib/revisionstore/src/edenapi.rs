@@ -77,6 +77,12 @@ impl<T: SaplingRemoteApiStoreKind> SaplingRemoteApiRemoteStore<T> {
     pub fn url(&self) -> Option<String> {
         self.client.url()
     }
+
+    /// Obtain the underlying SaplingRemoteApi client, e.g. to share it with
+    /// a store of a different kind.
+    pub fn client(&self) -> Arc<dyn SaplingRemoteApi> {
+        self.client.clone()
+    }
 }
 
 impl HgIdRemoteStore for SaplingRemoteApiRemoteStore<File> {
@@ -9,6 +9,9 @@ use std::io::Cursor;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::bail;
 use anyhow::Result;
@@ -18,6 +21,7 @@ use configmodel::convert::ByteCount;
 use configmodel::Config;
 use configmodel::ConfigExt;
 use indexedlog::log::IndexOutput;
+use metrics::Counter;
 use minibytes::Bytes;
 use types::hgid::ReadHgIdExt;
 use types::HgId;
@@ -32,22 +36,39 @@ use crate::scmstore::FileAuxData;
 /// See edenapi_types::FileAuxData and mononoke_types::ContentMetadataV2
 pub(crate) type Entry = FileAuxData;
 
+// Hit rate counters, rendered by the metrics exporters (e.g. a Prometheus
+// endpoint) outside this crate.
+static AUX_CACHE_HITS: Counter = Counter::new_counter("scmstore.aux_cache.hits");
+static AUX_CACHE_MISSES: Counter = Counter::new_counter("scmstore.aux_cache.misses");
+static AUX_CACHE_WRITES: Counter = Counter::new_counter("scmstore.aux_cache.writes");
+
 /// Serialize the Entry to Bytes.
 ///
-/// The serialization format (v2) is as follows:
+/// The serialization format (v3) is as follows:
 /// - HgId <20 bytes>
 /// - Version <1 byte> (for compatibility)
+/// - write timestamp <u64 VLQ, seconds since epoch>
 /// - total_size <u64 VLQ, 1-9 bytes>
 /// - content sha1 <20 bytes>
 /// - content blake3 <32 bytes>
 ///
-/// Note: (v1) was the same but containing also sha256 hash
+/// Note: (v2) was the same but without the write timestamp
+///       (v1) was the same but containing also sha256 hash
 ///       (v0) also contained content_id hash and blake3 hash was optional,
 ///       also, size field was close to the end, just before the blake3
 pub(crate) fn serialize(this: &FileAuxData, hgid: HgId) -> Result<Bytes> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    serialize_with_timestamp(this, hgid, timestamp)
+}
+
+fn serialize_with_timestamp(this: &FileAuxData, hgid: HgId, timestamp: u64) -> Result<Bytes> {
     let mut buf = Vec::new();
     buf.write_all(hgid.as_ref())?;
-    buf.write_u8(2)?; // write version
+    buf.write_u8(3)?; // write version
+    buf.write_vlq(timestamp)?;
     buf.write_vlq(this.total_size)?;
     buf.write_all(this.sha1.as_ref())?;
     buf.write_all(this.blake3.as_ref())?;
@@ -59,14 +80,16 @@ pub(crate) fn serialize(this: &FileAuxData, hgid: HgId) -> Result<Bytes> {
     Ok(buf.into())
 }
 
-fn deserialize(bytes: Bytes) -> Result<Option<(HgId, FileAuxData)>> {
+/// Deserialize an entry, returning the write timestamp alongside the aux
+/// data when the entry is recent enough (v3) to record one.
+fn deserialize(bytes: Bytes) -> Result<Option<(HgId, FileAuxData, Option<u64>)>> {
     let data: &[u8] = bytes.as_ref();
     let mut cur = Cursor::new(data);
 
     let hgid = cur.read_hgid()?;
 
     let version = cur.read_u8()?;
-    if version > 2 {
+    if version > 3 {
         bail!("unsupported auxstore entry version {}", version);
     }
 
@@ -101,8 +124,15 @@ fn deserialize(bytes: Bytes) -> Result<Option<(HgId, FileAuxData)>> {
                 // TODO(liubovd) support serialization and deserialization of the new field
                 file_header_metadata: None,
             },
+            None,
         )))
     } else {
+        let timestamp = if version == 3 {
+            Some(cur.read_vlq()?)
+        } else {
+            None
+        };
+
         let total_size: u64 = cur.read_vlq()?;
 
         let mut sha1 = [0u8; 20];
@@ -139,11 +169,18 @@ fn deserialize(bytes: Bytes) -> Result<Option<(HgId, FileAuxData)>> {
                 blake3: blake3.into(),
                 file_header_metadata,
             },
+            timestamp,
         )))
     }
 }
 
-pub struct AuxStore(Store);
+pub struct AuxStore {
+    store: Store,
+    // Entries older than this are treated as cache misses, so aux data is
+    // recomputed instead of trusted forever. Configured by
+    // scmstore.aux-cache-max-age.
+    max_age: Option<Duration>,
+}
 
 impl AuxStore {
     pub fn new(path: impl AsRef<Path>, config: &dyn Config, store_type: StoreType) -> Result<Self> {
@@ -155,7 +192,24 @@ impl AuxStore {
             StoreType::Rotated => open_options.rotated(&path),
         }?;
 
-        Ok(AuxStore(log))
+        let max_age = config.get_opt::<Duration>("scmstore", "aux-cache-max-age")?;
+
+        Ok(AuxStore {
+            store: log,
+            max_age,
+        })
+    }
+
+    /// Create a store backed entirely by memory, for unit tests. Nothing is
+    /// written to disk and flushes are no-ops.
+    pub fn new_for_testing() -> Result<Self> {
+        let config = std::collections::BTreeMap::<&str, &str>::new();
+        let log = AuxStore::open_options(&config)?.rotated_in_memory()?;
+
+        Ok(AuxStore {
+            store: log,
+            max_age: None,
+        })
     }
 
     fn open_options(config: &dyn Config) -> Result<StoreOpenOptions> {
@@ -189,36 +243,93 @@ impl AuxStore {
     }
 
     pub fn get(&self, hgid: HgId) -> Result<Option<FileAuxData>> {
-        let log = self.0.read();
+        let log = self.store.read();
         let mut entries = log.lookup(0, hgid)?;
 
         let slice = match entries.next() {
-            None => return Ok(None),
+            None => {
+                AUX_CACHE_MISSES.increment();
+                return Ok(None);
+            }
             Some(slice) => slice?,
         };
         let bytes = log.slice_to_bytes(slice);
         drop(log);
 
-        deserialize(bytes).map(|value| value.map(|(_hgid, entry)| entry))
+        Ok(match deserialize(bytes)? {
+            Some((_hgid, entry, timestamp)) if !self.is_stale(timestamp) => {
+                AUX_CACHE_HITS.increment();
+                Some(entry)
+            }
+            _ => {
+                AUX_CACHE_MISSES.increment();
+                None
+            }
+        })
     }
 
     pub fn contains(&self, hgid: HgId) -> Result<bool> {
-        let log = self.0.read();
+        if self.max_age.is_some() {
+            // Stale entries count as misses, so the index alone can't answer.
+            return Ok(self.get(hgid)?.is_some());
+        }
+        let log = self.store.read();
         Ok(!log.lookup(0, hgid)?.is_empty()?)
     }
 
     pub fn put(&self, hgid: HgId, entry: &Entry) -> Result<()> {
         let serialized = serialize(entry, hgid)?;
-        self.0.append(&serialized)
+        self.store.append(&serialized)?;
+        AUX_CACHE_WRITES.increment();
+        Ok(())
+    }
+
+    /// Write a batch of entries, taking the log's write lock once rather than
+    /// once per entry.
+    pub fn put_batch(&self, entries: impl IntoIterator<Item = (HgId, Entry)>) -> Result<()> {
+        // Serialize outside the lock.
+        let serialized = entries
+            .into_iter()
+            .map(|(hgid, entry)| serialize(&entry, hgid))
+            .collect::<Result<Vec<_>>>()?;
+        if serialized.is_empty() {
+            return Ok(());
+        }
+
+        let count = serialized.len();
+        let mut log = self.store.write();
+        for buf in serialized {
+            log.append(&buf)?;
+        }
+        AUX_CACHE_WRITES.add(count);
+        Ok(())
     }
 
     pub fn flush(&self) -> Result<()> {
-        self.0.flush()
+        self.store.flush()
+    }
+
+    /// Whether an entry written at `timestamp` (seconds since epoch) has
+    /// outlived `max_age`. Entries from before write timestamps were
+    /// recorded (pre-v3) are considered stale whenever an age limit is set.
+    fn is_stale(&self, timestamp: Option<u64>) -> bool {
+        let max_age = match self.max_age {
+            None => return false,
+            Some(max_age) => max_age,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match timestamp {
+            Some(timestamp) => now.saturating_sub(timestamp) > max_age.as_secs(),
+            None => true,
+        }
     }
 
     #[cfg(test)]
     pub(crate) fn hgids(&self) -> Result<Vec<HgId>> {
-        let log = self.0.read();
+        let log = self.store.read();
         Ok(log
             .iter()
             .map(|slice| {
@@ -227,7 +338,7 @@ impl AuxStore {
             })
             .collect::<Result<Vec<_>>>()?
             .into_iter()
-            .filter_map(|v| v.map(|(hgid, _entry)| hgid))
+            .filter_map(|v| v.map(|(hgid, _entry, _timestamp)| hgid))
             .collect())
     }
 }
@@ -291,6 +402,73 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_hit_rate_counters() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = AuxStore::new(&tempdir, &empty_config(), StoreType::Rotated)?;
+
+        let entry = Entry {
+            total_size: 1,
+            sha1: single_byte_sha1(1),
+            ..Default::default()
+        };
+        let k = key("a", "1");
+
+        // Other tests in this binary also touch the (global) counters, so
+        // only assert that each operation bumped its counter.
+        let writes = AUX_CACHE_WRITES.value();
+        store.put(k.hgid, &entry)?;
+        assert!(AUX_CACHE_WRITES.value() >= writes + 1);
+
+        let writes = AUX_CACHE_WRITES.value();
+        store.put_batch(vec![(key("b", "2").hgid, entry.clone())])?;
+        assert!(AUX_CACHE_WRITES.value() >= writes + 1);
+
+        let hits = AUX_CACHE_HITS.value();
+        assert!(store.get(k.hgid)?.is_some());
+        assert!(AUX_CACHE_HITS.value() >= hits + 1);
+
+        let misses = AUX_CACHE_MISSES.value();
+        assert!(store.get(key("c", "3").hgid)?.is_none());
+        assert!(AUX_CACHE_MISSES.value() >= misses + 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_age() -> Result<()> {
+        let tempdir = TempDir::new().unwrap();
+        let config = BTreeMap::from([("scmstore.aux-cache-max-age", "3600")]);
+        let store = AuxStore::new(&tempdir, &config, StoreType::Rotated)?;
+
+        let entry = Entry {
+            total_size: 1,
+            sha1: single_byte_sha1(1),
+            ..Default::default()
+        };
+
+        // A freshly written entry is within the age limit.
+        let k = key("a", "1");
+        store.put(k.hgid, &entry)?;
+        assert_eq!(store.get(k.hgid)?, Some(entry.clone()));
+        assert!(store.contains(k.hgid)?);
+
+        // An entry written long ago is treated as a miss.
+        let k2 = key("b", "2");
+        store
+            .store
+            .append(&serialize_with_timestamp(&entry, k2.hgid, 0)?)?;
+        assert_eq!(store.get(k2.hgid)?, None);
+        assert!(!store.contains(k2.hgid)?);
+
+        // Without an age limit the old entry is still served.
+        store.flush()?;
+        let store = AuxStore::new(&tempdir, &empty_config(), StoreType::Rotated)?;
+        assert_eq!(store.get(k2.hgid)?, Some(entry));
+
+        Ok(())
+    }
+
     #[test]
     fn test_lookup_failure() -> Result<()> {
         let tempdir = TempDir::new().unwrap();
@@ -581,7 +759,8 @@ mod tests {
             file_header_metadata: Some("\x01\ncopy: aaa/bbb/ccc/ddd/test_file.php\ncopyrev: 79c2d9e37f2f90e2ee3cb05762224eea0b864e12\n\x01\n".into()),
         };
         let bytes = serialize(&test_entry, hg_id)?;
-        let (hg_id1, test_entry1) = deserialize(bytes)?.expect("Failed to deserialize entry");
+        let (hg_id1, test_entry1, _timestamp) =
+            deserialize(bytes)?.expect("Failed to deserialize entry");
         assert_eq!(hg_id, hg_id1);
         assert_eq!(test_entry, test_entry1);
         Ok(())
@@ -600,7 +779,8 @@ mod tests {
             file_header_metadata: None,
         };
         let bytes = serialize(&test_entry, hg_id)?;
-        let (hg_id1, test_entry1) = deserialize(bytes)?.expect("Failed to deserialize entry");
+        let (hg_id1, test_entry1, _timestamp) =
+            deserialize(bytes)?.expect("Failed to deserialize entry");
         assert_eq!(hg_id, hg_id1);
         assert_eq!(test_entry, test_entry1);
         Ok(())
@@ -5,26 +5,35 @@
  * GNU General Public License version 2.
  */
 
+use std::future::Future;
 use std::io::Cursor;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Result;
+use async_runtime::spawn_blocking;
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
 use byteorder::WriteBytesExt;
 use configmodel::convert::ByteCount;
 use configmodel::Config;
+use configmodel::ConfigExt;
 use edenapi_types::FileEntry;
 use edenapi_types::TreeEntry;
 use indexedlog::log::IndexOutput;
+use indexedlog::rotate::RotationDropInfo;
 use lz4_pyframe::compress;
 use lz4_pyframe::decompress;
 use minibytes::Bytes;
 use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde_json::json;
 use tracing::warn;
 use types::hgid::ReadHgIdExt;
 use types::HgId;
@@ -43,6 +52,7 @@ use crate::localstore::ExtStoredPolicy;
 use crate::localstore::LocalStore;
 use crate::missing::MissingInjection;
 use crate::sliceext::SliceExt;
+use crate::types::ContentHash;
 use crate::types::StoreKey;
 use crate::ToKeys;
 
@@ -56,6 +66,15 @@ pub struct IndexedLogHgIdDataStore {
     store: Store,
     extstored_policy: ExtStoredPolicy,
     missing: MissingInjection,
+    // Skip appending an entry when the key already exists with identical
+    // content, since repack-less stores never deduplicate after the fact.
+    // Configured by indexedlog.data.skip-duplicate-writes.
+    skip_duplicate_writes: bool,
+    duplicate_writes_skipped: AtomicU64,
+    // Details about the most recent cache generation rotated out by
+    // `flush_log`, kept so `FileStore::flush` can record it in the activity
+    // log. Taken by `take_last_rotation_drop`.
+    last_rotation_drop: Mutex<Option<RotationDropInfo>>,
 }
 
 #[derive(Clone, Debug)]
@@ -218,10 +237,38 @@ impl IndexedLogHgIdDataStore {
             StoreType::Rotated => open_options.rotated(&path),
         }?;
 
+        let skip_duplicate_writes =
+            config.get_or_default::<bool>("indexedlog", "data.skip-duplicate-writes")?;
+
         Ok(IndexedLogHgIdDataStore {
             store: log,
             extstored_policy,
             missing: MissingInjection::new_from_env("MISSING_FILES"),
+            skip_duplicate_writes,
+            duplicate_writes_skipped: AtomicU64::new(0),
+            last_rotation_drop: Mutex::new(None),
+        })
+    }
+
+    /// Create a store backed entirely by memory, for unit tests. Nothing is
+    /// written to disk and flushes are no-ops.
+    pub fn new_for_testing() -> Result<Self> {
+        let config = std::collections::BTreeMap::<&str, &str>::new();
+        let log_config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log =
+            IndexedLogHgIdDataStore::open_options(&config, &log_config).rotated_in_memory()?;
+
+        Ok(IndexedLogHgIdDataStore {
+            store: log,
+            extstored_policy: ExtStoredPolicy::Use,
+            missing: MissingInjection::new_from_env("MISSING_FILES"),
+            skip_duplicate_writes: false,
+            duplicate_writes_skipped: AtomicU64::new(0),
+            last_rotation_drop: Mutex::new(None),
         })
     }
 
@@ -277,10 +324,48 @@ impl IndexedLogHgIdDataStore {
     }
 
     /// Attempt to read an Entry from IndexedLog, without overwriting the Key (return Key path may not match the request Key path)
-    pub(crate) fn get_raw_entry(&self, id: &HgId) -> Result<Option<Entry>> {
+    pub fn get_raw_entry(&self, id: &HgId) -> Result<Option<Entry>> {
         Entry::from_log(id.as_ref(), &self.store)
     }
 
+    /// Iterate over the keys of all entries in the store. Corrupt entries
+    /// yield an `Err` in place rather than terminating iteration.
+    pub fn iter_keys(&self) -> Vec<Result<Key>> {
+        self.to_keys()
+    }
+
+    /// Dump the entries in the store to `writer` as one JSON object per
+    /// line, for `debugindexedlogdatastore`-style tooling. Content is
+    /// replaced by its length and sha256. Corrupt entries are reported
+    /// inline as objects with an `error` field rather than terminating the
+    /// dump. At most `limit` entries are emitted when one is given.
+    pub fn dump_json(&self, writer: &mut dyn Write, limit: Option<usize>) -> Result<()> {
+        let log = self.store.read();
+        for bytes in log.iter().take(limit.unwrap_or(usize::MAX)) {
+            let json = match bytes
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Entry::from_bytes(log.slice_to_bytes(bytes)))
+                .and_then(|entry| {
+                    let content = entry.calculate_content()?;
+                    Ok(json!({
+                        "path": entry.key().path.as_str(),
+                        "node": entry.key().hgid.to_hex(),
+                        "flags": entry.metadata().flags,
+                        "size": entry.metadata().size,
+                        "is_lfs_pointer": entry.metadata().is_lfs(),
+                        "content_len": content.len(),
+                        "content_sha256": ContentHash::sha256(&content).unwrap_sha256().to_hex(),
+                    }))
+                }) {
+                Ok(json) => json,
+                Err(err) => json!({ "error": err.to_string() }),
+            };
+            serde_json::to_writer(&mut *writer, &json)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
     /// Return whether the store contains the given id.
     pub(crate) fn contains(&self, id: &HgId) -> Result<bool> {
         self.store.read().contains(0, id.as_ref())
@@ -302,15 +387,77 @@ impl IndexedLogHgIdDataStore {
     }
 
     /// Write an entry to the IndexedLog
+    ///
+    /// When indexedlog.data.skip-duplicate-writes is set, an entry whose key
+    /// already exists with identical metadata and content is not appended
+    /// again, since without repack duplicate records are never reclaimed.
     pub fn put_entry(&self, entry: Entry) -> Result<()> {
+        if self.skip_duplicate_writes && self.is_duplicate_write(&entry)? {
+            self.duplicate_writes_skipped.fetch_add(1, Ordering::Relaxed);
+            hg_metrics::increment_counter("indexedlog.data.duplicate_writes_skipped", 1);
+            return Ok(());
+        }
         entry.write_to_log(&self.store)
     }
 
+    /// Whether `entry` is already present with identical content. The node
+    /// index lookup is cheap; content is only compared when the metadata
+    /// matches, and the full comparison short-circuits on length.
+    fn is_duplicate_write(&self, entry: &Entry) -> Result<bool> {
+        let existing = match Entry::from_log(entry.key.hgid.as_ref(), &self.store)? {
+            None => return Ok(false),
+            Some(existing) => existing,
+        };
+        if existing.key != entry.key || existing.metadata != entry.metadata {
+            return Ok(false);
+        }
+        let existing_content = existing.calculate_content()?;
+        let content = entry.calculate_content()?;
+        Ok(existing_content.len() == content.len() && existing_content == content)
+    }
+
+    /// Number of writes skipped as duplicates since this store was opened.
+    pub fn duplicate_writes_skipped(&self) -> u64 {
+        self.duplicate_writes_skipped.load(Ordering::Relaxed)
+    }
+
     /// Flush the underlying IndexedLog
+    ///
+    /// If flushing a rotated store deleted its oldest generation, the drop is
+    /// counted and reported, since a silently shrinking cache makes
+    /// previously-fast operations slow with no other explanation.
     pub fn flush_log(&self) -> Result<()> {
         self.store.write().flush()?;
+        if let Some(dropped) = self.store.take_last_rotation_drop() {
+            hg_metrics::increment_counter("indexedlog.data.generations_dropped", 1);
+            tracing::info!(
+                target: "revisionstore::rotation",
+                entries = dropped.entries,
+                bytes = dropped.bytes,
+                max_log_count = dropped.max_log_count,
+                max_bytes_per_log = dropped.max_bytes_per_log,
+                "rotated cache deleted its oldest generation"
+            );
+            *self.last_rotation_drop.lock() = Some(dropped);
+        }
         Ok(())
     }
+
+    /// Take details about the most recent cache generation deleted by
+    /// rotation, or `None` if nothing was deleted since the last call.
+    pub fn take_last_rotation_drop(&self) -> Option<RotationDropInfo> {
+        self.last_rotation_drop.lock().take()
+    }
+
+    /// Flush the underlying IndexedLog on a blocking thread, returning a
+    /// future for the result. The flush starts immediately, so callers can
+    /// kick off several flushes and then await them together instead of
+    /// serializing the file I/O.
+    pub fn flush_async(self: &Arc<Self>) -> impl Future<Output = Result<()>> {
+        let store = Arc::clone(self);
+        let handle = spawn_blocking(move || store.flush_log());
+        async move { handle.await? }
+    }
 }
 
 // TODO(meyer): Remove these infallible conversions, replace with fallible or inherent in LazyFile.
@@ -433,18 +580,30 @@ impl ToKeys for IndexedLogHgIdDataStore {
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
+    use std::str::FromStr;
     use std::sync::Arc;
 
     use fs_err::remove_file;
+    use futures::StreamExt;
     use minibytes::Bytes;
     use tempfile::TempDir;
     use types::fetch_mode::FetchMode;
     use types::testutil::*;
+    use types::Sha256;
+    use url::Url;
 
     use super::*;
+    use crate::edenapi::File as FileMarker;
+    use crate::indexedlogauxstore::AuxStore;
+    use crate::lfs::LfsBlobsStore;
+    use crate::lfs::LfsClient;
+    use crate::lfs::LfsStore;
+    use crate::scmstore::file::PrefetchLimits;
     use crate::scmstore::FileAttributes;
+    use crate::scmstore::FileAuxData;
     use crate::scmstore::FileStore;
     use crate::testutil::*;
+    use crate::SaplingRemoteApiRemoteStore;
 
     #[test]
     fn test_empty() {
@@ -493,6 +652,48 @@ mod tests {
         log.flush().unwrap();
     }
 
+    #[test]
+    fn test_skip_duplicate_writes() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &BTreeMap::from([("indexedlog.data.skip-duplicate-writes", "true")]),
+            &tempdir,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Rotated,
+        )?;
+
+        let delta = Delta {
+            data: Bytes::from(&[1, 2, 3, 4][..]),
+            base: None,
+            key: key("a", "1"),
+        };
+        let metadata = Default::default();
+
+        // The second identical write is dropped, leaving one log record.
+        log.add(&delta, &metadata)?;
+        log.add(&delta, &metadata)?;
+        assert_eq!(log.to_keys().len(), 1);
+        assert_eq!(log.duplicate_writes_skipped(), 1);
+
+        // Same key with different content is still appended.
+        let changed = Delta {
+            data: Bytes::from(&[1, 2, 3, 5][..]),
+            ..delta.clone()
+        };
+        log.add(&changed, &metadata)?;
+        assert_eq!(log.to_keys().len(), 2);
+        assert_eq!(log.duplicate_writes_skipped(), 1);
+
+        log.flush()?;
+        Ok(())
+    }
+
     #[test]
     fn test_add_get() {
         let tempdir = TempDir::new().unwrap();
@@ -711,6 +912,62 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_iter_keys_and_dump_json() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tempdir,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Rotated,
+        )?;
+
+        // A normal entry, an LFS pointer entry, and a corrupt record.
+        let d = delta("1234", None, key("a", "1"));
+        log.add(&d, &Default::default())?;
+
+        let lfs = delta("pointer", None, key("b", "2"));
+        log.add(
+            &lfs,
+            &Metadata {
+                size: None,
+                flags: Some(Metadata::LFS_FLAG),
+            },
+        )?;
+
+        // Too short to parse past the hgid, but long enough for the node
+        // index.
+        log.store.append([0u8; 21])?;
+        log.flush_log()?;
+
+        let keys = log.iter_keys();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys.iter().filter(|key| key.is_ok()).count(), 2);
+
+        let mut out = Vec::new();
+        log.dump_json(&mut out, None)?;
+        let lines: Vec<&str> = std::str::from_utf8(&out)?.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("\"is_lfs_pointer\":false"));
+        assert!(lines[0].contains("\"content_len\":4"));
+        assert!(lines[0].contains("\"content_sha256\""));
+        assert!(lines[1].contains("\"is_lfs_pointer\":true"));
+        assert!(lines[2].contains("\"error\""));
+
+        // The limit caps how many entries are dumped.
+        let mut out = Vec::new();
+        log.dump_json(&mut out, Some(1))?;
+        assert_eq!(std::str::from_utf8(&out)?.lines().count(), 1);
+
+        Ok(())
+    }
+
     #[test]
     fn test_extstored_use() -> Result<()> {
         let tempdir = TempDir::new().unwrap();
@@ -750,6 +1007,48 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_rotation_drop_reported() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        // Tiny limits so every flush rotates and the second rotation drops
+        // a generation.
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: Some(2),
+            max_bytes_per_log: Some(ByteCount::from(10)),
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tempdir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?;
+
+        // First flush rotates but all generations still fit in the count
+        // limit, so nothing is dropped.
+        log.add(&delta("more than ten bytes", None, key("a", "1")), &Default::default())?;
+        log.flush_log()?;
+        assert!(log.take_last_rotation_drop().is_none());
+
+        // The second rotation pushes the oldest generation out.
+        log.add(&delta("more than ten bytes", None, key("b", "2")), &Default::default())?;
+        log.flush_log()?;
+
+        let dropped = log
+            .take_last_rotation_drop()
+            .expect("rotation should have dropped a generation");
+        assert_eq!(dropped.entries, 1);
+        assert!(dropped.bytes > 0);
+        assert_eq!(dropped.max_log_count, 2);
+        assert_eq!(dropped.max_bytes_per_log, 10);
+
+        // The drop is only reported once.
+        assert!(log.take_last_rotation_drop().is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn test_scmstore_read() -> Result<()> {
         let k = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
@@ -834,6 +1133,501 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_scmstore_build_for_testing() -> Result<()> {
+        let k = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let d = delta("1234", None, k.clone());
+
+        let store = FileStore::build_for_testing()?;
+        store.write_batch(std::iter::once((k.clone(), d.data.clone(), Default::default())))?;
+
+        let mut fetched = store
+            .fetch(
+                std::iter::once(k.clone()),
+                FileAttributes::CONTENT | FileAttributes::AUX,
+                FetchMode::LocalOnly,
+            )
+            .single()?
+            .expect("key not found");
+        assert_eq!(fetched.file_content()?.to_vec(), d.data.as_ref().to_vec());
+        assert_eq!(fetched.aux_data()?.total_size, 4);
+
+        // Flushing an in-memory store is a no-op rather than an error.
+        store.flush()?;
+
+        Ok(())
+    }
+
+    /// Write `count` distinct keys with distinct content into the store's
+    /// local layer.
+    fn populate_warm_store(store: &FileStore, count: usize) -> Result<Vec<Key>> {
+        let keys: Vec<Key> = (0..count)
+            .map(|i| key(&format!("file{}", i), &format!("{:040x}", i + 1)))
+            .collect();
+        store.write_batch(keys.iter().map(|k| {
+            (
+                k.clone(),
+                Bytes::from(format!("content {}", k.hgid).into_bytes()),
+                Default::default(),
+            )
+        }))?;
+        Ok(keys)
+    }
+
+    #[test]
+    fn test_scmstore_parallel_local_lookup() -> Result<()> {
+        let mut store = FileStore::build_for_testing()?;
+        let keys = populate_warm_store(&store, 4096)?;
+
+        // Large enough batch to shard across threads; results must match
+        // the single-threaded scan.
+        store.local_lookup_threads = 4;
+        let (found, missing, errors) = store
+            .fetch(
+                keys.iter().cloned(),
+                FileAttributes::CONTENT,
+                FetchMode::LocalOnly,
+            )
+            .consume();
+        assert!(missing.is_empty());
+        assert!(errors.is_empty());
+        assert_eq!(found.len(), keys.len());
+        for (k, mut file) in found {
+            assert_eq!(
+                file.file_content()?.to_vec(),
+                format!("content {}", k.hgid).into_bytes()
+            );
+        }
+        Ok(())
+    }
+
+    /// Not run by default: demonstrates the parallel local-lookup speedup
+    /// on a synthetic 100k-key warm store. Run with:
+    /// cargo test -p revisionstore --release bench_parallel_local_lookup -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_parallel_local_lookup() -> Result<()> {
+        let mut store = FileStore::build_for_testing()?;
+        let keys = populate_warm_store(&store, 100_000)?;
+
+        for threads in [1, 2, 4, 8] {
+            store.local_lookup_threads = threads;
+            let start = std::time::Instant::now();
+            let (found, _missing, _errors) = store
+                .fetch(
+                    keys.iter().cloned(),
+                    FileAttributes::CONTENT,
+                    FetchMode::LocalOnly,
+                )
+                .consume();
+            assert_eq!(found.len(), keys.len());
+            println!(
+                "local lookup of {} keys with {} thread(s): {:?}",
+                keys.len(),
+                threads,
+                start.elapsed()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_fetch_tracing_spans() -> Result<()> {
+        let k = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let d = delta("1234", None, k.clone());
+        let meta = Default::default();
+
+        // Setup local indexedlog
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let local = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tmp,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?);
+
+        local.add(&d, &meta).unwrap();
+        local.flush().unwrap();
+
+        // Set up local-only FileStore
+        let mut store = FileStore::empty();
+        store.indexedlog_local = Some(local);
+
+        // Fetch with a collecting subscriber and check the span fields.
+        let lines = dev_logger::traced("revisionstore::fetch=debug", || {
+            store
+                .fetch(
+                    std::iter::once(k),
+                    FileAttributes::CONTENT,
+                    FetchMode::AllowRemote,
+                )
+                .single()
+                .unwrap()
+                .expect("key not found");
+        });
+        assert!(
+            lines.iter().any(|line| line.contains("indexedlog_fetch")
+                && line.contains("location=\"local\"")
+                && line.contains("keys=1")
+                && line.contains("hits=1")
+                && line.contains("bytes=")),
+            "missing or incomplete indexedlog_fetch span: {:?}",
+            lines
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_prefetch_guardrails() -> Result<()> {
+        let k = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let d = delta("1234", None, k.clone());
+        let meta = Default::default();
+
+        // Setup local indexedlog
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let local = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tmp,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?);
+
+        local.add(&d, &meta).unwrap();
+        local.flush().unwrap();
+
+        // Set up local-only FileStore with guardrails that always trip.
+        let mut store = FileStore::empty();
+        store.indexedlog_local = Some(local);
+        store.prefetch_limits = PrefetchLimits {
+            warn_keys: Some(0),
+            warn_bytes: Some(0),
+            max_keys: None,
+            max_bytes: None,
+        };
+
+        let lines = dev_logger::traced("revisionstore::prefetch=warn", || {
+            store
+                .prefetch_with_cause(vec![k.clone()], Some("test-cause"))
+                .unwrap();
+        });
+        // One warning for the key count, one for the fetched bytes, both
+        // attributed to the caller-provided cause.
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.contains("oversized prefetch call")
+                    && line.contains("cause=\"test-cause\""))
+                .count(),
+            2,
+            "missing prefetch warnings: {:?}",
+            lines
+        );
+
+        // Hard key limit turns into an error.
+        store.prefetch_limits.max_keys = Some(0);
+        let err = store
+            .prefetch_with_cause(vec![k], Some("test-cause"))
+            .unwrap_err();
+        assert!(err.to_string().contains("scmstore.prefetch-max-keys"));
+        assert!(err.to_string().contains("test-cause"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_prefetch_split() -> Result<()> {
+        let keys = vec![key("a", "1"), key("b", "2"), key("c", "3")];
+
+        // Setup local indexedlog
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let local = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tmp,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?);
+        for k in &keys {
+            local.add(&delta("1234", None, k.clone()), &Default::default())?;
+        }
+        local.flush()?;
+
+        let mut store = FileStore::empty();
+        store.indexedlog_local = Some(local);
+        store.max_prefetch_size = 2;
+
+        // Three keys with a limit of two split into two fetches, and the
+        // split is reported to the caller, the log, and the metrics.
+        let lines = dev_logger::traced("revisionstore::prefetch=warn", || {
+            let result = store
+                .prefetch_with_cause(keys.clone(), Some("test-cause"))
+                .unwrap();
+            assert!(result.missing.is_empty());
+            let split = result.split.expect("prefetch split not reported");
+            assert_eq!(split.keys, 3);
+            assert_eq!(split.chunks, 2);
+            assert_eq!(split.max_prefetch_size, 2);
+        });
+        assert!(
+            lines.iter().any(|line| line
+                .contains("prefetch split by max-prefetch-size")
+                && line.contains("cause=\"test-cause\"")),
+            "missing split warning: {:?}",
+            lines
+        );
+        assert_eq!(store.metrics.read().prefetch.splits, 1);
+        assert_eq!(store.metrics.read().prefetch.overflow_keys, 1);
+
+        // A prefetch within the limit reports no split.
+        let result = store.prefetch(keys[..2].to_vec())?;
+        assert!(result.split.is_none());
+        assert_eq!(store.metrics.read().prefetch.splits, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_rebuild_aux_cache_from_content() -> Result<()> {
+        let k = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let d = delta("1234", None, k.clone());
+
+        // Setup cache indexedlog with a normal entry and an LFS pointer
+        // entry, which cannot be rebuilt locally.
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let cache = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tmp,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Rotated,
+        )?);
+        cache.add(&d, &Default::default())?;
+        cache.add(
+            &delta("pointer", None, key("b", "2")),
+            &Metadata {
+                size: None,
+                flags: Some(Metadata::LFS_FLAG),
+            },
+        )?;
+        cache.flush_log()?;
+
+        let aux_tmp = TempDir::new()?;
+        let aux = Arc::new(AuxStore::new(
+            &aux_tmp,
+            &BTreeMap::<&str, &str>::new(),
+            StoreType::Rotated,
+        )?);
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(cache);
+        store.aux_cache = Some(aux.clone());
+
+        let rebuilt = futures::executor::block_on(
+            store.rebuild_aux_cache_from_content().collect::<Vec<_>>(),
+        );
+        assert_eq!(rebuilt.len(), 1);
+        assert_eq!(*rebuilt[0].as_ref().unwrap(), k.hgid);
+
+        let aux_data = aux.get(k.hgid)?.expect("aux data not rebuilt");
+        assert_eq!(aux_data.total_size, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_prefetch_by_glob() -> Result<()> {
+        let rust_key = key("src/lib/a.rs", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let rust_delta = delta("1234", None, rust_key.clone());
+        let text_delta = delta("5678", None, key("b.txt", "2"));
+
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let cache = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tmp,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Rotated,
+        )?);
+        cache.add(&rust_delta, &Default::default())?;
+        cache.add(&text_delta, &Default::default())?;
+        cache.flush_log()?;
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(cache);
+
+        let fetched = futures::executor::block_on(
+            store.prefetch_by_glob("src/**/*.rs").collect::<Vec<_>>(),
+        );
+        assert_eq!(fetched.len(), 1);
+        let mut file = fetched.into_iter().next().unwrap()?;
+        assert_eq!(
+            file.file_content()?.to_vec(),
+            rust_delta.data.as_ref().to_vec()
+        );
+
+        // An invalid pattern surfaces as an error rather than a panic.
+        let results = futures::executor::block_on(
+            store.prefetch_by_glob("src/[").collect::<Vec<_>>(),
+        );
+        assert_eq!(results.len(), 1);
+        assert!(results.into_iter().next().unwrap().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_get_content_metadata_batch() -> Result<()> {
+        // One key's aux data is already cached; the other is only available
+        // remotely.
+        let cached_key = key("a", "1");
+        let cached_aux = FileAuxData::from_content(b"cached");
+        let remote_data = Bytes::from(&b"remote file"[..]);
+        let remote_key = key("b", "2");
+
+        let aux_tmp = TempDir::new()?;
+        let aux = Arc::new(AuxStore::new(
+            &aux_tmp,
+            &BTreeMap::<&str, &str>::new(),
+            StoreType::Rotated,
+        )?);
+        aux.put(cached_key.hgid, &cached_aux)?;
+
+        let client = FakeSaplingRemoteApi::new()
+            .files(vec![(remote_key.clone(), remote_data.clone())])
+            .into_arc();
+
+        let mut store = FileStore::empty();
+        store.aux_cache = Some(aux.clone());
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(
+            client.clone(),
+        ));
+
+        let results = futures::executor::block_on(
+            store
+                .get_content_metadata_batch(&[cached_key.clone(), remote_key.clone()])
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(results.len(), 2);
+        let mut results = results.into_iter();
+
+        // The cache hit comes first, before any remote results.
+        let (first_key, first_aux) = results.next().unwrap()?;
+        assert_eq!(first_key, cached_key);
+        assert_eq!(first_aux, cached_aux);
+
+        let (second_key, second_aux) = results.next().unwrap()?;
+        assert_eq!(second_key, remote_key);
+        assert_eq!(second_aux.total_size, remote_data.len() as u64);
+
+        // The miss was fetched in a single batch and written back to the
+        // aux cache.
+        assert_eq!(client.file_request_sizes(), vec![1]);
+        assert!(aux.get(remote_key.hgid)?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_fetch_modes() -> Result<()> {
+        // The same key has different content locally and remotely, so the
+        // result shows which store layers the fetch mode consulted.
+        let k = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let local_delta = delta("local", None, k.clone());
+        let remote_data = Bytes::from(&b"remote"[..]);
+
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let cache = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tmp,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Rotated,
+        )?);
+        cache.add(&local_delta, &Default::default())?;
+        cache.flush_log()?;
+
+        let client = FakeSaplingRemoteApi::new()
+            .files(vec![(k.clone(), remote_data.clone())])
+            .into_arc();
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(cache);
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(
+            client.clone(),
+        ));
+
+        // LocalOnly consults only the local stores.
+        let mut fetched = store
+            .fetch(
+                std::iter::once(k.clone()),
+                FileAttributes::CONTENT,
+                FetchMode::LocalOnly,
+            )
+            .single()?
+            .expect("key not found");
+        assert_eq!(fetched.file_content()?.to_vec(), b"local".to_vec());
+        assert!(client.file_request_sizes().is_empty());
+
+        // AllowRemote serves local hits without going to the server.
+        let mut fetched = store
+            .fetch(
+                std::iter::once(k.clone()),
+                FileAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .single()?
+            .expect("key not found");
+        assert_eq!(fetched.file_content()?.to_vec(), b"local".to_vec());
+        assert!(client.file_request_sizes().is_empty());
+
+        // RemoteOnly bypasses the local stores entirely.
+        let mut fetched = store
+            .fetch(
+                std::iter::once(k),
+                FileAttributes::CONTENT,
+                FetchMode::RemoteOnly,
+            )
+            .single()?
+            .expect("key not found");
+        assert_eq!(fetched.file_content()?.to_vec(), remote_data.as_ref().to_vec());
+        assert_eq!(client.file_request_sizes(), vec![1]);
+
+        Ok(())
+    }
+
     #[test]
     fn test_scmstore_extstore_use() -> Result<()> {
         let tempdir = TempDir::new()?;
@@ -896,6 +1690,180 @@ mod tests {
         Ok(())
     }
 
+    /// An indexedlog entry holding an LFS pointer to b"master".
+    fn lfs_pointer_entry(key: Key) -> (Entry, Sha256) {
+        let sha256 =
+            Sha256::from_str("fc613b4dfd6736a7bd268c8a0e74ed0d1c04a959f59dd74ef2874983fd443fc9")
+                .unwrap();
+        let pointer = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 6\nx-is-binary 0\n",
+            sha256.to_hex(),
+        );
+        let metadata = Metadata {
+            size: None,
+            flags: Some(Metadata::LFS_FLAG),
+        };
+        (Entry::new(key, Bytes::from(pointer.into_bytes()), metadata), sha256)
+    }
+
+    #[test]
+    fn test_scmstore_resolve_lfs_pointer_local() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tempdir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?;
+
+        let k = key("a", "1");
+        let (entry, sha256) = lfs_pointer_entry(k.clone());
+        log.put_entry(entry)?;
+
+        let lfsdir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let lfs_config = make_lfs_config(&server, &lfsdir, "test_resolve_lfs_pointer_local");
+        let lfs = Arc::new(LfsStore::rotated(&lfsdir, &lfs_config)?);
+        lfs.add_blob(&sha256, Bytes::from(&b"master"[..]))?;
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(Arc::new(log));
+        store.lfs_cache = Some(lfs);
+
+        let mut file = store
+            .fetch(vec![k], FileAttributes::CONTENT, FetchMode::LocalOnly)
+            .single()?
+            .expect("pointer not resolved");
+        assert_eq!(file.file_content()?, Bytes::from(&b"master"[..]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_resolve_lfs_pointer_remote() -> Result<()> {
+        let _env_lock = crate::env_lock();
+
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tempdir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?;
+
+        let k = key("a", "1");
+        let (entry, sha256) = lfs_pointer_entry(k.clone());
+        log.put_entry(entry)?;
+
+        let lfsdir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let mut lfs_config = make_lfs_config(&server, &lfsdir, "test_resolve_lfs_pointer_remote");
+
+        // Serve the blob from a loose file store over a file:// URL.
+        let remote = TempDir::new()?;
+        let remote_lfs_file_store = LfsBlobsStore::Loose(remote.path().to_path_buf(), false);
+        remote_lfs_file_store.add(&sha256, Bytes::from(&b"master"[..]))?;
+        remote_lfs_file_store.flush()?;
+        let url = Url::from_file_path(&remote).unwrap();
+        setconfig(&mut lfs_config, "lfs", "url", url.as_str());
+
+        let lfs = Arc::new(LfsStore::rotated(&lfsdir, &lfs_config)?);
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(Arc::new(log));
+        store.lfs_cache = Some(lfs.clone());
+        store.lfs_remote = Some(Arc::new(LfsClient::new(lfs, None, &lfs_config)?));
+
+        let mut file = store
+            .fetch(vec![k], FileAttributes::CONTENT, FetchMode::AllowRemote)
+            .single()?
+            .expect("pointer not resolved");
+        assert_eq!(file.file_content()?, Bytes::from(&b"master"[..]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_unresolvable_lfs_pointer() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tempdir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?;
+
+        let k = key("a", "1");
+        let (entry, sha256) = lfs_pointer_entry(k.clone());
+        log.put_entry(entry)?;
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(Arc::new(log));
+
+        // There is nowhere to resolve the pointer from, so a remote-allowed
+        // content fetch reports an error naming the missing oid.
+        let fetched = store.fetch(vec![k.clone()], FileAttributes::CONTENT, FetchMode::AllowRemote);
+        let (found, missing, _errors) = fetched.consume();
+        assert!(found.is_empty());
+        let err = missing.get(&k).expect("no error for key").to_string();
+        assert!(err.contains("failed to resolve LFS pointer"));
+        assert!(err.contains(&sha256.to_hex()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_fetch_lfs_pointer() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tempdir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?;
+
+        let k = key("a", "1");
+        let (entry, sha256) = lfs_pointer_entry(k.clone());
+        log.put_entry(entry)?;
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(Arc::new(log));
+
+        // Explicitly asking for the pointer doesn't require resolving it.
+        let file = store
+            .fetch(vec![k], FileAttributes::LFS_POINTER, FetchMode::LocalOnly)
+            .single()?
+            .expect("pointer not found");
+        let ptr = file.lfs_pointer().expect("no pointer attribute");
+        assert_eq!(ptr.sha256(), sha256);
+
+        Ok(())
+    }
+
     #[test]
     fn test_scmstore_extstore_ignore() -> Result<()> {
         let tempdir = TempDir::new()?;
@@ -954,4 +1922,59 @@ mod tests {
         assert!(missing.contains_key(&lfs_key));
         Ok(())
     }
+
+    #[test]
+    fn test_scmstore_file_batch_size() -> Result<()> {
+        let mut files = Vec::new();
+        let mut keys = Vec::new();
+        for i in 0..5 {
+            let data = Bytes::from(format!("content {}", i));
+            let k = Key::new(
+                repo_path_buf(&format!("file{}", i)),
+                HgId::from_content(&data, types::Parents::None),
+            );
+            files.push((k.clone(), data));
+            keys.push(k);
+        }
+
+        let client = FakeSaplingRemoteApi::new().files(files.clone()).into_arc();
+
+        let mut store = FileStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(
+            client.clone(),
+        ));
+        store.batch_size = Some(2);
+
+        let (found, missing, _errors) = store
+            .fetch(
+                keys.iter().cloned(),
+                FileAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(found.len(), 5);
+        assert!(missing.is_empty());
+        // Five keys with a batch size of two split into three requests.
+        assert_eq!(client.file_request_sizes(), vec![2, 2, 1]);
+
+        // Without a batch size the whole batch goes out as one request.
+        let client = FakeSaplingRemoteApi::new().files(files).into_arc();
+
+        let mut store = FileStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(
+            client.clone(),
+        ));
+
+        let (found, _missing, _errors) = store
+            .fetch(
+                keys.iter().cloned(),
+                FileAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(found.len(), 5);
+        assert_eq!(client.file_request_sizes(), vec![5]);
+
+        Ok(())
+    }
 }
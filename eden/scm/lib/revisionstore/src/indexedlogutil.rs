@@ -22,6 +22,7 @@ use indexedlog::log::LogLookupIter;
 use indexedlog::rotate;
 use indexedlog::rotate::RotateLog;
 use indexedlog::rotate::RotateLogLookupIter;
+use indexedlog::rotate::RotationDropInfo;
 use indexedlog::OpenWithRepair;
 use indexedlog::Result as IndexedlogResult;
 use minibytes::Bytes;
@@ -79,6 +80,15 @@ impl Store {
         self.write().flush()
     }
 
+    /// Take details about the most recent generation a rotated store deleted,
+    /// if any. Always `None` for permanent stores.
+    pub fn take_last_rotation_drop(&self) -> Option<RotationDropInfo> {
+        match &mut *self.write() {
+            Inner::Rotated(log) => log.take_last_dropped(),
+            Inner::Permanent(_) => None,
+        }
+    }
+
     fn sync_if_changed_on_disk(&self) -> RwLockReadGuard<'_, Inner> {
         let log = self.inner.read();
 
@@ -328,6 +338,20 @@ impl StoreOpenOptions {
         })
     }
 
+    /// Create a rotated `Store` backed entirely by memory, for unit tests.
+    ///
+    /// Nothing is written to disk and flushes are no-ops.
+    pub fn rotated_in_memory(self) -> Result<Store> {
+        let sync_if_changed_on_disk = self.sync_if_changed_on_disk;
+        let opts = self.into_rotated_open_options();
+        let rotate_log = opts.create_in_memory()?;
+        Ok(Store {
+            inner: RwLock::new(Inner::Rotated(rotate_log)),
+            auto_sync_count: AtomicU64::new(0),
+            sync_if_changed_on_disk,
+        })
+    }
+
     /// Attempts to repair corruption in a permanent indexedlog store.
     ///
     /// Note, this may delete data, though it should only delete data that is unreadable.
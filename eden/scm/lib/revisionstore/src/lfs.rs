@@ -5,12 +5,15 @@
  * GNU General Public License version 2.
  */
 
+use std::cmp::max;
 use std::cmp::min;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
 use std::iter;
 use std::mem;
@@ -75,6 +78,7 @@ use lfs_protocol::Sha256 as LfsSha256;
 use mincode::deserialize;
 use mincode::serialize;
 use minibytes::Bytes;
+use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use rand::thread_rng;
 use rand::Rng;
@@ -90,6 +94,8 @@ use tracing::Instrument;
 use types::HgId;
 use types::Key;
 use types::RepoPath;
+use types::RepoPathBuf;
+use types::Sha1;
 use types::Sha256;
 use url::Url;
 use util::path::create_dir;
@@ -114,12 +120,14 @@ use crate::localstore::LocalStore;
 use crate::redacted;
 use crate::redacted::is_redacted;
 use crate::remotestore::HgIdRemoteStore;
+use crate::throttle::NetworkThrottle;
 use crate::types::ContentHash;
 use crate::types::StoreKey;
 use crate::uniondatastore::UnionHgIdDataStore;
 use crate::util::get_lfs_blobs_path;
 use crate::util::get_lfs_objects_path;
 use crate::util::get_lfs_pointers_path;
+use crate::ToKeys;
 
 /// The `LfsPointersStore` holds the mapping between a `HgId` and the content hash (sha256) of the LFS blob.
 struct LfsPointersStore(Store);
@@ -142,8 +150,68 @@ pub enum LfsBlobsStore {
     Union(Box<LfsBlobsStore>, Box<LfsBlobsStore>),
 }
 
+/// Metadata about an LFS blob that can be derived from its content. Loose blobs record it in a
+/// `<oid>.meta` sidecar file at write time, so that aux data queries (in particular the content
+/// sha1) can be answered without reading and re-hashing the whole blob.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LfsBlobMetadata {
+    pub sha1: Sha1,
+    pub size: u64,
+}
+
+/// On-disk representation of `LfsBlobMetadata`. The sha1 is hex encoded to keep the sidecar
+/// human readable.
+#[derive(Serialize, Deserialize)]
+struct LfsBlobMetadataSerde {
+    sha1: String,
+    size: u64,
+}
+
+impl LfsBlobMetadata {
+    pub fn from_blob(blob: &[u8]) -> Self {
+        let sha1 = {
+            use sha1::Digest;
+            let mut hash = sha1::Sha1::new();
+            hash.update(blob);
+            let bytes: [u8; Sha1::len()] = hash.finalize().into();
+            Sha1::from(bytes)
+        };
+        Self {
+            sha1,
+            size: blob.len() as u64,
+        }
+    }
+
+    fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&LfsBlobMetadataSerde {
+            sha1: self.sha1.to_hex(),
+            size: self.size,
+        })?)
+    }
+
+    fn from_json(data: &[u8]) -> Result<Self> {
+        let serde: LfsBlobMetadataSerde = serde_json::from_slice(data)?;
+        Ok(Self {
+            sha1: Sha1::from_str(&serde.sha1)?,
+            size: serde.size,
+        })
+    }
+}
+
 pub struct HttpLfsRemote {
     url: Url,
+    /// Endpoints downloads may be served from, in preference order. The
+    /// first entry is always `url`; any others are mirrors taken from
+    /// `lfs.mirror-urls`. Uploads only ever go to `url`.
+    download_urls: Vec<Url>,
+    /// A caching proxy (e.g. a shared LAN cache in CI) that batch requests
+    /// and object downloads are sent through instead of the origin. The
+    /// proxy is assumed to implement the LFS batch API and to forward
+    /// authentication headers. From `lfs.proxy-url`.
+    proxy_url: Option<Url>,
+    /// Whether to retry against the origin when the proxy is down. From
+    /// `lfs.proxy-fallback`.
+    proxy_fallback: bool,
     client: Arc<HttpClient>,
     concurrent_fetches: usize,
     download_chunk_size: Option<NonZeroU64>,
@@ -151,6 +219,7 @@ pub struct HttpLfsRemote {
     http_options: Arc<HttpOptions>,
 }
 
+#[derive(Clone)]
 struct HttpOptions {
     accept_zstd: bool,
     http_version: HttpVersion,
@@ -159,6 +228,7 @@ struct HttpOptions {
     backoff_times: Vec<f32>,
     throttle_backoff_times: Vec<f32>,
     request_timeout: Duration,
+    network_throttle: Option<Arc<NetworkThrottle>>,
 }
 
 pub enum LfsRemote {
@@ -185,6 +255,15 @@ pub struct LfsClient {
 pub struct LfsStore {
     pointers: LfsPointersStore,
     blobs: LfsBlobsStore,
+
+    /// Optional replica store for disaster-recovery replication. Writes go to
+    /// both this store and the replica, reads are only served from this store.
+    replica: Option<Arc<LfsStore>>,
+
+    /// Check that blob and pointer writes agree on the content sha256 and
+    /// size before storing them (`lfs.verify-writes`, default on). Writes are
+    /// rare, and a mismatched pointer/blob pair corrupts the working copy.
+    verify_writes: bool,
 }
 
 /// When a blob is added to the `LfsMultiplexer`, is will either be written to an `LfsStore`, or to
@@ -443,6 +522,79 @@ impl LfsIndexedLogBlobsStore {
         }
     }
 
+    /// Read only `range` of the blob, extracting it from the chunks overlapping it without
+    /// assembling the whole blob.
+    ///
+    /// The caller is expected to clamp the range to the blob size recorded in the pointer. Like
+    /// `get`, the SHA256 hash isn't validated, and a missing chunk yields `None`.
+    pub fn get_range(&self, hash: &Sha256, range: Range<usize>) -> Result<Option<Bytes>> {
+        if range.is_empty() {
+            return Ok(Some(Bytes::new()));
+        }
+
+        let log = self.inner.read();
+        let chunks_iter = log.lookup(0, hash)?.map(|data| {
+            let data: Bytes = log.slice_to_bytes(data?);
+            let deserialized: LfsIndexedLogBlobsEntry =
+                data.as_deserialize_hint(|| deserialize(&data))?;
+            Ok(deserialized)
+        });
+
+        // As in `get`, filter corrupted entries; a valid copy of the chunk may
+        // still be present alongside them.
+        let mut chunks: Vec<(usize, LfsIndexedLogBlobsEntry)> = chunks_iter
+            .filter_map(|res: Result<_, Error>| res.ok())
+            .enumerate()
+            .collect();
+
+        if chunks.is_empty() {
+            return Ok(None);
+        }
+
+        // Make sure that the ranges are sorted in increasing order.
+        chunks.sort_unstable_by(|(a_idx, a), (b_idx, b)| {
+            a.range.start.cmp(&b.range.start).then(a_idx.cmp(b_idx))
+        });
+
+        let mut res = Vec::with_capacity(range.len());
+
+        let mut next_start = 0;
+        for (_, entry) in chunks.into_iter() {
+            // A chunk is missing.
+            if entry.range.start > next_start {
+                return Ok(None);
+            }
+
+            // This chunk is fully contained in the previous ones.
+            if entry.range.end <= next_start {
+                continue;
+            }
+
+            // The part of this chunk that is wanted and not yet covered.
+            let wanted_start = max(max(next_start, range.start), entry.range.start);
+            let wanted_end = min(entry.range.end, range.end);
+            if wanted_start < wanted_end {
+                res.extend_from_slice(
+                    entry
+                        .data
+                        .slice(wanted_start - entry.range.start..wanted_end - entry.range.start)
+                        .as_ref(),
+                );
+            }
+
+            next_start = entry.range.end;
+            if next_start >= range.end {
+                break;
+            }
+        }
+
+        if res.len() == range.len() {
+            Ok(Some(res.into()))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Test whether a blob is in the store. It returns true if at least one chunk is present, and
     /// thus it is possible that one of the chunk is missing.
     pub fn contains(&self, hash: &Sha256) -> Result<bool> {
@@ -530,6 +682,11 @@ impl LfsBlobsStore {
         path
     }
 
+    /// Path of the metadata sidecar written next to a loose blob.
+    fn metadata_path(path: &Path, hash: &Sha256) -> PathBuf {
+        LfsBlobsStore::path(path, hash).with_extension("meta")
+    }
+
     /// Read the blob matching the content hash.
     ///
     /// Blob hash should be validated by the underlying store.
@@ -572,6 +729,50 @@ impl LfsBlobsStore {
         Ok(blob)
     }
 
+    /// Read only `range` of the blob matching the content hash.
+    ///
+    /// Unlike `get`, the whole blob is never loaded, so the returned slice cannot be validated
+    /// against the content hash. The caller is expected to clamp the range to the blob size.
+    pub fn get_range(&self, hash: &Sha256, range: Range<u64>) -> Result<Option<Bytes>> {
+        let blob = match self {
+            LfsBlobsStore::Loose(path, _) => {
+                let path = LfsBlobsStore::path(path, hash);
+                let mut file = match File::open(path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        if e.kind() == ErrorKind::NotFound {
+                            return Ok(None);
+                        } else {
+                            return Err(e.into());
+                        }
+                    }
+                };
+
+                file.seek(SeekFrom::Start(range.start))?;
+                let mut buf = vec![0; (range.end - range.start) as usize];
+                match file.read_exact(&mut buf) {
+                    Ok(()) => Some(Bytes::from(buf)),
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            LfsBlobsStore::IndexedLog(log) => {
+                log.get_range(hash, range.start as usize..range.end as usize)?
+            }
+
+            LfsBlobsStore::Union(first, second) => {
+                if let Some(blob) = first.get_range(hash, range.clone())? {
+                    Some(blob)
+                } else {
+                    second.get_range(hash, range)?
+                }
+            }
+        };
+
+        Ok(blob)
+    }
+
     /// Test whether the blob store contains the hash.
     pub fn contains(&self, hash: &Sha256) -> Result<bool> {
         match self {
@@ -583,10 +784,57 @@ impl LfsBlobsStore {
         }
     }
 
+    /// Read the metadata of the blob matching the content hash.
+    ///
+    /// The sidecar written by `add` is used when present. Blobs written before sidecars existed,
+    /// or stored in an `IndexedLog`, fall back to reading and hashing the content.
+    pub fn get_metadata(&self, hash: &Sha256, size: u64) -> Result<Option<LfsBlobMetadata>> {
+        if let Some(metadata) = self.get_sidecar_metadata(hash)? {
+            return Ok(Some(metadata));
+        }
+
+        Ok(self
+            .get(hash, size)?
+            .map(|blob| LfsBlobMetadata::from_blob(&blob)))
+    }
+
+    fn get_sidecar_metadata(&self, hash: &Sha256) -> Result<Option<LfsBlobMetadata>> {
+        match self {
+            LfsBlobsStore::Loose(path, _) => {
+                let path = LfsBlobsStore::metadata_path(path, hash);
+                let mut file = match File::open(path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        if e.kind() == ErrorKind::NotFound {
+                            return Ok(None);
+                        } else {
+                            return Err(e.into());
+                        }
+                    }
+                };
+
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                Ok(Some(LfsBlobMetadata::from_json(&buf)?))
+            }
+
+            LfsBlobsStore::IndexedLog(_) => Ok(None),
+
+            LfsBlobsStore::Union(first, second) => {
+                if let Some(metadata) = first.get_sidecar_metadata(hash)? {
+                    Ok(Some(metadata))
+                } else {
+                    second.get_sidecar_metadata(hash)
+                }
+            }
+        }
+    }
+
     /// Add the blob to the store.
     pub fn add(&self, hash: &Sha256, blob: Bytes) -> Result<()> {
         match self {
             LfsBlobsStore::Loose(path, is_local) => {
+                let metadata_path = LfsBlobsStore::metadata_path(path, hash);
                 let path = LfsBlobsStore::path(path, hash);
                 let parent_path = path.parent().unwrap();
 
@@ -602,6 +850,13 @@ impl LfsBlobsStore {
                 if *is_local {
                     file.sync_all()?;
                 }
+
+                // Record the sha1 and size in a sidecar so future aux data queries don't have to
+                // re-read and re-hash the blob. The sidecar is derivable from the blob, no need
+                // to sync it.
+                let metadata = LfsBlobMetadata::from_blob(&blob);
+                let mut metadata_file = File::create(metadata_path)?;
+                metadata_file.write_all(&metadata.to_json()?)?;
             }
 
             LfsBlobsStore::IndexedLog(log) => log.add(hash, blob)?,
@@ -615,8 +870,13 @@ impl LfsBlobsStore {
     pub fn remove(&self, hash: &Sha256) -> Result<()> {
         match self {
             LfsBlobsStore::Loose(path, _) => {
+                let metadata_path = LfsBlobsStore::metadata_path(path, hash);
                 let path = LfsBlobsStore::path(path, hash);
                 remove_file(path).with_context(|| format!("Cannot remove LFS blob {}", hash))?;
+                if metadata_path.is_file() {
+                    remove_file(metadata_path)
+                        .with_context(|| format!("Cannot remove LFS blob metadata {}", hash))?;
+                }
             }
 
             _ => {}
@@ -639,9 +899,59 @@ pub(crate) enum LfsStoreEntry {
     PointerAndBlob(LfsPointersEntry, Bytes),
 }
 
+/// Check that the filesystem holding `path` has at least
+/// `lfs.min-disk-space-bytes` of free space. A permanent store on a nearly
+/// full filesystem (e.g. a size-limited tmpfs) silently fails to make
+/// progress, so it is better to complain up-front. By default a warning is
+/// emitted; set `lfs.min-disk-space-error` to turn it into a hard error.
+fn check_available_disk_space(path: &Path, config: &dyn Config) -> Result<()> {
+    let min_bytes = match config.get_opt::<ByteCount>("lfs", "min-disk-space-bytes")? {
+        Some(min_bytes) => min_bytes.value(),
+        None => return Ok(()),
+    };
+
+    // The store directory may not have been created yet, in which case the
+    // free space can't be queried. Don't fail store construction over it.
+    let available = match fs2::available_space(path) {
+        Ok(available) => available,
+        Err(_) => return Ok(()),
+    };
+
+    if available < min_bytes {
+        if config.get_or("lfs", "min-disk-space-error", || false)? {
+            bail!(
+                "not enough free disk space for LFS store at {:?}: {} bytes available, {} bytes required",
+                path,
+                available,
+                min_bytes
+            );
+        }
+        warn!(
+            "low disk space for LFS store at {:?}: {} bytes available, {} bytes required",
+            path, available, min_bytes
+        );
+    }
+
+    Ok(())
+}
+
 impl LfsStore {
-    fn new(pointers: LfsPointersStore, blobs: LfsBlobsStore) -> Result<Self> {
-        Ok(Self { pointers, blobs })
+    fn new(pointers: LfsPointersStore, blobs: LfsBlobsStore, verify_writes: bool) -> Result<Self> {
+        Ok(Self {
+            pointers,
+            blobs,
+            replica: None,
+            verify_writes,
+        })
+    }
+
+    /// Wrap this store so every write is also replicated to `replica`, for
+    /// deployments that mirror LFS blobs to a second storage backend. Both
+    /// writes must succeed for the write to be considered successful. Reads
+    /// are only served from the primary store.
+    pub fn with_replica(mut self, replica: Arc<LfsStore>) -> Arc<LfsStore> {
+        self.replica = Some(replica);
+        Arc::new(self)
     }
 
     /// Create a new permanent `LfsStore`.
@@ -649,9 +959,10 @@ impl LfsStore {
     /// Permanent stores will `fsync(2)` data to disk, and will never rotate data out of the store.
     pub fn permanent(path: impl AsRef<Path>, config: &dyn Config) -> Result<Self> {
         let path = path.as_ref();
+        check_available_disk_space(path, config)?;
         let pointers = LfsPointersStore::permanent(path, config)?;
         let blobs = LfsBlobsStore::loose_objects(path)?;
-        LfsStore::new(pointers, blobs)
+        LfsStore::new(pointers, blobs, config.get_or("lfs", "verify-writes", || true)?)
     }
 
     /// Create a new rotated `LfsStore`.
@@ -659,7 +970,7 @@ impl LfsStore {
         let path = path.as_ref();
         let pointers = LfsPointersStore::rotated(path, config)?;
         let blobs = LfsBlobsStore::rotated_or_loose_objects(path, config)?;
-        LfsStore::new(pointers, blobs)
+        LfsStore::new(pointers, blobs, config.get_or("lfs", "verify-writes", || true)?)
     }
 
     pub fn repair(path: impl AsRef<Path>) -> Result<String> {
@@ -740,6 +1051,26 @@ impl LfsStore {
         }
     }
 
+    /// Read `range` of the blob for `key`, along with the blob's total size, without loading the
+    /// whole blob. The range is clamped to the blob size.
+    pub fn get_blob_range(&self, key: &Key, range: Range<u64>) -> Result<Option<(Bytes, u64)>> {
+        let pointer = match self.pointers.entry(&StoreKey::hgid(key.clone()))? {
+            None => return Ok(None),
+            Some(pointer) => pointer,
+        };
+        let hash = match pointer.content_hashes.get(&ContentHashType::Sha256) {
+            None => return Ok(None),
+            Some(hash) => hash.clone().unwrap_sha256(),
+        };
+
+        let total_size = pointer.size;
+        let range = min(range.start, total_size)..min(range.end, total_size);
+        Ok(self
+            .blobs
+            .get_range(&hash, range)?
+            .map(|data| (data, total_size)))
+    }
+
     /// Directly get the local content. Do not ask remote servers.
     pub(crate) fn get_local_content_direct(&self, id: &HgId) -> Result<Option<Bytes>> {
         let pointer = match self.pointers.get_by_hgid(id)? {
@@ -753,15 +1084,75 @@ impl LfsStore {
         self.blobs.get(hash.sha256_ref(), pointer.size)
     }
 
+    /// Metadata (content sha1 and size) of the blob matching the content hash. Served from the
+    /// sidecar written at blob write time when available, avoiding a content re-read.
+    pub fn blob_metadata(&self, hash: &Sha256, size: u64) -> Result<Option<LfsBlobMetadata>> {
+        self.blobs.get_metadata(hash, size)
+    }
+
     pub fn add_blob(&self, hash: &Sha256, blob: Bytes) -> Result<()> {
+        if self.verify_writes {
+            verify_blob_write(hash, None, &blob)?;
+        }
+        if let Some(replica) = &self.replica {
+            replica.add_blob(hash, blob.clone())?;
+        }
         self.blobs.add(hash, blob)
     }
 
     pub(crate) fn add_pointer(&self, pointer_entry: LfsPointersEntry) -> Result<()> {
+        if self.verify_writes {
+            if let Some(content_hash) = pointer_entry.content_hashes.get(&ContentHashType::Sha256) {
+                let sha256 = content_hash.clone().unwrap_sha256();
+                // Only verify if the content is already stored locally; a
+                // pointer may legitimately precede its blob.
+                if self.blobs.contains(&sha256)? {
+                    match self.blobs.get(&sha256, pointer_entry.size)? {
+                        Some(blob) => verify_blob_write(&sha256, Some(pointer_entry.size), &blob)?,
+                        None => bail!(
+                            "LFS pointer write rejected for oid {}: the pointer size ({} bytes) \
+                             disagrees with the locally stored blob",
+                            sha256,
+                            pointer_entry.size,
+                        ),
+                    }
+                }
+            }
+        }
+        if let Some(replica) = &self.replica {
+            replica.add_pointer(pointer_entry.clone())?;
+        }
         self.pointers.add(pointer_entry)
     }
 }
 
+/// Check that `blob` matches the oid (and, when known, the pointer size) it
+/// is about to be stored under. Redacted placeholder blobs are exempt, like
+/// in the read path.
+fn verify_blob_write(hash: &Sha256, size: Option<u64>, blob: &Bytes) -> Result<()> {
+    if is_redacted(blob) {
+        return Ok(());
+    }
+    let computed = ContentHash::sha256(blob).unwrap_sha256();
+    ensure!(
+        &computed == hash,
+        "LFS blob write rejected for oid {}: the content hashes to {}",
+        hash,
+        computed,
+    );
+    if let Some(size) = size {
+        ensure!(
+            blob.len() as u64 == size,
+            "LFS blob write rejected for oid {}: the pointer size is {} bytes but the content \
+             has {} bytes",
+            hash,
+            size,
+            blob.len(),
+        );
+    }
+    Ok(())
+}
+
 impl LocalStore for LfsStore {
     fn get_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         Ok(keys
@@ -798,6 +1189,21 @@ impl LocalStore for LfsStore {
     }
 }
 
+impl ToKeys for LfsStore {
+    fn to_keys(&self) -> Vec<Result<Key>> {
+        // The pointers store doesn't record file paths, so the keys are
+        // yielded with an empty path.
+        let log = self.pointers.0.read();
+        log.iter()
+            .map(|entry| {
+                let bytes = log.slice_to_bytes(entry?);
+                LfsPointersStore::get_from_slice(bytes.as_ref())
+            })
+            .map(|entry| Ok(Key::new(RepoPathBuf::new(), entry?.hgid)))
+            .collect()
+    }
+}
+
 /// When a file was copied, Mercurial expects the blob that the store returns to contain this copy
 /// information
 pub(crate) fn rebuild_metadata(data: Bytes, entry: &LfsPointersEntry) -> Bytes {
@@ -868,11 +1274,14 @@ impl HgIdMutableDeltaStore for LfsStore {
     fn add(&self, delta: &Delta, _metadata: &Metadata) -> Result<()> {
         ensure!(delta.base.is_none(), "Deltas aren't supported.");
         let (lfs_pointer_entry, blob) = lfs_from_hg_file_blob(delta.key.hgid, &delta.data)?;
-        self.blobs.add(&lfs_pointer_entry.sha256(), blob)?;
-        self.pointers.add(lfs_pointer_entry)
+        self.add_blob(&lfs_pointer_entry.sha256(), blob)?;
+        self.add_pointer(lfs_pointer_entry)
     }
 
     fn flush(&self) -> Result<Option<Vec<PathBuf>>> {
+        if let Some(replica) = &self.replica {
+            replica.flush()?;
+        }
         self.blobs.flush()?;
         self.pointers.0.flush()?;
         Ok(None)
@@ -1098,6 +1507,41 @@ impl HgIdMutableDeltaStore for LfsMultiplexer {
     }
 }
 
+/// Endpoints that failed with a connection error or a server error. Tracked
+/// for the life of the process so a dead primary isn't retried for every
+/// object; a dead endpoint is only retried once every endpoint is dead.
+static UNHEALTHY_LFS_ENDPOINTS: Lazy<Mutex<HashSet<String>>> = Lazy::new(Default::default);
+
+fn is_endpoint_healthy(url: &Url) -> bool {
+    !UNHEALTHY_LFS_ENDPOINTS.lock().contains(url.as_str())
+}
+
+fn mark_endpoint_unhealthy(url: &Url) {
+    UNHEALTHY_LFS_ENDPOINTS.lock().insert(url.as_str().to_string());
+}
+
+/// Rewrite `url` to go through the LFS caching proxy: the proxy serves the
+/// same paths as the origin, so the origin's path (and query) is appended
+/// to the proxy URL.
+fn proxied_url(proxy: &Url, url: &Url) -> Result<Url> {
+    let mut relative = url.path().trim_start_matches('/').to_string();
+    if let Some(query) = url.query() {
+        relative.push('?');
+        relative.push_str(query);
+    }
+    Ok(proxy.join(&relative)?)
+}
+
+/// Whether `error` indicates the endpoint itself is down, as opposed to a
+/// problem with the request, and a mirror is worth trying.
+fn is_endpoint_failure(error: &TransferError) -> bool {
+    match error {
+        TransferError::HttpStatus(status, _) => status.is_server_error(),
+        TransferError::HttpClientError(_) => true,
+        _ => false,
+    }
+}
+
 impl LfsRemote {
     pub fn from_config(config: &dyn Config) -> Result<Self> {
         let mut url: String = config.must_get("lfs", "url")?;
@@ -1175,10 +1619,43 @@ impl LfsRemote {
             // Pick something relatively low. Doesn't seem like we need many concurrent LFS downloads to saturate available BW.
             let max_batch_size = config.get_or("lfs", "max-batch-size", || 100)?;
 
+            // Regional mirrors, tried in order when the endpoints before
+            // them are unhealthy. Uploads ignore these and always go to
+            // `lfs.url`.
+            let mut download_urls = vec![url.clone()];
+            for mut mirror in config.get_or_default::<Vec<String>>("lfs", "mirror-urls")? {
+                mirror.push('/');
+                let mirror = Url::parse(&mirror)?;
+                if !["http", "https"].contains(&mirror.scheme()) {
+                    bail!("Unsupported url: {}", mirror);
+                }
+                download_urls.push(mirror);
+            }
+
+            // A shared caching proxy, e.g. on a LAN server close to CI
+            // machines all fetching the same blobs.
+            let proxy_url = match config.get_opt::<String>("lfs", "proxy-url")? {
+                Some(mut proxy) => {
+                    proxy.push('/');
+                    let proxy = Url::parse(&proxy)?;
+                    if !["http", "https"].contains(&proxy.scheme()) {
+                        bail!("Unsupported url: {}", proxy);
+                    }
+                    Some(proxy)
+                }
+                None => None,
+            };
+            let proxy_fallback = config.get_or_default("lfs", "proxy-fallback")?;
+
+            let network_throttle = NetworkThrottle::from_config(config)?;
+
             let client = http_client("lfs", http_config(config, &url)?);
 
             Ok(Self::Http(HttpLfsRemote {
                 url,
+                download_urls,
+                proxy_url,
+                proxy_fallback,
                 client: Arc::new(client),
                 concurrent_fetches,
                 download_chunk_size,
@@ -1191,6 +1668,7 @@ impl LfsRemote {
                     backoff_times,
                     throttle_backoff_times,
                     request_timeout,
+                    network_throttle,
                 }),
             }))
         }
@@ -1216,6 +1694,95 @@ impl LfsRemote {
         }
     }
 
+    /// Fetch only `range` of the blob from the LFS server.
+    ///
+    /// The slice is not written to any store since partial blobs cannot be validated against
+    /// their content hash. The caller is expected to clamp the range to the blob size.
+    pub(crate) fn fetch_range(
+        &self,
+        sha256: &Sha256,
+        size: u64,
+        range: Range<u64>,
+    ) -> Result<Option<Bytes>> {
+        if range.is_empty() {
+            return Ok(Some(Bytes::new()));
+        }
+        match self {
+            LfsRemote::Http(http) => Self::fetch_range_http(http, sha256, size, range),
+            LfsRemote::File(file) => file.get_range(sha256, range),
+        }
+    }
+
+    fn fetch_range_http(
+        http: &HttpLfsRemote,
+        sha256: &Sha256,
+        size: u64,
+        range: Range<u64>,
+    ) -> Result<Option<Bytes>> {
+        let request_obj = RequestObject {
+            oid: LfsSha256(sha256.into_inner()),
+            size,
+        };
+        let response =
+            LfsRemote::send_batch_request(http, vec![request_obj], Operation::Download)?;
+        let response = match response {
+            None => return Ok(None),
+            Some(response) => response,
+        };
+
+        for object in response.objects {
+            let actions = match object.status {
+                ObjectStatus::Ok {
+                    authenticated: _,
+                    actions,
+                } => actions,
+                ObjectStatus::Err { error: e } => {
+                    bail!("LFS fetch error {} - {}", e.code, e.message)
+                }
+            };
+
+            for (op, action) in actions.into_iter() {
+                match op {
+                    Operation::Download => {}
+                    _ => continue,
+                }
+
+                let url = Url::from_str(&action.href.to_string())?;
+                let http_range = format!("bytes={}-{}", range.start, range.end - 1);
+
+                let response_fut = LfsRemote::send_with_retry(
+                    http.client.clone(),
+                    Method::Get,
+                    url,
+                    move |builder| {
+                        let builder = add_action_headers_to_request(builder, &action);
+                        builder.header("Range", &http_range)
+                    },
+                    |status| {
+                        if status == http::StatusCode::PARTIAL_CONTENT {
+                            return Ok(());
+                        }
+
+                        Err(TransferError::UnexpectedHttpStatus {
+                            expected: http::StatusCode::PARTIAL_CONTENT,
+                            received: status,
+                        })
+                    },
+                    http.http_options.clone(),
+                );
+
+                let maybe_client_request_info = get_client_request_info_thread_local();
+                let data = block_on(with_client_request_info_scope(
+                    maybe_client_request_info,
+                    response_fut,
+                ))?;
+                return Ok(Some(data));
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn batch_upload(
         &self,
         objs: &HashSet<(Sha256, usize)>,
@@ -1340,6 +1907,15 @@ impl LfsRemote {
                             }
                         })??;
 
+                        // Pause before reading more so the transport's
+                        // back-pressure keeps the download at the capped rate.
+                        if let Some(throttle) = &http_options.network_throttle {
+                            let wait = throttle.acquire(chunk.len());
+                            if !wait.is_zero() {
+                                sleep(wait).await;
+                            }
+                        }
+
                         chunks.push(chunk);
                     }
 
@@ -1433,27 +2009,72 @@ impl LfsRemote {
 
         let batch_json = serde_json::to_string(&batch)?;
 
-        let batch_url = http.url.join("objects/batch")?;
-
-        let response_fut = async move {
-            LfsRemote::send_with_retry(
-                http.client.clone(),
-                Method::Post,
-                batch_url,
-                move |builder| builder.body(batch_json.clone()),
-                |_| Ok(()),
-                http.http_options.clone(),
-            )
-            .await
+        // Uploads only ever go to the primary endpoint; downloads may fall
+        // back to a mirror when an endpoint is down. When a caching proxy
+        // is configured, it is tried first, with the origin endpoints as
+        // fallbacks only if `lfs.proxy-fallback` is enabled.
+        let proxied_endpoints: Vec<Url>;
+        let endpoints: &[Url] = match (operation, &http.proxy_url) {
+            (Operation::Upload, None) => std::slice::from_ref(&http.url),
+            (Operation::Download, None) => &http.download_urls,
+            (operation, Some(proxy)) => {
+                let mut endpoints = vec![proxied_url(proxy, &http.url)?];
+                if http.proxy_fallback {
+                    match operation {
+                        Operation::Upload => endpoints.push(http.url.clone()),
+                        Operation::Download => {
+                            endpoints.extend(http.download_urls.iter().cloned())
+                        }
+                    }
+                }
+                proxied_endpoints = endpoints;
+                &proxied_endpoints
+            }
         };
 
-        // Fetch ClientRequestInfo from a thread local and pass to async code
-        let maybe_client_request_info = get_client_request_info_thread_local();
-        let response = block_on(with_client_request_info_scope(
-            maybe_client_request_info,
-            response_fut,
-        ))?;
-        Ok(Some(serde_json::from_slice(response.as_ref())?))
+        // When every endpoint has already failed, try them all again rather
+        // than giving up outright.
+        let any_healthy = endpoints.iter().any(is_endpoint_healthy);
+
+        let mut last_error = None;
+        for url in endpoints {
+            if any_healthy && !is_endpoint_healthy(url) {
+                continue;
+            }
+
+            let batch_url = url.join("objects/batch")?;
+            let batch_json = batch_json.clone();
+            let response_fut = async move {
+                LfsRemote::send_with_retry(
+                    http.client.clone(),
+                    Method::Post,
+                    batch_url,
+                    move |builder| builder.body(batch_json.clone()),
+                    |_| Ok(()),
+                    http.http_options.clone(),
+                )
+                .await
+            };
+
+            // Fetch ClientRequestInfo from a thread local and pass to async code
+            let maybe_client_request_info = get_client_request_info_thread_local();
+            match block_on(with_client_request_info_scope(
+                maybe_client_request_info,
+                response_fut,
+            )) {
+                Ok(response) => return Ok(Some(serde_json::from_slice(response.as_ref())?)),
+                Err(err) if endpoints.len() > 1 && is_endpoint_failure(&err.error) => {
+                    warn!("LFS endpoint {} is down, trying the next one: {}", url, err);
+                    mark_endpoint_unhealthy(url);
+                    last_error = Some(err);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Err(last_error
+            .expect("at least one LFS endpoint must have been tried")
+            .into())
     }
 
     async fn process_upload(
@@ -1488,17 +2109,84 @@ impl LfsRemote {
         Ok(())
     }
 
-    async fn process_download(
+    /// Download `url`, explicitly following HTTP redirects.
+    ///
+    /// LFS servers commonly redirect large blob downloads to a CDN. The batch
+    /// action's headers typically carry authentication for the LFS server
+    /// itself, and CDNs reject requests carrying them, so they are dropped
+    /// once a redirect points at a different host.
+    async fn download_with_redirects(
+        client: Arc<HttpClient>,
+        mut url: Url,
+        action: &ObjectAction,
+        http_options: Arc<HttpOptions>,
+    ) -> Result<Bytes, FetchError> {
+        const MAX_REDIRECTS: usize = 5;
+
+        let mut send_action_headers = true;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let res = LfsRemote::send_with_retry(
+                client.clone(),
+                Method::Get,
+                url.clone(),
+                |builder| {
+                    if send_action_headers {
+                        add_action_headers_to_request(builder, action)
+                    } else {
+                        builder
+                    }
+                },
+                |_| Ok(()),
+                http_options.clone(),
+            )
+            .await;
+
+            let err = match res {
+                Ok(data) => return Ok(data),
+                Err(err) => err,
+            };
+
+            let redirected = match &err.error {
+                TransferError::HttpStatus(status, headers) if status.is_redirection() => headers
+                    .get(http::header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok()),
+                _ => None,
+            };
+
+            match redirected {
+                Some(redirected) => {
+                    if redirected.host_str() != url.host_str() {
+                        send_action_headers = false;
+                    }
+                    tracing::debug!(from = %url, to = %redirected, "following LFS redirect");
+                    url = redirected;
+                }
+                None => return Err(err),
+            }
+        }
+
+        Err(FetchError {
+            url,
+            method: Method::Get,
+            error: TransferError::InvalidResponse(format_err!(
+                "too many redirects (max {})",
+                MAX_REDIRECTS
+            )),
+        })
+    }
+
+    /// Download a single object from `url`, in chunks if configured.
+    async fn download_object(
         client: Arc<HttpClient>,
         chunk_size: Option<NonZeroU64>,
-        action: ObjectAction,
-        oid: Sha256,
+        url: Url,
+        action: &ObjectAction,
         size: u64,
         http_options: Arc<HttpOptions>,
-    ) -> Result<(Sha256, Bytes)> {
-        let url = Url::from_str(&action.href.to_string())?;
-
-        let data = match chunk_size {
+    ) -> Result<Bytes, FetchError> {
+        match chunk_size {
             Some(chunk_size) => {
                 let chunk_increment = chunk_size.get() - 1;
 
@@ -1544,15 +2232,63 @@ impl LfsRemote {
                 .await
             }
             None => {
-                LfsRemote::send_with_retry(
-                    client,
-                    Method::Get,
-                    url,
-                    |builder| add_action_headers_to_request(builder, &action),
-                    |_| Ok(()),
-                    http_options,
-                )
-                .await
+                LfsRemote::download_with_redirects(client, url, &action, http_options).await
+            }
+        }
+    }
+
+    async fn process_download(
+        client: Arc<HttpClient>,
+        chunk_size: Option<NonZeroU64>,
+        proxy_url: Option<Url>,
+        proxy_fallback: bool,
+        action: ObjectAction,
+        oid: Sha256,
+        size: u64,
+        http_options: Arc<HttpOptions>,
+    ) -> Result<(Sha256, Bytes)> {
+        // When a caching proxy is configured, download through it, falling
+        // back to the origin href only when `lfs.proxy-fallback` is enabled
+        // and the proxy looks down.
+        let origin_url = Url::from_str(&action.href.to_string())?;
+        let mut urls = match &proxy_url {
+            Some(proxy) => {
+                let mut urls = vec![proxied_url(proxy, &origin_url)?];
+                if proxy_fallback {
+                    urls.push(origin_url);
+                }
+                urls
+            }
+            None => vec![origin_url],
+        };
+
+        let last_url = urls.pop().expect("at least one download URL");
+        let mut data = None;
+        for url in urls {
+            match LfsRemote::download_object(
+                client.clone(),
+                chunk_size,
+                url.clone(),
+                &action,
+                size,
+                http_options.clone(),
+            )
+            .await
+            {
+                Err(err) if is_endpoint_failure(&err.error) => {
+                    warn!("LFS proxy {} is down, trying the origin: {}", url, err);
+                }
+                res => {
+                    data = Some(res);
+                    break;
+                }
+            }
+        }
+        let data = match data {
+            Some(data) => data,
+            None => {
+                LfsRemote::download_object(client, chunk_size, last_url, &action, size, http_options)
+                    .await
             }
         };
 
@@ -1568,6 +2304,18 @@ impl LfsRemote {
             },
         };
 
+        // The blob may have been served by a redirected (CDN) host; verify it
+        // hashes to the requested oid before handing it back.
+        if !is_redacted(&data) {
+            let apparent_hash = ContentHash::sha256(&data).unwrap_sha256();
+            ensure!(
+                apparent_hash == oid,
+                "downloaded LFS blob for {} hashes to {}",
+                oid,
+                apparent_hash
+            );
+        }
+
         Ok((oid, data))
     }
 
@@ -1638,6 +2386,8 @@ impl LfsRemote {
                         Operation::Download => LfsRemote::process_download(
                             http.client.clone(),
                             http.download_chunk_size,
+                            http.proxy_url.clone(),
+                            http.proxy_fallback,
                             action,
                             oid,
                             object.object.size,
@@ -1717,6 +2467,16 @@ impl LfsClient {
         })
     }
 
+    /// Override the download throttle picked up from the config, replacing it
+    /// with `throttle` (or removing it when `None`).
+    pub(crate) fn set_network_throttle(&mut self, throttle: Option<Arc<NetworkThrottle>>) {
+        if let LfsRemote::Http(http) = &mut self.remote {
+            let mut http_options = (*http.http_options).clone();
+            http_options.network_throttle = throttle;
+            http.http_options = Arc::new(http_options);
+        }
+    }
+
     fn batch_fetch(
         &self,
         objs: &HashSet<(Sha256, usize)>,
@@ -1767,6 +2527,9 @@ fn move_blob(hash: &Sha256, size: u64, from: &LfsStore, to: &LfsStore) -> Result
             .get(hash, size)?
             .ok_or_else(|| format_err!("Cannot find blob for {}", hash))?;
 
+        if to.verify_writes {
+            verify_blob_write(hash, Some(size), &blob)?;
+        }
         to.blobs.add(hash, blob)?;
         from.blobs.remove(hash)?;
 
@@ -2110,6 +2873,7 @@ fn join_chunks<T: AsRef<[u8]>>(chunks: &[T]) -> Bytes {
 mod tests {
     use std::str::FromStr;
 
+    use edenapi_types::FileAuxData;
     use quickcheck::quickcheck;
     use tempfile::TempDir;
     use types::testutil::*;
@@ -2119,6 +2883,7 @@ mod tests {
     use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
     use crate::indexedlogutil::StoreType;
     use crate::localstore::ExtStoredPolicy;
+    use crate::scmstore::FileStore;
     use crate::testutil::example_blob;
     #[cfg(feature = "fb")]
     use crate::testutil::example_blob2;
@@ -2161,6 +2926,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_new_permanent_min_disk_space() -> Result<()> {
+        let dir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let mut config = make_lfs_config(&server, &dir, "test_min_disk_space");
+
+        // No filesystem has this much free space, so the check always trips.
+        setconfig(&mut config, "lfs", "min-disk-space-bytes", "16000000T");
+
+        // By default only a warning is emitted.
+        let _ = LfsStore::permanent(&dir, &config)?;
+
+        setconfig(&mut config, "lfs", "min-disk-space-error", "true");
+        let err = LfsStore::permanent(&dir, &config).unwrap_err();
+        assert!(err.to_string().contains("not enough free disk space"));
+
+        Ok(())
+    }
+
     #[test]
     fn test_add() -> Result<()> {
         let dir = TempDir::new()?;
@@ -2191,6 +2975,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_add_with_replica() -> Result<()> {
+        let primary_dir = TempDir::new()?;
+        let replica_dir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let config = make_lfs_config(&server, &primary_dir, "test_add_with_replica");
+
+        let replica = Arc::new(LfsStore::rotated(&replica_dir, &config)?);
+        let store = LfsStore::rotated(&primary_dir, &config)?.with_replica(replica.clone());
+
+        let k1 = key("a", "2");
+        let delta = Delta {
+            data: Bytes::from(&[1, 2, 3, 4][..]),
+            base: None,
+            key: k1.clone(),
+        };
+
+        store.add(&delta, &Default::default())?;
+        store.flush()?;
+
+        let hash = ContentHash::sha256(&delta.data).unwrap_sha256();
+
+        // The write must land in both the primary and the replica.
+        assert!(store.blobs.contains(&hash)?);
+        assert!(replica.blobs.contains(&hash)?);
+        assert!(store.get_missing(&[StoreKey::from(&k1)])?.is_empty());
+        assert!(replica.get_missing(&[StoreKey::from(&k1)])?.is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_loose() -> Result<()> {
         let dir = TempDir::new()?;
@@ -2209,6 +3024,101 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_loose_metadata_sidecar() -> Result<()> {
+        let dir = TempDir::new()?;
+        let objects_path = get_lfs_objects_path(dir.path())?;
+        let loose_store = LfsBlobsStore::loose(objects_path.clone());
+
+        let data = Bytes::from(&[1, 2, 3, 4][..]);
+        let sha256 = ContentHash::sha256(&data).unwrap_sha256();
+        loose_store.add(&sha256, data.clone())?;
+
+        let metadata_path = LfsBlobsStore::metadata_path(&objects_path, &sha256);
+        assert!(metadata_path.is_file());
+
+        let metadata = loose_store.get_metadata(&sha256, data.len() as u64)?.unwrap();
+        assert_eq!(metadata, LfsBlobMetadata::from_blob(&data));
+        assert_eq!(metadata.sha1, FileAuxData::from_content(&data).sha1);
+        assert_eq!(metadata.size, data.len() as u64);
+
+        // Blobs written before sidecars existed don't have one; the metadata is then recomputed
+        // from the content.
+        remove_file(metadata_path)?;
+        assert_eq!(
+            loose_store.get_metadata(&sha256, data.len() as u64)?,
+            Some(LfsBlobMetadata::from_blob(&data))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_blob_metadata() -> Result<()> {
+        let dir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let config = make_lfs_config(&server, &dir, "test_blob_metadata");
+        let store = LfsStore::rotated(&dir, &config)?;
+
+        let data = Bytes::from(&[1, 2, 3, 4][..]);
+        let sha256 = ContentHash::sha256(&data).unwrap_sha256();
+        store.add_blob(&sha256, data.clone())?;
+
+        // IndexedLog blobs have no sidecar, the metadata comes from hashing the content.
+        assert_eq!(
+            store.blob_metadata(&sha256, data.len() as u64)?,
+            Some(LfsBlobMetadata::from_blob(&data))
+        );
+
+        let missing = ContentHash::sha256(&Bytes::from(&[5, 6, 7, 8][..])).unwrap_sha256();
+        assert_eq!(store.blob_metadata(&missing, 4)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_writes() -> Result<()> {
+        let dir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let config = make_lfs_config(&server, &dir, "test_verify_writes");
+        let store = LfsStore::rotated(&dir, &config)?;
+
+        let data = Bytes::from(&[1, 2, 3, 4][..]);
+        let sha256 = ContentHash::sha256(&data).unwrap_sha256();
+
+        // A blob stored under the wrong oid is rejected.
+        let wrong = ContentHash::sha256(&Bytes::from(&[5, 6][..])).unwrap_sha256();
+        let err = store.add_blob(&wrong, data.clone()).unwrap_err();
+        assert!(err.to_string().contains("LFS blob write rejected"));
+        assert!(!store.blobs.contains(&wrong)?);
+
+        // Happy path: a matching blob and pointer are accepted.
+        store.add_blob(&sha256, data.clone())?;
+        let pointer = LfsPointersEntry::from_file_content(key("a", "2").hgid, &data, None)?;
+        store.add_pointer(pointer)?;
+        assert_eq!(
+            store.get_local_content_direct(&key("a", "2").hgid)?,
+            Some(data.clone())
+        );
+
+        // A pointer whose size disagrees with the stored blob is rejected.
+        let mut pointer = LfsPointersEntry::from_file_content(key("b", "3").hgid, &data, None)?;
+        pointer.size = 42;
+        let err = store.add_pointer(pointer).unwrap_err();
+        assert!(err.to_string().contains("LFS pointer write rejected"));
+        assert_eq!(store.get_local_content_direct(&key("b", "3").hgid)?, None);
+
+        // The checks can be turned off.
+        let dir = TempDir::new()?;
+        let mut config = make_lfs_config(&server, &dir, "test_verify_writes_off");
+        setconfig(&mut config, "lfs", "verify-writes", "false");
+        let store = LfsStore::rotated(&dir, &config)?;
+        store.add_blob(&wrong, data.clone())?;
+        assert!(store.blobs.contains(&wrong)?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_add_get_missing() -> Result<()> {
         let dir = TempDir::new()?;
@@ -2942,6 +3852,224 @@ mod tests {
             Ok(())
         }
 
+        #[test]
+        fn test_lfs_mirror_fallback() -> Result<()> {
+            let _env_lock = crate::env_lock();
+
+            let sentinel = Sentinel::new();
+            let cachedir = TempDir::new()?;
+            let lfsdir = TempDir::new()?;
+
+            // The primary only ever answers 503; everything is served by
+            // the mirror.
+            let mut primary = mockito::Server::new();
+            let m1 = primary
+                .mock("POST", "/repo/objects/batch")
+                .with_status(503)
+                .expect(1)
+                .create();
+
+            let mut mirror = mockito::Server::new();
+            let blob = &example_blob();
+            let m2 = get_lfs_batch_mock(&mut mirror, 200, &[blob]).expect(2);
+            let _m3 = get_lfs_download_mock(&mut mirror, 200, blob);
+
+            let mut config = make_lfs_config(&primary, &cachedir, "test_lfs_mirror_fallback");
+            setconfig(
+                &mut config,
+                "lfs",
+                "mirror-urls",
+                &[mirror.url(), "/repo".to_string()].concat(),
+            );
+            setconfig(&mut config, "lfs", "backofftimes", "");
+
+            let lfs = Arc::new(LfsStore::rotated(&lfsdir, &config)?);
+            let remote = LfsClient::new(lfs, None, &config)?;
+
+            let objs = [(blob.sha, blob.size)]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>();
+            remote.batch_fetch(&objs, sentinel.as_callback(), |_, _| {})?;
+            assert!(sentinel.get());
+
+            // The dead primary was recorded, so the second fetch goes
+            // straight to the mirror without touching it again.
+            remote.batch_fetch(&objs, |_, _| Ok(()), |_, _| {})?;
+            m1.assert();
+            m2.assert();
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_lfs_caching_proxy() -> Result<()> {
+            let _env_lock = crate::env_lock();
+
+            let sentinel = Sentinel::new();
+            let cachedir = TempDir::new()?;
+            let lfsdir = TempDir::new()?;
+
+            // The origin must not be contacted at all; both the batch
+            // request and the download go through the proxy, which serves
+            // the same paths as the origin.
+            let origin = mockito::Server::new();
+
+            let mut proxy = mockito::Server::new();
+            let blob = &example_blob();
+            let m1 = get_lfs_batch_mock(&mut proxy, 200, &[blob]).expect(1);
+            let _m2 = get_lfs_download_mock(&mut proxy, 200, blob);
+
+            let mut config = make_lfs_config(&origin, &cachedir, "test_lfs_caching_proxy");
+            setconfig(&mut config, "lfs", "proxy-url", &proxy.url());
+
+            let lfs = Arc::new(LfsStore::rotated(&lfsdir, &config)?);
+            let remote = LfsClient::new(lfs, None, &config)?;
+
+            let objs = [(blob.sha, blob.size)]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>();
+            remote.batch_fetch(&objs, sentinel.as_callback(), |_, _| {})?;
+            assert!(sentinel.get());
+            m1.assert();
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_lfs_caching_proxy_fallback() -> Result<()> {
+            let _env_lock = crate::env_lock();
+
+            let sentinel = Sentinel::new();
+            let cachedir = TempDir::new()?;
+            let lfsdir = TempDir::new()?;
+
+            // The proxy only ever answers 503; with lfs.proxy-fallback the
+            // fetch is retried against the origin.
+            let mut proxy = mockito::Server::new();
+            let m1 = proxy
+                .mock("POST", "/repo/objects/batch")
+                .with_status(503)
+                .expect(1)
+                .create();
+
+            let mut origin = mockito::Server::new();
+            let blob = &example_blob();
+            let _m2 = get_lfs_batch_mock(&mut origin, 200, &[blob]);
+            let _m3 = get_lfs_download_mock(&mut origin, 200, blob);
+
+            let mut config =
+                make_lfs_config(&origin, &cachedir, "test_lfs_caching_proxy_fallback");
+            setconfig(&mut config, "lfs", "proxy-url", &proxy.url());
+            setconfig(&mut config, "lfs", "proxy-fallback", "true");
+            setconfig(&mut config, "lfs", "backofftimes", "");
+
+            let lfs = Arc::new(LfsStore::rotated(&lfsdir, &config)?);
+            let remote = LfsClient::new(lfs, None, &config)?;
+
+            let objs = [(blob.sha, blob.size)]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>();
+            remote.batch_fetch(&objs, sentinel.as_callback(), |_, _| {})?;
+            assert!(sentinel.get());
+            m1.assert();
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_lfs_redirect_download() -> Result<()> {
+            let _env_lock = crate::env_lock();
+
+            let cachedir = TempDir::new()?;
+            let lfsdir = TempDir::new()?;
+            let mut server = mockito::Server::new();
+            let config = make_lfs_config(&server, &cachedir, "test_lfs_redirect_download");
+
+            let blob = &example_blob();
+            let _m1 = get_lfs_batch_mock(&mut server, 200, &[blob]);
+
+            // The origin server redirects the download to a second (CDN)
+            // server, which must not receive the origin's action headers.
+            let mut cdn = mockito::Server::new();
+            let _m2 = server
+                .mock("GET", format!("/repo/download/{}", blob.oid).as_str())
+                .with_status(302)
+                .with_header("location", &format!("{}/cdn/{}", cdn.url(), blob.oid))
+                .create();
+            let _m3 = cdn
+                .mock("GET", format!("/cdn/{}", blob.oid).as_str())
+                .with_status(200)
+                .with_body(blob.content.as_ref())
+                .with_header("content-type", "application/octet-stream")
+                .create();
+
+            let lfs = Arc::new(LfsStore::rotated(&lfsdir, &config)?);
+            let remote = LfsClient::new(lfs, None, &config)?;
+
+            let objs = [(blob.sha, blob.size)]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>();
+            let fetched = Arc::new(std::sync::Mutex::new(None));
+            let fetched2 = fetched.clone();
+            remote.batch_fetch(
+                &objs,
+                move |_, data| {
+                    fetched2.lock().unwrap().replace(data);
+                    Ok(())
+                },
+                |_, _| {},
+            )?;
+
+            // The content was downloaded from the CDN and hash-verified.
+            let fetched = fetched.lock().unwrap().take().expect("blob not fetched");
+            assert_eq!(fetched, blob.content);
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_lfs_redirect_download_corrupt() -> Result<()> {
+            let _env_lock = crate::env_lock();
+
+            let cachedir = TempDir::new()?;
+            let lfsdir = TempDir::new()?;
+            let mut server = mockito::Server::new();
+            let config = make_lfs_config(&server, &cachedir, "test_lfs_redirect_download_corrupt");
+
+            let blob = &example_blob();
+            let _m1 = get_lfs_batch_mock(&mut server, 200, &[blob]);
+
+            // The CDN serves content that doesn't hash to the requested oid.
+            let mut cdn = mockito::Server::new();
+            let _m2 = server
+                .mock("GET", format!("/repo/download/{}", blob.oid).as_str())
+                .with_status(302)
+                .with_header("location", &format!("{}/cdn/{}", cdn.url(), blob.oid))
+                .create();
+            let _m3 = cdn
+                .mock("GET", format!("/cdn/{}", blob.oid).as_str())
+                .with_status(200)
+                .with_body(b"corrupt")
+                .with_header("content-type", "application/octet-stream")
+                .create();
+
+            let lfs = Arc::new(LfsStore::rotated(&lfsdir, &config)?);
+            let remote = LfsClient::new(lfs, None, &config)?;
+
+            let objs = [(blob.sha, blob.size)]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>();
+            let res = remote.batch_fetch(&objs, |_, _| unreachable!(), |_, _| {});
+            assert!(res.is_err());
+
+            Ok(())
+        }
+
         fn test_download<C>(
             server: &mut mockito::ServerGuard,
             configure: C,
@@ -3203,6 +4331,119 @@ mod tests {
         }
     }
 
+
+    #[test]
+    fn test_get_blob_range_loose() -> Result<()> {
+        let dir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let config = make_lfs_config(&server, &dir, "test_get_blob_range_loose");
+        let store = LfsStore::permanent(&dir, &config)?;
+
+        let k1 = key("a", "2");
+        let delta = Delta {
+            data: Bytes::from(&[1, 2, 3, 4, 5, 6, 7][..]),
+            base: None,
+            key: k1.clone(),
+        };
+
+        store.add(&delta, &Default::default())?;
+        store.flush()?;
+
+        assert_eq!(
+            store.get_blob_range(&k1, 2..5)?,
+            Some((delta.data.slice(2..5), 7))
+        );
+
+        // The range is clamped to the blob size.
+        assert_eq!(
+            store.get_blob_range(&k1, 5..100)?,
+            Some((delta.data.slice(5..7), 7))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_blob_range_chunked() -> Result<()> {
+        let dir = TempDir::new()?;
+        let server = mockito::Server::new();
+        let mut config = make_lfs_config(&server, &dir, "test_get_blob_range_chunked");
+        setconfig(&mut config, "lfs", "blobschunksize", "2");
+        let store = LfsStore::rotated(&dir, &config)?;
+
+        let k1 = key("a", "2");
+        let delta = Delta {
+            data: Bytes::from(&[1, 2, 3, 4, 5, 6, 7][..]),
+            base: None,
+            key: k1.clone(),
+        };
+
+        store.add(&delta, &Default::default())?;
+        store.flush()?;
+
+        // Spanning several chunks.
+        assert_eq!(
+            store.get_blob_range(&k1, 1..6)?,
+            Some((delta.data.slice(1..6), 7))
+        );
+
+        // Fully contained in a single chunk.
+        assert_eq!(
+            store.get_blob_range(&k1, 4..6)?,
+            Some((delta.data.slice(4..6), 7))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_fetch_range_remote() -> Result<()> {
+        let _env_lock = crate::env_lock();
+
+        let cachedir = TempDir::new()?;
+        let lfsdir = TempDir::new()?;
+        let mut server = mockito::Server::new();
+        let config = make_lfs_config(&server, &cachedir, "test_scmstore_fetch_range_remote");
+
+        let blob = example_blob();
+        let _batch_mock = get_lfs_batch_mock(&mut server, 200, &[&blob]);
+        let _download_mock = server
+            .mock("GET", format!("/repo/download/{}", blob.oid).as_str())
+            .match_header("Range", "bytes=1-3")
+            .with_status(206)
+            .with_body(b"ast")
+            .create();
+
+        let lfs = Arc::new(LfsStore::rotated(&lfsdir, &config)?);
+
+        let k1 = key("a", "1");
+        let mut content_hashes = HashMap::new();
+        content_hashes.insert(ContentHashType::Sha256, ContentHash::Sha256(blob.sha.clone()));
+        let pointer = LfsPointersEntry {
+            hgid: k1.hgid.clone(),
+            size: blob.size as u64,
+            is_binary: false,
+            copy_from: None,
+            content_hashes,
+        };
+        lfs.add_pointer(pointer)?;
+
+        let mut store = FileStore::empty();
+        store.lfs_cache = Some(lfs.clone());
+        store.lfs_remote = Some(Arc::new(LfsClient::new(lfs.clone(), None, &config)?));
+        store.lfs_range_requests = true;
+
+        assert_eq!(
+            store.fetch_range(k1, 1, 3)?,
+            Some((Bytes::from(&b"ast"[..]), 6))
+        );
+
+        // Only the requested range was transferred; nothing was cached.
+        assert!(!lfs.blobs.contains(&blob.sha)?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_lfs_remote_file() -> Result<()> {
         let _env_lock = crate::env_lock();
@@ -119,6 +119,7 @@ mod redacted;
 mod remotestore;
 mod repair;
 mod sliceext;
+mod throttle;
 mod types;
 mod unionstore;
 
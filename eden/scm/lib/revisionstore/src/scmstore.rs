@@ -5,14 +5,27 @@
  * GNU General Public License version 2.
  */
 
+pub use self::builder::detect_store_type;
 pub use self::builder::FileStoreBuilder;
+pub use self::builder::ScmStoreBuilder;
+pub use self::builder::StoreKind;
 pub use self::builder::TreeStoreBuilder;
+pub use self::builder::ValidationWarning;
 pub use self::fetch::KeyFetchError;
+pub use self::file::ContentHashFunction;
+pub use self::file::CopyStats;
 pub use self::file::FileAttributes;
 pub use self::file::FileAuxData;
 pub use self::file::FileStore;
+pub use self::file::FileStoreConfigSummary;
+pub use self::file::PrefetchResult;
+pub use self::file::PrefetchSplit;
 pub use self::file::StoreFile;
+pub use self::metrics::FetchOriginSummary;
+pub use self::metrics::FetchSummary;
+pub use self::tree::CacheWriteMode;
 pub use self::tree::TreeStore;
+pub use self::tree::TreeStoreConfigSummary;
 pub use self::util::file_to_async_key_stream;
 
 pub mod activitylogger;
@@ -23,5 +36,6 @@ pub mod tree;
 pub mod util;
 pub mod value;
 
+pub(crate) mod cert_skew;
 pub(crate) mod fetch;
 pub(crate) mod metrics;
@@ -9,9 +9,16 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TrySendError;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use std::time::SystemTime;
 
+use anyhow::anyhow;
 use anyhow::Result;
 use fs_err::File;
 use serde::Deserialize;
@@ -20,27 +27,141 @@ use types::Key;
 
 use super::FileAttributes;
 
+/// Maximum number of records buffered for the background writer.  Records
+/// logged while the channel is full are dropped (and counted) rather than
+/// blocking the fetch hot path.
+const CHANNEL_CAPACITY: usize = 1000;
+
+enum Message {
+    Log(String),
+    Flush(SyncSender<()>),
+}
+
+/// Version written into the `v` field of each record.  Bump this whenever
+/// the schema of a record changes incompatibly.
+pub const ACTIVITY_LOG_VERSION: u64 = 1;
+
 pub(crate) struct ActivityLogger {
-    f: File,
+    sender: Option<SyncSender<Message>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+/// A typed activity log record, serialized as one JSON object per line with
+/// an explicit `v` version field.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op")]
+pub enum ActivityRecord {
+    FileFetch {
+        v: u64,
+        keys: Vec<Key>,
+        attrs: FileAttributes,
+        start_millis: u128,
+        duration_millis: u128,
+    },
+    TreeFetch {
+        v: u64,
+        keys: Vec<Key>,
+        start_millis: u128,
+        duration_millis: u128,
+    },
+    Flush {
+        v: u64,
+        start_millis: u128,
+    },
+    CacheBust {
+        v: u64,
+        reason: String,
+        start_millis: u128,
+    },
+    /// A prefetch call exceeded one of the `scmstore.prefetch-warn-*`
+    /// guardrails.  `bytes` is zero when the key-count limit tripped before
+    /// anything was fetched.
+    PrefetchWarning {
+        v: u64,
+        cause: String,
+        keys: usize,
+        bytes: u64,
+        start_millis: u128,
+    },
+    /// A prefetch call exceeded `scmstore.max-prefetch-size` and was split
+    /// into `chunks` separate fetches.
+    PrefetchSplit {
+        v: u64,
+        cause: String,
+        keys: usize,
+        chunks: usize,
+        max_prefetch_size: usize,
+        start_millis: u128,
+    },
+    /// The rotated cache deleted its oldest generation because the
+    /// configured size limits were exceeded, so previously cached data has
+    /// to be re-fetched.
+    CacheRotation {
+        v: u64,
+        dropped_entries: u64,
+        dropped_bytes: u64,
+        max_log_count: u8,
+        max_bytes_per_log: u64,
+        start_millis: u128,
+    },
+    /// A record written by a newer version of the code.  Yielded by the
+    /// parser instead of erroring so that old readers tolerate new logs.
+    #[serde(skip)]
+    Unknown { v: u64 },
 }
 
+/// Legacy unversioned record format.  Only file fetches were ever written
+/// in this format; reading it is kept behind `ActivityLogParser` for one
+/// release.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct ActivityLog {
-    pub op: ActivityType,
-    pub keys: Vec<Key>,
-    pub attrs: FileAttributes,
-    pub start_millis: u128,
-    pub duration_millis: u128,
+struct LegacyActivityLog {
+    op: LegacyActivityType,
+    keys: Vec<Key>,
+    attrs: FileAttributes,
+    start_millis: u128,
+    duration_millis: u128,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub enum ActivityType {
+enum LegacyActivityType {
     FileFetch,
 }
 
 impl ActivityLogger {
-    pub(crate) fn new(f: File) -> Self {
-        ActivityLogger { f }
+    pub(crate) fn new(f: File, path: PathBuf, max_file_size_bytes: Option<u64>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Message>(CHANNEL_CAPACITY);
+        let writer = std::thread::Builder::new()
+            .name("activity-logger".to_string())
+            .spawn(move || {
+                let mut writer = Writer {
+                    f,
+                    path,
+                    max_file_size_bytes,
+                };
+                while let Ok(message) = receiver.recv() {
+                    match message {
+                        Message::Log(line) => {
+                            if let Err(err) = writer.write_line(&line) {
+                                tracing::error!("Error writing activity log: {}", err);
+                            }
+                        }
+                        Message::Flush(ack) => {
+                            if let Err(err) = writer.f.flush() {
+                                tracing::error!("Error flushing activity log: {}", err);
+                            }
+                            let _ = ack.send(());
+                        }
+                    }
+                }
+                let _ = writer.f.flush();
+            })
+            .ok();
+        ActivityLogger {
+            sender: Some(sender),
+            writer,
+            dropped: AtomicU64::new(0),
+        }
     }
 
     pub(crate) fn log_file_fetch(
@@ -49,27 +170,346 @@ impl ActivityLogger {
         attrs: FileAttributes,
         dur: Duration,
     ) -> Result<()> {
-        serde_json::to_writer(
-            &mut self.f,
-            &ActivityLog {
-                op: ActivityType::FileFetch,
-                keys,
-                attrs,
-                start_millis: (SystemTime::now() - dur)
-                    .duration_since(SystemTime::UNIX_EPOCH)?
-                    .as_millis(),
-                duration_millis: dur.as_millis(),
-            },
-        )?;
+        let line = serde_json::to_string(&ActivityRecord::FileFetch {
+            v: ACTIVITY_LOG_VERSION,
+            keys,
+            attrs,
+            start_millis: (SystemTime::now() - dur)
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis(),
+            duration_millis: dur.as_millis(),
+        })?;
+        if let Some(sender) = &self.sender {
+            match sender.try_send(Message::Log(line)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(anyhow!("activity log writer thread has exited"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn log_prefetch_warning(
+        &mut self,
+        cause: String,
+        keys: usize,
+        bytes: u64,
+    ) -> Result<()> {
+        let line = serde_json::to_string(&ActivityRecord::PrefetchWarning {
+            v: ACTIVITY_LOG_VERSION,
+            cause,
+            keys,
+            bytes,
+            start_millis: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis(),
+        })?;
+        if let Some(sender) = &self.sender {
+            match sender.try_send(Message::Log(line)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(anyhow!("activity log writer thread has exited"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn log_prefetch_split(
+        &mut self,
+        cause: String,
+        keys: usize,
+        chunks: usize,
+        max_prefetch_size: usize,
+    ) -> Result<()> {
+        let line = serde_json::to_string(&ActivityRecord::PrefetchSplit {
+            v: ACTIVITY_LOG_VERSION,
+            cause,
+            keys,
+            chunks,
+            max_prefetch_size,
+            start_millis: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis(),
+        })?;
+        if let Some(sender) = &self.sender {
+            match sender.try_send(Message::Log(line)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(anyhow!("activity log writer thread has exited"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn log_cache_rotation(
+        &mut self,
+        dropped_entries: u64,
+        dropped_bytes: u64,
+        max_log_count: u8,
+        max_bytes_per_log: u64,
+    ) -> Result<()> {
+        let line = serde_json::to_string(&ActivityRecord::CacheRotation {
+            v: ACTIVITY_LOG_VERSION,
+            dropped_entries,
+            dropped_bytes,
+            max_log_count,
+            max_bytes_per_log,
+            start_millis: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis(),
+        })?;
+        if let Some(sender) = &self.sender {
+            match sender.try_send(Message::Log(line)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(anyhow!("activity log writer thread has exited"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for all buffered records to be written out to the log file.
+    pub(crate) fn flush(&self) -> Result<()> {
+        if let Some(sender) = &self.sender {
+            let (ack_sender, ack_receiver) = mpsc::sync_channel(1);
+            if sender.send(Message::Flush(ack_sender)).is_ok() {
+                let _ = ack_receiver.recv();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ActivityLogger {
+    fn drop(&mut self) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            tracing::warn!("{} activity log records dropped", dropped);
+        }
+        // Disconnect the channel so the writer thread drains and exits, then
+        // wait for it so buffered records make it to disk.
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Writer {
+    f: File,
+    path: PathBuf,
+    max_file_size_bytes: Option<u64>,
+}
+
+impl Writer {
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        self.f.write_all(line.as_bytes())?;
         self.f.write_all(&[b'\n'])?;
+        self.maybe_rotate()?;
         Ok(())
     }
+
+    /// Rotate the log file if it has grown beyond the configured size limit.
+    /// The current log is renamed to `<path>.1`, discarding any previous
+    /// rotated log, and a fresh log file is opened.
+    fn maybe_rotate(&mut self) -> Result<()> {
+        if let Some(max_file_size_bytes) = self.max_file_size_bytes {
+            if self.f.metadata()?.len() > max_file_size_bytes {
+                let mut rotated = self.path.clone().into_os_string();
+                rotated.push(".1");
+                fs_err::rename(&self.path, rotated)?;
+                self.f = fs_err::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&self.path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ActivityLogParser;
+
+impl ActivityLogParser {
+    /// Parse activity log lines into typed records.  Legacy unversioned
+    /// records are upgraded to `ActivityRecord::FileFetch` with `v: 0`, and
+    /// records written by a future version of the schema are yielded as
+    /// `ActivityRecord::Unknown` rather than erroring.
+    pub fn parse<R: BufRead>(reader: R) -> impl Iterator<Item = Result<ActivityRecord>> {
+        reader.lines().map(|line| {
+            let line = line?;
+            let value: serde_json::Value = serde_json::from_str(&line)?;
+            match value.get("v").and_then(|v| v.as_u64()) {
+                None => {
+                    let log: LegacyActivityLog = serde_json::from_str(&line)?;
+                    Ok(ActivityRecord::FileFetch {
+                        v: 0,
+                        keys: log.keys,
+                        attrs: log.attrs,
+                        start_millis: log.start_millis,
+                        duration_millis: log.duration_millis,
+                    })
+                }
+                Some(v) if v <= ACTIVITY_LOG_VERSION => match serde_json::from_str(&line) {
+                    Ok(record) => Ok(record),
+                    // An op we don't know about, e.g. one added after this
+                    // version of the schema.
+                    Err(_) => Ok(ActivityRecord::Unknown { v }),
+                },
+                Some(v) => Ok(ActivityRecord::Unknown { v }),
+            }
+        })
+    }
 }
 
-pub fn log_iter<P: Into<PathBuf>>(path: P) -> Result<impl Iterator<Item = Result<ActivityLog>>> {
+pub fn log_iter<P: Into<PathBuf>>(path: P) -> Result<impl Iterator<Item = Result<ActivityRecord>>> {
     let file = File::open(path)?;
-    Ok(BufReader::new(file).lines().map(|line| {
-        let log: ActivityLog = serde_json::from_str(&line?)?;
-        Ok(log)
-    }))
+    Ok(ActivityLogParser::parse(BufReader::new(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use types::testutil::*;
+
+    use super::*;
+
+    #[test]
+    fn test_records_written_before_drop_appear_in_file() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("activitylog");
+        let f = fs_err::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut logger = ActivityLogger::new(f, path.clone(), None);
+        for i in 0..10u64 {
+            logger.log_file_fetch(
+                vec![key("a", &format!("{}", i))],
+                FileAttributes::CONTENT,
+                Duration::from_millis(i),
+            )?;
+        }
+        drop(logger);
+
+        let logs = log_iter(path)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(logs.len(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let records = vec![
+            ActivityRecord::FileFetch {
+                v: ACTIVITY_LOG_VERSION,
+                keys: vec![key("a", "1")],
+                attrs: FileAttributes::CONTENT,
+                start_millis: 1,
+                duration_millis: 2,
+            },
+            ActivityRecord::TreeFetch {
+                v: ACTIVITY_LOG_VERSION,
+                keys: vec![key("b", "2")],
+                start_millis: 3,
+                duration_millis: 4,
+            },
+            ActivityRecord::Flush {
+                v: ACTIVITY_LOG_VERSION,
+                start_millis: 5,
+            },
+            ActivityRecord::CacheBust {
+                v: ACTIVITY_LOG_VERSION,
+                reason: "remount".to_string(),
+                start_millis: 6,
+            },
+            ActivityRecord::PrefetchWarning {
+                v: ACTIVITY_LOG_VERSION,
+                cause: "test".to_string(),
+                keys: 7,
+                bytes: 8,
+                start_millis: 9,
+            },
+            ActivityRecord::PrefetchSplit {
+                v: ACTIVITY_LOG_VERSION,
+                cause: "test".to_string(),
+                keys: 10,
+                chunks: 11,
+                max_prefetch_size: 12,
+                start_millis: 13,
+            },
+            ActivityRecord::CacheRotation {
+                v: ACTIVITY_LOG_VERSION,
+                dropped_entries: 14,
+                dropped_bytes: 15,
+                max_log_count: 16,
+                max_bytes_per_log: 17,
+                start_millis: 18,
+            },
+        ];
+        let lines = records
+            .iter()
+            .map(|record| serde_json::to_string(record))
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n");
+        let parsed = ActivityLogParser::parse(lines.as_bytes()).collect::<Result<Vec<_>>>()?;
+        assert_eq!(parsed.len(), records.len());
+        for (parsed, expected) in parsed.iter().zip(&records) {
+            assert_eq!(
+                serde_json::to_string(parsed)?,
+                serde_json::to_string(expected)?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_legacy_format() -> Result<()> {
+        let line = serde_json::to_string(&LegacyActivityLog {
+            op: LegacyActivityType::FileFetch,
+            keys: vec![key("a", "1")],
+            attrs: FileAttributes::CONTENT,
+            start_millis: 1,
+            duration_millis: 2,
+        })?;
+        let parsed = ActivityLogParser::parse(line.as_bytes()).collect::<Result<Vec<_>>>()?;
+        match &parsed[..] {
+            [ActivityRecord::FileFetch { v: 0, keys, .. }] => {
+                assert_eq!(keys, &vec![key("a", "1")]);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_tolerates_future_versions() -> Result<()> {
+        let lines = r#"{"op":"HyperFetch","v":999,"warp_factor":9}
+{"op":"BrandNewOp","v":1}"#;
+        let parsed = ActivityLogParser::parse(lines.as_bytes()).collect::<Result<Vec<_>>>()?;
+        match &parsed[..] {
+            [
+                ActivityRecord::Unknown { v: 999 },
+                ActivityRecord::Unknown { v: 1 },
+            ] => {}
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+        Ok(())
+    }
 }
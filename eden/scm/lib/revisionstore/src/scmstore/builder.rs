@@ -6,20 +6,32 @@
  */
 
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+use anyhow::bail;
 use anyhow::Result;
+use async_runtime::block_on;
 use cas_client::CasClient;
 use configmodel::convert::ByteCount;
 use configmodel::Config;
 use configmodel::ConfigExt;
 use edenapi::Builder;
 use fn_error_context::context;
+use hg_http::http_config;
 use hgtime::HgTime;
 use parking_lot::Mutex;
 use progress_model::AggregatingProgressBar;
+use storemodel::ReadRootTreeIds;
+use thiserror::Error;
+use url::Url;
+use util::lock::PathLock;
 
 use crate::indexedlogauxstore::AuxStore;
 use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
@@ -29,12 +41,19 @@ use crate::indexedlogutil::StoreType;
 use crate::lfs::LfsClient;
 use crate::lfs::LfsStore;
 use crate::scmstore::activitylogger::ActivityLogger;
+use crate::scmstore::file::ContentHashFunction;
 use crate::scmstore::file::FileStoreMetrics;
+use crate::scmstore::file::PrefetchLimits;
+use crate::scmstore::file::DEFAULT_CONCURRENT_CACHE_WRITERS;
+use crate::scmstore::tree::CacheWriteMode;
 use crate::scmstore::tree::TreeMetadataMode;
 use crate::scmstore::FileStore;
 use crate::scmstore::TreeStore;
+use crate::throttle::NetworkThrottle;
 use crate::util::check_run_once;
+use crate::util::check_run_once_marker;
 use crate::util::get_cache_path;
+use crate::util::get_cache_path_for_repo;
 use crate::util::get_indexedlogdatastore_aux_path;
 use crate::util::get_indexedlogdatastore_path;
 use crate::util::get_indexedloghistorystore_path;
@@ -50,7 +69,12 @@ pub struct FileStoreBuilder<'a> {
     config: &'a dyn Config,
     local_path: Option<PathBuf>,
     suffix: Option<PathBuf>,
+    repo_name: Option<String>,
     override_edenapi: Option<bool>,
+    edenapi_timeout: Option<Duration>,
+    concurrent_cache_writers: Option<usize>,
+    network_throttle: Option<u64>,
+    no_lfs_remote: bool,
 
     indexedlog_local: Option<Arc<IndexedLogHgIdDataStore>>,
     indexedlog_cache: Option<Arc<IndexedLogHgIdDataStore>>,
@@ -59,6 +83,9 @@ pub struct FileStoreBuilder<'a> {
 
     edenapi: Option<Arc<SaplingRemoteApiFileStore>>,
     cas_client: Option<Arc<dyn CasClient>>,
+    hash_function: Option<ContentHashFunction>,
+    #[cfg(debug_assertions)]
+    request_dumper_path: Option<PathBuf>,
 }
 
 impl<'a> FileStoreBuilder<'a> {
@@ -67,13 +94,21 @@ impl<'a> FileStoreBuilder<'a> {
             config,
             local_path: None,
             suffix: None,
+            repo_name: None,
             override_edenapi: None,
+            edenapi_timeout: None,
+            concurrent_cache_writers: None,
+            network_throttle: None,
+            no_lfs_remote: false,
             indexedlog_local: None,
             indexedlog_cache: None,
             lfs_local: None,
             lfs_cache: None,
             edenapi: None,
             cas_client: None,
+            hash_function: None,
+            #[cfg(debug_assertions)]
+            request_dumper_path: None,
         }
     }
 
@@ -87,6 +122,14 @@ impl<'a> FileStoreBuilder<'a> {
         self
     }
 
+    /// Override the repo name used to construct the shared cache path,
+    /// instead of deriving it from `remotefilelog.reponame`. Useful when one
+    /// config is shared across multiple repos.
+    pub fn with_repo_name(mut self, name: impl Into<String>) -> Self {
+        self.repo_name = Some(name.into());
+        self
+    }
+
     pub fn override_edenapi(mut self, use_edenapi: bool) -> Self {
         self.override_edenapi = Some(use_edenapi);
         self
@@ -97,11 +140,61 @@ impl<'a> FileStoreBuilder<'a> {
         self
     }
 
+    /// Override the HTTP client timeout for edenapi requests, e.g. so that
+    /// integration tests can use a very short timeout to exercise timeout
+    /// handling.
+    pub fn edenapi_timeout(mut self, timeout: Duration) -> Self {
+        self.edenapi_timeout = Some(timeout);
+        self
+    }
+
+    /// Use `n` concurrent tasks to drain the remote fetch stream and write
+    /// results back to `indexedlog_cache` and `aux_cache` in parallel.
+    pub fn with_concurrent_cache_writers(mut self, n: usize) -> Self {
+        self.concurrent_cache_writers = Some(n);
+        self
+    }
+
+    /// Cap SaplingRemoteAPI and LFS download bandwidth at `bytes_per_sec`,
+    /// overriding `scmstore.max-download-bytes-per-sec`. 0 disables
+    /// throttling.
+    pub fn with_network_throttle(mut self, bytes_per_sec: u64) -> Self {
+        self.network_throttle = Some(bytes_per_sec);
+        self
+    }
+
+    /// Keep the local LFS stores but do not construct an LFS remote, e.g.
+    /// for air-gapped environments where the cache is pre-seeded. Fetches
+    /// and uploads that would need the remote fail with an "LFS remote
+    /// disabled" error. Equivalent to setting `scmstore.lfs-remote=false`.
+    pub fn no_lfs_remote(mut self) -> Self {
+        self.no_lfs_remote = true;
+        self
+    }
+
+    /// Override how content IDs are computed for local writes, so tests can
+    /// store entries under deterministic `HgId`s without hashing content.
+    /// Remote fetches are unaffected and always carry real hashes. Production
+    /// behavior is unchanged when this is not set.
+    pub fn with_custom_hash_function(mut self, hash_function: ContentHashFunction) -> Self {
+        self.hash_function = Some(hash_function);
+        self
+    }
+
     pub fn cas_client(mut self, cas_client: Arc<dyn CasClient>) -> Self {
         self.cas_client = Some(cas_client);
         self
     }
 
+    /// Serialize every `fetch()` call's key list to a newline-delimited JSON
+    /// file at `path`, for later replay with `FileStore::replay_from_dump`.
+    /// Debug builds only, to keep the overhead out of production.
+    #[cfg(debug_assertions)]
+    pub fn with_request_dumper(mut self, path: PathBuf) -> Self {
+        self.request_dumper_path = Some(path);
+        self
+    }
+
     pub fn indexedlog_cache(mut self, indexedlog: Arc<IndexedLogHgIdDataStore>) -> Self {
         self.indexedlog_cache = Some(indexedlog);
         self
@@ -122,6 +215,19 @@ impl<'a> FileStoreBuilder<'a> {
         self
     }
 
+    /// Seed this builder from an existing `FileStore` so that the built store
+    /// shares the underlying cache and remote resources, e.g. for a secondary
+    /// checkout of the same repo. Only `indexedlog_local` is left unset, so
+    /// `build()` constructs a new instance at this builder's local path.
+    pub fn inherit_from(mut self, other: &FileStore) -> Self {
+        self.indexedlog_cache = other.indexedlog_cache.clone();
+        self.lfs_local = other.lfs_local.clone();
+        self.lfs_cache = other.lfs_cache.clone();
+        self.edenapi = other.edenapi.clone();
+        self.cas_client = other.cas_client.clone();
+        self
+    }
+
     fn get_extstored_policy(&self) -> Result<ExtStoredPolicy> {
         // This is to keep compatibility w/ the Python lfs extension.
         // Contentstore would "upgrade" Python LFS pointers from the pack store
@@ -149,8 +255,11 @@ impl<'a> FileStoreBuilder<'a> {
             .unwrap_or_default()
     }
 
+    /// Whether `build` will set up a SaplingRemoteApi store. This only
+    /// inspects the builder and config, so it can be called upfront without
+    /// paying the cost of building the store.
     #[context("unable to determine whether use edenapi")]
-    fn use_edenapi(&self) -> Result<bool> {
+    pub fn try_use_edenapi(&self) -> Result<bool> {
         Ok(if let Some(use_edenapi) = self.override_edenapi {
             use_edenapi
         } else {
@@ -163,9 +272,21 @@ impl<'a> FileStoreBuilder<'a> {
         Ok(self.get_lfs_threshold()?.is_some())
     }
 
+    #[context("unable to determine whether to use the lfs remote")]
+    fn use_lfs_remote(&self) -> Result<bool> {
+        if self.no_lfs_remote {
+            return Ok(false);
+        }
+        Ok(self.config.get_or("scmstore", "lfs-remote", || true)?)
+    }
+
     #[context("unable to build edenapi")]
     fn build_edenapi(&self) -> Result<Arc<SaplingRemoteApiFileStore>> {
-        let client = Builder::from_config(self.config)?.build()?;
+        let mut builder = Builder::from_config(self.config)?;
+        if let Some(timeout) = self.edenapi_timeout {
+            builder = builder.timeout(timeout);
+        }
+        let client = builder.build()?;
 
         Ok(SaplingRemoteApiFileStore::new(client))
     }
@@ -193,7 +314,11 @@ impl<'a> FileStoreBuilder<'a> {
 
     #[context("failed to build indexedlog cache")]
     pub fn build_indexedlog_cache(&self) -> Result<Option<Arc<IndexedLogHgIdDataStore>>> {
-        let cache_path = match get_cache_path(self.config, &self.suffix)? {
+        let cache_path = match get_cache_path_for_repo(
+            self.config,
+            &self.suffix,
+            self.repo_name.as_deref(),
+        )? {
             Some(p) => p,
             None => return Ok(None),
         };
@@ -223,7 +348,11 @@ impl<'a> FileStoreBuilder<'a> {
 
     #[context("failed to build aux cache")]
     pub fn build_aux_cache(&self) -> Result<Option<Arc<AuxStore>>> {
-        let cache_path = match get_cache_path(self.config, &self.suffix)? {
+        let cache_path = match get_cache_path_for_repo(
+            self.config,
+            &self.suffix,
+            self.repo_name.as_deref(),
+        )? {
             Some(p) => p,
             None => return Ok(None),
         };
@@ -246,6 +375,7 @@ impl<'a> FileStoreBuilder<'a> {
             let local_path = get_local_path(local_path, &self.suffix)?;
             Some(Arc::new(LfsStore::permanent(local_path, self.config)?))
         } else {
+            tracing::warn!("LFS is enabled but no local path is configured; LFS file writes will fail");
             None
         })
     }
@@ -256,7 +386,11 @@ impl<'a> FileStoreBuilder<'a> {
             return Ok(None);
         }
 
-        let cache_path = match get_cache_path(self.config, &self.suffix)? {
+        let cache_path = match get_cache_path_for_repo(
+            self.config,
+            &self.suffix,
+            self.repo_name.as_deref(),
+        )? {
             Some(p) => p,
             None => return Ok(None),
         };
@@ -267,9 +401,18 @@ impl<'a> FileStoreBuilder<'a> {
     #[context("failed to build config revisionstore")]
     pub fn build(mut self) -> Result<FileStore> {
         tracing::trace!(target: "revisionstore::filestore", "checking cache");
-        if let Some(cache_path) = get_cache_path(self.config, &self.suffix)? {
-            check_cache_buster(&self.config, &cache_path);
+        let cache_path =
+            get_cache_path_for_repo(self.config, &self.suffix, self.repo_name.as_deref())?;
+        // Held until every cache store (data, aux, LFS) is open, so
+        // concurrent first-runs take turns instead of corrupting each other.
+        let _cache_init_lock = match cache_path.as_deref() {
+            Some(cache_path) => lock_cache_init(self.config, cache_path)?,
+            None => None,
+        };
+        if let Some(cache_path) = &cache_path {
+            check_cache_buster(&self.config, cache_path, FILE_CACHE_BUSTER_SCOPES);
         }
+        let local_path = self.local_path.clone();
 
         tracing::trace!(target: "revisionstore::filestore", "processing extstored policy");
         let extstored_policy = self.get_extstored_policy()?;
@@ -310,16 +453,24 @@ impl<'a> FileStoreBuilder<'a> {
         tracing::trace!(target: "revisionstore::filestore", "processing aux data");
         let aux_cache = self.build_aux_cache()?;
 
+        // Builder override wins over scmstore.max-download-bytes-per-sec.
+        let network_throttle = match self.network_throttle {
+            Some(bytes_per_sec) => NetworkThrottle::new(bytes_per_sec),
+            None => NetworkThrottle::from_config(self.config)?,
+        };
+
         tracing::trace!(target: "revisionstore::filestore", "processing lfs remote");
-        let lfs_remote = if self.use_lfs()? {
+        let lfs_remote_disabled = self.use_lfs()? && !self.use_lfs_remote()?;
+        let lfs_remote = if self.use_lfs()? && !lfs_remote_disabled {
             if let Some(ref lfs_cache) = lfs_cache {
                 // TODO(meyer): Refactor upload functionality so we don't need to use LfsRemote with it's own references to the
                 // underlying stores.
-                Some(Arc::new(LfsClient::new(
-                    lfs_cache.clone(),
-                    lfs_local.clone(),
-                    self.config,
-                )?))
+                let mut lfs_client =
+                    LfsClient::new(lfs_cache.clone(), lfs_local.clone(), self.config)?;
+                if self.network_throttle.is_some() {
+                    lfs_client.set_network_throttle(network_throttle.clone());
+                }
+                Some(Arc::new(lfs_client))
             } else {
                 None
             }
@@ -328,7 +479,7 @@ impl<'a> FileStoreBuilder<'a> {
         };
 
         tracing::trace!(target: "revisionstore::filestore", "processing edenapi");
-        let edenapi = if self.use_edenapi()? {
+        let edenapi = if self.try_use_edenapi()? {
             if let Some(edenapi) = self.edenapi.take() {
                 Some(edenapi)
             } else {
@@ -357,8 +508,15 @@ impl<'a> FileStoreBuilder<'a> {
                 let f = fs_err::OpenOptions::new()
                     .append(true)
                     .create(true)
-                    .open(path)?;
-                Some(Arc::new(Mutex::new(ActivityLogger::new(f))))
+                    .open(&path)?;
+                let max_file_size_bytes = self
+                    .config
+                    .get_opt::<u64>("scmstore", "activitylog-max-size")?;
+                Some(Arc::new(Mutex::new(ActivityLogger::new(
+                    f,
+                    path.into(),
+                    max_file_size_bytes,
+                ))))
             } else {
                 None
             };
@@ -370,6 +528,42 @@ impl<'a> FileStoreBuilder<'a> {
             .config
             .get_or("scmstore", "max-prefetch-size", || 100_000)?;
 
+        let resolve_lfs_pointers = self
+            .config
+            .get_or("scmstore", "resolve-lfs-pointers", || true)?;
+
+        let lfs_range_requests = self
+            .config
+            .get_or("lfs", "allow-range-requests", || false)?;
+
+        let local_lookup_threads =
+            match self.config.get_opt::<usize>("scmstore", "local-lookup-threads")? {
+                Some(threads) => threads.max(1),
+                None => std::thread::available_parallelism().map_or(1, |n| n.get().min(8)),
+            };
+
+        let batch_size = self.config.get_opt::<usize>("scmstore", "file-batch-size")?;
+
+        let warm_on_fetch_miss = self.config.get_or_default("scmstore", "warm-on-miss")?;
+
+        // Resolved eagerly so TLS failures can be explained without adding
+        // a round trip to the fetch path.
+        let client_cert_path =
+            client_cert_path(self.config, edenapi.as_ref().and_then(|e| e.url()));
+
+        let prefetch_limits = PrefetchLimits {
+            warn_keys: self.config.get_opt("scmstore", "prefetch-warn-keys")?,
+            warn_bytes: self
+                .config
+                .get_opt::<ByteCount>("scmstore", "prefetch-warn-bytes")?
+                .map(|bytes| bytes.value()),
+            max_keys: self.config.get_opt("scmstore", "prefetch-max-keys")?,
+            max_bytes: self
+                .config
+                .get_opt::<ByteCount>("scmstore", "prefetch-max-bytes")?
+                .map(|bytes| bytes.value()),
+        };
+
         let cas_client = if self.config.get_or_default("scmstore", "fetch-from-cas")?
             || self
                 .config
@@ -381,16 +575,40 @@ impl<'a> FileStoreBuilder<'a> {
             None
         };
 
+        #[cfg(debug_assertions)]
+        let request_dumper = match &self.request_dumper_path {
+            Some(path) => {
+                let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+                Some(Arc::new(Mutex::new(file)))
+            }
+            None => None,
+        };
+
         tracing::trace!(target: "revisionstore::filestore", "constructing FileStore");
         Ok(FileStore {
             extstored_policy,
             lfs_threshold_bytes,
             edenapi_retries,
             allow_write_lfs_ptrs,
+            hash_function: self.hash_function,
 
             prefetch_aux_data,
             compute_aux_data,
             max_prefetch_size,
+            prefetch_limits,
+            resolve_lfs_pointers,
+            lfs_range_requests,
+            lfs_remote_disabled,
+            concurrent_cache_writers: self
+                .concurrent_cache_writers
+                .unwrap_or(DEFAULT_CONCURRENT_CACHE_WRITERS),
+            local_lookup_threads,
+            batch_size,
+            warm_on_fetch_miss,
+            client_cert_path,
+            network_throttle,
+            local_path,
+            cache_path,
 
             indexedlog_local,
             lfs_local,
@@ -404,13 +622,67 @@ impl<'a> FileStoreBuilder<'a> {
 
             activity_logger,
             metrics: FileStoreMetrics::new(),
+            #[cfg(debug_assertions)]
+            request_dumper,
+            pending_writes: Default::default(),
 
             aux_cache,
 
             lfs_progress: AggregatingProgressBar::new("fetching", "LFS"),
+            edenapi_progress: AggregatingProgressBar::new("downloading", "files"),
             flush_on_drop: true,
         })
     }
+
+    /// Like `build`, but also report non-fatal configuration issues. The
+    /// returned store is fully usable when warnings are present; the
+    /// warnings explain setups that are likely to perform poorly or fail
+    /// later (e.g. every fetch going to the server because there is no
+    /// cache path).
+    pub fn build_with_validation(self) -> Result<(FileStore, Vec<ValidationWarning>)> {
+        let mut warnings = Vec::new();
+
+        if get_cache_path_for_repo(self.config, &self.suffix, self.repo_name.as_deref())?.is_none()
+        {
+            warnings.push(ValidationWarning::NoCachePath);
+        }
+
+        if self.use_lfs()? && self.config.get_opt::<String>("lfs", "url")?.is_none() {
+            warnings.push(ValidationWarning::LfsUrlNotSet);
+        }
+
+        let store = self.build()?;
+
+        if let Some(url) = store.edenapi.as_ref().and_then(|e| e.url()) {
+            if let Ok(url) = Url::parse(&url) {
+                if url.scheme() != "https" {
+                    warnings.push(ValidationWarning::EdenApiUrlNotHttps(url.into()));
+                }
+            }
+        }
+
+        Ok((store, warnings))
+    }
+}
+
+/// A non-fatal issue found by [`FileStoreBuilder::build_with_validation`].
+/// Each variant describes a configuration that works but is probably not
+/// what the user intended.
+#[derive(Clone, Debug, Eq, PartialEq, Error)]
+pub enum ValidationWarning {
+    /// No shared cache path is configured, so nothing fetched from the
+    /// server is kept across commands.
+    #[error("no shared cache path is configured")]
+    NoCachePath,
+
+    /// The SaplingRemoteApi URL uses a scheme other than https.
+    #[error("SaplingRemoteApi URL {0} does not use https")]
+    EdenApiUrlNotHttps(String),
+
+    /// LFS is enabled via `lfs.threshold` but `lfs.url` is not set, so
+    /// LFS pointers cannot be resolved remotely.
+    #[error("lfs.threshold is set but lfs.url is not")]
+    LfsUrlNotSet,
 }
 
 pub struct TreeStoreBuilder<'a> {
@@ -424,7 +696,10 @@ pub struct TreeStoreBuilder<'a> {
     edenapi: Option<Arc<SaplingRemoteApiTreeStore>>,
     tree_aux_store: Option<Arc<TreeAuxStore>>,
     filestore: Option<Arc<FileStore>>,
+    commit_store: Option<Arc<dyn ReadRootTreeIds + Send + Sync>>,
     cas_client: Option<Arc<dyn CasClient>>,
+    progress_bar: Option<Arc<AggregatingProgressBar>>,
+    cache_write_mode: Option<CacheWriteMode>,
 }
 
 impl<'a> TreeStoreBuilder<'a> {
@@ -439,7 +714,10 @@ impl<'a> TreeStoreBuilder<'a> {
             edenapi: None,
             tree_aux_store: None,
             filestore: None,
+            commit_store: None,
             cas_client: None,
+            progress_bar: None,
+            cache_write_mode: None,
         }
     }
 
@@ -493,6 +771,27 @@ impl<'a> TreeStoreBuilder<'a> {
         self
     }
 
+    /// Commit store used to resolve commit ids to root tree ids, enabling
+    /// `TreeStore::get_root_tree`.
+    pub fn commit_store(mut self, commit_store: Arc<dyn ReadRootTreeIds + Send + Sync>) -> Self {
+        self.commit_store = Some(commit_store);
+        self
+    }
+
+    /// Use `bar` for tree download progress instead of a store-private bar,
+    /// so multiple stores can aggregate into a single user-visible bar.
+    pub fn with_progress_bar(mut self, bar: Arc<AggregatingProgressBar>) -> Self {
+        self.progress_bar = Some(bar);
+        self
+    }
+
+    /// Control how remotely fetched trees are written back to the caches.
+    /// Defaults to `CacheWriteMode::Immediate`.
+    pub fn cache_write_mode(mut self, mode: CacheWriteMode) -> Self {
+        self.cache_write_mode = Some(mode);
+        self
+    }
+
     #[context("failed to determine whether to use edenapi")]
     fn use_edenapi(&self) -> Result<bool> {
         Ok(if let Some(use_edenapi) = self.override_edenapi {
@@ -616,9 +915,17 @@ impl<'a> TreeStoreBuilder<'a> {
         // TODO(meyer): Clean this up, just copied and pasted from the other version & did some ugly hacks to get this
         // (the SaplingRemoteApiAdapter stuff needs to be fixed in particular)
         tracing::trace!(target: "revisionstore::treestore", "checking cache");
-        if let Some(cache_path) = get_cache_path(self.config, &self.suffix)? {
-            check_cache_buster(&self.config, &cache_path);
+        let cache_path = get_cache_path(self.config, &self.suffix)?;
+        // Held until every cache store is open, so concurrent first-runs
+        // take turns instead of corrupting each other.
+        let _cache_init_lock = match cache_path.as_deref() {
+            Some(cache_path) => lock_cache_init(self.config, cache_path)?,
+            None => None,
+        };
+        if let Some(cache_path) = &cache_path {
+            check_cache_buster(&self.config, cache_path, TREE_CACHE_BUSTER_SCOPES);
         }
+        let local_path = self.local_path.clone();
 
         tracing::trace!(target: "revisionstore::treestore", "processing local");
         let indexedlog_local = if let Some(indexedlog_local) = self.indexedlog_local.take() {
@@ -676,17 +983,77 @@ impl<'a> TreeStoreBuilder<'a> {
             .config
             .get_or_default("scmstore", "prefetch-tree-parents")?;
 
-        let tree_metadata_mode = match self.config.get("scmstore", "tree-metadata-mode").as_deref()
-        {
-            Some("always") => TreeMetadataMode::Always,
-            None | Some("opt-in") => TreeMetadataMode::OptIn,
-            _ => TreeMetadataMode::Never,
-        };
+        let verify_writes = self.config.get_or_default("scmstore", "verify-writes")?;
+
+        let mut tree_metadata_mode =
+            match self.config.get("scmstore", "tree-metadata-mode").as_deref() {
+                Some("always") => TreeMetadataMode::Always,
+                None | Some("opt-in") => TreeMetadataMode::OptIn,
+                _ => TreeMetadataMode::Never,
+            };
 
-        let fetch_tree_aux_data = self
+        let mut fetch_tree_aux_data = self
             .config
             .get_or_default::<bool>("scmstore", "fetch-tree-aux-data")?;
 
+        // Older servers reject requests for augmented trees or child
+        // metadata outright, so check what this server advertises before
+        // asking for them. scmstore.assume-server-capabilities skips the
+        // query and forces either behavior.
+        if let Some(edenapi) = &edenapi {
+            let needs_capabilities =
+                fetch_tree_aux_data || matches!(tree_metadata_mode, TreeMetadataMode::Always);
+
+            let capabilities = match self
+                .config
+                .get_opt::<bool>("scmstore", "assume-server-capabilities")?
+            {
+                Some(true) => None,
+                Some(false) => Some(vec![]),
+                None if needs_capabilities => {
+                    let max_age = self
+                        .config
+                        .get_opt::<Duration>("scmstore", "capabilities-max-age")?
+                        .unwrap_or(DEFAULT_CAPABILITIES_MAX_AGE);
+                    server_capabilities(edenapi, cache_path.as_deref(), max_age)
+                }
+                None => None,
+            };
+
+            if let Some(capabilities) = capabilities {
+                let has = |name: &str| capabilities.iter().any(|cap| cap == name);
+
+                if fetch_tree_aux_data && !has(CAP_AUGMENTED_TREES) {
+                    tracing::warn!(
+                        "server does not advertise {}; not fetching tree aux data",
+                        CAP_AUGMENTED_TREES
+                    );
+                    fetch_tree_aux_data = false;
+                }
+                if !matches!(tree_metadata_mode, TreeMetadataMode::Never)
+                    && !has(CAP_TREES_CHILD_METADATA)
+                {
+                    tracing::warn!(
+                        "server does not advertise {}; not fetching children metadata",
+                        CAP_TREES_CHILD_METADATA
+                    );
+                    tree_metadata_mode = TreeMetadataMode::Never;
+                }
+            }
+        }
+
+        let batch_size = self.config.get_opt::<usize>("scmstore", "tree-batch-size")?;
+
+        let edenapi_retries = self
+            .config
+            .get_or_default::<i32>("scmstore", "retries")
+            .unwrap_or_default();
+
+        // Resolved eagerly so TLS failures can be explained without adding
+        // a round trip to the fetch path.
+        let client_cert_path =
+            client_cert_path(self.config, edenapi.as_ref().and_then(|e| e.url()));
+
         if fetch_tree_aux_data && tree_aux_store.is_none() {
             tracing::warn!(
                 "fetch-tree-aux-data is set, but store-tree-aux-data is not set resulting in no tree aux data locally cached"
@@ -718,21 +1085,112 @@ impl<'a> TreeStoreBuilder<'a> {
             indexedlog_local,
             indexedlog_cache,
             cache_to_local_cache: true,
+            cache_write_mode: self
+                .cache_write_mode
+                .take()
+                .unwrap_or(CacheWriteMode::Immediate),
             edenapi,
             cas_client,
             tree_aux_store,
             historystore_local,
             historystore_cache,
             prefetch_tree_parents,
+            verify_writes,
             filestore: self.filestore,
+            commit_store: self.commit_store,
             tree_metadata_mode,
             fetch_tree_aux_data,
+            batch_size,
+            edenapi_retries,
+            client_cert_path,
             flush_on_drop: true,
             metrics: Default::default(),
+            edenapi_progress: self
+                .progress_bar
+                .unwrap_or_else(|| AggregatingProgressBar::new("downloading", "trees")),
+            written_trees: Default::default(),
+            local_path,
+            cache_path,
         })
     }
 }
 
+/// Builder that constructs a `FileStore` and a `TreeStore` together, sharing
+/// the underlying edenapi client and wiring the file store into the tree
+/// store for aux data write-through.  Callers that only need one of the two
+/// stores should keep using the individual builders.
+pub struct ScmStoreBuilder<'a> {
+    config: &'a dyn Config,
+    local_path: Option<PathBuf>,
+    suffix: Option<PathBuf>,
+}
+
+impl<'a> ScmStoreBuilder<'a> {
+    pub fn new(config: &'a dyn Config) -> Self {
+        Self {
+            config,
+            local_path: None,
+            suffix: None,
+        }
+    }
+
+    pub fn local_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.local_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Cache path suffix for the tree store's indexedlog.  Defaults to
+    /// "manifests".
+    pub fn suffix(mut self, suffix: impl AsRef<Path>) -> Self {
+        self.suffix = Some(suffix.as_ref().to_path_buf());
+        self
+    }
+
+    #[context("failed to build combined scmstore")]
+    pub fn build(self) -> Result<(Arc<FileStore>, Arc<TreeStore>)> {
+        let mut file_builder = FileStoreBuilder::new(self.config);
+        if let Some(local_path) = &self.local_path {
+            file_builder = file_builder.local_path(local_path);
+        }
+        let file_store = Arc::new(file_builder.build()?);
+
+        let mut tree_builder = TreeStoreBuilder::new(self.config)
+            .suffix(self.suffix.unwrap_or_else(|| PathBuf::from("manifests")))
+            .filestore(file_store.clone());
+        if let Some(local_path) = &self.local_path {
+            tree_builder = tree_builder.local_path(local_path);
+        }
+        // Share the edenapi HTTP client rather than constructing a second
+        // one from config.
+        if let Some(edenapi) = &file_store.edenapi {
+            tree_builder = tree_builder.edenapi(SaplingRemoteApiTreeStore::new(edenapi.client()));
+        }
+        let tree_store = Arc::new(tree_builder.build()?);
+
+        Ok((file_store, tree_store))
+    }
+}
+
+/// Which store implementation should be used for this repo.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StoreKind {
+    ScmStore,
+    ContentStore,
+}
+
+/// The authoritative decision point for whether to use the scmstore
+/// implementation (`FileStore`/`TreeStore`) or the legacy `ContentStore`.
+/// Callers should use this to decide which builder to invoke rather than
+/// duplicating config checks.
+#[context("failed to detect store type via config")]
+pub fn detect_store_type(config: &dyn Config) -> Result<StoreKind> {
+    if config.get_or::<bool>("scmstore", "enabled", || true)? {
+        Ok(StoreKind::ScmStore)
+    } else {
+        Ok(StoreKind::ContentStore)
+    }
+}
+
 #[context("failed to get edenapi via config")]
 fn use_edenapi_via_config(config: &dyn Config) -> Result<bool> {
     let mut use_edenapi: bool = config.get_or_default("remotefilelog", "http")?;
@@ -747,10 +1205,224 @@ fn use_edenapi_via_config(config: &dyn Config) -> Result<bool> {
     Ok(use_edenapi)
 }
 
+/// Store families under the repo-level cache path that can be invalidated
+/// independently by a scoped cache buster, keyed by scope name.
+pub const FILE_CACHE_BUSTER_SCOPES: &[(&str, &[&str])] = &[
+    ("data", &["indexedlogdatastore", "indexedloghistorystore"]),
+    ("aux", &["indexedlogdatastore_aux"]),
+    ("lfs", &["lfs"]),
+];
+
+/// Store families under the tree cache path (the `manifests` suffix
+/// directory) that can be invalidated by a scoped cache buster.
+pub const TREE_CACHE_BUSTER_SCOPES: &[(&str, &[&str])] = &[(
+    "trees",
+    &[
+        "indexedlogdatastore",
+        "indexedlogdatastore_aux",
+        "indexedloghistorystore",
+        "treeaux",
+    ],
+)];
+
+/// Name of the file under the cache path recording the server's advertised
+/// capabilities and when they were fetched.
+const CAPABILITIES_FILENAME: &str = "edenapi_capabilities";
+
+/// Capability advertised by servers that can serve augmented (aux-data
+/// carrying) trees.
+const CAP_AUGMENTED_TREES: &str = "sapling-augmented-trees";
+
+/// Capability advertised by servers that can attach children metadata to
+/// tree responses.
+const CAP_TREES_CHILD_METADATA: &str = "sapling-trees-child-metadata";
+
+/// How long cached capabilities stay valid before they are refetched.
+/// Configured by scmstore.capabilities-max-age.
+const DEFAULT_CAPABILITIES_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Capabilities cached by a previous run, if they are fresher than `max_age`.
+fn load_cached_capabilities(store_path: &Path, max_age: Duration) -> Option<Vec<String>> {
+    let content = fs::read_to_string(store_path.join(CAPABILITIES_FILENAME)).ok()?;
+    let mut lines = content.lines();
+    let written: u64 = lines.next()?.trim().parse().ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    if now.saturating_sub(written) > max_age.as_secs() {
+        return None;
+    }
+    Some(lines.map(|line| line.to_string()).collect())
+}
+
+/// Record the server's capabilities under the cache path for later runs.
+fn store_cached_capabilities(store_path: &Path, capabilities: &[String]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut content = now.to_string();
+    for capability in capabilities {
+        content.push('\n');
+        content.push_str(capability);
+    }
+    let _ = fs::write(store_path.join(CAPABILITIES_FILENAME), content);
+}
+
+/// The capabilities the server advertises, from the on-disk cache when fresh
+/// enough, otherwise from the server itself. Returns `None` when the server
+/// can't be queried, in which case the configured behavior is kept.
+fn server_capabilities(
+    edenapi: &SaplingRemoteApiTreeStore,
+    cache_path: Option<&Path>,
+    max_age: Duration,
+) -> Option<Vec<String>> {
+    if let Some(cache_path) = cache_path {
+        if let Some(capabilities) = load_cached_capabilities(cache_path, max_age) {
+            return Some(capabilities);
+        }
+    }
+
+    match block_on(edenapi.client().capabilities()) {
+        Ok(capabilities) => {
+            if let Some(cache_path) = cache_path {
+                store_cached_capabilities(cache_path, &capabilities);
+            }
+            Some(capabilities)
+        }
+        Err(err) => {
+            tracing::debug!(?err, "error fetching server capabilities");
+            None
+        }
+    }
+}
+
+/// Look up the client certificate configured for `url`, if any. Only used
+/// to produce better error messages when a TLS fetch fails.
+fn client_cert_path(config: &dyn Config, url: Option<String>) -> Option<PathBuf> {
+    let url = Url::parse(&url?).ok()?;
+    http_config(config, &url).ok()?.cert_path
+}
+
+/// Name of the lock file taken under the cache root while a builder opens
+/// the stores inside it.
+const CACHE_INIT_LOCK_FILENAME: &str = "init.lock";
+
+/// Serialize cache initialization against concurrent builders. Two commands
+/// racing to populate a brand-new cache directory can collide in
+/// `check_cache_buster` and indexedlog creation, leaving a half-initialized
+/// cache that later runs refuse to open. Holding this lock while the cache
+/// stores are opened makes the racers take turns; once the cache exists it
+/// only costs an uncontended flock.
+///
+/// Failing to take the lock degrades to the old unguarded behavior rather
+/// than failing the build. The exception is scmstore.store-open-timeout:
+/// when set and the lock is still held after that long, the build fails
+/// with an error naming the lock file and its holder, instead of blocking
+/// indefinitely on e.g. a wedged background prefetch.
+fn lock_cache_init(config: &dyn Config, cache_path: &Path) -> Result<Option<PathLock>> {
+    let lock_path = cache_path.join(CACHE_INIT_LOCK_FILENAME);
+
+    let timeout = match config.get_opt::<Duration>("scmstore", "store-open-timeout")? {
+        Some(timeout) => timeout,
+        None => {
+            // No timeout configured - block until the lock is free.
+            return Ok(match PathLock::exclusive(&lock_path) {
+                Ok(lock) => {
+                    write_lock_holder_info(&lock);
+                    Some(lock)
+                }
+                Err(err) => {
+                    tracing::warn!(?err, ?cache_path, "failed to take cache initialization lock");
+                    None
+                }
+            });
+        }
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match PathLock::try_exclusive(&lock_path) {
+            Ok(Some(lock)) => {
+                write_lock_holder_info(&lock);
+                return Ok(Some(lock));
+            }
+            Ok(None) => {
+                if Instant::now() >= deadline {
+                    bail!(
+                        "timed out after {:?} waiting for store lock {}{}",
+                        timeout,
+                        lock_path.display(),
+                        describe_lock_holder(&lock_path),
+                    );
+                }
+                std::thread::sleep(STORE_OPEN_LOCK_POLL_INTERVAL.min(timeout));
+            }
+            Err(err) => {
+                tracing::warn!(?err, ?cache_path, "failed to take cache initialization lock");
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// How often to re-probe a held store lock when scmstore.store-open-timeout
+/// is set.
+const STORE_OPEN_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Record our PID and command in the (locked) lock file, so a process stuck
+/// waiting on it can report who is holding it.
+fn write_lock_holder_info(lock: &PathLock) {
+    let mut file = lock.as_file();
+    let _ = (|| -> std::io::Result<()> {
+        file.set_len(0)?;
+        write!(
+            file,
+            "{}\n{}\n",
+            std::process::id(),
+            std::env::args().next().unwrap_or_default()
+        )
+    })();
+}
+
+/// Describe the holder recorded in a lock file by `write_lock_holder_info`,
+/// for inclusion in a timeout error. Empty if nothing usable was recorded.
+fn describe_lock_holder(lock_path: &Path) -> String {
+    match fs::read_to_string(lock_path) {
+        Ok(contents) => {
+            let mut lines = contents.lines();
+            match (lines.next(), lines.next()) {
+                (Some(pid), cmd) if !pid.trim().is_empty() => match cmd {
+                    Some(cmd) if !cmd.trim().is_empty() => {
+                        format!(" (held by pid {}, {})", pid.trim(), cmd.trim())
+                    }
+                    _ => format!(" (held by pid {})", pid.trim()),
+                },
+                _ => String::new(),
+            }
+        }
+        Err(_) => String::new(),
+    }
+}
+
+fn is_cache_buster_scope(key: &str) -> bool {
+    FILE_CACHE_BUSTER_SCOPES
+        .iter()
+        .chain(TREE_CACHE_BUSTER_SCOPES.iter())
+        .any(|(scope, _)| *scope == key)
+}
+
 /// Reads the configs and deletes the hgcache if a hgcache-purge.$KEY=$DATE value hasn't already
 /// been processed.
-pub fn check_cache_buster(config: &dyn Config, store_path: &Path) {
+///
+/// Keys named after a scope in `scopes` (e.g. `data`, `aux`, `lfs`, `trees`)
+/// only clear the corresponding store family, tracked by a per-scope marker
+/// file, so that e.g. busting LFS blobs does not nuke the much larger data
+/// cache. Any other key keeps the legacy behavior of deleting the entire
+/// cache directory.
+pub fn check_cache_buster(config: &dyn Config, store_path: &Path, scopes: &[(&str, &[&str])]) {
     for key in config.keys("hgcache-purge").into_iter() {
+        if is_cache_buster_scope(&key) {
+            continue;
+        }
         if let Some(cutoff) = config
             .get("hgcache-purge", &key)
             .and_then(|c| HgTime::parse(&c))
@@ -761,6 +1433,24 @@ pub fn check_cache_buster(config: &dyn Config, store_path: &Path) {
             }
         }
     }
+
+    for (scope, subdirs) in scopes.iter().copied() {
+        if let Some(value) = config.get("hgcache-purge", scope) {
+            if let Some(cutoff) = HgTime::parse(&value) {
+                // Keyed by the raw config value, so bumping the value fires
+                // the buster again.
+                let marker = format!("{}-{}", RUN_ONCE_FILENAME, scope);
+                if check_run_once_marker(store_path, &marker, &value, cutoff) {
+                    for subdir in subdirs.iter().copied() {
+                        let path = store_path.join(subdir);
+                        if path.exists() {
+                            let _ = fs::remove_dir_all(&path);
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
 /// Recursively deletes the contents of the path, excluding the run-once marker file.
@@ -769,7 +1459,13 @@ fn delete_hgcache(store_path: &Path) -> Result<()> {
     for file in fs::read_dir(store_path)? {
         let _ = (|| -> Result<()> {
             let file = file?;
-            if file.file_name() == RUN_ONCE_FILENAME {
+            // Keep the run-once marker and the per-scope marker files, so a
+            // full purge doesn't re-trigger the scoped busters afterwards.
+            if file
+                .file_name()
+                .to_string_lossy()
+                .starts_with(RUN_ONCE_FILENAME)
+            {
                 return Ok(());
             }
 
@@ -785,3 +1481,502 @@ fn delete_hgcache(store_path: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use minibytes::Bytes;
+    use tempfile::TempDir;
+    use types::fetch_mode::FetchMode;
+    use types::testutil::repo_path_buf;
+    use types::HgId;
+    use types::Key;
+    use types::Parents;
+
+    use super::*;
+    use crate::edenapi::Tree as TreeMarker;
+    use crate::lfs::LfsPointersEntry;
+    use crate::scmstore::FileAttributes;
+    use crate::testutil::make_config;
+    use crate::testutil::FakeSaplingRemoteApi;
+    use crate::RemoteDataStore;
+    use crate::SaplingRemoteApiRemoteStore;
+    use crate::StoreKey;
+
+    #[test]
+    fn test_file_store_config_summary() -> Result<()> {
+        let dir = TempDir::new()?;
+        let config = BTreeMap::from([
+            ("remotefilelog.lfs".to_string(), "true".to_string()),
+            ("lfs.threshold".to_string(), "1000".to_string()),
+            ("scmstore.retries".to_string(), "3".to_string()),
+            ("scmstore.max-prefetch-size".to_string(), "123".to_string()),
+        ]);
+
+        let store = FileStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .build()?;
+
+        let summary = store.config_summary();
+        assert_eq!(summary.lfs_threshold_bytes, Some(1000));
+        assert_eq!(summary.edenapi_retries, 3);
+        assert_eq!(summary.max_prefetch_size, 123);
+        assert_eq!(summary.local_path.as_deref(), Some(dir.path()));
+        assert_eq!(summary.cache_path, None);
+        assert!(summary.has_indexedlog_local);
+        assert!(summary.has_lfs_local);
+        assert!(!summary.has_indexedlog_cache);
+        assert!(!summary.has_edenapi);
+        assert!(!summary.has_cas_client);
+
+        // The summary is serializable for snapshotting.
+        let json = serde_json::to_value(&summary)?;
+        assert_eq!(json["lfs_threshold_bytes"], 1000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_store_build_with_validation() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        // No cache path and no LFS configured: only the cache warning.
+        let config = BTreeMap::<String, String>::new();
+        let (store, warnings) = FileStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .build_with_validation()?;
+        assert_eq!(warnings, vec![ValidationWarning::NoCachePath]);
+
+        // The store is still usable when warnings are present.
+        assert!(store.config_summary().has_indexedlog_local);
+        drop(store);
+
+        // LFS enabled without an lfs.url adds a warning.
+        let config = BTreeMap::from([
+            ("remotefilelog.lfs".to_string(), "true".to_string()),
+            ("lfs.threshold".to_string(), "1000".to_string()),
+        ]);
+        let (_store, warnings) = FileStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .build_with_validation()?;
+        assert_eq!(
+            warnings,
+            vec![
+                ValidationWarning::NoCachePath,
+                ValidationWarning::LfsUrlNotSet
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_lfs_remote() -> Result<()> {
+        let dir = TempDir::new()?;
+        let config = BTreeMap::from([
+            ("remotefilelog.lfs".to_string(), "true".to_string()),
+            ("lfs.threshold".to_string(), "1".to_string()),
+            ("scmstore.lfs-remote".to_string(), "false".to_string()),
+        ]);
+
+        let store = FileStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .build()?;
+
+        // The local LFS stores are still constructed.
+        let summary = store.config_summary();
+        assert!(summary.has_lfs_local);
+        assert!(!summary.has_lfs_remote);
+
+        // Blobs already present locally resolve without the remote.
+        let content = Bytes::from(&b"lfs file content"[..]);
+        let key = store.write_file_content(repo_path_buf("a"), content.clone())?;
+        let mut file = store
+            .fetch([key], FileAttributes::CONTENT, FetchMode::AllowRemote)
+            .single()?
+            .expect("file not found");
+        assert_eq!(file.file_content()?, content);
+
+        // A pointer whose blob was never downloaded fails with a clear
+        // error naming the oid instead of being reported as missing.
+        let missing_content = Bytes::from(&b"blob that was never downloaded"[..]);
+        let hgid = HgId::from_content(&missing_content, Parents::None);
+        let ptr = LfsPointersEntry::from_file_content(hgid, &missing_content, None)?;
+        let oid = ptr.sha256();
+        store.lfs_local.as_ref().unwrap().add_pointer(ptr)?;
+
+        let key = Key::new(repo_path_buf("b"), hgid);
+        let err = store
+            .fetch([key], FileAttributes::CONTENT, FetchMode::AllowRemote)
+            .single()
+            .unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("LFS remote disabled"), "{}", msg);
+        assert!(msg.contains(&oid.to_string()), "{}", msg);
+
+        // Uploads fail fast with the same error.
+        let err = RemoteDataStore::upload(
+            &store,
+            &[StoreKey::hgid(Key::new(repo_path_buf("b"), hgid))],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("LFS remote disabled"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_open_timeout() -> Result<()> {
+        let cache = TempDir::new()?;
+        let mut config = make_config(&cache);
+        config.insert(
+            "scmstore.store-open-timeout".to_string(),
+            "0.05".to_string(),
+        );
+
+        // Simulate another process holding the cache init lock.
+        let cache_path = get_cache_path(&config, &None::<PathBuf>)?.unwrap();
+        fs::create_dir_all(&cache_path)?;
+        let held = PathLock::exclusive(cache_path.join(CACHE_INIT_LOCK_FILENAME))?;
+        write_lock_holder_info(&held);
+
+        let local = TempDir::new()?;
+        let err = FileStoreBuilder::new(&config)
+            .local_path(local.path())
+            .build()
+            .unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains(CACHE_INIT_LOCK_FILENAME), "{}", msg);
+        assert!(
+            msg.contains(&format!("pid {}", std::process::id())),
+            "{}",
+            msg
+        );
+
+        // Once the lock is released the build goes through.
+        drop(held);
+        let store = FileStoreBuilder::new(&config)
+            .local_path(local.path())
+            .build()?;
+        drop(store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_hash_function() -> Result<()> {
+        let dir = TempDir::new()?;
+        let config = BTreeMap::<String, String>::new();
+
+        let fixed_id = HgId::from_byte_array([0xab; HgId::len()]);
+        let store = FileStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .with_custom_hash_function(Arc::new(move |_: &Bytes| fixed_id))
+            .build()?;
+
+        // Local writes use the custom hash function.
+        let content = Bytes::from(&b"some file content"[..]);
+        let key = store.write_file_content(repo_path_buf("a"), content.clone())?;
+        assert_eq!(key.hgid, fixed_id);
+
+        // The entry is readable back under the deterministic ID.
+        let mut file = store
+            .fetch([key], FileAttributes::CONTENT, FetchMode::LocalOnly)
+            .single()?
+            .expect("file not found");
+        assert_eq!(file.file_content()?, content);
+
+        // Without the override, the real hg content hash is used.
+        let dir = TempDir::new()?;
+        let store = FileStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .build()?;
+        let key = store.write_file_content(repo_path_buf("a"), content.clone())?;
+        assert_eq!(key.hgid, HgId::from_content(&content, Parents::None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_file_store_builds() -> Result<()> {
+        let cache = TempDir::new()?;
+        let config = make_config(&cache);
+
+        // Builders racing to initialize the same empty cache path must all
+        // succeed rather than leave a half-initialized cache behind.
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let config = config.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    let local = TempDir::new()?;
+                    let store = FileStoreBuilder::new(&config)
+                        .local_path(local.path())
+                        .build()?;
+                    drop(store);
+                    Ok(())
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().expect("builder thread panicked")?;
+        }
+
+        // The cache is usable afterwards.
+        let local = TempDir::new()?;
+        let store = FileStoreBuilder::new(&config)
+            .local_path(local.path())
+            .build()?;
+        assert!(store.config_summary().has_indexedlog_cache);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_repo_name() -> Result<()> {
+        let cache = TempDir::new()?;
+        // make_config sets remotefilelog.reponame to "test".
+        let config = make_config(&cache);
+
+        let store = FileStoreBuilder::new(&config)
+            .with_repo_name("other")
+            .build()?;
+        drop(store);
+
+        // The cache lives under the override, not remotefilelog.reponame.
+        assert!(cache.path().join("other").is_dir());
+        assert!(!cache.path().join("test").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_store_config_summary() -> Result<()> {
+        let dir = TempDir::new()?;
+        let config = BTreeMap::from([
+            (
+                "scmstore.tree-metadata-mode".to_string(),
+                "always".to_string(),
+            ),
+            ("scmstore.verify-writes".to_string(), "true".to_string()),
+        ]);
+
+        let store = TreeStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .build()?;
+
+        let summary = store.config_summary();
+        assert_eq!(summary.tree_metadata_mode, "Always");
+        assert!(summary.verify_writes);
+        assert_eq!(summary.local_path.as_deref(), Some(dir.path()));
+        assert!(summary.has_indexedlog_local);
+        assert!(!summary.has_edenapi);
+        assert!(!summary.has_filestore);
+
+        // The summary can be printed as JSON for bug reports.
+        let json = serde_json::to_value(&summary)?;
+        assert_eq!(json["tree_metadata_mode"], "Always");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_store_server_capabilities() -> Result<()> {
+        let config = BTreeMap::from([
+            (
+                "scmstore.tree-metadata-mode".to_string(),
+                "always".to_string(),
+            ),
+            (
+                "scmstore.fetch-tree-aux-data".to_string(),
+                "true".to_string(),
+            ),
+            (
+                "scmstore.store-tree-aux-data".to_string(),
+                "false".to_string(),
+            ),
+        ]);
+
+        // A server advertising both capabilities keeps the configured
+        // behavior.
+        let client = FakeSaplingRemoteApi::new()
+            .capabilities(vec![
+                CAP_AUGMENTED_TREES.to_string(),
+                CAP_TREES_CHILD_METADATA.to_string(),
+            ])
+            .into_arc();
+        let store = TreeStoreBuilder::new(&config)
+            .edenapi(SaplingRemoteApiRemoteStore::<TreeMarker>::new(client))
+            .build()?;
+        assert!(store.fetch_tree_aux_data);
+        assert!(matches!(store.tree_metadata_mode, TreeMetadataMode::Always));
+
+        // An older server advertising neither falls back to the legacy
+        // attributes.
+        let client = FakeSaplingRemoteApi::new().capabilities(vec![]).into_arc();
+        let store = TreeStoreBuilder::new(&config)
+            .edenapi(SaplingRemoteApiRemoteStore::<TreeMarker>::new(client))
+            .build()?;
+        assert!(!store.fetch_tree_aux_data);
+        assert!(matches!(store.tree_metadata_mode, TreeMetadataMode::Never));
+
+        // The override forces the configured behavior without querying; this
+        // server reports NotSupported for the capabilities endpoint.
+        let mut config = config.clone();
+        config.insert(
+            "scmstore.assume-server-capabilities".to_string(),
+            "true".to_string(),
+        );
+        let client = FakeSaplingRemoteApi::new().into_arc();
+        let store = TreeStoreBuilder::new(&config)
+            .edenapi(SaplingRemoteApiRemoteStore::<TreeMarker>::new(client))
+            .build()?;
+        assert!(store.fetch_tree_aux_data);
+        assert!(matches!(store.tree_metadata_mode, TreeMetadataMode::Always));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_metadata_mode_accessors() -> Result<()> {
+        let config = BTreeMap::from([(
+            "scmstore.tree-metadata-mode".to_string(),
+            "always".to_string(),
+        )]);
+
+        let client = FakeSaplingRemoteApi::new()
+            .capabilities(vec![CAP_TREES_CHILD_METADATA.to_string()])
+            .into_arc();
+        let mut store = TreeStoreBuilder::new(&config)
+            .edenapi(SaplingRemoteApiRemoteStore::<TreeMarker>::new(client))
+            .build()?;
+
+        // The getter reflects the mode the builder resolved.
+        assert!(matches!(
+            store.get_tree_metadata_mode(),
+            TreeMetadataMode::Always
+        ));
+
+        store.set_tree_metadata_mode(TreeMetadataMode::Never);
+        assert!(matches!(
+            store.get_tree_metadata_mode(),
+            TreeMetadataMode::Never
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capabilities_cache_ttl() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        store_cached_capabilities(dir.path(), &[CAP_AUGMENTED_TREES.to_string()]);
+        assert_eq!(
+            load_cached_capabilities(dir.path(), DEFAULT_CAPABILITIES_MAX_AGE),
+            Some(vec![CAP_AUGMENTED_TREES.to_string()])
+        );
+
+        // Entries older than the TTL are ignored.
+        fs::write(
+            dir.path().join(CAPABILITIES_FILENAME),
+            format!("0\n{}", CAP_AUGMENTED_TREES),
+        )?;
+        assert_eq!(
+            load_cached_capabilities(dir.path(), DEFAULT_CAPABILITIES_MAX_AGE),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scm_store_builder_shares_file_store() -> Result<()> {
+        let dir = TempDir::new()?;
+        let config = BTreeMap::<String, String>::new();
+
+        let (file_store, tree_store) = ScmStoreBuilder::new(&config)
+            .local_path(dir.path())
+            .build()?;
+
+        // The tree store is wired up with the same file store instance for
+        // aux data write-through.
+        let shared = tree_store
+            .filestore
+            .clone()
+            .expect("tree store should share the file store");
+        assert!(Arc::ptr_eq(&shared, &file_store));
+
+        // Both stores are functional after a combined build.
+        file_store.flush()?;
+        tree_store.flush()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoped_cache_buster_only_clears_its_scope() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        for subdir in ["indexedlogdatastore", "indexedlogdatastore_aux", "lfs"] {
+            fs::create_dir(dir.path().join(subdir))?;
+            fs::write(dir.path().join(subdir).join("contents"), b"data")?;
+        }
+
+        let config = BTreeMap::from([(
+            "hgcache-purge.lfs".to_string(),
+            "2100-01-01".to_string(),
+        )]);
+
+        check_cache_buster(&config, dir.path(), FILE_CACHE_BUSTER_SCOPES);
+
+        // Only the LFS store family was cleared.
+        assert!(!dir.path().join("lfs").exists());
+        assert!(dir.path().join("indexedlogdatastore").join("contents").exists());
+        assert!(
+            dir.path()
+                .join("indexedlogdatastore_aux")
+                .join("contents")
+                .exists()
+        );
+
+        // The scoped buster only fires once per value.
+        fs::create_dir(dir.path().join("lfs"))?;
+        fs::write(dir.path().join("lfs").join("contents"), b"data")?;
+        check_cache_buster(&config, dir.path(), FILE_CACHE_BUSTER_SCOPES);
+        assert!(dir.path().join("lfs").join("contents").exists());
+
+        // Bumping the value fires it again.
+        let config = BTreeMap::from([(
+            "hgcache-purge.lfs".to_string(),
+            "2100-01-02".to_string(),
+        )]);
+        check_cache_buster(&config, dir.path(), FILE_CACHE_BUSTER_SCOPES);
+        assert!(!dir.path().join("lfs").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_cache_buster_clears_everything() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        for subdir in ["indexedlogdatastore", "indexedlogdatastore_aux", "lfs"] {
+            fs::create_dir(dir.path().join(subdir))?;
+            fs::write(dir.path().join(subdir).join("contents"), b"data")?;
+        }
+
+        let config = BTreeMap::from([(
+            "hgcache-purge.some-event".to_string(),
+            "2100-01-01".to_string(),
+        )]);
+
+        check_cache_buster(&config, dir.path(), FILE_CACHE_BUSTER_SCOPES);
+
+        assert!(!dir.path().join("indexedlogdatastore").exists());
+        assert!(!dir.path().join("indexedlogdatastore_aux").exists());
+        assert!(!dir.path().join("lfs").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Classification of TLS certificate errors caused by clock skew.
+//!
+//! A large fraction of generic "TLS failed" fetch errors turn out to be a
+//! client certificate that is expired or not yet valid because the system
+//! clock is wrong. This module inspects a failed remote fetch after the
+//! fact (the success path is untouched) and, when the certificate's
+//! validity window is the culprit, wraps the error with a message naming
+//! the certificate, its validity window, and the detected clock skew.
+
+use std::path::Path;
+
+use anyhow::Error;
+use auth::x509::X509ErrorKind;
+use chrono::offset::Local;
+use chrono::offset::Utc;
+use chrono::DateTime;
+use chrono::FixedOffset;
+use edenapi::SaplingRemoteApiError;
+use http_client::HttpClientError;
+use http_client::TlsError;
+use http_client::TlsErrorKind;
+
+/// If `err` looks like a certificate validity failure and the client
+/// certificate at `cert_path` is outside its validity window, wrap `err`
+/// with an actionable message. Otherwise return `err` unchanged. Only
+/// inspects the error chain and the certificate file; no network traffic.
+pub(crate) fn annotate_cert_skew(err: Error, cert_path: Option<&Path>) -> Error {
+    if !is_cert_validity_error(&err) {
+        return err;
+    }
+
+    let cert_path = match cert_path {
+        Some(path) => path,
+        None => return err,
+    };
+
+    let cert_err = match auth::check_certs(cert_path) {
+        Ok(()) => return err,
+        Err(cert_err) => cert_err,
+    };
+
+    if let X509ErrorKind::Expired {
+        not_before,
+        not_after,
+    } = &cert_err.kind
+    {
+        let skew = match server_date(&err) {
+            Some(server_now) => {
+                let skew = Utc::now().signed_duration_since(server_now.with_timezone(&Utc));
+                format!(
+                    "; the system clock is {}s ahead of the server, check your clock",
+                    skew.num_seconds()
+                )
+            }
+            None => String::new(),
+        };
+        err.context(format!(
+            "certificate {} is outside its validity window (valid from {} to {}){}",
+            cert_path.display(),
+            not_before.with_timezone(&Local),
+            not_after.with_timezone(&Local),
+            skew,
+        ))
+    } else {
+        err
+    }
+}
+
+/// Does the error chain contain a TLS failure that could be explained by a
+/// certificate outside its validity window?
+fn is_cert_validity_error(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        let kind = if let Some(TlsError { kind, .. }) = cause.downcast_ref::<TlsError>() {
+            kind
+        } else if let Some(SaplingRemoteApiError::Http(HttpClientError::Tls(TlsError {
+            kind,
+            ..
+        }))) = cause.downcast_ref::<SaplingRemoteApiError>()
+        {
+            kind
+        } else {
+            return false;
+        };
+        matches!(
+            kind,
+            TlsErrorKind::CertProblem | TlsErrorKind::CaCert | TlsErrorKind::InvalidCertStatus
+        )
+    })
+}
+
+/// Extract the server's clock from an HTTP `Date` response header buried in
+/// the error chain, if any response made it that far.
+fn server_date(err: &Error) -> Option<DateTime<FixedOffset>> {
+    err.chain().find_map(|cause| {
+        let headers = match cause.downcast_ref::<SaplingRemoteApiError>()? {
+            SaplingRemoteApiError::HttpError { headers, .. } => headers,
+            _ => return None,
+        };
+        let date = headers.get(http::header::DATE)?.to_str().ok()?;
+        DateTime::parse_from_rfc2822(date).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::HeaderMap;
+    use http::StatusCode;
+
+    use super::*;
+
+    fn tls_error(kind: TlsErrorKind) -> TlsError {
+        TlsError {
+            source: curl::Error::new(0),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_cert_validity_classification() {
+        // A certificate problem is recognized anywhere in the chain.
+        let err = Error::new(SaplingRemoteApiError::Http(HttpClientError::Tls(tls_error(
+            TlsErrorKind::CertProblem,
+        ))))
+        .context("fetch failed");
+        assert!(is_cert_validity_error(&err));
+
+        // A bare TlsError is recognized too.
+        let err = Error::new(tls_error(TlsErrorKind::CaCert));
+        assert!(is_cert_validity_error(&err));
+
+        // Other TLS problems aren't mistaken for validity failures.
+        let err = Error::new(tls_error(TlsErrorKind::EngineNotFound));
+        assert!(!is_cert_validity_error(&err));
+
+        // Non-TLS errors don't match at all.
+        let err = anyhow::anyhow!("connection refused");
+        assert!(!is_cert_validity_error(&err));
+    }
+
+    #[test]
+    fn test_server_date_extraction() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::DATE,
+            "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap(),
+        );
+        let err = Error::new(SaplingRemoteApiError::HttpError {
+            status: StatusCode::UNAUTHORIZED,
+            message: "unauthorized".to_string(),
+            headers,
+            url: "https://example.com/".to_string(),
+        })
+        .context("fetch failed");
+
+        let date = server_date(&err).expect("no date parsed");
+        assert_eq!(date.timestamp(), 784111777);
+
+        // No Date header means no skew estimate.
+        let err = Error::new(SaplingRemoteApiError::HttpError {
+            status: StatusCode::UNAUTHORIZED,
+            message: "unauthorized".to_string(),
+            headers: HeaderMap::new(),
+            url: "https://example.com/".to_string(),
+        });
+        assert!(server_date(&err).is_none());
+    }
+
+    #[test]
+    fn test_annotate_passthrough() {
+        // Errors unrelated to certificate validity come back unchanged even
+        // when a certificate path is available.
+        let err = anyhow::anyhow!("connection refused");
+        let annotated = annotate_cert_skew(err, Some(Path::new("/no/such/cert.pem")));
+        assert_eq!(format!("{}", annotated), "connection refused");
+
+        // Without a certificate path there is nothing to inspect.
+        let err = Error::new(tls_error(TlsErrorKind::CertProblem));
+        let annotated = annotate_cert_skew(err, None);
+        assert!(format!("{}", annotated).contains("TlsError"));
+    }
+}
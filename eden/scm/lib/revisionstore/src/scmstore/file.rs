@@ -9,27 +9,39 @@ mod fetch;
 mod metrics;
 mod types;
 
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 
+use ::types::errors::KeyedError;
 use ::types::fetch_mode::FetchMode;
 use ::types::HgId;
 use ::types::Key;
+use ::types::Parents;
+use ::types::RepoPathBuf;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Result;
+use async_runtime::spawn_blocking;
 use cas_client::CasClient;
 use clientinfo::get_client_request_info_thread_local;
 use clientinfo::set_client_request_info_thread_local;
 use crossbeam::channel::unbounded;
+use futures::stream;
+use futures::FutureExt;
+use futures::Stream;
+use futures::StreamExt;
 use itertools::Itertools;
 use minibytes::Bytes;
+use parking_lot::Condvar;
 use parking_lot::Mutex;
 use parking_lot::RwLock;
+use pathmatcher::TreeMatcher;
 use progress_model::AggregatingProgressBar;
 use rand::Rng;
+use serde::Serialize;
 use tracing::debug;
 
 pub(crate) use self::fetch::FetchState;
@@ -50,10 +62,14 @@ use crate::lfs::lfs_from_hg_file_blob;
 use crate::lfs::LfsClient;
 use crate::lfs::LfsPointersEntry;
 use crate::lfs::LfsStore;
+use crate::lfs::LfsStoreEntry;
 use crate::remotestore::HgIdRemoteStore;
 use crate::scmstore::activitylogger::ActivityLogger;
 use crate::scmstore::fetch::FetchResults;
+use crate::scmstore::fetch::KeyFetchError;
+use crate::scmstore::metrics::FetchSummary;
 use crate::scmstore::metrics::StoreLocation;
+use crate::throttle::NetworkThrottle;
 use crate::ContentDataStore;
 use crate::ContentMetadata;
 use crate::Delta;
@@ -65,6 +81,133 @@ use crate::MultiplexDeltaStore;
 use crate::SaplingRemoteApiFileStore;
 use crate::StoreKey;
 use crate::StoreResult;
+use crate::ToKeys;
+
+/// Default number of tasks writing remote fetch results back to the cache.
+pub(crate) const DEFAULT_CONCURRENT_CACHE_WRITERS: usize = 4;
+
+/// Guardrails on the size of a single `prefetch` call, so accidental
+/// whole-repo prefetches can be found (warn) or stopped (max).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PrefetchLimits {
+    // Warn when a single prefetch call requests more than this many keys.
+    // Configured by scmstore.prefetch-warn-keys.
+    pub(crate) warn_keys: Option<usize>,
+    // Warn when a single prefetch call fetches more than this many bytes.
+    // Configured by scmstore.prefetch-warn-bytes.
+    pub(crate) warn_bytes: Option<u64>,
+    // Fail prefetch calls requesting more than this many keys.
+    // Configured by scmstore.prefetch-max-keys.
+    pub(crate) max_keys: Option<usize>,
+    // Fail prefetch calls once they have fetched more than this many bytes.
+    // Configured by scmstore.prefetch-max-bytes.
+    pub(crate) max_bytes: Option<u64>,
+}
+
+/// The outcome of a `prefetch` call.
+#[derive(Debug, Default)]
+pub struct PrefetchResult {
+    /// Requested keys that could not be found anywhere.
+    pub missing: Vec<Key>,
+    /// Present when `scmstore.max-prefetch-size` split the request, so
+    /// callers can warn that the prefetch ran as several smaller fetches.
+    pub split: Option<PrefetchSplit>,
+}
+
+/// Details of a prefetch that was split by `scmstore.max-prefetch-size`.
+#[derive(Clone, Debug)]
+pub struct PrefetchSplit {
+    /// Number of keys in the original request.
+    pub keys: usize,
+    /// Number of fetches the request was split into.
+    pub chunks: usize,
+    /// The configured `scmstore.max-prefetch-size` limit.
+    pub max_prefetch_size: usize,
+}
+
+/// Computes the content ID (`HgId`) for a file blob written locally.
+/// Overridable via [`FileStoreBuilder::with_custom_hash_function`] so tests
+/// can store entries under deterministic IDs without hashing content.
+///
+/// [`FileStoreBuilder::with_custom_hash_function`]: crate::scmstore::FileStoreBuilder::with_custom_hash_function
+pub type ContentHashFunction = Arc<dyn Fn(&Bytes) -> HgId + Send + Sync>;
+
+/// Tracks background threads that may still be writing to the local stores,
+/// so [`FileStore::wait_for_pending_writes`] can block until they finish.
+#[derive(Default)]
+pub(crate) struct PendingWrites {
+    count: Mutex<usize>,
+    done: Condvar,
+}
+
+impl PendingWrites {
+    /// Increment the in-flight count, returning a guard that decrements it
+    /// (and wakes waiters) when dropped, even if the thread panics.
+    fn guard(self: Arc<Self>) -> PendingWriteGuard {
+        *self.count.lock() += 1;
+        PendingWriteGuard(self)
+    }
+
+    fn wait(&self) {
+        let mut count = self.count.lock();
+        while *count > 0 {
+            self.done.wait(&mut count);
+        }
+    }
+}
+
+pub(crate) struct PendingWriteGuard(Arc<PendingWrites>);
+
+impl Drop for PendingWriteGuard {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock();
+        *count -= 1;
+        if *count == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// The effective configuration of a [`FileStore`], as resolved at build
+/// time (defaults applied, not the raw config). Returned by
+/// [`FileStore::config_summary`] for debugging; contains only plain data so
+/// it can be serialized and snapshot-tested.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FileStoreConfigSummary {
+    pub lfs_threshold_bytes: Option<u64>,
+    pub edenapi_retries: i32,
+    pub allow_write_lfs_ptrs: bool,
+    pub compute_aux_data: bool,
+    pub prefetch_aux_data: bool,
+    pub max_prefetch_size: usize,
+    pub resolve_lfs_pointers: bool,
+    pub lfs_range_requests: bool,
+    pub concurrent_cache_writers: usize,
+    pub local_lookup_threads: usize,
+    pub batch_size: Option<usize>,
+    pub warm_on_fetch_miss: bool,
+    pub max_download_bytes_per_sec: Option<u64>,
+    pub local_path: Option<PathBuf>,
+    pub cache_path: Option<PathBuf>,
+    pub has_indexedlog_local: bool,
+    pub has_indexedlog_cache: bool,
+    pub has_lfs_local: bool,
+    pub has_lfs_cache: bool,
+    pub has_lfs_remote: bool,
+    pub has_edenapi: bool,
+    pub has_aux_cache: bool,
+    pub has_cas_client: bool,
+}
+
+/// What [`FileStore::copy_local_to_cache`] did: how many entries were
+/// copied into the cache, their total (uncompressed) content size, and how
+/// many were skipped because the cache already had them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CopyStats {
+    pub entries_copied: u64,
+    pub bytes_copied: u64,
+    pub entries_skipped: u64,
+}
 
 #[derive(Clone)]
 pub struct FileStore {
@@ -76,6 +219,12 @@ pub struct FileStore {
     /// Allow explicitly writing serialized LFS pointers outside of tests
     pub(crate) allow_write_lfs_ptrs: bool,
 
+    // Overrides how content IDs are computed for local writes. `None` means
+    // the real hg content hash. Only tests set this, via
+    // `FileStoreBuilder::with_custom_hash_function`; remote fetches are
+    // unaffected and always carry real hashes.
+    pub(crate) hash_function: Option<ContentHashFunction>,
+
     // Top level flag allow disabling all local computation of aux data.
     pub(crate) compute_aux_data: bool,
     // Make prefetch() calls request aux data.
@@ -85,6 +234,59 @@ pub struct FileStore {
     // Configured by scmstore.max-prefetch-size, where 0 means unlimited.
     pub(crate) max_prefetch_size: usize,
 
+    // Soft and hard limits on the size of a single prefetch() call.
+    pub(crate) prefetch_limits: PrefetchLimits,
+
+    // Resolve LFS pointers found in the indexedlog layers through the LFS
+    // stores instead of returning pointer bytes as content.
+    // Configured by scmstore.resolve-lfs-pointers.
+    pub(crate) resolve_lfs_pointers: bool,
+
+    // Serve fetch_range() calls for remote LFS blobs with HTTP range
+    // requests instead of downloading the whole blob into the cache.
+    // Configured by lfs.allow-range-requests.
+    pub(crate) lfs_range_requests: bool,
+
+    // The LFS remote was explicitly disabled (scmstore.lfs-remote=false or
+    // `FileStoreBuilder::no_lfs_remote`), so fetches and uploads that need
+    // it fail with a clear error instead of reporting the blobs as missing.
+    pub(crate) lfs_remote_disabled: bool,
+
+    // Number of tasks draining the remote fetch stream and writing results
+    // to the cache in parallel.
+    pub(crate) concurrent_cache_writers: usize,
+
+    // Number of threads probing the indexedlog stores when fetching a large
+    // batch of keys. A value of 1 probes inline on the fetch thread.
+    // Configured by scmstore.local-lookup-threads.
+    pub(crate) local_lookup_threads: usize,
+
+    // Largest number of keys sent to the SaplingRemoteAPI server in a single
+    // request. Batches above this size are split into multiple requests so a
+    // failure only affects one chunk. `None` sends the whole batch at once.
+    // Configured by scmstore.file-batch-size.
+    pub(crate) batch_size: Option<usize>,
+
+    // When a fetch misses every local store, speculatively prefetch files
+    // adjacent to the missed keys in the background, similar to OS
+    // read-ahead. Configured by scmstore.warm-on-miss.
+    pub(crate) warm_on_fetch_miss: bool,
+
+    // Client certificate the builder resolved for the SaplingRemoteAPI URL,
+    // used to explain TLS failures caused by an expired certificate or a
+    // skewed system clock.
+    pub(crate) client_cert_path: Option<PathBuf>,
+
+    // Caps SaplingRemoteAPI download bandwidth. `None` means unthrottled.
+    // Configured by scmstore.max-download-bytes-per-sec or
+    // `FileStoreBuilder::with_network_throttle`.
+    pub(crate) network_throttle: Option<Arc<NetworkThrottle>>,
+
+    // Paths the builder resolved the local and cache stores to, kept for
+    // config_summary(). `None` when the store was constructed without one.
+    pub(crate) local_path: Option<PathBuf>,
+    pub(crate) cache_path: Option<PathBuf>,
+
     // Local-only stores
     pub(crate) indexedlog_local: Option<Arc<IndexedLogHgIdDataStore>>,
     pub(crate) lfs_local: Option<Arc<LfsStore>>,
@@ -108,7 +310,19 @@ pub struct FileStore {
     pub(crate) activity_logger: Option<Arc<Mutex<ActivityLogger>>>,
     pub(crate) metrics: Arc<RwLock<FileStoreMetrics>>,
 
+    // Dump every fetch() key list to a newline-delimited JSON file for
+    // replay testing. Debug builds only; see
+    // `FileStoreBuilder::with_request_dumper`.
+    #[cfg(debug_assertions)]
+    pub(crate) request_dumper: Option<Arc<Mutex<std::fs::File>>>,
+
+    // Background threads (large fetches, sibling prefetch) that may still be
+    // writing to the local stores. Shared between clones of the store so
+    // `wait_for_pending_writes` sees all of them.
+    pub(crate) pending_writes: Arc<PendingWrites>,
+
     pub(crate) lfs_progress: Arc<AggregatingProgressBar>,
+    pub(crate) edenapi_progress: Arc<AggregatingProgressBar>,
 
     // Don't flush on drop when we're using FileStore in a "disposable" context, like backingstore
     pub flush_on_drop: bool,
@@ -148,6 +362,9 @@ impl FileStore {
         attrs: FileAttributes,
         fetch_mode: FetchMode,
     ) -> FetchResults<StoreFile> {
+        #[cfg(debug_assertions)]
+        let keys = self.dump_fetch_request(keys);
+
         let (found_tx, found_rx) = unbounded();
         let mut state = FetchState::new(
             keys,
@@ -175,12 +392,23 @@ impl FileStore {
         let edenapi = self.edenapi.clone();
         let cas_client = self.cas_client.clone();
         let lfs_remote = self.lfs_remote.clone();
+        let lfs_remote_disabled = self.lfs_remote_disabled;
         let metrics = self.metrics.clone();
         let activity_logger = self.activity_logger.clone();
 
         let fetch_local = fetch_mode.contains(FetchMode::LOCAL);
         let fetch_remote = fetch_mode.contains(FetchMode::REMOTE);
 
+        let warm_store = if self.warm_on_fetch_miss && fetch_local && fetch_remote {
+            // Clear the flag on the clone so the speculative prefetches
+            // can't recursively trigger more warming.
+            let mut warm_store = self.clone();
+            warm_store.warm_on_fetch_miss = false;
+            Some(warm_store)
+        } else {
+            None
+        };
+
         let process_func = move || {
             let start_instant = Instant::now();
 
@@ -235,6 +463,11 @@ impl FileStore {
             }
 
             if fetch_remote {
+                if let Some(warm_store) = &warm_store {
+                    // Everything still pending missed every local store.
+                    warm_store.warm_siblings(&state.all_keys());
+                }
+
                 if let Some(cas_client) = &cas_client {
                     state.fetch_cas(cas_client);
                 }
@@ -254,12 +487,18 @@ impl FileStore {
                         lfs_local.clone(),
                         lfs_cache.clone(),
                     );
+                } else if lfs_remote_disabled {
+                    state.error_lfs_remote_disabled();
                 }
             }
 
             state.derive_computable(aux_cache.as_ref().map(|s| s.as_ref()));
 
-            metrics.write().fetch += state.metrics().clone();
+            {
+                let mut metrics = metrics.write();
+                metrics.fetch_summary += state.metrics().summary();
+                metrics.fetch += state.metrics().clone();
+            }
             if let Err(err) = state.metrics().update_ods() {
                 tracing::error!("Error updating ods fetch metrics: {}", err);
             }
@@ -279,7 +518,9 @@ impl FileStore {
         // Only kick off a thread if there's a substantial amount of work.
         if keys_len > 1000 {
             let cri = get_client_request_info_thread_local();
+            let pending_write_guard = self.pending_writes.clone().guard();
             std::thread::spawn(move || {
+                let _pending_write_guard = pending_write_guard;
                 if let Some(cri) = cri {
                     set_client_request_info_thread_local(cri);
                 }
@@ -330,9 +571,21 @@ impl FileStore {
     }
 
     pub fn write_batch(&self, entries: impl Iterator<Item = (Key, Bytes, Metadata)>) -> Result<()> {
+        let span = tracing::debug_span!(
+            target: "revisionstore::fetch",
+            "write_batch",
+            keys = tracing::field::Empty,
+            bytes = tracing::field::Empty,
+        );
+        let _enter = span.enter();
+        let mut written_keys: usize = 0;
+        let mut written_bytes: u64 = 0;
+
         // TODO(meyer): Don't fail the whole batch for a single write error.
         let mut metrics = FileStoreWriteMetrics::default();
         for (key, bytes, meta) in entries {
+            written_keys += 1;
+            written_bytes += bytes.len() as u64;
             if meta.is_lfs() && self.lfs_threshold_bytes.is_some() {
                 metrics.lfsptr.item(1);
                 if let Err(e) = self.write_lfsptr(key, bytes) {
@@ -363,10 +616,145 @@ impl FileStore {
                 metrics.nonlfs.ok(1);
             }
         }
+        span.record("keys", written_keys);
+        span.record("bytes", written_bytes);
         self.metrics.write().write += metrics;
         Ok(())
     }
 
+    /// Compute the content ID a local write of `bytes` will be stored under.
+    /// Uses the hash function installed by
+    /// `FileStoreBuilder::with_custom_hash_function` if there is one, and the
+    /// real hg content hash otherwise.
+    pub fn content_hgid(&self, bytes: &Bytes) -> HgId {
+        match &self.hash_function {
+            Some(hash_function) => hash_function(bytes),
+            None => HgId::from_content(bytes, Parents::None),
+        }
+    }
+
+    /// Write `bytes` as a local file entry at `path`, computing the content
+    /// ID with `content_hgid`, and return the key it was stored under.
+    pub fn write_file_content(&self, path: RepoPathBuf, bytes: Bytes) -> Result<Key> {
+        let key = Key::new(path, self.content_hgid(&bytes));
+        self.write_batch(std::iter::once((
+            key.clone(),
+            bytes,
+            Metadata::default(),
+        )))?;
+        Ok(key)
+    }
+
+    /// Find keys that have a content entry in `indexedlog_cache` but no
+    /// corresponding entry in `aux_cache`, e.g. after a partial migration
+    /// left gaps in the aux data.  This powers `debugauditaux`.
+    pub fn audit_missing_aux_data(&self, keys: &[Key]) -> impl futures::Stream<Item = Key> {
+        let mut missing = Vec::new();
+        if let (Some(indexedlog_cache), Some(aux_cache)) = (&self.indexedlog_cache, &self.aux_cache)
+        {
+            for key in keys {
+                let has_content = indexedlog_cache
+                    .get_raw_entry(&key.hgid)
+                    .ok()
+                    .flatten()
+                    .is_some();
+                let has_aux = aux_cache.contains(key.hgid).unwrap_or(false);
+                if has_content && !has_aux {
+                    missing.push(key.clone());
+                }
+            }
+        }
+        futures::stream::iter(missing)
+    }
+
+    /// Append this fetch's key list to the request dump as one JSON line.
+    /// Dump failures are logged rather than failing the fetch.
+    #[cfg(debug_assertions)]
+    fn dump_fetch_request(&self, keys: impl IntoIterator<Item = Key>) -> Vec<Key> {
+        let keys: Vec<Key> = keys.into_iter().collect();
+        if let Some(dumper) = &self.request_dumper {
+            let mut dumper = dumper.lock();
+            let result = serde_json::to_writer(&mut *dumper, &keys)
+                .map_err(anyhow::Error::from)
+                .and_then(|()| {
+                    std::io::Write::write_all(&mut *dumper, b"\n").map_err(anyhow::Error::from)
+                });
+            if let Err(error) = result {
+                tracing::error!(%error, "failed to dump fetch request");
+            }
+        }
+        keys
+    }
+
+    /// Replay the fetches recorded by `FileStoreBuilder::with_request_dumper`
+    /// against this store, in the recorded order. Yields `Ok(key)` for each
+    /// key fetched successfully, and an error per key that failed or went
+    /// missing.
+    #[cfg(debug_assertions)]
+    pub fn replay_from_dump(&self, path: &std::path::Path) -> impl Stream<Item = Result<Key>> {
+        let mut results: Vec<Result<Key>> = Vec::new();
+        match std::fs::read_to_string(path) {
+            Ok(dump) => {
+                for line in dump.lines().filter(|line| !line.is_empty()) {
+                    let keys: Vec<Key> = match serde_json::from_str(line) {
+                        Ok(keys) => keys,
+                        Err(err) => {
+                            results.push(Err(
+                                anyhow::Error::from(err).context("malformed request dump line")
+                            ));
+                            continue;
+                        }
+                    };
+                    let (found, mut missing, errors) = self
+                        .fetch(keys.clone(), FileAttributes::CONTENT, FetchMode::AllowRemote)
+                        .consume();
+                    for key in keys {
+                        if found.contains_key(&key) {
+                            results.push(Ok(key));
+                        } else if let Some(err) = missing.remove(&key) {
+                            results.push(Err(err));
+                        } else {
+                            results.push(Err(anyhow!("{}: not fetched during replay", key)));
+                        }
+                    }
+                    results.extend(errors.into_iter().map(Err));
+                }
+            }
+            Err(err) => results.push(Err(anyhow::Error::from(err)
+                .context(format!("reading request dump {}", path.display())))),
+        }
+        stream::iter(results)
+    }
+
+    /// Copy entries that are in the permanent `indexedlog_local` store but
+    /// not in `indexedlog_cache` into the cache, so other checkouts sharing
+    /// only the cache directory can read them (e.g. content committed in
+    /// this checkout). Entries the cache already has are skipped.
+    pub fn copy_local_to_cache(&self) -> Result<CopyStats> {
+        let mut stats = CopyStats::default();
+        let (indexedlog_local, indexedlog_cache) =
+            match (&self.indexedlog_local, &self.indexedlog_cache) {
+                (Some(local), Some(cache)) => (local, cache),
+                _ => return Ok(stats),
+            };
+
+        for key in indexedlog_local.iter_keys() {
+            let key = key?;
+            if indexedlog_cache.contains(&key.hgid)? {
+                stats.entries_skipped += 1;
+                continue;
+            }
+            let entry = indexedlog_local
+                .get_entry(key.clone())?
+                .ok_or_else(|| anyhow!("{} disappeared from the local store during copy", key))?;
+            stats.bytes_copied += entry.content()?.len() as u64;
+            indexedlog_cache.put_entry(entry)?;
+            stats.entries_copied += 1;
+        }
+
+        Ok(stats)
+    }
+
     #[allow(unused_must_use)]
     pub fn flush(&self) -> Result<()> {
         let mut result = Ok(());
@@ -375,12 +763,15 @@ impl FileStore {
             result = Err(error);
         };
 
-        if let Some(ref indexedlog_local) = self.indexedlog_local {
-            indexedlog_local.flush_log().map_err(&mut handle_error);
-        }
-
-        if let Some(ref indexedlog_cache) = self.indexedlog_cache {
-            indexedlog_cache.flush_log().map_err(&mut handle_error);
+        // The indexedlog flushes run on blocking threads, so awaiting them
+        // together overlaps their file I/O.
+        let flushes = self
+            .indexedlog_local
+            .iter()
+            .chain(self.indexedlog_cache.iter())
+            .map(|log| log.flush_async());
+        for flushed in futures::executor::block_on(futures::future::join_all(flushes)) {
+            flushed.map_err(&mut handle_error);
         }
 
         if let Some(ref lfs_local) = self.lfs_local {
@@ -395,15 +786,50 @@ impl FileStore {
             aux_cache.flush().map_err(&mut handle_error);
         }
 
+        if let Some(ref activity_logger) = self.activity_logger {
+            // Record any cache generations the flushes rotated out, so
+            // support can explain why the cache shrank.
+            for log in self
+                .indexedlog_local
+                .iter()
+                .chain(self.indexedlog_cache.iter())
+            {
+                if let Some(dropped) = log.take_last_rotation_drop() {
+                    activity_logger
+                        .lock()
+                        .log_cache_rotation(
+                            dropped.entries,
+                            dropped.bytes,
+                            dropped.max_log_count,
+                            dropped.max_bytes_per_log,
+                        )
+                        .map_err(&mut handle_error);
+                }
+            }
+            activity_logger.lock().flush().map_err(&mut handle_error);
+        }
+
         let mut metrics = self.metrics.write();
         for (k, v) in metrics.metrics() {
             hg_metrics::increment_counter(k, v as u64);
         }
+        // The fetch summary survives the reset - it is only cleared when the
+        // command takes it at the end.
+        let fetch_summary = std::mem::take(&mut metrics.fetch_summary);
         *metrics = Default::default();
+        metrics.fetch_summary = fetch_summary;
 
         result
     }
 
+    /// Block until all background write threads (large fetches, sibling
+    /// prefetches) have finished, so callers that skip `flush_on_drop` can
+    /// verify the caches are consistent before exiting.
+    pub fn wait_for_pending_writes(&self) -> Result<()> {
+        self.pending_writes.wait();
+        Ok(())
+    }
+
     pub fn refresh(&self) -> Result<()> {
         self.metrics.write().api.hg_refresh.call(0);
         self.flush()
@@ -413,16 +839,94 @@ impl FileStore {
         self.metrics.read().metrics().collect()
     }
 
+    /// Return the per-origin fetch totals accumulated since the last call
+    /// and reset them, so a command can report what it fetched at the end.
+    pub fn take_fetch_summary(&self) -> FetchSummary {
+        std::mem::take(&mut self.metrics.write().fetch_summary)
+    }
+
+    /// Enumerate the keys of everything in the local (non-cache) stores, for
+    /// diagnostic and export tools. A file present both as regular content
+    /// and as an LFS pointer is only yielded once. The LFS pointers store
+    /// doesn't record file paths, so keys coming from it have an empty path.
+    pub fn iter_local_keys(&self) -> impl Iterator<Item = Result<Key>> {
+        let mut seen = HashSet::new();
+        let mut keys = Vec::new();
+        for store_keys in self
+            .indexedlog_local
+            .iter()
+            .map(|store| store.to_keys())
+            .chain(self.lfs_local.iter().map(|store| store.to_keys()))
+        {
+            for key in store_keys {
+                match key {
+                    Ok(key) => {
+                        if seen.insert(key.hgid) {
+                            keys.push(Ok(key));
+                        }
+                    }
+                    Err(err) => keys.push(Err(err)),
+                }
+            }
+        }
+        keys.into_iter()
+    }
+
+    /// A snapshot of the settings this store ended up with, for debugging.
+    pub fn config_summary(&self) -> FileStoreConfigSummary {
+        FileStoreConfigSummary {
+            lfs_threshold_bytes: self.lfs_threshold_bytes,
+            edenapi_retries: self.edenapi_retries,
+            allow_write_lfs_ptrs: self.allow_write_lfs_ptrs,
+            compute_aux_data: self.compute_aux_data,
+            prefetch_aux_data: self.prefetch_aux_data,
+            max_prefetch_size: self.max_prefetch_size,
+            resolve_lfs_pointers: self.resolve_lfs_pointers,
+            lfs_range_requests: self.lfs_range_requests,
+            concurrent_cache_writers: self.concurrent_cache_writers,
+            local_lookup_threads: self.local_lookup_threads,
+            batch_size: self.batch_size,
+            warm_on_fetch_miss: self.warm_on_fetch_miss,
+            max_download_bytes_per_sec: self
+                .network_throttle
+                .as_ref()
+                .map(|throttle| throttle.bytes_per_sec()),
+            local_path: self.local_path.clone(),
+            cache_path: self.cache_path.clone(),
+            has_indexedlog_local: self.indexedlog_local.is_some(),
+            has_indexedlog_cache: self.indexedlog_cache.is_some(),
+            has_lfs_local: self.lfs_local.is_some(),
+            has_lfs_cache: self.lfs_cache.is_some(),
+            has_lfs_remote: self.lfs_remote.is_some(),
+            has_edenapi: self.edenapi.is_some(),
+            has_aux_cache: self.aux_cache.is_some(),
+            has_cas_client: self.cas_client.is_some(),
+        }
+    }
+
     pub fn empty() -> Self {
         FileStore {
             extstored_policy: ExtStoredPolicy::Ignore,
             lfs_threshold_bytes: None,
             edenapi_retries: 0,
             allow_write_lfs_ptrs: false,
+            hash_function: None,
 
             prefetch_aux_data: false,
             compute_aux_data: false,
             max_prefetch_size: 0,
+            prefetch_limits: PrefetchLimits::default(),
+            resolve_lfs_pointers: true,
+            lfs_range_requests: false,
+            lfs_remote_disabled: false,
+            concurrent_cache_writers: DEFAULT_CONCURRENT_CACHE_WRITERS,
+            local_lookup_threads: 1,
+            batch_size: None,
+            warm_on_fetch_miss: false,
+            client_cert_path: None,
+            network_throttle: None,
+            local_path: None,
+            cache_path: None,
 
             indexedlog_local: None,
             lfs_local: None,
@@ -436,14 +940,30 @@ impl FileStore {
 
             metrics: FileStoreMetrics::new(),
             activity_logger: None,
+            #[cfg(debug_assertions)]
+            request_dumper: None,
+            pending_writes: Default::default(),
 
             aux_cache: None,
 
             lfs_progress: AggregatingProgressBar::new("fetching", "LFS"),
+            edenapi_progress: AggregatingProgressBar::new("downloading", "files"),
             flush_on_drop: true,
         }
     }
 
+    /// Create a store with in-memory local and cache stores and all remote
+    /// stores disabled, so unit tests exercising `FileStore` logic need no
+    /// tempdir or config setup. Nothing is written to disk.
+    pub fn build_for_testing() -> Result<Self> {
+        let mut store = FileStore::empty();
+        store.indexedlog_local = Some(Arc::new(IndexedLogHgIdDataStore::new_for_testing()?));
+        store.indexedlog_cache = Some(Arc::new(IndexedLogHgIdDataStore::new_for_testing()?));
+        store.aux_cache = Some(Arc::new(AuxStore::new_for_testing()?));
+        store.compute_aux_data = true;
+        Ok(store)
+    }
+
     pub fn indexedlog_local(&self) -> Option<Arc<IndexedLogHgIdDataStore>> {
         self.indexedlog_local.clone()
     }
@@ -466,10 +986,23 @@ impl FileStore {
             lfs_threshold_bytes: self.lfs_threshold_bytes.clone(),
             edenapi_retries: self.edenapi_retries.clone(),
             allow_write_lfs_ptrs: self.allow_write_lfs_ptrs,
+            hash_function: self.hash_function.clone(),
 
             prefetch_aux_data: self.prefetch_aux_data,
             compute_aux_data: self.compute_aux_data,
             max_prefetch_size: self.max_prefetch_size,
+            prefetch_limits: self.prefetch_limits.clone(),
+            resolve_lfs_pointers: self.resolve_lfs_pointers,
+            lfs_range_requests: self.lfs_range_requests,
+            lfs_remote_disabled: self.lfs_remote_disabled,
+            concurrent_cache_writers: self.concurrent_cache_writers,
+            local_lookup_threads: self.local_lookup_threads,
+            batch_size: self.batch_size,
+            warm_on_fetch_miss: self.warm_on_fetch_miss,
+            client_cert_path: self.client_cert_path.clone(),
+            network_throttle: self.network_throttle.clone(),
+            local_path: self.local_path.clone(),
+            cache_path: self.cache_path.clone(),
 
             indexedlog_local: self.indexedlog_cache.clone(),
             lfs_local: self.lfs_cache.clone(),
@@ -483,10 +1016,14 @@ impl FileStore {
 
             metrics: self.metrics.clone(),
             activity_logger: self.activity_logger.clone(),
+            #[cfg(debug_assertions)]
+            request_dumper: self.request_dumper.clone(),
+            pending_writes: self.pending_writes.clone(),
 
             aux_cache: None,
 
             lfs_progress: self.lfs_progress.clone(),
+            edenapi_progress: self.edenapi_progress.clone(),
 
             // Conservatively flushing on drop here, didn't see perf problems and might be needed by Python
             flush_on_drop: true,
@@ -565,9 +1102,41 @@ impl HgIdDataStore for FileStore {
 }
 
 impl FileStore {
-    pub fn prefetch(&self, keys: Vec<Key>) -> Result<Vec<Key>> {
+    pub fn prefetch(&self, keys: Vec<Key>) -> Result<PrefetchResult> {
+        self.prefetch_with_cause(keys, None)
+    }
+
+    /// Same as `prefetch`, but with a `cause` string identifying the calling
+    /// code path, which is included in guardrail warnings (see
+    /// `scmstore.prefetch-warn-keys` and friends) so oversized prefetches can
+    /// be tracked back to their source.
+    pub fn prefetch_with_cause(
+        &self,
+        keys: Vec<Key>,
+        cause: Option<&str>,
+    ) -> Result<PrefetchResult> {
         self.metrics.write().api.hg_prefetch.call(keys.len());
 
+        let limits = &self.prefetch_limits;
+        let cause = cause.unwrap_or("unknown");
+
+        if let Some(max_keys) = limits.max_keys {
+            if keys.len() > max_keys {
+                bail!(
+                    "prefetch of {} keys (cause: {}) exceeds scmstore.prefetch-max-keys={}",
+                    keys.len(),
+                    cause,
+                    max_keys
+                );
+            }
+        }
+
+        if let Some(warn_keys) = limits.warn_keys {
+            if keys.len() > warn_keys {
+                self.log_prefetch_warning(cause, keys.len(), 0);
+            }
+        }
+
         let mut attrs = FileAttributes::CONTENT;
         if self.prefetch_aux_data {
             attrs |= FileAttributes::AUX;
@@ -580,19 +1149,367 @@ impl FileStore {
             max => max,
         };
 
-        for chunk in &keys.into_iter().chunks(max_size) {
-            missing.extend_from_slice(
-                &self
-                    .fetch(
-                        chunk,
-                        attrs,
-                        FetchMode::AllowRemote | FetchMode::IGNORE_RESULT,
-                    )
-                    .missing()?,
+        // Byte limits can only be checked by consuming the fetched content,
+        // so only pay that cost when one is configured.
+        let measure_bytes = limits.warn_bytes.is_some() || limits.max_bytes.is_some();
+        let key_count = keys.len();
+        let mut fetched_bytes: u64 = 0;
+
+        // Surface the split instead of doing it silently: callers are
+        // otherwise surprised that "prefetch finished" while later fetches
+        // still went remote.
+        let split = if key_count > max_size {
+            let chunks = (key_count + max_size - 1) / max_size;
+            tracing::warn!(
+                target: "revisionstore::prefetch",
+                cause,
+                keys = key_count,
+                chunks,
+                max_prefetch_size = max_size,
+                "prefetch split by max-prefetch-size",
             );
+            {
+                let mut metrics = self.metrics.write();
+                metrics.prefetch.splits += 1;
+                metrics.prefetch.overflow_keys += key_count - max_size;
+            }
+            if let Some(activity_logger) = &self.activity_logger {
+                if let Err(err) = activity_logger.lock().log_prefetch_split(
+                    cause.to_string(),
+                    key_count,
+                    chunks,
+                    max_size,
+                ) {
+                    tracing::error!("Error writing activity log: {}", err);
+                }
+            }
+            Some(PrefetchSplit {
+                keys: key_count,
+                chunks,
+                max_prefetch_size: max_size,
+            })
+        } else {
+            None
+        };
+
+        for chunk in &keys.into_iter().chunks(max_size) {
+            if !measure_bytes {
+                missing.extend_from_slice(
+                    &self
+                        .fetch(
+                            chunk,
+                            attrs,
+                            FetchMode::AllowRemote | FetchMode::IGNORE_RESULT,
+                        )
+                        .missing()?,
+                );
+                continue;
+            }
+
+            for result in self.fetch(chunk, attrs, FetchMode::AllowRemote) {
+                match result {
+                    Ok((_key, mut file)) => {
+                        fetched_bytes += file.file_content()?.len() as u64;
+                        if let Some(max_bytes) = limits.max_bytes {
+                            if fetched_bytes > max_bytes {
+                                bail!(
+                                    "prefetch (cause: {}) exceeds scmstore.prefetch-max-bytes={} after {} bytes",
+                                    cause,
+                                    max_bytes,
+                                    fetched_bytes
+                                );
+                            }
+                        }
+                    }
+                    Err(KeyFetchError::KeyedError(KeyedError(key, _err))) => {
+                        missing.push(key);
+                    }
+                    Err(KeyFetchError::Other(err)) => {
+                        return Err(err);
+                    }
+                }
+            }
         }
 
-        Ok(missing)
+        if let Some(warn_bytes) = limits.warn_bytes {
+            if fetched_bytes > warn_bytes {
+                self.log_prefetch_warning(cause, key_count, fetched_bytes);
+            }
+        }
+
+        Ok(PrefetchResult { missing, split })
+    }
+
+    /// Fetch only `len` bytes of content for `key` starting at `offset`,
+    /// along with the file's total size.
+    ///
+    /// Non-LFS entries are bounded by the LFS threshold, so they are loaded
+    /// whole and sliced. LFS blobs are sliced directly out of the local
+    /// stores without assembling the whole blob. A blob that is only
+    /// available remotely is downloaded into the cache first, unless
+    /// `lfs.allow-range-requests` is set, in which case only the requested
+    /// byte range is transferred (and nothing is cached).
+    pub fn fetch_range(&self, key: Key, offset: u64, len: u64) -> Result<Option<(Bytes, u64)>> {
+        let range = offset..offset.saturating_add(len);
+
+        for lfs_store in [&self.lfs_cache, &self.lfs_local].into_iter().flatten() {
+            if let Some(found) = lfs_store.get_blob_range(&key, range.clone())? {
+                return Ok(Some(found));
+            }
+        }
+
+        let mut pointer: Option<LfsPointersEntry> = None;
+        for log in [&self.indexedlog_cache, &self.indexedlog_local]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(entry) = log.get_raw_entry(&key.hgid)? {
+                if entry.metadata().is_lfs() {
+                    pointer = Some(entry.try_into()?);
+                    break;
+                }
+                let content = LazyFile::IndexedLog(entry).file_content()?;
+                return Ok(Some(slice_range(content, range)));
+            }
+        }
+
+        // The pointer store may know about the blob even when the blob itself
+        // is missing locally.
+        if pointer.is_none() {
+            for lfs_store in [&self.lfs_cache, &self.lfs_local].into_iter().flatten() {
+                if let Some(LfsStoreEntry::PointerOnly(ptr)) =
+                    lfs_store.fetch_available(&StoreKey::hgid(key.clone()), false)?
+                {
+                    pointer = Some(ptr);
+                    break;
+                }
+            }
+        }
+
+        if self.lfs_range_requests {
+            if let (Some(pointer), Some(lfs_remote)) = (&pointer, &self.lfs_remote) {
+                let total_size = pointer.size();
+                let range = range.start.min(total_size)..range.end.min(total_size);
+                return Ok(lfs_remote
+                    .remote
+                    .fetch_range(&pointer.sha256(), total_size, range)?
+                    .map(|data| (data, total_size)));
+            }
+        }
+
+        // Fetch the whole file through the regular pipeline, which writes LFS
+        // blobs to the cache, and slice the result.
+        match self
+            .fetch(
+                std::iter::once(key),
+                FileAttributes::PURE_CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .single()?
+        {
+            Some(mut file) => Ok(Some(slice_range(file.file_content()?, range))),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch aux data (sha1, blake3, size) for a batch of keys. Each key is
+    /// looked up in `aux_cache` first; the misses are fetched through the
+    /// regular pipeline in a single batch, which writes remotely fetched aux
+    /// data back to `aux_cache`. Cache hits are yielded before any remote
+    /// results start streaming.
+    pub fn get_content_metadata_batch(
+        &self,
+        keys: &[Key],
+    ) -> impl Stream<Item = Result<(Key, FileAuxData)>> {
+        let mut hits: Vec<Result<(Key, FileAuxData)>> = Vec::new();
+        let mut misses: Vec<Key> = Vec::new();
+        for key in keys {
+            let cached = match &self.aux_cache {
+                Some(aux_cache) => aux_cache.get(key.hgid),
+                None => Ok(None),
+            };
+            match cached {
+                Ok(Some(aux_data)) => hits.push(Ok((key.clone(), aux_data))),
+                Ok(None) => misses.push(key.clone()),
+                Err(err) => hits.push(Err(err)),
+            }
+        }
+
+        let this = self.clone();
+        stream::iter(hits).chain(
+            stream::once(
+                spawn_blocking(move || {
+                    if misses.is_empty() {
+                        return Vec::new();
+                    }
+                    this.fetch(misses, FileAttributes::AUX, FetchMode::AllowRemote)
+                        .into_iter()
+                        .map(|res| match res {
+                            Ok((key, file)) => Ok((key, file.aux_data()?)),
+                            Err(err) => Err(err.into()),
+                        })
+                        .collect::<Vec<Result<(Key, FileAuxData)>>>()
+                })
+                .map(|res| match res {
+                    Ok(results) => results,
+                    Err(err) => vec![Err(err.into())],
+                }),
+            )
+            .flat_map(stream::iter),
+        )
+    }
+
+    /// Recompute aux data (sha1, blake3, size) from the raw content of every
+    /// entry in `indexedlog_cache` and write it to `aux_cache`, for use when
+    /// the aux cache has been corrupted or cleared. Entries are processed one
+    /// at a time so the whole cache is never held in memory, yielding the
+    /// `HgId` of each successfully rebuilt entry. LFS pointer entries are
+    /// skipped since their content isn't available locally.
+    pub fn rebuild_aux_cache_from_content(&self) -> impl Stream<Item = Result<HgId>> {
+        let indexedlog_cache = self.indexedlog_cache.clone();
+        let aux_cache = self.aux_cache.clone();
+
+        let keys = match (&indexedlog_cache, &aux_cache) {
+            (Some(indexedlog_cache), Some(_)) => indexedlog_cache.iter_keys(),
+            _ => vec![Err(anyhow!(
+                "rebuilding the aux cache requires both an indexedlog cache and an aux cache"
+            ))],
+        };
+
+        stream::iter(keys.into_iter().filter_map(move |key| {
+            let result: Result<Option<HgId>> = (|| {
+                let key = key?;
+                let indexedlog_cache = indexedlog_cache.as_ref().unwrap();
+                let aux_cache = aux_cache.as_ref().unwrap();
+
+                let entry = indexedlog_cache
+                    .get_raw_entry(&key.hgid)?
+                    .ok_or_else(|| anyhow!("{} disappeared from cache during rebuild", key.hgid))?;
+                if entry.metadata().is_lfs() {
+                    return Ok(None);
+                }
+
+                let aux_data = LazyFile::IndexedLog(entry).aux_data()?;
+                aux_cache.put(key.hgid, &aux_data)?;
+                Ok(Some(key.hgid))
+            })();
+            result.transpose()
+        }))
+    }
+
+    /// Fetch the content of every locally known file whose path matches
+    /// `pattern`, a glob in [`TreeMatcher`] syntax (e.g. `**/*.rs`).
+    /// Candidate keys come from the local and cache indexedlog stores,
+    /// since the aux cache is keyed by hash alone and doesn't record
+    /// paths. Matching files are fetched in chunks as the stream is
+    /// polled, with a few chunks in flight at a time.
+    pub fn prefetch_by_glob(&self, pattern: &str) -> impl Stream<Item = Result<StoreFile>> {
+        const CHUNK_SIZE: usize = 1000;
+        const CONCURRENT_CHUNKS: usize = 4;
+
+        let mut errors: Vec<anyhow::Error> = Vec::new();
+        let mut matched: Vec<Key> = Vec::new();
+        match TreeMatcher::from_rules(std::iter::once(pattern), true) {
+            Ok(matcher) => {
+                let mut seen = HashSet::new();
+                for store in [&self.indexedlog_cache, &self.indexedlog_local]
+                    .into_iter()
+                    .flatten()
+                {
+                    for key in store.iter_keys() {
+                        match key {
+                            Ok(key) => {
+                                if matcher.matches(key.path.as_str()) && seen.insert(key.clone()) {
+                                    matched.push(key);
+                                }
+                            }
+                            Err(err) => errors.push(err),
+                        }
+                    }
+                }
+            }
+            Err(err) => errors.push(err.into()),
+        }
+
+        let this = self.clone();
+        let chunks: Vec<Vec<Key>> = matched
+            .into_iter()
+            .chunks(CHUNK_SIZE)
+            .into_iter()
+            .map(|chunk| chunk.collect())
+            .collect();
+
+        stream::iter(errors.into_iter().map(Err)).chain(
+            stream::iter(chunks)
+                .map(move |chunk| {
+                    let this = this.clone();
+                    spawn_blocking(move || {
+                        this.fetch(chunk, FileAttributes::CONTENT, FetchMode::AllowRemote)
+                            .into_iter()
+                            .map(|res| match res {
+                                Ok((_key, file)) => Ok(file),
+                                Err(err) => Err(err.into()),
+                            })
+                            .collect::<Vec<Result<StoreFile>>>()
+                    })
+                    .map(|res| match res {
+                        Ok(results) => results,
+                        Err(err) => vec![Err(err.into())],
+                    })
+                })
+                .buffer_unordered(CONCURRENT_CHUNKS)
+                .flat_map(stream::iter),
+        )
+    }
+
+    /// Speculatively warm the caches with files adjacent to `missed`,
+    /// akin to OS read-ahead: a miss on `foo/bar/baz.rs` suggests the
+    /// rest of `foo/bar` will be wanted soon. The prefetch runs on a
+    /// background thread and any errors are ignored; it only ever
+    /// widens the caches.
+    fn warm_siblings(&self, missed: &[Key]) {
+        let dirs: HashSet<String> = missed
+            .iter()
+            .filter_map(|key| key.path.parent())
+            .map(|dir| dir.to_string())
+            .collect();
+        if dirs.is_empty() {
+            return;
+        }
+
+        let this = self.clone();
+        let pending_write_guard = self.pending_writes.clone().guard();
+        std::thread::spawn(move || {
+            let _pending_write_guard = pending_write_guard;
+            for dir in dirs {
+                let pattern = if dir.is_empty() {
+                    "*".to_string()
+                } else {
+                    format!("{}/*", dir)
+                };
+                let fetched = this.prefetch_by_glob(&pattern);
+                futures::executor::block_on(fetched.for_each(|_| futures::future::ready(())));
+            }
+        });
+    }
+
+    fn log_prefetch_warning(&self, cause: &str, keys: usize, bytes: u64) {
+        tracing::warn!(
+            target: "revisionstore::prefetch",
+            cause,
+            keys,
+            bytes,
+            "oversized prefetch call",
+        );
+        if let Some(activity_logger) = &self.activity_logger {
+            if let Err(err) =
+                activity_logger
+                    .lock()
+                    .log_prefetch_warning(cause.to_string(), keys, bytes)
+            {
+                tracing::error!("Error writing activity log: {}", err);
+            }
+        }
     }
 }
 
@@ -605,6 +1522,7 @@ impl RemoteDataStore for FileStore {
                     .filter_map(|sk| sk.maybe_into_key())
                     .collect(),
             )?
+            .missing
             .into_iter()
             .map(StoreKey::HgId)
             .collect();
@@ -613,6 +1531,12 @@ impl RemoteDataStore for FileStore {
 
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         self.metrics.write().api.hg_upload.call(keys.len());
+        if self.lfs_remote_disabled {
+            if let Some(key) = keys.first() {
+                bail!("LFS remote disabled; cannot upload {:?}", key);
+            }
+            return Ok(vec![]);
+        }
         // TODO(meyer): Eliminate usage of legacy API, or at least minimize it (do we really need multiplex, etc)
         if let Some(ref lfs_remote) = self.lfs_remote {
             let mut multiplex = MultiplexDeltaStore::new();
@@ -706,3 +1630,433 @@ impl ContentDataStore for FileStore {
         )
     }
 }
+
+/// Slice `range` out of `content`, clamping it to the content size, and
+/// return it along with the total size.
+fn slice_range(content: Bytes, range: std::ops::Range<u64>) -> (Bytes, u64) {
+    let total_size = content.len() as u64;
+    let start = range.start.min(total_size) as usize;
+    let end = range.end.min(total_size) as usize;
+    (content.slice(start..end), total_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+
+    use ::types::testutil::*;
+    use ::types::Parents;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::edenapi::File as FileMarker;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+    use crate::indexedlogutil::StoreType;
+    use crate::testutil::FakeSaplingRemoteApi;
+    use crate::SaplingRemoteApiRemoteStore;
+
+    #[test]
+    fn test_prefetch_streams_content_to_cache() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let cache = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &tmp,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?);
+
+        let small = Bytes::from(&b"small"[..]);
+        let large = Bytes::from(&b"a much larger file blob"[..]);
+        let small_key = Key::new(
+            repo_path_buf("a"),
+            HgId::from_content(&small, Parents::None),
+        );
+        let large_key = Key::new(
+            repo_path_buf("b"),
+            HgId::from_content(&large, Parents::None),
+        );
+
+        let mut store = FileStore::empty();
+        store.indexedlog_cache = Some(cache);
+        store.concurrent_cache_writers = 1;
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(
+            FakeSaplingRemoteApi::new()
+                .files(vec![
+                    (small_key.clone(), small.clone()),
+                    (large_key.clone(), large.clone()),
+                ])
+                .into_arc(),
+        ));
+
+        let (fetched, key_errors, other_errors) = store
+            .fetch(
+                [small_key.clone(), large_key.clone()].into_iter(),
+                FileAttributes::CONTENT,
+                FetchMode::AllowRemote | FetchMode::IGNORE_RESULT,
+            )
+            .consume();
+        assert!(key_errors.is_empty());
+        assert!(other_errors.is_empty());
+        // IGNORE_RESULT fetches don't return entries to the caller.
+        assert!(fetched.is_empty());
+
+        // Both blobs were written back to the cache as they streamed in.
+        let cache = store.indexedlog_cache.as_ref().unwrap();
+        assert!(cache.get_entry(small_key)?.is_some());
+        assert!(cache.get_entry(large_key)?.is_some());
+
+        // With a single cache writer, each blob is dropped right after its
+        // cache write, so the peak never exceeds the largest single blob.
+        let metrics: HashMap<String, usize> = store.metrics().into_iter().collect();
+        assert_eq!(
+            metrics.get("scmstore.file.fetch.edenapi.bytes_peak"),
+            Some(&large.len())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_dumper_replay() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dump_path = tmp.path().join("requests.ndjson");
+
+        let mut store = FileStore::build_for_testing()?;
+        store.request_dumper = Some(Arc::new(Mutex::new(std::fs::File::create(&dump_path)?)));
+
+        let a = Bytes::from(&b"file a"[..]);
+        let b = Bytes::from(&b"file b"[..]);
+        let key_a = Key::new(repo_path_buf("a"), HgId::from_content(&a, Parents::None));
+        let key_b = Key::new(repo_path_buf("b"), HgId::from_content(&b, Parents::None));
+        let missing_key = key("c", "1");
+        store.write_batch(
+            [
+                (key_a.clone(), a, Metadata::default()),
+                (key_b.clone(), b, Metadata::default()),
+            ]
+            .into_iter(),
+        )?;
+
+        // Each fetch is recorded as one dump line, including the miss.
+        store
+            .fetch(
+                std::iter::once(key_a.clone()),
+                FileAttributes::CONTENT,
+                FetchMode::LocalOnly,
+            )
+            .consume();
+        store
+            .fetch(
+                [key_a.clone(), key_b.clone()].into_iter(),
+                FileAttributes::CONTENT,
+                FetchMode::LocalOnly,
+            )
+            .consume();
+        store
+            .fetch(
+                std::iter::once(missing_key.clone()),
+                FileAttributes::CONTENT,
+                FetchMode::LocalOnly,
+            )
+            .consume();
+
+        let dump = std::fs::read_to_string(&dump_path)?;
+        assert_eq!(dump.lines().count(), 3);
+
+        // Stop dumping so the replayed fetches don't extend the dump.
+        store.request_dumper = None;
+
+        let results = futures::executor::block_on(
+            store.replay_from_dump(&dump_path).collect::<Vec<_>>(),
+        );
+        assert_eq!(results.len(), 4);
+        let replayed: Vec<&Key> = results.iter().filter_map(|res| res.as_ref().ok()).collect();
+        assert_eq!(replayed, vec![&key_a, &key_a, &key_b]);
+        assert!(results[3].is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_local_to_cache() -> Result<()> {
+        let local_dir = TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let local = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &local_dir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Permanent,
+        )?);
+        let cache = Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            &cache_dir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Rotated,
+        )?);
+
+        let mut store = FileStore::empty();
+        store.indexedlog_local = Some(local);
+        store.indexedlog_cache = Some(cache);
+
+        let committed = Bytes::from(&b"committed content"[..]);
+        let committed_key = Key::new(
+            repo_path_buf("a"),
+            HgId::from_content(&committed, Parents::None),
+        );
+        let shared = Bytes::from(&b"already shared"[..]);
+        let shared_key = Key::new(
+            repo_path_buf("b"),
+            HgId::from_content(&shared, Parents::None),
+        );
+        store.write_batch(
+            [
+                (committed_key.clone(), committed.clone(), Metadata::default()),
+                (shared_key.clone(), shared.clone(), Metadata::default()),
+            ]
+            .into_iter(),
+        )?;
+        // The cache already has one of the entries.
+        store
+            .indexedlog_cache
+            .as_ref()
+            .unwrap()
+            .put_entry(Entry::new(
+                shared_key.clone(),
+                shared.clone(),
+                Metadata::default(),
+            ))?;
+
+        let stats = store.copy_local_to_cache()?;
+        assert_eq!(
+            stats,
+            CopyStats {
+                entries_copied: 1,
+                bytes_copied: committed.len() as u64,
+                entries_skipped: 1,
+            }
+        );
+
+        // The committed entry is now readable from the cache.
+        let cache = store.indexedlog_cache.as_ref().unwrap();
+        let entry = cache
+            .get_entry(committed_key)?
+            .expect("entry not copied to cache");
+        assert_eq!(entry.content()?, committed);
+
+        // A second pass has nothing left to copy.
+        let stats = store.copy_local_to_cache()?;
+        assert_eq!(
+            stats,
+            CopyStats {
+                entries_copied: 0,
+                bytes_copied: 0,
+                entries_skipped: 2,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_retries_transient_key_errors() -> Result<()> {
+        let good = Bytes::from(&b"good"[..]);
+        let flaky = Bytes::from(&b"flaky"[..]);
+        let good_key = Key::new(repo_path_buf("a"), HgId::from_content(&good, Parents::None));
+        let flaky_key = Key::new(
+            repo_path_buf("b"),
+            HgId::from_content(&flaky, Parents::None),
+        );
+
+        // The server fails one key twice with a retriable error before
+        // serving it; the client re-requests just that key.
+        let client = FakeSaplingRemoteApi::new()
+            .files(vec![
+                (good_key.clone(), good.clone()),
+                (flaky_key.clone(), flaky.clone()),
+            ])
+            .fail_key_transiently(flaky_key.clone(), 2)
+            .into_arc();
+
+        let mut store = FileStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(
+            client.clone(),
+        ));
+        store.edenapi_retries = 3;
+
+        let (fetched, key_errors, other_errors) = store
+            .fetch(
+                [good_key.clone(), flaky_key.clone()].into_iter(),
+                FileAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(fetched.len(), 2);
+        assert!(key_errors.is_empty());
+        assert!(other_errors.is_empty());
+        // The full batch, then the failed key re-requested twice.
+        assert_eq!(client.file_request_sizes(), vec![2, 1, 1]);
+        let metrics: HashMap<String, usize> = store.metrics().into_iter().collect();
+        assert_eq!(metrics.get("scmstore.file.fetch.edenapi.retried"), Some(&1));
+
+        // Once retries are exhausted the key fails, but the others still
+        // succeed.
+        let client = FakeSaplingRemoteApi::new()
+            .files(vec![(good_key.clone(), good), (flaky_key.clone(), flaky)])
+            .fail_key_transiently(flaky_key.clone(), 2)
+            .into_arc();
+
+        let mut store = FileStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(
+            client.clone(),
+        ));
+        store.edenapi_retries = 1;
+
+        let (fetched, key_errors, _other_errors) = store
+            .fetch(
+                [good_key, flaky_key.clone()].into_iter(),
+                FileAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(key_errors.len(), 1);
+        assert!(key_errors.contains_key(&flaky_key));
+        assert_eq!(client.file_request_sizes(), vec![2, 1]);
+
+        Ok(())
+    }
+
+    /// An hg file blob with a copy header pointing at `other/file`, plus the
+    /// key the copy info should parse to.
+    fn blob_with_copy_header() -> (Bytes, Key) {
+        let copy_hgid = hgid("1234");
+        let header = format!(
+            "\x01\ncopy: other/file\ncopyrev: {}\n\x01\n",
+            copy_hgid.to_hex()
+        );
+        let blob = Bytes::from(format!("{}file content", header));
+        let copy_from = Key::new(repo_path_buf("other/file"), copy_hgid);
+        (blob, copy_from)
+    }
+
+    #[test]
+    fn test_header_from_local_content() -> Result<()> {
+        let store = FileStore::build_for_testing()?;
+
+        let (blob, copy_from) = blob_with_copy_header();
+        let key = Key::new(repo_path_buf("a"), HgId::from_content(&blob, Parents::None));
+        store.write_batch(std::iter::once((key.clone(), blob, Metadata::default())))?;
+
+        // The header is parsed from the locally stored entry.
+        let mut file = store
+            .fetch([key], FileAttributes::HEADER, FetchMode::LocalOnly)
+            .single()?
+            .expect("file not found");
+        assert_eq!(file.copy_info()?, Some(copy_from));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_from_local_aux_data() -> Result<()> {
+        let store = FileStore::build_for_testing()?;
+
+        let (blob, copy_from) = blob_with_copy_header();
+        let (_, header) = hgstore::split_hg_file_metadata(&blob);
+        let key = Key::new(repo_path_buf("a"), HgId::from_content(&blob, Parents::None));
+
+        // Only aux data is staged - no content anywhere.
+        let mut aux = FileAuxData::from_content(&blob);
+        aux.file_header_metadata = Some(header.to_vec().into());
+        store.aux_cache.as_ref().unwrap().put(key.hgid, &aux)?;
+
+        let mut file = store
+            .fetch([key], FileAttributes::HEADER, FetchMode::LocalOnly)
+            .single()?
+            .expect("file not found");
+        assert_eq!(file.copy_info()?, Some(copy_from));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_from_remote() -> Result<()> {
+        let (blob, copy_from) = blob_with_copy_header();
+        let key = Key::new(repo_path_buf("a"), HgId::from_content(&blob, Parents::None));
+
+        // Served from aux data's file_header_metadata in a single request,
+        // without downloading content.
+        let fake = FakeSaplingRemoteApi::new()
+            .files(vec![(key.clone(), blob.clone())])
+            .into_arc();
+        let mut store = FileStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(fake.clone()));
+        let mut file = store
+            .fetch(
+                [key.clone()],
+                FileAttributes::HEADER,
+                FetchMode::AllowRemote,
+            )
+            .single()?
+            .expect("file not found");
+        assert_eq!(file.copy_info()?, Some(copy_from.clone()));
+        assert_eq!(fake.file_request_sizes(), vec![1]);
+
+        // A server that doesn't populate file_header_metadata triggers the
+        // fallback content fetch.
+        let fake = FakeSaplingRemoteApi::new()
+            .files(vec![(key.clone(), blob)])
+            .omit_aux_file_header()
+            .into_arc();
+        let mut store = FileStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<FileMarker>::new(fake.clone()));
+        let mut file = store
+            .fetch([key], FileAttributes::HEADER, FetchMode::AllowRemote)
+            .single()?
+            .expect("file not found");
+        assert_eq!(file.copy_info()?, Some(copy_from));
+        assert_eq!(fake.file_request_sizes(), vec![1, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_pending_writes() -> Result<()> {
+        let store = FileStore::build_for_testing()?;
+
+        // Nothing in flight - returns immediately.
+        store.wait_for_pending_writes()?;
+
+        // Simulate a background write thread holding a guard.
+        let finished = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let guard = store.pending_writes.clone().guard();
+        let handle = std::thread::spawn({
+            let finished = finished.clone();
+            move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                finished.store(true, std::sync::atomic::Ordering::Release);
+                drop(guard);
+            }
+        });
+
+        store.wait_for_pending_writes()?;
+        assert!(finished.load(std::sync::atomic::Ordering::Acquire));
+        handle.join().unwrap();
+
+        Ok(())
+    }
+}
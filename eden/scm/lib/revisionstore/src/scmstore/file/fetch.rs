@@ -7,6 +7,9 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -19,8 +22,11 @@ use cas_client::CasClient;
 use clientinfo::get_client_request_info_thread_local;
 use clientinfo_async::with_client_request_info_scope;
 use crossbeam::channel::Sender;
+use edenapi::Stats;
 use edenapi_types::FileResponse;
 use edenapi_types::FileSpec;
+use edenapi_types::ServerError;
+use edenapi_types::ServerErrorKind;
 use futures::StreamExt;
 use futures::TryFutureExt;
 use minibytes::Bytes;
@@ -31,6 +37,7 @@ use types::errors::NetworkError;
 use types::fetch_mode::FetchMode;
 use types::CasDigest;
 use types::CasDigestType;
+use types::HgId;
 use types::Key;
 use types::Sha256;
 
@@ -43,6 +50,7 @@ use crate::lfs::LfsRemote;
 use crate::lfs::LfsStore;
 use crate::lfs::LfsStoreEntry;
 use crate::scmstore::attrs::StoreAttrs;
+use crate::scmstore::cert_skew;
 use crate::scmstore::fetch::CommonFetchState;
 use crate::scmstore::fetch::FetchErrors;
 use crate::scmstore::fetch::KeyFetchError;
@@ -54,6 +62,7 @@ use crate::scmstore::FileAttributes;
 use crate::scmstore::FileAuxData;
 use crate::scmstore::FileStore;
 use crate::scmstore::StoreFile;
+use crate::throttle::NetworkThrottle;
 use crate::util;
 use crate::ContentHash;
 use crate::ExtStoredPolicy;
@@ -72,15 +81,44 @@ pub struct FetchState {
 
     lfs_progress: Arc<AggregatingProgressBar>,
 
+    edenapi_progress: Arc<AggregatingProgressBar>,
+
     /// Track fetch metrics,
     metrics: FileStoreFetchMetrics,
 
     // Config
     extstored_policy: ExtStoredPolicy,
     compute_aux_data: bool,
+    concurrent_cache_writers: usize,
 
     lfs_enabled: bool,
 
+    /// Transparently resolve LFS pointers found in the indexedlog layers
+    /// through the LFS stores instead of returning pointer bytes as content.
+    /// Configured by scmstore.resolve-lfs-pointers.
+    resolve_lfs_pointers: bool,
+
+    /// Number of threads probing the indexedlog stores for a large batch.
+    /// Configured by scmstore.local-lookup-threads.
+    local_lookup_threads: usize,
+
+    /// Largest number of keys sent to the SaplingRemoteAPI server in a
+    /// single request. Configured by scmstore.file-batch-size.
+    batch_size: Option<usize>,
+
+    /// Number of times a key that failed with a retriable per-key server
+    /// error is re-requested before being reported as an error.
+    /// Configured by scmstore.retries.
+    edenapi_retries: i32,
+
+    /// Client certificate for the SaplingRemoteAPI URL, used to explain
+    /// TLS failures caused by an expired certificate or a skewed clock.
+    client_cert_path: Option<PathBuf>,
+
+    /// Caps SaplingRemoteAPI download bandwidth. Configured by
+    /// scmstore.max-download-bytes-per-sec.
+    network_throttle: Option<Arc<NetworkThrottle>>,
+
     fetch_mode: FetchMode,
 }
 
@@ -102,8 +140,16 @@ impl FetchState {
 
             extstored_policy: file_store.extstored_policy,
             compute_aux_data: file_store.compute_aux_data,
+            concurrent_cache_writers: file_store.concurrent_cache_writers,
             lfs_progress: file_store.lfs_progress.clone(),
+            edenapi_progress: file_store.edenapi_progress.clone(),
             lfs_enabled,
+            resolve_lfs_pointers: file_store.resolve_lfs_pointers,
+            local_lookup_threads: file_store.local_lookup_threads,
+            batch_size: file_store.batch_size,
+            edenapi_retries: file_store.edenapi_retries,
+            client_cert_path: file_store.client_cert_path.clone(),
+            network_throttle: file_store.network_throttle.clone(),
             fetch_mode,
         }
     }
@@ -155,6 +201,15 @@ impl FetchState {
     }
 
     fn found_pointer(&mut self, key: Key, ptr: LfsPointersEntry, write: bool) {
+        if self.common.request_attrs.lfs_pointer {
+            self.found_attributes(
+                key.clone(),
+                StoreFile {
+                    lfs_pointer: Some(ptr.clone()),
+                    ..Default::default()
+                },
+            );
+        }
         self.lfs_pointers.insert(key, (ptr, write));
     }
 
@@ -207,19 +262,85 @@ impl FetchState {
         }
     }
 
+    /// Probe `store` for `pending` across a small thread pool, returning the
+    /// raw lookup results keyed by node. Returns `None` when the batch is
+    /// small or scmstore.local-lookup-threads is 1, in which case the caller
+    /// probes inline, reproducing the single-threaded behavior exactly.
+    ///
+    /// A concurrent writer flushing can only add entries, so probing up
+    /// front never produces stale hits, only (harmless) misses for keys
+    /// written mid-fetch, which the next fetch phase picks up.
+    fn probe_indexedlog_parallel(
+        &self,
+        store: &IndexedLogHgIdDataStore,
+        pending: &[Key],
+    ) -> Option<HashMap<HgId, Result<Option<Entry>>>> {
+        // Sharding only pays off once each thread has a decent amount of work.
+        const MIN_KEYS_PER_THREAD: usize = 1000;
+
+        if self.fetch_mode.ignore_result() {
+            return None;
+        }
+        let threads = self
+            .local_lookup_threads
+            .min(pending.len() / MIN_KEYS_PER_THREAD);
+        if threads <= 1 {
+            return None;
+        }
+
+        let chunk_size = (pending.len() + threads - 1) / threads;
+        let mut results = HashMap::with_capacity(pending.len());
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = pending
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|key| (key.hgid, store.get_raw_entry(&key.hgid)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            for handle in handles {
+                if let Ok(chunk_results) = handle.join() {
+                    results.extend(chunk_results);
+                }
+            }
+        });
+        Some(results)
+    }
+
     pub(crate) fn fetch_indexedlog(
         &mut self,
         store: &IndexedLogHgIdDataStore,
         lfs_store: Option<&LfsStore>,
         loc: StoreLocation,
     ) {
-        let pending = self.pending_nonlfs(FileAttributes::CONTENT);
+        let pending = self.pending_nonlfs(FileAttributes::CONTENT | FileAttributes::LFS_POINTER);
         if pending.is_empty() {
             return;
         }
 
+        // Results are applied in pending-key order below, so a sharded probe
+        // merges back to the same outcome as the inline scan.
+        let mut probed = self.probe_indexedlog_parallel(store, &pending);
+
         let fetch_start = std::time::Instant::now();
 
+        let span = tracing::debug_span!(
+            target: "revisionstore::fetch",
+            "indexedlog_fetch",
+            location = match loc {
+                StoreLocation::Cache => "cache",
+                StoreLocation::Local => "local",
+            },
+            keys = pending.len(),
+            hits = field::Empty,
+            bytes = field::Empty,
+        );
+        let _enter = span.enter();
+
         debug!(
             "Checking store Indexedlog ({cache}) for {key}{more}",
             cache = match loc {
@@ -236,64 +357,82 @@ impl FetchState {
 
         let mut found = 0;
         let mut count = 0;
+        let mut bytes: u64 = 0;
         let mut errors = 0;
         let mut error: Option<String> = None;
         let mut lfs_pointers_to_upgrade = Vec::new();
 
-        self.metrics.indexedlog.store(loc).fetch(pending.len());
+        self.metrics.record_cache_fetch(loc, pending.len());
 
         self.common
-            .iter_pending(FileAttributes::CONTENT, self.compute_aux_data, |key| {
-                count += 1;
-
-                let res = if self.fetch_mode.ignore_result() {
-                    store.contains(&key.hgid).map(|contains| {
-                        if contains {
-                            // Insert a stub entry if caller is ignoring the results.
-                            Some(Entry::new(key.clone(), Bytes::new(), Metadata::default()))
-                        } else {
-                            None
-                        }
-                    })
-                } else {
-                    store.get_raw_entry(&key.hgid)
-                };
-
-                match res {
-                    Ok(Some(entry)) => {
-                        self.metrics.indexedlog.store(loc).hit(1);
-                        found += 1;
+            .iter_pending(
+                FileAttributes::CONTENT | FileAttributes::LFS_POINTER,
+                self.compute_aux_data,
+                |key| {
+                    count += 1;
+
+                    let res = if self.fetch_mode.ignore_result() {
+                        store.contains(&key.hgid).map(|contains| {
+                            if contains {
+                                // Insert a stub entry if caller is ignoring the results.
+                                Some(Entry::new(key.clone(), Bytes::new(), Metadata::default()))
+                            } else {
+                                None
+                            }
+                        })
+                    } else if let Some(probed) = probed.as_mut() {
+                        probed
+                            .remove(&key.hgid)
+                            .unwrap_or_else(|| store.get_raw_entry(&key.hgid))
+                    } else {
+                        store.get_raw_entry(&key.hgid)
+                    };
 
-                        if entry.metadata().is_lfs() && self.lfs_enabled {
-                            // This is mainly for tests. We are handling the transition
-                            // from the Python lfs extension (which stored pointers in the
-                            // regular file store), the remotefilelog lfs implementation
-                            // (which stores pointers in a separate store).
-                            if self.extstored_policy == ExtStoredPolicy::Use {
-                                lfs_pointers_to_upgrade.push((key.clone(), entry));
+                    match res {
+                        Ok(Some(entry)) => {
+                            self.metrics.record_cache_hit(loc, 1);
+                            found += 1;
+                            bytes += entry.metadata().size.unwrap_or(0);
+
+                            if entry.metadata().is_lfs()
+                                && (self.lfs_enabled || self.resolve_lfs_pointers)
+                            {
+                                // This is mainly for tests. We are handling the transition
+                                // from the Python lfs extension (which stored pointers in the
+                                // regular file store), the remotefilelog lfs implementation
+                                // (which stores pointers in a separate store).
+                                if self.extstored_policy == ExtStoredPolicy::Use
+                                    || self.resolve_lfs_pointers
+                                {
+                                    lfs_pointers_to_upgrade.push((key.clone(), entry));
+                                }
+                            } else {
+                                return Some(LazyFile::IndexedLog(entry).into());
                             }
-                        } else {
-                            return Some(LazyFile::IndexedLog(entry).into());
                         }
-                    }
-                    Ok(None) => {
-                        self.metrics.indexedlog.store(loc).miss(1);
-                    }
-                    Err(err) => {
-                        self.metrics.indexedlog.store(loc).err(1);
-                        errors += 1;
-                        if error.is_none() {
-                            error.replace(format!("{}: {}", key, err));
+                        Ok(None) => {
+                            self.metrics.record_cache_miss(loc, 1);
+                        }
+                        Err(err) => {
+                            self.metrics.record_cache_err(loc, 1);
+                            errors += 1;
+                            if error.is_none() {
+                                error.replace(format!("{}: {}", key, err));
+                            }
+                            self.errors.keyed_error(key.clone(), err);
                         }
-                        self.errors.keyed_error(key.clone(), err);
                     }
-                }
 
-                None
-            });
+                    None
+                },
+            );
 
         self.ugprade_lfs_pointers(lfs_pointers_to_upgrade, lfs_store);
 
+        span.record("hits", found);
+        span.record("bytes", bytes);
+
+        self.metrics.record_cache_bytes(loc, bytes as usize);
         self.metrics
             .indexedlog
             .store(loc)
@@ -331,6 +470,11 @@ impl FetchState {
         let ignore_results = self.fetch_mode.ignore_result() && !have_cas;
 
         let mut wants_aux = FileAttributes::AUX;
+        if self.common.request_attrs.content_header && !self.common.request_attrs.pure_content {
+            // Header-only fetches (e.g. copy tracing) can be satisfied by aux
+            // data's file_header_metadata without touching content.
+            wants_aux |= FileAttributes::HEADER;
+        }
         if have_cas && loc == StoreLocation::Cache {
             // Also fetch AUX data if we are going to try fetching from CAS. This does two things:
             // 1. Fetches hash and size info needed to query CAS for file contents.
@@ -359,7 +503,7 @@ impl FetchState {
                         if have_cas {
                             tracing::trace!(target: "cas", ?key, ?aux, "found file aux data");
                         }
-                        self.metrics.aux.store(loc).hit(1);
+                        self.metrics.record_aux_hit(loc, 1);
                         found += 1;
                         return Some(aux.into());
                     }
@@ -367,10 +511,10 @@ impl FetchState {
                         if have_cas {
                             tracing::trace!(target: "cas", ?key, "no file aux data");
                         }
-                        self.metrics.aux.store(loc).miss(1);
+                        self.metrics.record_aux_miss(loc, 1);
                     }
                     Err(err) => {
-                        self.metrics.aux.store(loc).err(1);
+                        self.metrics.record_aux_err(loc, 1);
                         errors += 1;
                         if error.is_none() {
                             error.replace(format!("{}: {}", key, err));
@@ -394,7 +538,7 @@ impl FetchState {
             },
         );
 
-        self.metrics.aux.store(loc).fetch(count);
+        self.metrics.record_aux_fetch(loc, count);
 
         self.metrics
             .aux
@@ -417,20 +561,37 @@ impl FetchState {
     fn found_lfs(&mut self, key: Key, entry: LfsStoreEntry) {
         match entry {
             LfsStoreEntry::PointerAndBlob(ptr, blob) => {
-                self.found_attributes(key, LazyFile::Lfs(blob, ptr).into())
+                let mut file: StoreFile = LazyFile::Lfs(blob, ptr.clone()).into();
+                if self.common.request_attrs.lfs_pointer {
+                    file.lfs_pointer = Some(ptr);
+                }
+                self.found_attributes(key, file)
             }
             LfsStoreEntry::PointerOnly(ptr) => self.found_pointer(key, ptr, false),
         }
     }
 
     pub(crate) fn fetch_lfs(&mut self, store: &LfsStore, loc: StoreLocation) {
-        let pending = self.pending_storekey(FileAttributes::CONTENT);
+        let pending = self.pending_storekey(FileAttributes::CONTENT | FileAttributes::LFS_POINTER);
         if pending.is_empty() {
             return;
         }
 
         let fetch_start = std::time::Instant::now();
 
+        let span = tracing::debug_span!(
+            target: "revisionstore::fetch",
+            "lfs_fetch",
+            location = match loc {
+                StoreLocation::Cache => "cache",
+                StoreLocation::Local => "local",
+            },
+            keys = pending.len(),
+            hits = field::Empty,
+            bytes = field::Empty,
+        );
+        let _enter = span.enter();
+
         debug!(
             "Checking store LFS ({cache}) - Count = {count}",
             cache = match loc {
@@ -442,10 +603,11 @@ impl FetchState {
 
         let mut found = 0;
         let mut found_pointers = 0;
+        let mut bytes: u64 = 0;
         let mut errors = 0;
         let mut error: Option<String> = None;
 
-        self.metrics.lfs.store(loc).fetch(pending.len());
+        self.metrics.record_lfs_fetch(loc, pending.len());
         for store_key in pending.into_iter() {
             let key = store_key.clone().maybe_into_key().expect(
                 "no Key present in StoreKey, even though this should be guaranteed by pending_all",
@@ -453,19 +615,22 @@ impl FetchState {
             match store.fetch_available(&store_key, self.fetch_mode.ignore_result()) {
                 Ok(Some(entry)) => {
                     // TODO(meyer): Make found behavior w/r/t LFS pointers and content consistent
-                    self.metrics.lfs.store(loc).hit(1);
+                    self.metrics.record_lfs_hit(loc, 1);
                     if let LfsStoreEntry::PointerOnly(_) = &entry {
                         found_pointers += 1;
                     } else {
                         found += 1;
                     }
+                    if let LfsStoreEntry::PointerAndBlob(_, blob) = &entry {
+                        bytes += blob.len() as u64;
+                    }
                     self.found_lfs(key, entry)
                 }
                 Ok(None) => {
-                    self.metrics.lfs.store(loc).miss(1);
+                    self.metrics.record_lfs_miss(loc, 1);
                 }
                 Err(err) => {
-                    self.metrics.lfs.store(loc).err(1);
+                    self.metrics.record_lfs_err(loc, 1);
                     errors += 1;
                     if error.is_none() {
                         error.replace(format!("{}: {}", key, err));
@@ -475,6 +640,10 @@ impl FetchState {
             }
         }
 
+        span.record("hits", found);
+        span.record("bytes", bytes);
+
+        self.metrics.record_lfs_bytes(loc, bytes as usize);
         self.metrics
             .lfs
             .store(loc)
@@ -504,6 +673,7 @@ impl FetchState {
         indexedlog_cache: Option<Arc<IndexedLogHgIdDataStore>>,
         lfs_cache: Option<Arc<LfsStore>>,
         aux_cache: Option<Arc<AuxStore>>,
+        ignore_result: bool,
     ) -> Result<(StoreFile, Option<LfsPointersEntry>)> {
         let entry = entry.result?;
 
@@ -526,6 +696,22 @@ impl FetchState {
                     lfs_cache.add_pointer(ptr.clone())?;
                 }
                 lfsptr = Some(ptr);
+            } else if ignore_result {
+                // The caller won't look at the content (e.g. a prefetch), so
+                // write it straight through to the cache and drop it instead
+                // of buffering the full blob. Insert a stub entry to mark the
+                // key found.
+                let content = LazyFile::SaplingRemoteApi(entry);
+                if let Some(indexedlog_cache) = indexedlog_cache.as_ref() {
+                    if let Some(cache_entry) = content.indexedlog_cache_entry(key.clone())? {
+                        indexedlog_cache.put_entry(cache_entry)?;
+                    }
+                }
+                file.content = Some(LazyFile::IndexedLog(Entry::new(
+                    key,
+                    Bytes::new(),
+                    Metadata::default(),
+                )));
             } else if let Some(indexedlog_cache) = indexedlog_cache.as_ref() {
                 file.content = Some(Self::evict_to_cache(
                     key,
@@ -554,18 +740,36 @@ impl FetchState {
             return;
         }
 
-        let mut fetching_keys: HashSet<Key> = pending.iter().cloned().collect();
-
-        let count = pending.len();
+        let mut count = pending.len();
         debug!("Fetching SaplingRemoteAPI - Count = {}", count);
 
+        let fetch_span = tracing::debug_span!(
+            target: "revisionstore::fetch",
+            "edenapi_fetch",
+            keys = count,
+            hits = field::Empty,
+            bytes = field::Empty,
+        );
+        let _fetch_enter = fetch_span.enter();
+
+        let prog = self.edenapi_progress.create_or_extend(count as u64);
+
         let mut found = 0;
         let mut found_pointers = 0;
         let mut errors = 0;
         let mut error: Option<String> = None;
+        let mut agg_stats = Stats::default();
+
+        let ignore_result = self.fetch_mode.ignore_result();
+
+        // Track how many fetched-content bytes are held in memory at once,
+        // so regressions to the streaming write-back show up as a growing
+        // peak rather than an OOM.
+        let bytes_held = Arc::new(AtomicUsize::new(0));
+        let bytes_peak = Arc::new(AtomicUsize::new(0));
 
         // TODO(meyer): Iterators or otherwise clean this up
-        let pending_attrs: Vec<_> = pending
+        let mut pending_attrs: Vec<_> = pending
             .into_iter()
             .map(|k| {
                 let actionable = self.common.actionable(&k, fetchable, self.compute_aux_data);
@@ -576,103 +780,243 @@ impl FetchState {
             })
             .collect();
 
-        // Fetch ClientRequestInfo from a thread local and pass to async code
-        let maybe_client_request_info = get_client_request_info_thread_local();
-        let response = match block_on(
-            with_client_request_info_scope(
-                maybe_client_request_info,
-                store.files_attrs(pending_attrs),
-            )
-            .map_err(|e| e.tag_network()),
-        ) {
-            Ok(r) => r,
-            Err(err) => {
-                let err = ClonableError::new(err);
-                for key in fetching_keys.into_iter() {
-                    self.errors.keyed_error(key, err.clone().into());
-                }
-                return;
-            }
-        };
+        // Split the request into bounded chunks so a server error only fails
+        // one chunk's keys rather than the whole batch. Progress and stats
+        // aggregate across chunks.
+        let batch_size = self.batch_size.unwrap_or(pending_attrs.len()).max(1);
+
+        let mut tried_header_fallback = false;
+        let mut retries_left = self.edenapi_retries.max(0);
+        let mut retried_keys: HashSet<Key> = HashSet::new();
+        while !pending_attrs.is_empty() {
+            let mut retriable = Vec::new();
+            for chunk in pending_attrs.chunks(batch_size) {
+                let mut fetching_keys: HashSet<Key> =
+                    chunk.iter().map(|spec| spec.key.clone()).collect();
 
-        let entries = response
-            .entries
-            .map(move |res_entry| {
                 let lfs_cache = lfs_cache.clone();
                 let indexedlog_cache = indexedlog_cache.clone();
                 let aux_cache = aux_cache.clone();
-                spawn_blocking(move || {
-                    res_entry.map(move |entry| {
-                        (
-                            entry.key.clone(),
-                            Self::found_edenapi(entry, indexedlog_cache, lfs_cache, aux_cache),
-                        )
-                    })
-                })
 
-                // Processing a response may involve compressing the response, which
-                // can be expensive. If we don't process entries fast enough, edenapi
-                // can start queueing up responses which causes forever increasing
-                // memory usage. So let's process responses in parallel to stay ahead
-                // of download speeds.
-            })
-            .buffer_unordered(4);
-
-        // Record found entries
-        let mut unknown_error: Option<ClonableError> = None;
-        for res in stream_to_iter(entries) {
-            // TODO(meyer): This outer SaplingRemoteApi error with no key sucks
-            let (key, res) = match res {
-                Ok(result) => match result.map_err(|e| e.tag_network()) {
-                    Ok(result) => result,
+                // Fetch ClientRequestInfo from a thread local and pass to async code
+                let maybe_client_request_info = get_client_request_info_thread_local();
+                let response = match block_on(
+                    with_client_request_info_scope(
+                        maybe_client_request_info,
+                        store.files_attrs(chunk.to_vec()),
+                    )
+                    .map_err(|e| e.tag_network()),
+                ) {
+                    Ok(r) => r,
                     Err(err) => {
-                        if unknown_error.is_none() {
-                            unknown_error.replace(ClonableError::new(err));
+                        let err = cert_skew::annotate_cert_skew(err, self.client_cert_path.as_deref());
+                        let err = ClonableError::new(err);
+                        for key in fetching_keys.into_iter() {
+                            self.errors.keyed_error(key, err.clone().into());
                         }
                         continue;
                     }
-                },
-                // JoinError
-                Err(err) => {
-                    if unknown_error.is_none() {
-                        unknown_error.replace(ClonableError::new(err.into()));
+                };
+
+                let network_throttle = self.network_throttle.clone();
+                let bytes_held = bytes_held.clone();
+                let bytes_peak = bytes_peak.clone();
+                let entries = response
+                    .entries
+                    .map(move |res_entry| {
+                        let lfs_cache = lfs_cache.clone();
+                        let indexedlog_cache = indexedlog_cache.clone();
+                        let aux_cache = aux_cache.clone();
+                        let network_throttle = network_throttle.clone();
+                        let bytes_held = bytes_held.clone();
+                        let bytes_peak = bytes_peak.clone();
+                        async move {
+                            let blob_len = res_entry
+                                .as_ref()
+                                .ok()
+                                .and_then(|entry| entry.result.as_ref().ok())
+                                .and_then(|entry| entry.content.as_ref())
+                                .map_or(0, |content| content.hg_file_blob.len());
+
+                            // Pause before processing so the transport's
+                            // back-pressure keeps the download at the capped rate.
+                            if let Some(throttle) = network_throttle {
+                                let wait = throttle.acquire(blob_len);
+                                if !wait.is_zero() {
+                                    tokio::time::sleep(wait).await;
+                                }
+                            }
+
+                            let held = bytes_held.fetch_add(blob_len, Ordering::Relaxed) + blob_len;
+                            bytes_peak.fetch_max(held, Ordering::Relaxed);
+
+                            // The blob stays in memory only if it is neither
+                            // evicted to the cache nor dropped as unwanted.
+                            let blob_released = indexedlog_cache.is_some() || ignore_result;
+
+                            let res = spawn_blocking(move || {
+                                res_entry.map(move |entry| {
+                                    (
+                                        entry.key.clone(),
+                                        Self::found_edenapi(
+                                            entry,
+                                            indexedlog_cache,
+                                            lfs_cache,
+                                            aux_cache,
+                                            ignore_result,
+                                        ),
+                                    )
+                                })
+                            })
+                            .await;
+
+                            if blob_released {
+                                bytes_held.fetch_sub(blob_len, Ordering::Relaxed);
+                            }
+
+                            res
+                        }
+
+                        // Processing a response may involve compressing the response, which
+                        // can be expensive. If we don't process entries fast enough, edenapi
+                        // can start queueing up responses which causes forever increasing
+                        // memory usage. So let's process responses in parallel to stay ahead
+                        // of download speeds. Each task writes its entry to the caches, so
+                        // this also parallelizes the cache write-back.
+                    })
+                    .buffer_unordered(self.concurrent_cache_writers.max(1));
+
+                // Record found entries
+                let mut unknown_error: Option<ClonableError> = None;
+                for res in stream_to_iter(entries) {
+                    // TODO(meyer): This outer SaplingRemoteApi error with no key sucks
+                    let (key, res) = match res {
+                        Ok(result) => match result.map_err(|e| e.tag_network()) {
+                            Ok(result) => result,
+                            Err(err) => {
+                                if unknown_error.is_none() {
+                                    unknown_error.replace(ClonableError::new(err));
+                                }
+                                continue;
+                            }
+                        },
+                        // JoinError
+                        Err(err) => {
+                            if unknown_error.is_none() {
+                                unknown_error.replace(ClonableError::new(err.into()));
+                            }
+                            continue;
+                        }
+                    };
+
+                    fetching_keys.remove(&key);
+                    prog.increase_position(1);
+                    match res {
+                        Ok((file, maybe_lfsptr)) => {
+                            if retried_keys.remove(&key) {
+                                self.metrics.edenapi.retried(1);
+                            }
+                            if let Some(lfsptr) = maybe_lfsptr {
+                                found_pointers += 1;
+                                self.found_pointer(key.clone(), lfsptr, false);
+                            } else {
+                                found += 1;
+                            }
+                            self.found_attributes(key, file);
+                        }
+                        // A keyed error only affects that key. Classify it:
+                        // transient failures are worth a re-request, missing
+                        // keys are left unfetched so they are reported as
+                        // missing, anything else fails just this key.
+                        Err(err) => match err.downcast_ref::<ServerError>().map(|e| e.kind()) {
+                            Some(ServerErrorKind::Retriable) => retriable.push((key, err)),
+                            Some(ServerErrorKind::NotFound) => {
+                                debug!("server reported {} as not found", key);
+                            }
+                            _ => {
+                                errors += 1;
+                                if error.is_none() {
+                                    error.replace(format!("{}: {}", key, err));
+                                }
+                                self.errors.keyed_error(key, NetworkError::wrap(err))
+                            }
+                        },
                     }
-                    continue;
                 }
-            };
 
-            fetching_keys.remove(&key);
-            match res {
-                Ok((file, maybe_lfsptr)) => {
-                    if let Some(lfsptr) = maybe_lfsptr {
-                        found_pointers += 1;
-                        self.found_pointer(key.clone(), lfsptr, false);
-                    } else {
-                        found += 1;
-                    }
-                    self.found_attributes(key, file);
+                for missing_key in fetching_keys.into_iter() {
+                    match &unknown_error {
+                        Some(error) => self.errors.keyed_error(missing_key, error.clone().into()),
+                        None => {
+                            // This should never happen.
+                            self.errors.keyed_error(
+                                missing_key,
+                                anyhow!("key not returned from files_attr request"),
+                            )
+                        }
+                    };
                 }
-                Err(err) => {
+
+                if let Ok(stats) = block_on(response.stats) {
+                    agg_stats += stats;
+                }
+            }
+
+            if !retriable.is_empty() {
+                if retries_left > 0 {
+                    retries_left -= 1;
+                    debug!(
+                        "    Retrying {} keys after retriable server errors",
+                        retriable.len()
+                    );
+                    pending_attrs = retriable
+                        .into_iter()
+                        .map(|(key, _err)| {
+                            retried_keys.insert(key.clone());
+                            let actionable =
+                                self.common.actionable(&key, fetchable, self.compute_aux_data);
+                            FileSpec {
+                                key,
+                                attrs: actionable.into(),
+                            }
+                        })
+                        .collect();
+                    count += pending_attrs.len();
+                    continue;
+                }
+                for (key, err) in retriable {
                     errors += 1;
                     if error.is_none() {
                         error.replace(format!("{}: {}", key, err));
                     }
-                    self.errors.keyed_error(key, NetworkError::wrap(err))
+                    self.errors.keyed_error(key, NetworkError::wrap(err));
                 }
             }
-        }
 
-        for missing_key in fetching_keys.into_iter() {
-            match &unknown_error {
-                Some(error) => self.errors.keyed_error(missing_key, error.clone().into()),
-                None => {
-                    // This should never happen.
-                    self.errors.keyed_error(
-                        missing_key,
-                        anyhow!("key not returned from files_attr request"),
-                    )
-                }
-            };
+            if tried_header_fallback {
+                break;
+            }
+            tried_header_fallback = true;
+
+            // Header-only keys are requested via aux data above. If the server
+            // didn't return file_header_metadata for them, fall back to a
+            // content fetch, which always carries the header.
+            pending_attrs = self
+                .pending_nonlfs(FileAttributes::HEADER)
+                .into_iter()
+                .filter_map(|key| {
+                    let actionable = self.common.actionable(&key, fetchable, self.compute_aux_data);
+                    if actionable.content_header && !actionable.pure_content {
+                        Some(FileSpec {
+                            key,
+                            attrs: FileAttributes::PURE_CONTENT.into(),
+                        })
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            count += pending_attrs.len();
         }
 
         if found != 0 {
@@ -704,20 +1048,26 @@ impl FetchState {
         );
         let _enter = span.enter();
 
-        if let Ok(stats) = block_on(response.stats) {
-            util::record_edenapi_stats(&span, &stats);
-            // Mononoke already records the time it takes to send the request
-            // (from first byte to last byte sent). We are more interested in
-            // the total time since it includes time not recorded by Mononoke
-            // (routing, cross regional latency, etc).
-            self.metrics.edenapi.time_from_duration(stats.time).ok();
-        }
+        util::record_edenapi_stats(&span, &agg_stats);
+        fetch_span.record("bytes", agg_stats.downloaded);
+        // Mononoke already records the time it takes to send the request
+        // (from first byte to last byte sent). We are more interested in
+        // the total time since it includes time not recorded by Mononoke
+        // (routing, cross regional latency, etc).
+        self.metrics.edenapi.time_from_duration(agg_stats.time).ok();
+
+        fetch_span.record("hits", found);
 
         // We subtract any lfs pointers that were found -- these requests were
         // fulfiled by LFS, not SaplingRemoteAPI
-        self.metrics.edenapi.fetch(count - found_pointers);
-        self.metrics.edenapi.err(errors);
-        self.metrics.edenapi.hit(found);
+        self.metrics.record_remote_fetch(count - found_pointers);
+        self.metrics.record_remote_err(errors);
+        self.metrics.record_remote_hit(found);
+        self.metrics.record_remote_bytes(agg_stats.downloaded);
+        self.metrics.edenapi_bytes_peak = self
+            .metrics
+            .edenapi_bytes_peak
+            .max(bytes_peak.load(Ordering::Relaxed));
     }
 
     pub(crate) fn fetch_cas(&mut self, cas_client: &dyn CasClient) {
@@ -781,6 +1131,7 @@ impl FetchState {
         let mut found = 0;
         let mut error = 0;
         let mut reqs = 0;
+        let mut bytes = 0;
 
         // TODO: configure
         let max_batch_size = 1000;
@@ -811,12 +1162,13 @@ impl FetchState {
                             }
                             Ok(Some(data)) => {
                                 found += 1;
+                                bytes += data.len();
                                 tracing::trace!(target: "cas", ?key, ?digest, "file found in cas");
                                 self.found_attributes(
                                     key,
                                     StoreFile {
                                         content: Some(LazyFile::Cas(data.into())),
-                                        aux_data: None,
+                                        ..Default::default()
                                     },
                                 );
                             }
@@ -837,9 +1189,10 @@ impl FetchState {
         span.record("time", start_time.elapsed().as_millis() as u64);
 
         let _ = self.metrics.cas.time_from_duration(start_time.elapsed());
-        self.metrics.cas.fetch(digests.len());
-        self.metrics.cas.err(error);
-        self.metrics.cas.hit(found);
+        self.metrics.record_cas_fetch(digests.len());
+        self.metrics.record_cas_err(error);
+        self.metrics.record_cas_hit(found);
+        self.metrics.record_cas_bytes(bytes);
     }
 
     pub(crate) fn fetch_lfs_remote(
@@ -933,6 +1286,20 @@ impl FetchState {
         }
     }
 
+    /// Fail every pending LFS pointer: the store was built with the LFS
+    /// remote explicitly disabled (`scmstore.lfs-remote=false`), so the
+    /// blobs cannot be downloaded. Naming the oid lets the user pre-seed
+    /// the local stores out of band.
+    pub(crate) fn error_lfs_remote_disabled(&mut self) {
+        let pointers = std::mem::take(&mut self.lfs_pointers);
+        for (key, (ptr, _write)) in pointers {
+            self.errors.keyed_error(
+                key,
+                anyhow!("LFS remote disabled; cannot fetch oid {}", ptr.sha256()),
+            );
+        }
+    }
+
     // TODO(meyer): Improve how local caching works. At the very least do this in the background.
     // TODO(meyer): Log errors here instead of just ignoring.
     pub(crate) fn derive_computable(&mut self, aux_cache: Option<&AuxStore>) {
@@ -966,7 +1333,7 @@ impl FetchState {
                     if new.attrs().has(self.common.request_attrs) {
                         tracing::debug!("marking complete");
 
-                        self.metrics.aux.store(StoreLocation::Cache).computed(1);
+                        self.metrics.record_aux_computed(StoreLocation::Cache, 1);
 
                         if let Some(aux_cache) = aux_cache {
                             if let Some(ref aux_data) = new.aux_data {
@@ -993,7 +1360,25 @@ impl FetchState {
         });
     }
 
-    pub(crate) fn finish(self) {
+    pub(crate) fn finish(mut self) {
+        // If we were asked for content and to resolve pointers, a pointer
+        // that is still unresolved after the remote stores ran is an error,
+        // not a miss; return it with the oid so the blob can be tracked down.
+        if self.resolve_lfs_pointers
+            && self.common.request_attrs.pure_content
+            && self.fetch_mode.contains(FetchMode::REMOTE)
+        {
+            let lfs_pointers = std::mem::take(&mut self.lfs_pointers);
+            for (key, (ptr, _)) in lfs_pointers {
+                if self.common.pending.contains_key(&key) {
+                    self.errors.keyed_error(
+                        key,
+                        anyhow!("failed to resolve LFS pointer (oid: {})", ptr.sha256()),
+                    );
+                }
+            }
+        }
+
         self.common.results(self.errors);
     }
 }
@@ -15,7 +15,9 @@ use stats::prelude::*;
 use crate::scmstore::metrics::namespaced;
 use crate::scmstore::metrics::ApiMetrics;
 use crate::scmstore::metrics::FetchMetrics;
+use crate::scmstore::metrics::FetchSummary;
 use crate::scmstore::metrics::LocalAndCacheFetchMetrics;
+use crate::scmstore::metrics::StoreLocation;
 use crate::scmstore::metrics::WriteMetrics;
 
 #[derive(Clone, Debug, Default)]
@@ -25,6 +27,10 @@ pub struct FileStoreFetchMetrics {
     pub(crate) aux: LocalAndCacheFetchMetrics,
     pub(crate) edenapi: FetchMetrics,
     pub(crate) cas: FetchMetrics,
+
+    /// Peak number of fetched-content bytes held in memory at once while
+    /// processing remote responses.
+    pub(crate) edenapi_bytes_peak: usize,
 }
 
 impl AddAssign for FileStoreFetchMetrics {
@@ -34,17 +40,154 @@ impl AddAssign for FileStoreFetchMetrics {
         self.aux += rhs.aux;
         self.edenapi += rhs.edenapi;
         self.cas += rhs.cas;
+        self.edenapi_bytes_peak = self.edenapi_bytes_peak.max(rhs.edenapi_bytes_peak);
     }
 }
 
 impl FileStoreFetchMetrics {
+    /// Record keys requested from an indexedlog store.
+    pub(crate) fn record_cache_fetch(&mut self, loc: StoreLocation, keys: usize) {
+        self.indexedlog.store(loc).fetch(keys);
+    }
+
+    /// Record keys satisfied by an indexedlog store, attributing the hit to
+    /// the store's location so different fetch paths don't double-account.
+    pub(crate) fn record_cache_hit(&mut self, loc: StoreLocation, keys: usize) {
+        self.indexedlog.store(loc).hit(keys);
+    }
+
+    /// Record keys missing from an indexedlog store.
+    pub(crate) fn record_cache_miss(&mut self, loc: StoreLocation, keys: usize) {
+        self.indexedlog.store(loc).miss(keys);
+    }
+
+    /// Record keys that failed to be read from an indexedlog store.
+    pub(crate) fn record_cache_err(&mut self, loc: StoreLocation, keys: usize) {
+        self.indexedlog.store(loc).err(keys);
+    }
+
+    /// Record content bytes served from an indexedlog store.
+    pub(crate) fn record_cache_bytes(&mut self, loc: StoreLocation, bytes: usize) {
+        self.indexedlog.store(loc).bytes(bytes);
+    }
+
+    /// Record keys requested from an LFS store.
+    pub(crate) fn record_lfs_fetch(&mut self, loc: StoreLocation, keys: usize) {
+        self.lfs.store(loc).fetch(keys);
+    }
+
+    /// Record keys satisfied by an LFS store.
+    pub(crate) fn record_lfs_hit(&mut self, loc: StoreLocation, keys: usize) {
+        self.lfs.store(loc).hit(keys);
+    }
+
+    /// Record keys missing from an LFS store.
+    pub(crate) fn record_lfs_miss(&mut self, loc: StoreLocation, keys: usize) {
+        self.lfs.store(loc).miss(keys);
+    }
+
+    /// Record keys that failed to be read from an LFS store.
+    pub(crate) fn record_lfs_err(&mut self, loc: StoreLocation, keys: usize) {
+        self.lfs.store(loc).err(keys);
+    }
+
+    /// Record content bytes served from an LFS store.
+    pub(crate) fn record_lfs_bytes(&mut self, loc: StoreLocation, bytes: usize) {
+        self.lfs.store(loc).bytes(bytes);
+    }
+
+    /// Record keys requested from an aux data store.
+    pub(crate) fn record_aux_fetch(&mut self, loc: StoreLocation, keys: usize) {
+        self.aux.store(loc).fetch(keys);
+    }
+
+    /// Record keys satisfied by an aux data store.
+    pub(crate) fn record_aux_hit(&mut self, loc: StoreLocation, keys: usize) {
+        self.aux.store(loc).hit(keys);
+    }
+
+    /// Record keys missing from an aux data store.
+    pub(crate) fn record_aux_miss(&mut self, loc: StoreLocation, keys: usize) {
+        self.aux.store(loc).miss(keys);
+    }
+
+    /// Record keys that failed to be read from an aux data store.
+    pub(crate) fn record_aux_err(&mut self, loc: StoreLocation, keys: usize) {
+        self.aux.store(loc).err(keys);
+    }
+
+    /// Record aux data computed from file content instead of fetched.
+    pub(crate) fn record_aux_computed(&mut self, loc: StoreLocation, keys: usize) {
+        self.aux.store(loc).computed(keys);
+    }
+
+    /// Record keys requested from the remote SaplingRemoteAPI store.
+    pub(crate) fn record_remote_fetch(&mut self, keys: usize) {
+        self.edenapi.fetch(keys);
+    }
+
+    /// Record keys satisfied by the remote SaplingRemoteAPI store.
+    pub(crate) fn record_remote_hit(&mut self, keys: usize) {
+        self.edenapi.hit(keys);
+    }
+
+    /// Record keys that failed to be fetched from the remote SaplingRemoteAPI
+    /// store.
+    pub(crate) fn record_remote_err(&mut self, keys: usize) {
+        self.edenapi.err(keys);
+    }
+
+    /// Record content bytes downloaded from the remote SaplingRemoteAPI store.
+    pub(crate) fn record_remote_bytes(&mut self, bytes: usize) {
+        self.edenapi.bytes(bytes);
+    }
+
+    /// Record digests requested from CAS.
+    pub(crate) fn record_cas_fetch(&mut self, keys: usize) {
+        self.cas.fetch(keys);
+    }
+
+    /// Record digests satisfied by CAS.
+    pub(crate) fn record_cas_hit(&mut self, keys: usize) {
+        self.cas.hit(keys);
+    }
+
+    /// Record digests that failed to be fetched from CAS.
+    pub(crate) fn record_cas_err(&mut self, keys: usize) {
+        self.cas.err(keys);
+    }
+
+    /// Record content bytes downloaded from CAS.
+    pub(crate) fn record_cas_bytes(&mut self, bytes: usize) {
+        self.cas.bytes(bytes);
+    }
+
     fn metrics(&self) -> impl Iterator<Item = (String, usize)> {
         namespaced("indexedlog", self.indexedlog.metrics())
             .chain(namespaced("lfs", self.lfs.metrics()))
             .chain(namespaced("aux", self.aux.metrics()))
             .chain(namespaced("edenapi", self.edenapi.metrics()))
+            .chain(namespaced(
+                "edenapi",
+                std::iter::once(("bytes_peak", self.edenapi_bytes_peak))
+                    .filter(|&(_, value)| value != 0),
+            ))
             .chain(namespaced("cas", self.cas.metrics()))
     }
+
+    /// Roll this fetch's metrics up into per-origin totals for the
+    /// end-of-command summary.
+    pub(crate) fn summary(&self) -> FetchSummary {
+        let mut summary = FetchSummary::default();
+        for store in [&self.indexedlog, &self.lfs, &self.aux] {
+            let (local, cache) = store.summary();
+            summary.local += local;
+            summary.cache += cache;
+        }
+        summary.remote += self.edenapi.summary();
+        summary.remote += self.cas.summary();
+        summary
+    }
     /// Update ODS stats.
     /// This assumes that fbinit was called higher up the stack.
     /// It is meant to be used when called from eden which uses the `revisionstore` with
@@ -94,6 +237,34 @@ impl FileStoreWriteMetrics {
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct FileStorePrefetchMetrics {
+    /// Number of prefetch calls split into multiple fetches by
+    /// scmstore.max-prefetch-size.
+    pub(crate) splits: usize,
+
+    /// Keys beyond the first chunk of each split prefetch.
+    pub(crate) overflow_keys: usize,
+}
+
+impl AddAssign for FileStorePrefetchMetrics {
+    fn add_assign(&mut self, rhs: Self) {
+        self.splits += rhs.splits;
+        self.overflow_keys += rhs.overflow_keys;
+    }
+}
+
+impl FileStorePrefetchMetrics {
+    fn metrics(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        [
+            ("splits", self.splits),
+            ("overflow_keys", self.overflow_keys),
+        ]
+        .into_iter()
+        .filter(|&(_, v)| v != 0)
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct FileStoreApiMetrics {
     pub(crate) hg_getfilecontent: ApiMetrics,
@@ -160,6 +331,11 @@ pub struct FileStoreMetrics {
     pub(crate) fetch: FileStoreFetchMetrics,
     pub(crate) write: FileStoreWriteMetrics,
     pub(crate) api: FileStoreApiMetrics,
+    pub(crate) prefetch: FileStorePrefetchMetrics,
+
+    /// Per-origin fetch totals since `FileStore::take_fetch_summary` was
+    /// last called. Unlike `fetch`, this is not cleared by `flush`.
+    pub(crate) fetch_summary: FetchSummary,
 }
 
 impl FileStoreMetrics {
@@ -172,7 +348,8 @@ impl FileStoreMetrics {
             "scmstore.file",
             namespaced("fetch", self.fetch.metrics())
                 .chain(namespaced("write", self.write.metrics()))
-                .chain(namespaced("api", self.api.metrics())),
+                .chain(namespaced("api", self.api.metrics()))
+                .chain(namespaced("prefetch", self.prefetch.metrics())),
         )
     }
 }
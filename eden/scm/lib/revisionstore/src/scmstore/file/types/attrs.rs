@@ -23,13 +23,18 @@ pub struct FileAttributes {
     pub pure_content: bool,
     pub content_header: bool,
     pub aux_data: bool,
+    // The raw LFS pointer, for callers (e.g. upload paths) that explicitly
+    // want pointers rather than resolved content.
+    pub lfs_pointer: bool,
 }
 
 impl From<FileAttributes> for SaplingRemoteApiFileAttributes {
     fn from(v: FileAttributes) -> Self {
         SaplingRemoteApiFileAttributes {
-            content: v.pure_content || v.content_header,
-            aux_data: v.aux_data,
+            content: v.pure_content,
+            // A header-only fetch is served from aux data's
+            // `file_header_metadata` rather than downloading content.
+            aux_data: v.aux_data || (v.content_header && !v.pure_content),
         }
     }
 }
@@ -39,6 +44,7 @@ impl StoreAttrs for FileAttributes {
         pure_content: false,
         content_header: false,
         aux_data: false,
+        lfs_pointer: false,
     };
 
     /// Returns all the attributes which are present or can be computed from present attributes.
@@ -56,6 +62,7 @@ impl FileAttributes {
         pure_content: true,
         content_header: true,
         aux_data: false,
+        lfs_pointer: false,
     };
 
     // Don't need the content header.
@@ -63,12 +70,30 @@ impl FileAttributes {
         pure_content: true,
         content_header: false,
         aux_data: false,
+        lfs_pointer: false,
+    };
+
+    // Only the hg file header (e.g. copy info), not the content itself.
+    // Copy tracing uses this to avoid pulling content for every candidate.
+    pub const HEADER: Self = FileAttributes {
+        pure_content: false,
+        content_header: true,
+        aux_data: false,
+        lfs_pointer: false,
     };
 
     pub const AUX: Self = FileAttributes {
         pure_content: false,
         content_header: false,
         aux_data: true,
+        lfs_pointer: false,
+    };
+
+    pub const LFS_POINTER: Self = FileAttributes {
+        pure_content: false,
+        content_header: false,
+        aux_data: false,
+        lfs_pointer: true,
     };
 }
 
@@ -80,6 +105,7 @@ impl Not for FileAttributes {
             pure_content: !self.pure_content,
             content_header: !self.content_header,
             aux_data: !self.aux_data,
+            lfs_pointer: !self.lfs_pointer,
         }
     }
 }
@@ -92,6 +118,7 @@ impl BitAnd for FileAttributes {
             pure_content: self.pure_content & rhs.pure_content,
             content_header: self.content_header & rhs.content_header,
             aux_data: self.aux_data & rhs.aux_data,
+            lfs_pointer: self.lfs_pointer & rhs.lfs_pointer,
         }
     }
 }
@@ -104,6 +131,7 @@ impl BitOr for FileAttributes {
             pure_content: self.pure_content | rhs.pure_content,
             content_header: self.content_header | rhs.content_header,
             aux_data: self.aux_data | rhs.aux_data,
+            lfs_pointer: self.lfs_pointer | rhs.lfs_pointer,
         }
     }
 }
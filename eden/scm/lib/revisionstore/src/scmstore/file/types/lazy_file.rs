@@ -9,6 +9,8 @@ use anyhow::bail;
 use anyhow::Error;
 use anyhow::Result;
 use edenapi_types::FileEntry;
+use hgstore::parse_copy_from_hg_file_metadata;
+use hgstore::split_hg_file_metadata;
 use hgstore::strip_hg_file_metadata;
 use minibytes::Bytes;
 use types::HgId;
@@ -89,6 +91,24 @@ impl LazyFile {
         })
     }
 
+    /// The copy info alone, parsed from the hg file header without splitting
+    /// off the content.
+    pub(crate) fn copy_info(&mut self) -> Result<Option<Key>> {
+        use LazyFile::*;
+        Ok(match self {
+            IndexedLog(ref mut entry) => {
+                let (_, header) = split_hg_file_metadata(&entry.content()?);
+                parse_copy_from_hg_file_metadata(&header)?
+            }
+            Lfs(_, ref ptr) => ptr.copy_from().clone(),
+            SaplingRemoteApi(ref entry) => {
+                let (_, header) = split_hg_file_metadata(&entry.data()?.into());
+                parse_copy_from_hg_file_metadata(&header)?
+            }
+            Cas(_) => bail!("CAS data has no copy info"),
+        })
+    }
+
     /// The file content, as would be encoded in the Mercurial blob (with copy header)
     pub(crate) fn hg_content(&self) -> Result<Bytes> {
         use LazyFile::*;
@@ -13,6 +13,7 @@ use hgstore::parse_copy_from_hg_file_metadata;
 use minibytes::Bytes;
 use types::Key;
 
+use crate::lfs::LfsPointersEntry;
 use crate::scmstore::file::LazyFile;
 use crate::scmstore::value::StoreValue;
 use crate::scmstore::FileAttributes;
@@ -23,6 +24,7 @@ pub struct StoreFile {
     // TODO(meyer): We'll probably eventually need a better "canonical lazy file" abstraction, since SaplingRemoteApi FileEntry won't always carry content
     pub(crate) content: Option<LazyFile>,
     pub(crate) aux_data: Option<FileAuxData>,
+    pub(crate) lfs_pointer: Option<LfsPointersEntry>,
 }
 
 impl StoreValue for StoreFile {
@@ -43,6 +45,7 @@ impl StoreValue for StoreFile {
                     .as_ref()
                     .is_some_and(|aux| aux.file_header_metadata.is_some()),
             aux_data: self.aux_data.is_some(),
+            lfs_pointer: self.lfs_pointer.is_some(),
         }
     }
 
@@ -55,11 +58,22 @@ impl StoreValue for StoreFile {
                 None
             },
             aux_data: if attrs.aux_data { self.aux_data } else { None },
+            lfs_pointer: if attrs.lfs_pointer {
+                self.lfs_pointer
+            } else {
+                None
+            },
         }
     }
 }
 
 impl StoreFile {
+    /// The raw LFS pointer, if one was requested via
+    /// `FileAttributes::LFS_POINTER` and the file is LFS.
+    pub fn lfs_pointer(&self) -> Option<&LfsPointersEntry> {
+        self.lfs_pointer.as_ref()
+    }
+
     pub fn aux_data(&self) -> Result<FileAuxData> {
         self.aux_data
             .clone()
@@ -83,6 +97,24 @@ impl StoreFile {
             .file_content()
     }
 
+    /// The parsed copy-from info alone, for callers (e.g. copy tracing) that
+    /// fetched `FileAttributes::HEADER` and don't want content. Prefers aux
+    /// data's `file_header_metadata`, falling back to the content header.
+    pub fn copy_info(&mut self) -> Result<Option<Key>> {
+        if let Some(FileAuxData {
+            file_header_metadata: Some(header),
+            ..
+        }) = &self.aux_data
+        {
+            return parse_copy_from_hg_file_metadata(header);
+        }
+
+        self.content
+            .as_mut()
+            .ok_or_else(|| anyhow!("no file header available"))?
+            .copy_info()
+    }
+
     pub fn file_content_with_copy_info(&mut self) -> Result<(Bytes, Option<Key>)> {
         let content = self
             .content
@@ -113,6 +145,7 @@ impl BitOr for StoreFile {
         StoreFile {
             content: self.content.or(rhs.content),
             aux_data: self.aux_data.or(rhs.aux_data),
+            lfs_pointer: self.lfs_pointer.or(rhs.lfs_pointer),
         }
     }
 }
@@ -122,6 +155,7 @@ impl From<FileAuxData> for StoreFile {
         StoreFile {
             content: None,
             aux_data: Some(v),
+            lfs_pointer: None,
         }
     }
 }
@@ -131,6 +165,7 @@ impl From<LazyFile> for StoreFile {
         StoreFile {
             content: Some(v),
             aux_data: None,
+            lfs_pointer: None,
         }
     }
 }
@@ -29,6 +29,13 @@ pub struct FetchMetrics {
 
     // Number of times data was computed/derved (i.e. aux data based on content).
     computed: usize,
+
+    /// Number of bytes fetched
+    bytes: usize,
+
+    /// Number of entities recovered by a per-key retry after a retriable
+    /// server error
+    retried: usize,
 }
 
 impl AddAssign for FetchMetrics {
@@ -40,6 +47,8 @@ impl AddAssign for FetchMetrics {
         self.errors += rhs.errors;
         self.time += rhs.time;
         self.computed += rhs.computed;
+        self.bytes += rhs.bytes;
+        self.retried += rhs.retried;
     }
 }
 
@@ -65,6 +74,14 @@ impl FetchMetrics {
         self.computed += keys;
     }
 
+    pub(crate) fn bytes(&mut self, bytes: usize) {
+        self.bytes += bytes;
+    }
+
+    pub(crate) fn retried(&mut self, keys: usize) {
+        self.retried += keys;
+    }
+
     // Provide the time as microseconds
     pub(crate) fn time(&mut self, keys: usize) {
         self.time += keys;
@@ -92,10 +109,69 @@ impl FetchMetrics {
             ("errors", self.errors),
             ("time", self.time),
             ("computed", self.computed),
+            ("bytes", self.bytes),
+            ("retried", self.retried),
         ]
         .into_iter()
         .filter(|&(_, v)| v != 0)
     }
+
+    pub(crate) fn summary(&self) -> FetchOriginSummary {
+        FetchOriginSummary {
+            requests: self.requests,
+            hits: self.hits,
+            errors: self.errors,
+            bytes: self.bytes,
+            time_us: self.time,
+        }
+    }
+}
+
+/// Roll-up of the fetch activity against a single origin (local stores,
+/// caches, or remote servers), for an end-of-command summary line.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FetchOriginSummary {
+    /// Number of requests / batches sent to this origin.
+    pub requests: usize,
+
+    /// Number of entities fetched from this origin.
+    pub hits: usize,
+
+    /// Number of entities which returned a fetch error.
+    pub errors: usize,
+
+    /// Number of bytes fetched from this origin.
+    pub bytes: usize,
+
+    /// Total time spent fetching from this origin, in microseconds.
+    pub time_us: usize,
+}
+
+impl AddAssign for FetchOriginSummary {
+    fn add_assign(&mut self, rhs: Self) {
+        self.requests += rhs.requests;
+        self.hits += rhs.hits;
+        self.errors += rhs.errors;
+        self.bytes += rhs.bytes;
+        self.time_us += rhs.time_us;
+    }
+}
+
+/// Per-origin roll-up of all fetch activity since the summary was last
+/// taken. See `FileStore::take_fetch_summary`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FetchSummary {
+    pub local: FetchOriginSummary,
+    pub cache: FetchOriginSummary,
+    pub remote: FetchOriginSummary,
+}
+
+impl AddAssign for FetchSummary {
+    fn add_assign(&mut self, rhs: Self) {
+        self.local += rhs.local;
+        self.cache += rhs.cache;
+        self.remote += rhs.remote;
+    }
 }
 
 // TODO(meyer): I don't think this is in any critical paths, but it'd be nicer to rewrite this
@@ -133,6 +209,10 @@ impl LocalAndCacheFetchMetrics {
     pub(crate) fn metrics(&self) -> impl Iterator<Item = (String, usize)> {
         namespaced("local", self.local.metrics()).chain(namespaced("cache", self.cache.metrics()))
     }
+
+    pub(crate) fn summary(&self) -> (FetchOriginSummary, FetchOriginSummary) {
+        (self.local.summary(), self.cache.summary())
+    }
 }
 
 impl AddAssign for LocalAndCacheFetchMetrics {
@@ -5,9 +5,10 @@
  * GNU General Public License version 2.
  */
 
-use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
 
 use ::types::fetch_mode::FetchMode;
@@ -24,19 +25,29 @@ use ::types::RepoPath;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Result;
+use async_runtime::block_on;
 use cas_client::CasClient;
 use clientinfo::get_client_request_info_thread_local;
 use clientinfo::set_client_request_info_thread_local;
 use crossbeam::channel::unbounded;
+use edenapi_types::AnyId;
 use edenapi_types::FileAuxData;
 use edenapi_types::TreeAuxData;
 use edenapi_types::TreeChildEntry;
+use edenapi_types::UploadTreeEntry;
+use edenapi_types::UploadTreeResponse;
 use fetch::FetchState;
+use futures::stream;
+use futures::Stream;
+use futures::StreamExt;
 use minibytes::Bytes;
 use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 use parking_lot::RwLock;
+use progress_model::AggregatingProgressBar;
+use serde::Serialize;
 use storemodel::BoxIterator;
-use storemodel::SerializationFormat;
+use storemodel::ReadRootTreeIds;
 use storemodel::TreeEntry;
 
 pub use self::metrics::TreeStoreMetrics;
@@ -45,11 +56,15 @@ use crate::datastore::RemoteDataStore;
 use crate::indexedlogdatastore::Entry;
 use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
 use crate::indexedlogtreeauxstore::TreeAuxStore;
+use crate::scmstore::cert_skew;
 use crate::scmstore::fetch::FetchResults;
 use crate::scmstore::fetch::KeyFetchError;
 use crate::scmstore::file::FileStore;
+use crate::scmstore::metrics::FetchOriginSummary;
+use crate::scmstore::metrics::FetchSummary;
 use crate::scmstore::metrics::StoreLocation;
 use crate::scmstore::tree::types::LazyTree;
+use crate::scmstore::tree::types::ParsedTree;
 use crate::scmstore::tree::types::StoreTree;
 use crate::scmstore::tree::types::TreeAttributes;
 use crate::ContentDataStore;
@@ -77,6 +92,25 @@ pub enum TreeMetadataMode {
     OptIn,
 }
 
+/// How trees fetched from a remote store are written back to the caches.
+#[derive(Clone, Debug)]
+pub enum CacheWriteMode {
+    /// Write each tree to the cache as soon as it is fetched.
+    Immediate,
+
+    /// Buffer fetched trees and write them to the cache in batches, either
+    /// when `max_batch_size` entries have accumulated or when
+    /// `flush_interval` has elapsed since the last batch. Large prefetches
+    /// otherwise pay for many small synchronous writes.
+    Batched {
+        max_batch_size: usize,
+        flush_interval: Duration,
+    },
+
+    /// Never write fetched trees back to a cache (read-only mode).
+    Disabled,
+}
+
 #[derive(Clone)]
 pub struct TreeStore {
     /// The "local" indexedlog store. Stores content that is created locally.
@@ -90,6 +124,10 @@ pub struct TreeStore {
     /// will the written to indexedlog_cache.
     pub cache_to_local_cache: bool,
 
+    /// How data found by falling back to a remote store is written back to
+    /// the caches: immediately, in batches, or not at all.
+    pub cache_write_mode: CacheWriteMode,
+
     /// An SaplingRemoteApi Client, SaplingRemoteApiTreeStore provides the tree-specific subset of SaplingRemoteApi functionality
     /// used by TreeStore.
     pub edenapi: Option<Arc<SaplingRemoteApiTreeStore>>,
@@ -100,6 +138,10 @@ pub struct TreeStore {
     /// A TreeAuxStore, for storing directory metadata about each tree.
     pub tree_aux_store: Option<Arc<TreeAuxStore>>,
 
+    /// Resolves commit ids to root tree ids, for `get_root_tree`. Typically
+    /// backed by the repo's commit store.
+    pub commit_store: Option<Arc<dyn ReadRootTreeIds + Send + Sync>>,
+
     /// Whether we should request extra children metadata from SaplingRemoteAPI and write back to
     /// filestore's aux cache.
     pub tree_metadata_mode: TreeMetadataMode,
@@ -112,12 +154,69 @@ pub struct TreeStore {
     /// Write tree parents to history cache even if parents weren't requested.
     pub prefetch_tree_parents: bool,
 
+    /// Verify that the node hash of locally written trees matches their
+    /// content and parents. Configured by scmstore.verify-writes.
+    pub verify_writes: bool,
+
     pub flush_on_drop: bool,
 
     /// Whether to fetch trees aux data from remote (provided by the augmented trees)
     pub fetch_tree_aux_data: bool,
 
+    /// Largest number of keys sent to the SaplingRemoteAPI server in a single
+    /// request. Batches above this size are split into multiple requests so a
+    /// failure only affects one chunk. `None` sends the whole batch at once.
+    /// Configured by scmstore.tree-batch-size.
+    pub(crate) batch_size: Option<usize>,
+
+    /// Number of times a key that failed with a retriable per-key server
+    /// error is re-requested before being reported as an error.
+    /// Configured by scmstore.retries.
+    pub(crate) edenapi_retries: i32,
+
+    /// Client certificate the builder resolved for the SaplingRemoteAPI URL,
+    /// used to explain TLS failures caused by an expired certificate or a
+    /// skewed system clock.
+    pub(crate) client_cert_path: Option<PathBuf>,
+
     pub(crate) metrics: Arc<RwLock<TreeStoreMetrics>>,
+
+    pub(crate) edenapi_progress: Arc<AggregatingProgressBar>,
+
+    /// Keys written by `write_batch` that haven't been flushed yet.
+    pub(crate) written_trees: Arc<Mutex<Vec<Key>>>,
+
+    /// Paths the builder resolved the local and cache stores to, kept for
+    /// `config_summary`. `None` when the store was constructed without one.
+    pub(crate) local_path: Option<PathBuf>,
+    pub(crate) cache_path: Option<PathBuf>,
+}
+
+/// The effective configuration of a [`TreeStore`], as resolved at build
+/// time (defaults applied, not the raw config). Returned by
+/// [`TreeStore::config_summary`] for debugging; contains only plain data so
+/// it can be serialized and snapshot-tested.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TreeStoreConfigSummary {
+    pub cache_to_local_cache: bool,
+    pub cache_write_mode: String,
+    pub tree_metadata_mode: String,
+    pub prefetch_tree_parents: bool,
+    pub verify_writes: bool,
+    pub fetch_tree_aux_data: bool,
+    pub batch_size: Option<usize>,
+    pub edenapi_retries: i32,
+    pub local_path: Option<PathBuf>,
+    pub cache_path: Option<PathBuf>,
+    pub has_indexedlog_local: bool,
+    pub has_indexedlog_cache: bool,
+    pub has_historystore_local: bool,
+    pub has_historystore_cache: bool,
+    pub has_tree_aux_store: bool,
+    pub has_filestore: bool,
+    pub has_commit_store: bool,
+    pub has_edenapi: bool,
+    pub has_cas_client: bool,
 }
 
 impl Drop for TreeStore {
@@ -149,6 +248,8 @@ impl TreeStore {
         let historystore_local = self.historystore_local.clone();
 
         let cache_to_local_cache = self.cache_to_local_cache;
+        let cache_write_mode = self.cache_write_mode.clone();
+        let edenapi_progress = self.edenapi_progress.clone();
         let aux_cache = self.filestore.as_ref().and_then(|fs| fs.aux_cache.clone());
         let tree_aux_store = self.tree_aux_store.clone();
         let cas_client = self.cas_client.clone();
@@ -160,6 +261,8 @@ impl TreeStore {
         };
         let fetch_tree_aux_data = self.fetch_tree_aux_data || attrs.aux_data;
         let fetch_parents = attrs.parents || self.prefetch_tree_parents;
+        let batch_size = self.batch_size;
+        let edenapi_retries = self.edenapi_retries;
 
         let fetch_local = fetch_mode.contains(FetchMode::LOCAL);
         let fetch_remote = fetch_mode.contains(FetchMode::REMOTE);
@@ -196,10 +299,25 @@ impl TreeStore {
 
                         store_metrics.fetch(fetch_count);
 
+                        let span = tracing::debug_span!(
+                            target: "revisionstore::fetch",
+                            "indexedlog_fetch",
+                            location = match location {
+                                StoreLocation::Cache => "cache",
+                                StoreLocation::Local => "local",
+                            },
+                            keys = fetch_count,
+                            hits = tracing::field::Empty,
+                            bytes = tracing::field::Empty,
+                        );
+                        let _enter = span.enter();
+
                         let mut found_count: usize = 0;
+                        let mut found_bytes: u64 = 0;
                         for key in pending.into_iter() {
                             if let Some(entry) = log.get_entry(key)? {
                                 tracing::trace!("{:?} found in {:?}", entry.key(), location);
+                                found_bytes += entry.metadata().size.unwrap_or(0);
                                 state
                                     .common
                                     .found(entry.key().clone(), LazyTree::IndexedLog(entry).into());
@@ -207,8 +325,12 @@ impl TreeStore {
                             }
                         }
 
+                        span.record("hits", found_count);
+                        span.record("bytes", found_bytes);
+
                         store_metrics.hit(found_count);
                         store_metrics.miss(fetch_count - found_count);
+                        store_metrics.bytes(found_bytes as usize);
                         let _ = store_metrics.time_from_duration(start_time.elapsed());
                     }
                 }
@@ -274,8 +396,16 @@ impl TreeStore {
             }
 
             if fetch_remote {
+                // In read-only mode, hand no cache handles to the remote
+                // fetches so they leave every cache untouched.
+                let cache_writes = !matches!(cache_write_mode, CacheWriteMode::Disabled);
+
                 if let Some(cas_client) = &cas_client {
-                    state.fetch_cas(cas_client, aux_cache.as_deref(), tree_aux_store.as_deref());
+                    state.fetch_cas(
+                        cas_client,
+                        aux_cache.as_deref().filter(|_| cache_writes),
+                        tree_aux_store.as_deref().filter(|_| cache_writes),
+                    );
                 }
 
                 if let Some(edenapi) = &edenapi {
@@ -285,6 +415,9 @@ impl TreeStore {
                         parents: true,
                         // Include file and tree aux data for entries, if available (tree aux data requires augmented_trees=true).
                         child_metadata: fetch_children_metadata,
+                        // The file header (copy metadata) is only served with
+                        // augmented trees; the legacy path doesn't need it.
+                        child_file_header_metadata: false,
                         // Use pre-derived "augmented" tree data, which includes tree aux data.
                         augmented_trees: fetch_tree_aux_data,
                     };
@@ -292,18 +425,22 @@ impl TreeStore {
                     state.fetch_edenapi(
                         edenapi,
                         attributes,
-                        if cache_to_local_cache {
+                        if cache_to_local_cache && cache_writes {
                             indexedlog_cache.as_deref()
                         } else {
                             None
                         },
-                        aux_cache.as_deref(),
-                        tree_aux_store.as_deref(),
-                        if fetch_parents {
+                        &cache_write_mode,
+                        aux_cache.as_deref().filter(|_| cache_writes),
+                        tree_aux_store.as_deref().filter(|_| cache_writes),
+                        if fetch_parents && cache_writes {
                             historystore_cache.as_deref()
                         } else {
                             None
                         },
+                        edenapi_progress,
+                        batch_size,
+                        edenapi_retries,
                     )?;
                 } else {
                     tracing::debug!("no SaplingRemoteApi associated with TreeStore");
@@ -317,12 +454,16 @@ impl TreeStore {
                 tracing::error!(?err, "error updating tree ods counters");
             }
 
-            store_metrics.write().fetch += state.metrics;
+            let mut store_metrics = store_metrics.write();
+            store_metrics.fetch_summary += state.metrics.summary();
+            store_metrics.fetch += state.metrics;
 
             Ok(())
         };
+        let client_cert_path = self.client_cert_path.clone();
         let process_func_errors = move || {
             if let Err(err) = process_func() {
+                let err = cert_skew::annotate_cert_skew(err, client_cert_path.as_deref());
                 let _ = found_tx2.send(Err(KeyFetchError::Other(err)));
             }
         };
@@ -343,31 +484,258 @@ impl TreeStore {
         FetchResults::new(Box::new(found_rx.into_iter()))
     }
 
-    fn write_batch(&self, entries: impl Iterator<Item = (Key, Bytes, Metadata)>) -> Result<()> {
-        if let Some(ref indexedlog_local) = self.indexedlog_local {
-            for (key, bytes, meta) in entries {
-                indexedlog_local.put_entry(Entry::new(key, bytes, meta))?;
+    /// Fetch the root tree of `commit_id`: resolve the commit to its root
+    /// tree id via `commit_store`, then fetch that tree. Returns `Ok(None)`
+    /// if the commit store doesn't know the commit. Errors if no commit
+    /// store is configured.
+    pub fn get_root_tree(&self, commit_id: HgId) -> Result<Option<StoreTree>> {
+        let commit_store = self
+            .commit_store
+            .as_ref()
+            .ok_or_else(|| anyhow!("cannot resolve root tree: no commit store is available"))?;
+
+        let tree_id = match block_on(commit_store.read_root_tree_ids(vec![commit_id]))?
+            .into_iter()
+            .next()
+        {
+            Some((_commit_id, tree_id)) => tree_id,
+            None => return Ok(None),
+        };
+
+        let key = Key::new(RepoPath::empty().to_owned(), tree_id);
+        self.fetch_batch(
+            std::iter::once(key),
+            TreeAttributes::CONTENT,
+            FetchMode::AllowRemote,
+        )
+        .single()
+    }
+
+    /// Fetch trees and parse their manifest blobs into typed child entries,
+    /// so callers don't have to re-implement manifest parsing. Missing keys
+    /// and fetch errors fail the whole call; parse errors identify the key
+    /// and the byte offset of the malformed element.
+    pub fn fetch_parsed(
+        &self,
+        keys: impl Iterator<Item = Key>,
+        fetch_mode: FetchMode,
+    ) -> Result<Vec<(Key, ParsedTree)>> {
+        let fetched = self.fetch_batch(keys, TreeAttributes::CONTENT, fetch_mode);
+        let mut parsed = Vec::new();
+        for entry in fetched.into_iter() {
+            let (key, store_tree) = entry?;
+            let content = store_tree
+                .content
+                .ok_or_else(|| anyhow!("no content available for {}", key))?;
+            let tree = ParsedTree::parse(key.clone(), content.hg_content()?)?;
+            parsed.push((key, tree));
+        }
+        Ok(parsed)
+    }
+
+    /// Write a batch of locally created trees to `indexedlog_local`, e.g.
+    /// when seeding the local store during a bundle import. Parents are
+    /// recorded in the local history store, if one is configured. Writes are
+    /// immediately visible to reads in this process; call `flush` to persist
+    /// them. When `verify_writes` is set, each entry's node hash is checked
+    /// against its content and parents before anything is written. Any keys
+    /// that failed to write are reported in the returned error.
+    pub fn write_batch(&self, entries: &[(Key, Bytes, Parents)]) -> Result<()> {
+        let indexedlog_local = self.indexedlog_local.as_ref().ok_or_else(|| {
+            anyhow!("cannot write trees: no local indexedlog store is available")
+        })?;
+
+        let span = tracing::debug_span!(
+            target: "revisionstore::fetch",
+            "write_batch",
+            keys = entries.len(),
+            bytes = entries.iter().map(|(_, bytes, _)| bytes.len() as u64).sum::<u64>(),
+        );
+        let _enter = span.enter();
+
+        if self.verify_writes {
+            for (key, bytes, parents) in entries {
+                let computed = HgId::from_content(bytes, *parents);
+                if computed != key.hgid {
+                    bail!(
+                        "tree hash verification failed for {}: computed node is {}",
+                        key,
+                        computed
+                    );
+                }
+            }
+        }
+
+        let mut failed: Vec<Key> = Vec::new();
+        for (key, bytes, parents) in entries {
+            if indexedlog_local
+                .put_entry(Entry::new(key.clone(), bytes.clone(), Metadata::default()))
+                .is_err()
+            {
+                failed.push(key.clone());
+                continue;
+            }
+            if let Some(historystore_local) = &self.historystore_local {
+                let info = NodeInfo {
+                    parents: parents.to_keys(),
+                    linknode: NULL_ID,
+                };
+                if historystore_local.add(key, &info).is_err() {
+                    failed.push(key.clone());
+                    continue;
+                }
             }
+            self.written_trees.lock().push(key.clone());
+        }
+
+        if !failed.is_empty() {
+            bail!(
+                "failed to write {} tree(s): {}",
+                failed.len(),
+                failed.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(", ")
+            );
         }
         Ok(())
     }
 
+    /// Upload a batch of locally created trees to the server via the
+    /// SaplingRemoteAPI upload-trees endpoint, e.g. after `hg commit`. The
+    /// returned stream yields the key of each tree the server acknowledged
+    /// and an error for each tree it did not; one failed upload does not
+    /// abort the rest of the batch.
+    pub fn upload_batch(
+        &self,
+        entries: impl Iterator<Item = (Key, Bytes, Parents)>,
+    ) -> impl Stream<Item = Result<Key>> {
+        let mut items: Vec<UploadTreeEntry> = Vec::new();
+        let mut pending: HashMap<HgId, Key> = HashMap::new();
+        for (key, bytes, parents) in entries {
+            items.push(UploadTreeEntry {
+                node_id: key.hgid,
+                data: bytes.to_vec(),
+                parents,
+            });
+            pending.insert(key.hgid, key);
+        }
+
+        let results = match self.upload_trees(items, pending) {
+            Ok(results) => results,
+            Err(err) => vec![Err(err)],
+        };
+        stream::iter(results)
+    }
+
+    /// Perform the upload for `upload_batch`, mapping each returned upload
+    /// token back to the key it acknowledges. Keys missing from the server's
+    /// response are reported as individual errors.
+    fn upload_trees(
+        &self,
+        items: Vec<UploadTreeEntry>,
+        mut pending: HashMap<HgId, Key>,
+    ) -> Result<Vec<Result<Key>>> {
+        let edenapi = self.edenapi.as_ref().ok_or_else(|| {
+            anyhow!("cannot upload trees: no SaplingRemoteAPI client is available")
+        })?;
+
+        let span = tracing::debug_span!(
+            target: "revisionstore::fetch",
+            "upload_batch",
+            keys = items.len(),
+        );
+        let _enter = span.enter();
+
+        let response = block_on(edenapi.client().upload_trees_batch(items))?;
+        let entries = block_on(response.entries.collect::<Vec<_>>());
+
+        let mut results = Vec::with_capacity(entries.len() + pending.len());
+        for entry in entries {
+            match entry {
+                Ok(UploadTreeResponse { token }) => match token.data.id {
+                    AnyId::HgTreeId(hgid) => match pending.remove(&hgid) {
+                        Some(key) => results.push(Ok(key)),
+                        None => results.push(Err(anyhow!(
+                            "server acknowledged an upload that was not requested: {}",
+                            hgid
+                        ))),
+                    },
+                    id => results.push(Err(anyhow!(
+                        "unexpected id in tree upload token: {:?}",
+                        id
+                    ))),
+                },
+                Err(err) => results.push(Err(err.into())),
+            }
+        }
+        results.extend(
+            pending
+                .into_values()
+                .map(|key| Err(anyhow!("server did not acknowledge upload of {}", key))),
+        );
+        Ok(results)
+    }
+
     pub fn empty() -> Self {
         TreeStore {
             indexedlog_local: None,
             indexedlog_cache: None,
             cache_to_local_cache: true,
+            cache_write_mode: CacheWriteMode::Immediate,
             edenapi: None,
             cas_client: None,
             historystore_cache: None,
             historystore_local: None,
             filestore: None,
             tree_aux_store: None,
+            commit_store: None,
             flush_on_drop: true,
             tree_metadata_mode: TreeMetadataMode::Never,
             fetch_tree_aux_data: false,
+            batch_size: None,
+            edenapi_retries: 0,
+            client_cert_path: None,
             metrics: Default::default(),
             prefetch_tree_parents: false,
+            verify_writes: false,
+            edenapi_progress: AggregatingProgressBar::new("downloading", "trees"),
+            written_trees: Default::default(),
+            local_path: None,
+            cache_path: None,
+        }
+    }
+
+    /// The configured [`TreeMetadataMode`], for callers that adapt their
+    /// behavior to whether children metadata is fetched.
+    pub fn get_tree_metadata_mode(&self) -> TreeMetadataMode {
+        self.tree_metadata_mode.clone()
+    }
+
+    /// Reconfigure the [`TreeMetadataMode`] at runtime.
+    pub fn set_tree_metadata_mode(&mut self, mode: TreeMetadataMode) {
+        self.tree_metadata_mode = mode;
+    }
+
+    /// A snapshot of the settings this store ended up with, for debugging.
+    pub fn config_summary(&self) -> TreeStoreConfigSummary {
+        TreeStoreConfigSummary {
+            cache_to_local_cache: self.cache_to_local_cache,
+            cache_write_mode: format!("{:?}", self.cache_write_mode),
+            tree_metadata_mode: format!("{:?}", self.tree_metadata_mode),
+            prefetch_tree_parents: self.prefetch_tree_parents,
+            verify_writes: self.verify_writes,
+            fetch_tree_aux_data: self.fetch_tree_aux_data,
+            batch_size: self.batch_size,
+            edenapi_retries: self.edenapi_retries,
+            local_path: self.local_path.clone(),
+            cache_path: self.cache_path.clone(),
+            has_indexedlog_local: self.indexedlog_local.is_some(),
+            has_indexedlog_cache: self.indexedlog_cache.is_some(),
+            has_historystore_local: self.historystore_local.is_some(),
+            has_historystore_cache: self.historystore_cache.is_some(),
+            has_tree_aux_store: self.tree_aux_store.is_some(),
+            has_filestore: self.filestore.is_some(),
+            has_commit_store: self.commit_store.is_some(),
+            has_edenapi: self.edenapi.is_some(),
+            has_cas_client: self.cas_client.is_some(),
         }
     }
 
@@ -399,11 +767,24 @@ impl TreeStore {
             historystore_cache.flush().map_err(&mut handle_error);
         }
 
+        let written_trees = std::mem::take(&mut *self.written_trees.lock());
+        if !written_trees.is_empty() {
+            tracing::debug!(
+                target: "revisionstore::treestore",
+                keys = written_trees.len(),
+                "flushed locally written trees",
+            );
+        }
+
         let mut metrics = self.metrics.write();
         for (k, v) in metrics.metrics() {
             hg_metrics::increment_counter(k, v as u64);
         }
+        // The fetch summary survives the reset - it is only cleared when the
+        // command takes it at the end.
+        let fetch_summary = std::mem::take(&mut metrics.fetch_summary);
         *metrics = Default::default();
+        metrics.fetch_summary = fetch_summary;
 
         result
     }
@@ -411,6 +792,12 @@ impl TreeStore {
     pub fn refresh(&self) -> Result<()> {
         self.flush()
     }
+
+    /// Return the per-origin fetch totals accumulated since the last call
+    /// and reset them, so a command can report what it fetched at the end.
+    pub fn take_fetch_summary(&self) -> FetchSummary {
+        std::mem::take(&mut self.metrics.write().fetch_summary)
+    }
 }
 
 impl LegacyStore for TreeStore {
@@ -428,15 +815,25 @@ impl LegacyStore for TreeStore {
             historystore_local: self.historystore_cache.clone(),
             historystore_cache: None,
             cache_to_local_cache: false,
+            cache_write_mode: self.cache_write_mode.clone(),
             edenapi: None,
             cas_client: None,
             filestore: None,
             tree_aux_store: None,
+            commit_store: None,
             flush_on_drop: true,
             tree_metadata_mode: TreeMetadataMode::Never,
             fetch_tree_aux_data: false,
+            batch_size: self.batch_size,
+            edenapi_retries: self.edenapi_retries,
+            client_cert_path: self.client_cert_path.clone(),
             metrics: self.metrics.clone(),
             prefetch_tree_parents: false,
+            verify_writes: self.verify_writes,
+            edenapi_progress: self.edenapi_progress.clone(),
+            written_trees: Default::default(),
+            local_path: self.local_path.clone(),
+            cache_path: self.cache_path.clone(),
         })
     }
 
@@ -557,7 +954,10 @@ impl HgIdMutableDeltaStore for TreeStore {
             key,
         } = delta.clone()
         {
-            self.write_batch(std::iter::once((key, data, metadata.clone())))
+            if let Some(ref indexedlog_local) = self.indexedlog_local {
+                indexedlog_local.put_entry(Entry::new(key, data, metadata.clone()))?;
+            }
+            Ok(())
         } else {
             bail!("Deltas with non-None base are not supported")
         }
@@ -710,29 +1110,35 @@ impl storemodel::KeyStore for TreeStore {
 /// Extends a basic `TreeEntry` with aux data.
 struct ScmStoreTreeEntry {
     tree: LazyTree,
-    // The "basic" version of `TreeEntry` that does not have aux data.
-    basic_tree_entry: OnceCell<Box<dyn TreeEntry>>,
+    // The typed child entries; parsing is shared with
+    // `TreeStore::fetch_parsed`.
+    parsed_tree: OnceCell<ParsedTree>,
 }
 
 impl ScmStoreTreeEntry {
-    fn basic_tree_entry(&self) -> Result<&dyn TreeEntry> {
-        self.basic_tree_entry
-            .get_or_try_init(|| {
-                let data = self.tree.hg_content()?;
-                let entry = storemodel::basic_parse_tree(data, SerializationFormat::Hg)?;
-                Ok(entry)
-            })
-            .map(Borrow::borrow)
+    fn parsed_tree(&self) -> Result<&ParsedTree> {
+        self.parsed_tree.get_or_try_init(|| {
+            let key = Key::new(
+                RepoPath::empty().to_owned(),
+                self.tree.hgid().unwrap_or(NULL_ID),
+            );
+            ParsedTree::parse(key, self.tree.hg_content()?)
+        })
     }
 }
 
 impl TreeEntry for ScmStoreTreeEntry {
     fn iter(&self) -> Result<BoxIterator<Result<(PathComponentBuf, HgId, TreeItemFlag)>>> {
-        self.basic_tree_entry()?.iter()
+        let children = self
+            .parsed_tree()?
+            .children()
+            .map(|(name, hgid, flag)| Ok((name.to_owned(), hgid, flag)))
+            .collect::<Vec<_>>();
+        Ok(Box::new(children.into_iter()))
     }
 
     fn lookup(&self, name: &PathComponent) -> Result<Option<(HgId, TreeItemFlag)>> {
-        self.basic_tree_entry()?.lookup(name)
+        Ok(self.parsed_tree()?.lookup(name))
     }
 
     fn file_aux_iter(&self) -> anyhow::Result<BoxIterator<anyhow::Result<(HgId, FileAuxData)>>> {
@@ -818,7 +1224,7 @@ impl storemodel::TreeStore for TreeStore {
                 // ScmStoreTreeEntry supports aux data.
                 let tree_entry = ScmStoreTreeEntry {
                     tree,
-                    basic_tree_entry: OnceCell::new(),
+                    parsed_tree: OnceCell::new(),
                 };
                 Ok((key, Box::new(tree_entry)))
             });
@@ -843,3 +1249,672 @@ impl storemodel::TreeStore for TreeStore {
         Ok(Box::new(iter))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+    use std::collections::HashSet;
+
+    use tempfile::TempDir;
+    use types::testutil::*;
+    use types::FileType;
+
+    use super::*;
+    use crate::edenapi::Tree as TreeMarker;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+    use crate::indexedlogutil::StoreType;
+    use crate::localstore::ExtStoredPolicy;
+    use crate::scmstore::FileAttributes;
+    use crate::testutil::FakeSaplingRemoteApi;
+    use crate::AuxStore;
+    use crate::SaplingRemoteApiRemoteStore;
+
+    fn local_store(dir: &TempDir) -> Result<Arc<IndexedLogHgIdDataStore>> {
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        Ok(Arc::new(IndexedLogHgIdDataStore::new(
+            &BTreeMap::<&str, &str>::new(),
+            dir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Permanent,
+        )?))
+    }
+
+    #[test]
+    fn test_write_batch_read_without_flush() -> Result<()> {
+        let tmp = TempDir::new()?;
+
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(local_store(&tmp)?);
+
+        let data = Bytes::from(&b"1234"[..]);
+        let k = Key::new(
+            repo_path_buf("a"),
+            HgId::from_content(&data, Parents::None),
+        );
+
+        store.write_batch(&[(k.clone(), data.clone(), Parents::None)])?;
+
+        // The write must be readable before any flush.
+        let mut fetched = store
+            .fetch_batch(
+                std::iter::once(k.clone()),
+                TreeAttributes::CONTENT,
+                FetchMode::LocalOnly,
+            )
+            .single()?
+            .expect("key not found");
+        assert_eq!(
+            fetched.manifest_tree_entry()?.0.to_vec(),
+            data.as_ref().to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_batch_verify_writes() -> Result<()> {
+        let tmp = TempDir::new()?;
+
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(local_store(&tmp)?);
+        store.verify_writes = true;
+
+        let data = Bytes::from(&b"1234"[..]);
+
+        // A key whose node doesn't match the content is rejected.
+        let bad = key("a", "1");
+        let err = store
+            .write_batch(&[(bad, data.clone(), Parents::None)])
+            .unwrap_err();
+        assert!(err.to_string().contains("tree hash verification failed"));
+
+        // The correct node is accepted.
+        let k = Key::new(
+            repo_path_buf("a"),
+            HgId::from_content(&data, Parents::None),
+        );
+        store.write_batch(&[(k, data, Parents::None)])?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_batch_flush_persistence() -> Result<()> {
+        let tmp = TempDir::new()?;
+
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(local_store(&tmp)?);
+
+        let data = Bytes::from(&b"1234"[..]);
+        let k = Key::new(
+            repo_path_buf("a"),
+            HgId::from_content(&data, Parents::None),
+        );
+
+        store.write_batch(&[(k.clone(), data.clone(), Parents::None)])?;
+        store.flush()?;
+        drop(store);
+
+        // The write survives reopening the store.
+        let reopened = local_store(&tmp)?;
+        let entry = reopened.get_entry(k)?.expect("key not found after reopen");
+        assert_eq!(entry.content()?.to_vec(), data.as_ref().to_vec());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_upload_batch() -> Result<()> {
+        let mut entries = Vec::new();
+        for i in 0..3 {
+            let data = Bytes::from(format!("tree {}", i));
+            let k = Key::new(
+                repo_path_buf(&format!("d{}", i)),
+                HgId::from_content(&data, Parents::None),
+            );
+            entries.push((k, data, Parents::None));
+        }
+
+        // The server fails to persist the second tree.
+        let rejected: HashSet<HgId> = std::iter::once(entries[1].0.hgid).collect();
+        let client = FakeSaplingRemoteApi::new()
+            .rejected_uploads(rejected)
+            .into_arc();
+
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            client.clone(),
+        ));
+
+        let results = block_on(store.upload_batch(entries.iter().cloned()).collect::<Vec<_>>());
+        assert_eq!(results.len(), 3);
+
+        let uploaded: HashSet<Key> = results
+            .iter()
+            .filter_map(|res| res.as_ref().ok().cloned())
+            .collect();
+        assert_eq!(
+            uploaded,
+            [entries[0].0.clone(), entries[2].0.clone()]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(client.uploaded_trees().len(), 2);
+
+        // The rejected tree gets its own error; the rest still succeed.
+        let err = results
+            .iter()
+            .find_map(|res| res.as_ref().err())
+            .expect("missing error for rejected upload");
+        assert!(err
+            .to_string()
+            .contains("server did not acknowledge upload"));
+
+        // Without a client, the stream is a single error.
+        let store = TreeStore::empty();
+        let results = block_on(store.upload_batch(entries.into_iter()).collect::<Vec<_>>());
+        assert_eq!(results.len(), 1);
+        assert!(results[0]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("no SaplingRemoteAPI client is available"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_parsed() -> Result<()> {
+        let tmp = TempDir::new()?;
+
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(local_store(&tmp)?);
+
+        let (file, exec, link, sub) = (hgid("1"), hgid("2"), hgid("3"), hgid("4"));
+        let data = Bytes::from(format!(
+            "a.txt\0{}\nb.sh\0{}x\nlink\0{}l\nsub\0{}t\n",
+            file.to_hex(),
+            exec.to_hex(),
+            link.to_hex(),
+            sub.to_hex(),
+        ));
+        let k = Key::new(
+            repo_path_buf("d"),
+            HgId::from_content(&data, Parents::None),
+        );
+        store.write_batch(&[(k.clone(), data, Parents::None)])?;
+
+        let parsed = store.fetch_parsed(std::iter::once(k.clone()), FetchMode::LocalOnly)?;
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].0, k);
+
+        let tree = &parsed[0].1;
+        let children: Vec<_> = tree
+            .children()
+            .map(|(name, hgid, flag)| (name.to_owned(), hgid, flag))
+            .collect();
+        assert_eq!(
+            children,
+            vec![
+                (
+                    path_component_buf("a.txt"),
+                    file,
+                    TreeItemFlag::File(FileType::Regular),
+                ),
+                (
+                    path_component_buf("b.sh"),
+                    exec,
+                    TreeItemFlag::File(FileType::Executable),
+                ),
+                (
+                    path_component_buf("link"),
+                    link,
+                    TreeItemFlag::File(FileType::Symlink),
+                ),
+                (path_component_buf("sub"), sub, TreeItemFlag::Directory),
+            ]
+        );
+        assert_eq!(
+            tree.lookup(path_component("b.sh")),
+            Some((exec, TreeItemFlag::File(FileType::Executable)))
+        );
+        assert_eq!(tree.lookup(path_component("missing")), None);
+
+        // A malformed element fails with the key and the byte offset of the
+        // element that didn't parse.
+        let good_line = format!("a.txt\0{}\n", file.to_hex());
+        let bad = Bytes::from(format!("{}b.sh\0not-hex\n", good_line));
+        let bad_key = Key::new(
+            repo_path_buf("d2"),
+            HgId::from_content(&bad, Parents::None),
+        );
+        store.write_batch(&[(bad_key.clone(), bad, Parents::None)])?;
+        let err = store
+            .fetch_parsed(std::iter::once(bad_key.clone()), FetchMode::LocalOnly)
+            .unwrap_err();
+        assert!(err.to_string().contains(&format!(
+            "failed to parse manifest for {} at byte offset {}",
+            bad_key,
+            good_line.len()
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edenapi_fetch_batch_size() -> Result<()> {
+        let mut trees = HashMap::new();
+        let mut keys = Vec::new();
+        for i in 0..5 {
+            let data = Bytes::from(format!("tree {}", i));
+            let k = Key::new(
+                repo_path_buf(&format!("d{}", i)),
+                HgId::from_content(&data, Parents::None),
+            );
+            trees.insert(k.clone(), data);
+            keys.push(k);
+        }
+
+        let client = FakeSaplingRemoteApi::new().trees(trees).into_arc();
+
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            client.clone(),
+        ));
+        store.batch_size = Some(2);
+
+        let (found, missing, _errors) = store
+            .fetch_batch(
+                keys.iter().cloned(),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(found.len(), 5);
+        assert!(missing.is_empty());
+        // Five keys with a batch size of two split into three requests.
+        assert_eq!(client.tree_request_sizes(), vec![2, 2, 1]);
+
+        // Without a batch size the whole batch goes out as one request.
+        let client = FakeSaplingRemoteApi::new()
+            .trees(
+                keys.iter()
+                    .enumerate()
+                    .map(|(i, k)| (k.clone(), Bytes::from(format!("tree {}", i))))
+                    .collect(),
+            )
+            .into_arc();
+
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            client.clone(),
+        ));
+
+        let (found, _missing, _errors) = store
+            .fetch_batch(
+                keys.iter().cloned(),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(found.len(), 5);
+        assert_eq!(client.tree_request_sizes(), vec![5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edenapi_fetch_resumes_truncated_response() -> Result<()> {
+        let mut trees = HashMap::new();
+        let mut keys = Vec::new();
+        for i in 0..5 {
+            let data = Bytes::from(format!("tree {}", i));
+            let k = Key::new(
+                repo_path_buf(&format!("d{}", i)),
+                HgId::from_content(&data, Parents::None),
+            );
+            trees.insert(k.clone(), data);
+            keys.push(k);
+        }
+
+        // The server truncates each response after two trees and emits a
+        // marker for the rest; the client re-requests until all are served.
+        let client = FakeSaplingRemoteApi::new()
+            .trees(trees)
+            .trees_per_request_limit(2)
+            .into_arc();
+
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            client.clone(),
+        ));
+
+        let (found, missing, _errors) = store
+            .fetch_batch(
+                keys.iter().cloned(),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(found.len(), 5);
+        assert!(missing.is_empty());
+        // Five keys, then the three left unserved, then the final one.
+        assert_eq!(client.tree_request_sizes(), vec![5, 3, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edenapi_fetch_retries_transient_key_errors() -> Result<()> {
+        let mut trees = HashMap::new();
+        let mut keys = Vec::new();
+        for i in 0..3 {
+            let data = Bytes::from(format!("tree {}", i));
+            let k = Key::new(
+                repo_path_buf(&format!("d{}", i)),
+                HgId::from_content(&data, Parents::None),
+            );
+            trees.insert(k.clone(), data);
+            keys.push(k);
+        }
+
+        // The server fails one key twice with a retriable error before
+        // serving it; the client re-requests just that key.
+        let client = FakeSaplingRemoteApi::new()
+            .trees(trees.clone())
+            .fail_key_transiently(keys[1].clone(), 2)
+            .into_arc();
+
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            client.clone(),
+        ));
+        store.edenapi_retries = 3;
+
+        let (found, missing, _errors) = store
+            .fetch_batch(
+                keys.iter().cloned(),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(found.len(), 3);
+        assert!(missing.is_empty());
+        // The full batch, then the failed key re-requested twice.
+        assert_eq!(client.tree_request_sizes(), vec![3, 1, 1]);
+
+        // Once retries are exhausted the key fails, but the others still
+        // succeed.
+        let client = FakeSaplingRemoteApi::new()
+            .trees(trees)
+            .fail_key_transiently(keys[1].clone(), 2)
+            .into_arc();
+
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            client.clone(),
+        ));
+        store.edenapi_retries = 1;
+
+        let (found, missing, _errors) = store
+            .fetch_batch(
+                keys.iter().cloned(),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+        assert_eq!(found.len(), 2);
+        assert_eq!(missing.len(), 1);
+        assert!(missing.contains_key(&keys[1]));
+        assert_eq!(client.tree_request_sizes(), vec![3, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edenapi_fetch_harvests_child_aux() -> Result<()> {
+        let file_data = Bytes::from(&b"file content"[..]);
+        let file_key = Key::new(
+            repo_path_buf("d/file"),
+            HgId::from_content(&file_data, Parents::None),
+        );
+        let file_aux = FileAuxData::from_content(&file_data);
+
+        let tree_data = Bytes::from(&b"tree"[..]);
+        let tree_key = Key::new(
+            repo_path_buf("d"),
+            HgId::from_content(&tree_data, Parents::None),
+        );
+
+        let fake_remote = || {
+            FakeSaplingRemoteApi::new()
+                .trees(std::iter::once((tree_key.clone(), tree_data.clone())).collect())
+                .tree_children(
+                    tree_key.clone(),
+                    vec![TreeChildEntry::new_file_entry(
+                        file_key.clone(),
+                        file_aux.clone().into(),
+                    )],
+                )
+                .into_arc()
+        };
+
+        let mut filestore = FileStore::empty();
+        filestore.aux_cache = Some(Arc::new(AuxStore::new_for_testing()?));
+        let filestore = Arc::new(filestore);
+
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            fake_remote(),
+        ));
+        store.filestore = Some(filestore.clone());
+
+        let fetched = store
+            .fetch_batch(
+                std::iter::once(tree_key.clone()),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .single()?;
+        assert!(fetched.is_some());
+
+        let metrics: HashMap<String, usize> = store.metrics.read().metrics().collect();
+        assert_eq!(metrics.get("scmstore.tree.fetch.aux.harvested"), Some(&1));
+
+        // The harvested aux data satisfies a file aux fetch without going
+        // anywhere near a file remote.
+        let file = filestore
+            .fetch(
+                std::iter::once(file_key.clone()),
+                FileAttributes::AUX,
+                FetchMode::LocalOnly,
+            )
+            .single()?
+            .expect("aux data not harvested");
+        assert_eq!(file.aux_data()?, file_aux);
+
+        // A filestore without an aux cache ignores the child aux data.
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            fake_remote(),
+        ));
+        store.filestore = Some(Arc::new(FileStore::empty()));
+
+        let fetched = store
+            .fetch_batch(
+                std::iter::once(tree_key),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .single()?;
+        assert!(fetched.is_some());
+
+        let metrics: HashMap<String, usize> = store.metrics.read().metrics().collect();
+        assert_eq!(metrics.get("scmstore.tree.fetch.aux.harvested"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_write_mode() -> Result<()> {
+        let data = Bytes::from(&b"tree"[..]);
+        let k = Key::new(
+            repo_path_buf("d"),
+            HgId::from_content(&data, Parents::None),
+        );
+        let trees: HashMap<_, _> = std::iter::once((k.clone(), data)).collect();
+
+        // Disabled leaves the cache untouched.
+        let tmp = TempDir::new()?;
+        let client = FakeSaplingRemoteApi::new().trees(trees.clone()).into_arc();
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(client));
+        store.indexedlog_cache = Some(local_store(&tmp)?);
+        store.cache_write_mode = CacheWriteMode::Disabled;
+
+        let fetched = store
+            .fetch_batch(
+                std::iter::once(k.clone()),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .single()?;
+        assert!(fetched.is_some());
+        assert!(
+            store
+                .indexedlog_cache
+                .as_ref()
+                .unwrap()
+                .get_entry(k.clone())?
+                .is_none()
+        );
+
+        // Batched writes are flushed at the end of the fetch even if the
+        // batch never fills up.
+        let tmp = TempDir::new()?;
+        let client = FakeSaplingRemoteApi::new().trees(trees).into_arc();
+        let mut store = TreeStore::empty();
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(client));
+        store.indexedlog_cache = Some(local_store(&tmp)?);
+        store.cache_write_mode = CacheWriteMode::Batched {
+            max_batch_size: 100,
+            flush_interval: Duration::from_secs(3600),
+        };
+
+        let fetched = store
+            .fetch_batch(
+                std::iter::once(k.clone()),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .single()?;
+        assert!(fetched.is_some());
+        assert!(
+            store
+                .indexedlog_cache
+                .as_ref()
+                .unwrap()
+                .get_entry(k)?
+                .is_some()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_fetch_summary() -> Result<()> {
+        let tmp = TempDir::new()?;
+
+        // One tree in the local store, one only available remotely.
+        let local_data = Bytes::from(&b"local tree"[..]);
+        let local_key = Key::new(
+            repo_path_buf("a"),
+            HgId::from_content(&local_data, Parents::None),
+        );
+        let remote_data = Bytes::from(&b"remote tree"[..]);
+        let remote_key = Key::new(
+            repo_path_buf("b"),
+            HgId::from_content(&remote_data, Parents::None),
+        );
+        let trees: HashMap<_, _> = std::iter::once((remote_key.clone(), remote_data)).collect();
+
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(local_store(&tmp)?);
+        store.edenapi = Some(SaplingRemoteApiRemoteStore::<TreeMarker>::new(
+            FakeSaplingRemoteApi::new().trees(trees).into_arc(),
+        ));
+        store.write_batch(&[(local_key.clone(), local_data, Parents::None)])?;
+
+        store
+            .fetch_batch(
+                [local_key, remote_key].into_iter(),
+                TreeAttributes::CONTENT,
+                FetchMode::AllowRemote,
+            )
+            .consume();
+
+        let summary = store.take_fetch_summary();
+        assert_eq!(summary.local.hits, 1);
+        assert_eq!(summary.local.requests, 1);
+        assert_eq!(summary.remote.hits, 1);
+        assert_eq!(summary.remote.requests, 1);
+        assert_eq!(summary.cache, FetchOriginSummary::default());
+
+        // Taking the summary resets it.
+        assert_eq!(store.take_fetch_summary(), FetchSummary::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_root_tree() -> Result<()> {
+        struct FakeCommitStore(HashMap<HgId, HgId>);
+
+        #[async_trait::async_trait]
+        impl ReadRootTreeIds for FakeCommitStore {
+            async fn read_root_tree_ids(&self, commits: Vec<HgId>) -> Result<Vec<(HgId, HgId)>> {
+                Ok(commits
+                    .into_iter()
+                    .filter_map(|commit| self.0.get(&commit).map(|tree| (commit, *tree)))
+                    .collect())
+            }
+        }
+
+        let tmp = TempDir::new()?;
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(local_store(&tmp)?);
+
+        let data = Bytes::from(&b"root tree"[..]);
+        let tree_id = HgId::from_content(&data, Parents::None);
+        let k = Key::new(RepoPath::empty().to_owned(), tree_id);
+        store.write_batch(&[(k, data.clone(), Parents::None)])?;
+
+        let commit_id = hgid("1");
+        store.commit_store = Some(Arc::new(FakeCommitStore(
+            std::iter::once((commit_id, tree_id)).collect(),
+        )));
+
+        let fetched = store
+            .get_root_tree(commit_id)?
+            .expect("root tree not found");
+        assert_eq!(
+            fetched.manifest_tree_entry()?.0.to_vec(),
+            data.as_ref().to_vec()
+        );
+
+        // A commit the commit store doesn't know resolves to no tree.
+        assert!(store.get_root_tree(hgid("2"))?.is_none());
+
+        // Without a commit store the lookup errors rather than guessing.
+        store.commit_store = None;
+        assert!(store.get_root_tree(commit_id).is_err());
+
+        Ok(())
+    }
+}
@@ -6,12 +6,17 @@
  */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Instant;
 
 use anyhow::Result;
 use async_runtime::block_on;
 use cas_client::CasClient;
 use crossbeam::channel::Sender;
+use edenapi::Stats;
+use edenapi_types::ServerErrorKind;
+use progress_model::AggregatingProgressBar;
 use tracing::field;
 use types::fetch_mode::FetchMode;
 use types::hgid::NULL_ID;
@@ -25,7 +30,9 @@ use types::NodeInfo;
 use super::metrics::TreeStoreFetchMetrics;
 use super::types::StoreTree;
 use super::types::TreeAttributes;
+use crate::indexedlogdatastore::Entry;
 use crate::indexedlogtreeauxstore::TreeAuxStore;
+use crate::scmstore::tree::CacheWriteMode;
 use crate::scmstore::fetch::CommonFetchState;
 use crate::scmstore::fetch::FetchErrors;
 use crate::scmstore::tree::types::AuxData;
@@ -66,11 +73,15 @@ impl FetchState {
         edenapi: &SaplingRemoteApiTreeStore,
         attributes: edenapi_types::TreeAttributes,
         indexedlog_cache: Option<&IndexedLogHgIdDataStore>,
+        cache_write_mode: &CacheWriteMode,
         aux_cache: Option<&AuxStore>,
         tree_aux_store: Option<&TreeAuxStore>,
         historystore_cache: Option<&IndexedLogHgIdHistoryStore>,
+        edenapi_progress: Arc<AggregatingProgressBar>,
+        batch_size: Option<usize>,
+        edenapi_retries: i32,
     ) -> Result<()> {
-        let pending: Vec<_> = self
+        let mut pending: Vec<_> = self
             .common
             .pending(
                 TreeAttributes::CONTENT | TreeAttributes::PARENTS | TreeAttributes::AUX_DATA,
@@ -87,6 +98,17 @@ impl FetchState {
 
         self.metrics.edenapi.fetch(pending.len());
 
+        let fetch_span = tracing::debug_span!(
+            target: "revisionstore::fetch",
+            "edenapi_fetch",
+            keys = pending.len(),
+            hits = field::Empty,
+            bytes = field::Empty,
+        );
+        let _fetch_enter = fetch_span.enter();
+
+        let prog = edenapi_progress.create_or_extend(pending.len() as u64);
+
         let span = tracing::info_span!(
             "fetch_edenapi",
             downloaded = field::Empty,
@@ -103,51 +125,137 @@ impl FetchState {
             edenapi.url()
         );
 
-        let response = edenapi
-            .trees_blocking(pending, Some(attributes))
-            .map_err(|e| e.tag_network())?;
-        for entry in response.entries {
-            let entry = entry?;
-            let key = entry.key.clone();
-            let entry = LazyTree::SaplingRemoteApi(entry);
-
-            if aux_cache.is_some() || tree_aux_store.is_some() {
-                cache_child_aux_data(&entry, aux_cache, tree_aux_store)?;
-
-                if let Some(aux_data) = entry.aux_data() {
-                    if let Some(tree_aux_store) = tree_aux_store.as_ref() {
-                        tracing::trace!(
-                            hgid = %key.hgid,
-                            "writing self to tree aux store"
+        // Split the request into bounded chunks so a server error only fails
+        // (and retries) one chunk rather than the whole batch. Progress and
+        // stats aggregate across chunks.
+        let batch_size = batch_size.unwrap_or(pending.len()).max(1);
+
+        let mut found = 0;
+        let mut stats = Stats::default();
+        let mut cache_writer = CacheWriter::new(cache_write_mode, indexedlog_cache);
+        let mut retries_left = edenapi_retries.max(0);
+        let mut retried_keys: HashSet<Key> = HashSet::new();
+        while !pending.is_empty() {
+            let chunk: Vec<Key> = pending.drain(..batch_size.min(pending.len())).collect();
+            let response = edenapi
+                .trees_blocking(chunk, Some(attributes))
+                .map_err(|e| e.tag_network())?;
+            let mut found_in_chunk = 0;
+            let mut retriable = Vec::new();
+            for entry in response.entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => match err.key.clone() {
+                        // A keyed error only affects that key. Classify it:
+                        // transient failures are worth a re-request, missing
+                        // keys are left unfetched so they are reported as
+                        // missing, anything else fails just this key.
+                        Some(key) => {
+                            match err.kind() {
+                                ServerErrorKind::Retriable => retriable.push((key, err)),
+                                ServerErrorKind::NotFound => {
+                                    tracing::debug!(%key, "server reported tree as not found")
+                                }
+                                ServerErrorKind::Fatal => self.errors.keyed_error(key, err.into()),
+                            }
+                            continue;
+                        }
+                        None => return Err(err.into()),
+                    },
+                };
+
+                // The server truncated the response to fit its byte budget;
+                // re-request the keys it didn't serve. Require progress so a
+                // misbehaving server can't put us in a loop.
+                if let Some(not_served) = entry.not_served {
+                    if found_in_chunk > 0 {
+                        tracing::debug!(
+                            "tree response truncated by server; re-requesting {} keys",
+                            not_served.len()
+                        );
+                        pending.extend(not_served);
+                    } else {
+                        tracing::warn!(
+                            "tree response truncated without serving any trees; not retrying"
                         );
-                        tree_aux_store.put(key.hgid, &aux_data)?;
+                    }
+                    continue;
+                }
+
+                found += 1;
+                found_in_chunk += 1;
+                prog.increase_position(1);
+                let key = entry.key.clone();
+                if retried_keys.remove(&key) {
+                    self.metrics.edenapi.retried(1);
+                }
+                let entry = LazyTree::SaplingRemoteApi(entry);
+
+                if aux_cache.is_some() || tree_aux_store.is_some() {
+                    self.metrics.aux_harvested +=
+                        cache_child_aux_data(&entry, aux_cache, tree_aux_store)?;
+
+                    if let Some(aux_data) = entry.aux_data() {
+                        if let Some(tree_aux_store) = tree_aux_store.as_ref() {
+                            tracing::trace!(
+                                hgid = %key.hgid,
+                                "writing self to tree aux store"
+                            );
+                            tree_aux_store.put(key.hgid, &aux_data)?;
+                        }
+                    }
+                }
+
+                if cache_writer.wants_entries() {
+                    if let Some(entry) = entry.indexedlog_cache_entry(key.clone())? {
+                        cache_writer.write(entry)?;
                     }
                 }
-            }
 
-            if let Some(indexedlog_cache) = &indexedlog_cache {
-                if let Some(entry) = entry.indexedlog_cache_entry(key.clone())? {
-                    indexedlog_cache.put_entry(entry)?;
+                if let Some(historystore_cache) = &historystore_cache {
+                    if let Some(parents) = entry.parents() {
+                        historystore_cache.add(
+                            &key,
+                            &NodeInfo {
+                                parents: parents.to_keys(),
+                                linknode: NULL_ID,
+                            },
+                        )?;
+                    }
                 }
+
+                self.common.found(key, entry.into());
             }
 
-            if let Some(historystore_cache) = &historystore_cache {
-                if let Some(parents) = entry.parents() {
-                    historystore_cache.add(
-                        &key,
-                        &NodeInfo {
-                            parents: parents.to_keys(),
-                            linknode: NULL_ID,
-                        },
-                    )?;
+            if !retriable.is_empty() {
+                if retries_left > 0 {
+                    retries_left -= 1;
+                    tracing::debug!(
+                        "re-requesting {} keys after retriable server errors",
+                        retriable.len()
+                    );
+                    for (key, _err) in retriable {
+                        retried_keys.insert(key.clone());
+                        pending.push(key);
+                    }
+                } else {
+                    for (key, err) in retriable {
+                        self.errors.keyed_error(key, err.into());
+                    }
                 }
             }
 
-            self.common.found(key, entry.into());
+            stats += response.stats;
         }
 
-        crate::util::record_edenapi_stats(&span, &response.stats);
+        cache_writer.flush()?;
 
+        crate::util::record_edenapi_stats(&span, &stats);
+        fetch_span.record("hits", found);
+        fetch_span.record("bytes", stats.downloaded);
+
+        self.metrics.edenapi.hit(found);
+        self.metrics.edenapi.bytes(stats.downloaded);
         let _ = self
             .metrics
             .edenapi
@@ -207,6 +315,7 @@ impl FetchState {
         let mut found = 0;
         let mut error = 0;
         let mut reqs = 0;
+        let mut bytes = 0;
 
         // TODO: configure
         let max_batch_size = 1000;
@@ -238,6 +347,7 @@ impl FetchState {
                             Ok(Some(data)) => match AugmentedTree::try_deserialize(&*data) {
                                 Ok(tree) => {
                                     found += 1;
+                                    bytes += data.len();
                                     tracing::trace!(target: "cas", ?key, ?digest, "tree found in cas");
 
                                     let lazy_tree = LazyTree::Cas(AugmentedTreeWithDigest {
@@ -246,19 +356,25 @@ impl FetchState {
                                         augmented_tree: tree,
                                     });
 
-                                    if let Err(err) =
-                                        cache_child_aux_data(&lazy_tree, aux_cache, tree_aux_store)
-                                    {
-                                        self.errors.keyed_error(key, err);
-                                    } else {
-                                        self.common.found(
-                                            key,
-                                            StoreTree {
-                                                content: Some(lazy_tree),
-                                                parents: None,
-                                                aux_data: None,
-                                            },
-                                        );
+                                    match cache_child_aux_data(
+                                        &lazy_tree,
+                                        aux_cache,
+                                        tree_aux_store,
+                                    ) {
+                                        Err(err) => {
+                                            self.errors.keyed_error(key, err);
+                                        }
+                                        Ok(harvested) => {
+                                            self.metrics.aux_harvested += harvested;
+                                            self.common.found(
+                                                key,
+                                                StoreTree {
+                                                    content: Some(lazy_tree),
+                                                    parents: None,
+                                                    aux_data: None,
+                                                },
+                                            );
+                                        }
                                     }
                                 }
                                 Err(err) => {
@@ -286,26 +402,93 @@ impl FetchState {
         self.metrics.cas.fetch(digests.len());
         self.metrics.cas.err(error);
         self.metrics.cas.hit(found);
+        self.metrics.cas.bytes(bytes);
+    }
+}
+
+/// Applies the store's `CacheWriteMode` to remotely fetched trees: entries
+/// are written through immediately, buffered and written in batches, or
+/// dropped, depending on the mode.
+struct CacheWriter<'a> {
+    mode: &'a CacheWriteMode,
+    cache: Option<&'a IndexedLogHgIdDataStore>,
+    pending: Vec<Entry>,
+    last_flush: Instant,
+}
+
+impl<'a> CacheWriter<'a> {
+    fn new(mode: &'a CacheWriteMode, cache: Option<&'a IndexedLogHgIdDataStore>) -> Self {
+        CacheWriter {
+            mode,
+            cache,
+            pending: Vec::new(),
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Whether `write` can do anything at all, so callers can skip
+    /// preparing cache entries that would only be dropped.
+    fn wants_entries(&self) -> bool {
+        self.cache.is_some() && !matches!(self.mode, CacheWriteMode::Disabled)
+    }
+
+    fn write(&mut self, entry: Entry) -> Result<()> {
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None => return Ok(()),
+        };
+
+        match self.mode {
+            CacheWriteMode::Immediate => cache.put_entry(entry),
+            CacheWriteMode::Batched {
+                max_batch_size,
+                flush_interval,
+            } => {
+                self.pending.push(entry);
+                if self.pending.len() >= *max_batch_size
+                    || self.last_flush.elapsed() >= *flush_interval
+                {
+                    self.flush()?;
+                }
+                Ok(())
+            }
+            CacheWriteMode::Disabled => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(cache) = self.cache {
+            for entry in self.pending.drain(..) {
+                cache.put_entry(entry)?;
+            }
+        }
+        self.last_flush = Instant::now();
+        Ok(())
     }
 }
 
+/// Write child aux data carried by a fetched tree through to the file aux
+/// cache and tree aux store, returning the number of file aux entries
+/// written. File aux entries are written in a single batched call so the
+/// aux log's write lock is only taken once per tree.
 fn cache_child_aux_data(
     tree: &LazyTree,
     aux_cache: Option<&AuxStore>,
     tree_aux_store: Option<&TreeAuxStore>,
-) -> Result<()> {
+) -> Result<usize> {
     if aux_cache.is_none() && tree_aux_store.is_none() {
-        return Ok(());
+        return Ok(0);
     }
 
     let aux_data = tree.children_aux_data();
+    let mut file_aux_batch = Vec::new();
     for (hgid, aux) in aux_data.into_iter() {
         match aux {
             AuxData::File(file_aux) => {
                 if let Some(aux_cache) = aux_cache.as_ref() {
                     if !aux_cache.contains(hgid)? {
                         tracing::trace!(?hgid, "writing to aux cache");
-                        aux_cache.put(hgid, &file_aux)?;
+                        file_aux_batch.push((hgid, file_aux));
                     }
                 }
             }
@@ -319,5 +502,10 @@ fn cache_child_aux_data(
             }
         }
     }
-    Ok(())
+
+    let harvested = file_aux_batch.len();
+    if let Some(aux_cache) = aux_cache {
+        aux_cache.put_batch(file_aux_batch)?;
+    }
+    Ok(harvested)
 }
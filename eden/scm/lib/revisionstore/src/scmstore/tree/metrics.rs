@@ -14,6 +14,7 @@ use stats::prelude::*;
 
 use crate::scmstore::metrics::namespaced;
 use crate::scmstore::metrics::FetchMetrics;
+use crate::scmstore::metrics::FetchSummary;
 use crate::scmstore::metrics::LocalAndCacheFetchMetrics;
 
 #[derive(Clone, Debug, Default)]
@@ -22,6 +23,10 @@ pub struct TreeStoreFetchMetrics {
     pub(crate) aux: LocalAndCacheFetchMetrics,
     pub(crate) edenapi: FetchMetrics,
     pub(crate) cas: FetchMetrics,
+
+    /// File aux data entries harvested from the children of fetched trees
+    /// and written to the file aux cache.
+    pub(crate) aux_harvested: usize,
 }
 
 impl AddAssign for TreeStoreFetchMetrics {
@@ -30,6 +35,7 @@ impl AddAssign for TreeStoreFetchMetrics {
         self.aux += rhs.aux;
         self.edenapi += rhs.edenapi;
         self.cas += rhs.cas;
+        self.aux_harvested += rhs.aux_harvested;
     }
 }
 
@@ -37,10 +43,29 @@ impl TreeStoreFetchMetrics {
     fn metrics(&self) -> impl Iterator<Item = (String, usize)> {
         namespaced("indexedlog", self.indexedlog.metrics())
             .chain(namespaced("aux", self.aux.metrics()))
+            .chain(namespaced(
+                "aux",
+                std::iter::once(("harvested", self.aux_harvested))
+                    .filter(|&(_, value)| value != 0),
+            ))
             .chain(namespaced("edenapi", self.edenapi.metrics()))
             .chain(namespaced("cas", self.cas.metrics()))
     }
 
+    /// Roll this fetch's metrics up into per-origin totals for the
+    /// end-of-command summary.
+    pub(crate) fn summary(&self) -> FetchSummary {
+        let mut summary = FetchSummary::default();
+        for store in [&self.indexedlog, &self.aux] {
+            let (local, cache) = store.summary();
+            summary.local += local;
+            summary.cache += cache;
+        }
+        summary.remote += self.edenapi.summary();
+        summary.remote += self.cas.summary();
+        summary
+    }
+
     /// Update ODS stats.
     /// This assumes that fbinit was called higher up the stack.
     /// It is meant to be used when called from eden which uses the `revisionstore` with
@@ -66,6 +91,10 @@ impl TreeStoreFetchMetrics {
 #[derive(Debug, Default, Clone)]
 pub struct TreeStoreMetrics {
     pub(crate) fetch: TreeStoreFetchMetrics,
+
+    /// Per-origin fetch totals since `TreeStore::take_fetch_summary` was
+    /// last called.
+    pub(crate) fetch_summary: FetchSummary,
 }
 
 impl TreeStoreMetrics {
@@ -7,9 +7,11 @@
 
 mod attrs;
 mod lazy_tree;
+mod parsed_tree;
 mod store_tree;
 
 pub use self::attrs::TreeAttributes;
 pub(crate) use self::lazy_tree::AuxData;
 pub(crate) use self::lazy_tree::LazyTree;
+pub use self::parsed_tree::ParsedTree;
 pub use self::store_tree::StoreTree;
@@ -46,8 +46,7 @@ pub enum AuxData {
 }
 
 impl LazyTree {
-    #[allow(dead_code)]
-    fn hgid(&self) -> Option<HgId> {
+    pub(crate) fn hgid(&self) -> Option<HgId> {
         use LazyTree::*;
         match self {
             IndexedLog(entry) => Some(entry.key().hgid),
@@ -0,0 +1,72 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Context;
+use anyhow::Result;
+use manifest_tree::TreeElement;
+use manifest_tree::TreeEntry as ManifestTreeEntry;
+use minibytes::Bytes;
+use storemodel::SerializationFormat;
+use types::tree::TreeItemFlag;
+use types::HgId;
+use types::Key;
+use types::PathComponent;
+
+/// A tree whose manifest blob has been parsed into typed child entries, so
+/// callers don't have to re-implement manifest parsing and error handling.
+#[derive(Clone, Debug)]
+pub struct ParsedTree {
+    key: Key,
+    elements: Vec<TreeElement>,
+}
+
+impl ParsedTree {
+    /// Parse `data`, the hg manifest blob for `key`, eagerly. Parse errors
+    /// identify the key and the byte offset of the malformed element.
+    pub(crate) fn parse(key: Key, data: Bytes) -> Result<Self> {
+        // Currently revisionstore is only for hg format.
+        let entry = ManifestTreeEntry(data.clone(), SerializationFormat::Hg);
+        let mut elements = Vec::new();
+        // Tracks the start of the element being parsed; hg-format elements
+        // are newline terminated.
+        let mut offset = 0usize;
+        for element in entry.elements() {
+            let element = element.with_context(|| {
+                format!(
+                    "failed to parse manifest for {} at byte offset {}",
+                    key, offset
+                )
+            })?;
+            offset = data[offset..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(data.len(), |delta| offset + delta + 1);
+            elements.push(element);
+        }
+        Ok(ParsedTree { key, elements })
+    }
+
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    /// Iterate over this tree's `(name, hgid, flag)` children, in manifest
+    /// order.
+    pub fn children(&self) -> impl Iterator<Item = (&PathComponent, HgId, TreeItemFlag)> + '_ {
+        self.elements
+            .iter()
+            .map(|element| (element.component.as_path_component(), element.hgid, element.flag))
+    }
+
+    /// Look up a child by name.
+    pub fn lookup(&self, name: &PathComponent) -> Option<(HgId, TreeItemFlag)> {
+        self.elements
+            .iter()
+            .find(|element| element.component.as_path_component() == name)
+            .map(|element| (element.hgid, element.flag))
+    }
+}
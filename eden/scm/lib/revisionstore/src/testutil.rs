@@ -7,8 +7,10 @@
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use anyhow::Error;
 use anyhow::Result;
@@ -18,6 +20,7 @@ use edenapi::ResponseMeta;
 use edenapi::SaplingRemoteApi;
 use edenapi::SaplingRemoteApiError;
 use edenapi::Stats;
+use edenapi_types::AnyId;
 use edenapi_types::FileAttributes;
 use edenapi_types::FileAuxData;
 use edenapi_types::FileContent;
@@ -26,12 +29,19 @@ use edenapi_types::FileResponse;
 use edenapi_types::FileSpec;
 use edenapi_types::HistoryEntry;
 use edenapi_types::SaplingRemoteApiServerError;
+use edenapi_types::ServerError;
 use edenapi_types::TreeAttributes;
+use edenapi_types::TreeChildEntry;
 use edenapi_types::TreeEntry;
+use edenapi_types::UploadToken;
+use edenapi_types::UploadTreeEntry;
+use edenapi_types::UploadTreeResponse;
 use futures::prelude::*;
+use hgstore::split_hg_file_metadata;
 #[cfg(test)]
 pub use lfs_mocks::*;
 use minibytes::Bytes;
+use types::HgId;
 use types::Key;
 use types::NodeInfo;
 use types::Parents;
@@ -210,7 +220,32 @@ impl LocalStore for FakeRemoteHistoryStore {
 pub struct FakeSaplingRemoteApi {
     files: HashMap<Key, (Bytes, Option<u64>)>,
     trees: HashMap<Key, Bytes>,
+    /// Child entries (with aux data) attached to served trees.
+    tree_children: HashMap<Key, Vec<TreeChildEntry>>,
     history: HashMap<Key, NodeInfo>,
+    /// Capabilities reported by the server. `None` makes the endpoint report
+    /// `NotSupported`, like a server predating it.
+    capabilities: Option<Vec<String>>,
+    /// Leave `file_header_metadata` unset in aux data responses, like a
+    /// server that doesn't serve the hg file header.
+    omit_aux_file_header: bool,
+    /// Serve at most this many trees per request, ending the response with a
+    /// truncation marker listing the unserved keys, like a server whose
+    /// response byte budget was exhausted.
+    trees_per_request_limit: Option<usize>,
+    /// Keys that fail with a retriable server error this many more times
+    /// before being served normally, like a server with a flaky backend.
+    transient_failures: Mutex<HashMap<Key, usize>>,
+    /// Nodes whose upload is silently dropped from the response, like a
+    /// server that failed to persist them.
+    rejected_uploads: HashSet<HgId>,
+    /// Nodes of the trees uploaded via `upload_trees_batch`, in arrival
+    /// order.
+    uploaded_trees: Mutex<Vec<HgId>>,
+    /// Number of keys in each file request received, in arrival order.
+    file_requests: Mutex<Vec<usize>>,
+    /// Number of keys in each tree request received, in arrival order.
+    tree_requests: Mutex<Vec<usize>>,
 }
 
 impl FakeSaplingRemoteApi {
@@ -239,28 +274,110 @@ impl FakeSaplingRemoteApi {
         Self { trees, ..self }
     }
 
+    /// Attach child entries to a served tree, as a server with augmented
+    /// trees enabled would.
+    pub fn tree_children(mut self, key: Key, children: Vec<TreeChildEntry>) -> Self {
+        self.tree_children.insert(key, children);
+        self
+    }
+
     pub fn history(self, history: HashMap<Key, NodeInfo>) -> Self {
         Self { history, ..self }
     }
 
+    pub fn capabilities(self, capabilities: Vec<String>) -> Self {
+        Self {
+            capabilities: Some(capabilities),
+            ..self
+        }
+    }
+
+    /// Don't include `file_header_metadata` in aux data responses, like a
+    /// server that doesn't serve the hg file header.
+    pub fn omit_aux_file_header(self) -> Self {
+        Self {
+            omit_aux_file_header: true,
+            ..self
+        }
+    }
+
+    /// Truncate each tree response after `limit` entries and emit a marker
+    /// for the unserved keys, like a server with a response byte budget.
+    pub fn trees_per_request_limit(self, limit: usize) -> Self {
+        Self {
+            trees_per_request_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Fail requests for `key` with a retriable server error `times` times
+    /// before serving it normally, like a server with a flaky backend.
+    pub fn fail_key_transiently(self, key: Key, times: usize) -> Self {
+        self.transient_failures.lock().unwrap().insert(key, times);
+        self
+    }
+
+    /// Whether a request for `key` should fail this time, consuming one of
+    /// its transient failures.
+    fn take_transient_failure(&self, key: &Key) -> bool {
+        match self.transient_failures.lock().unwrap().get_mut(key) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn into_arc(self) -> Arc<Self> {
         Arc::new(self)
     }
 
+    /// Sizes of the file requests served so far, in arrival order.
+    pub fn file_request_sizes(&self) -> Vec<usize> {
+        self.file_requests.lock().unwrap().clone()
+    }
+
+    /// Sizes of the tree requests served so far, in arrival order.
+    pub fn tree_request_sizes(&self) -> Vec<usize> {
+        self.tree_requests.lock().unwrap().clone()
+    }
+
+    /// Silently drop these nodes from upload responses, like a server that
+    /// failed to persist them.
+    pub fn rejected_uploads(self, rejected_uploads: HashSet<HgId>) -> Self {
+        Self {
+            rejected_uploads,
+            ..self
+        }
+    }
+
+    pub fn uploaded_trees(&self) -> Vec<HgId> {
+        self.uploaded_trees.lock().unwrap().clone()
+    }
+
     fn get_files(
-        map: &HashMap<Key, (Bytes, Option<u64>)>,
+        &self,
         reqs: impl Iterator<Item = FileSpec>,
     ) -> Result<Response<FileResponse>, SaplingRemoteApiError> {
         let entries = reqs
             .filter_map(|spec| {
+                if self.take_transient_failure(&spec.key) {
+                    return Some(Ok(FileResponse {
+                        key: spec.key,
+                        result: Err(ServerError::new("transient failure", 4)),
+                    }));
+                }
+
                 let parents = Parents::default();
                 let mut entry = FileEntry::new(spec.key.clone(), parents);
 
-                let (data, flags) = map.get(&spec.key)?.clone();
+                let (data, flags) = self.files.get(&spec.key)?.clone();
                 let metadata = Metadata {
                     flags,
                     size: Some(data.len() as u64),
                 };
+                let (_, header) = split_hg_file_metadata(&data);
                 let data = data.to_vec().into();
                 let content = FileContent {
                     hg_file_blob: data,
@@ -268,7 +385,11 @@ impl FakeSaplingRemoteApi {
                 };
 
                 if spec.attrs.aux_data {
-                    let aux = FileAuxData::from_content(&content.hg_file_blob);
+                    let mut aux = FileAuxData::from_content(&content.hg_file_blob);
+                    if !self.omit_aux_file_header {
+                        // Real servers include the hg file header in aux data.
+                        aux.file_header_metadata = Some(header.to_vec().into());
+                    }
                     entry = entry.with_aux_data(aux);
                 }
 
@@ -290,22 +411,44 @@ impl FakeSaplingRemoteApi {
     }
 
     fn get_trees(
-        map: &HashMap<Key, Bytes>,
+        &self,
         keys: Vec<Key>,
     ) -> Result<Response<Result<TreeEntry, SaplingRemoteApiServerError>>, SaplingRemoteApiError>
     {
-        let entries = keys
-            .into_iter()
-            .filter_map(|key| {
-                let data = map.get(&key)?.clone();
-                let parents = Parents::default();
-                let data = data.to_vec().into();
-                let mut tree_entry = TreeEntry::new(key);
-                tree_entry.with_parents(Some(parents));
-                tree_entry.with_data(Some(data));
-                Some(Ok(Ok(tree_entry)))
-            })
-            .collect::<Vec<_>>();
+        let limit = self.trees_per_request_limit.unwrap_or(usize::MAX);
+        let mut entries = Vec::new();
+        let mut not_served = Vec::new();
+        for key in keys {
+            if entries.len() >= limit {
+                not_served.push(key);
+                continue;
+            }
+            if self.take_transient_failure(&key) {
+                entries.push(Ok(Err(SaplingRemoteApiServerError::with_key_and_code(
+                    key,
+                    ServerError::new("transient failure", 4),
+                ))));
+                continue;
+            }
+            let data = match self.trees.get(&key) {
+                Some(data) => data.clone(),
+                None => continue,
+            };
+            let children = self
+                .tree_children
+                .get(&key)
+                .map(|children| children.iter().cloned().map(Ok).collect());
+            let parents = Parents::default();
+            let data = data.to_vec().into();
+            let mut tree_entry = TreeEntry::new(key);
+            tree_entry.with_parents(Some(parents));
+            tree_entry.with_data(Some(data));
+            tree_entry.with_children(children);
+            entries.push(Ok(Ok(tree_entry)));
+        }
+        if !not_served.is_empty() {
+            entries.push(Ok(Ok(TreeEntry::new_truncated(not_served))));
+        }
 
         Ok(Response {
             entries: Box::pin(stream::iter(entries)),
@@ -320,24 +463,30 @@ impl SaplingRemoteApi for FakeSaplingRemoteApi {
         Ok(ResponseMeta::default())
     }
 
+    async fn capabilities(&self) -> Result<Vec<String>, SaplingRemoteApiError> {
+        match &self.capabilities {
+            Some(capabilities) => Ok(capabilities.clone()),
+            None => Err(SaplingRemoteApiError::NotSupported),
+        }
+    }
+
     async fn files(&self, keys: Vec<Key>) -> Result<Response<FileResponse>, SaplingRemoteApiError> {
-        Self::get_files(
-            &self.files,
-            keys.into_iter().map(|key| FileSpec {
-                key,
-                attrs: FileAttributes {
-                    content: true,
-                    aux_data: false,
-                },
-            }),
-        )
+        self.file_requests.lock().unwrap().push(keys.len());
+        self.get_files(keys.into_iter().map(|key| FileSpec {
+            key,
+            attrs: FileAttributes {
+                content: true,
+                aux_data: false,
+            },
+        }))
     }
 
     async fn files_attrs(
         &self,
         reqs: Vec<FileSpec>,
     ) -> Result<Response<FileResponse>, SaplingRemoteApiError> {
-        Self::get_files(&self.files, reqs.into_iter())
+        self.file_requests.lock().unwrap().push(reqs.len());
+        self.get_files(reqs.into_iter())
     }
 
     async fn history(
@@ -365,7 +514,29 @@ impl SaplingRemoteApi for FakeSaplingRemoteApi {
         _attrs: Option<TreeAttributes>,
     ) -> Result<Response<Result<TreeEntry, SaplingRemoteApiServerError>>, SaplingRemoteApiError>
     {
-        Self::get_trees(&self.trees, keys)
+        self.tree_requests.lock().unwrap().push(keys.len());
+        self.get_trees(keys)
+    }
+
+    async fn upload_trees_batch(
+        &self,
+        items: Vec<UploadTreeEntry>,
+    ) -> Result<Response<UploadTreeResponse>, SaplingRemoteApiError> {
+        let mut entries = Vec::new();
+        for item in items {
+            if self.rejected_uploads.contains(&item.node_id) {
+                continue;
+            }
+            self.uploaded_trees.lock().unwrap().push(item.node_id);
+            entries.push(Ok(UploadTreeResponse {
+                token: UploadToken::new_fake_token(AnyId::HgTreeId(item.node_id), None),
+            }));
+        }
+
+        Ok(Response {
+            entries: Box::pin(stream::iter(entries)),
+            stats: Box::pin(future::ok(Stats::default())),
+        })
     }
 }
 
@@ -0,0 +1,123 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use configmodel::convert::ByteCount;
+use configmodel::Config;
+use configmodel::ConfigExt;
+use parking_lot::Mutex;
+
+/// A token-bucket limiter capping download bandwidth.
+///
+/// Download paths record received bytes with `acquire` and sleep for the
+/// returned duration before reading more of the response. Pausing the reader
+/// leaves data queued in the transport, so the resulting back-pressure slows
+/// the server down to roughly the configured rate.
+pub struct NetworkThrottle {
+    bytes_per_sec: u64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    /// Bytes that may be consumed without waiting. Goes negative when a
+    /// single read overshoots the bucket; the debt is paid off by refills.
+    available: f64,
+    last_refill: Instant,
+}
+
+impl NetworkThrottle {
+    /// Create a throttle capping downloads at `bytes_per_sec`. A rate of 0
+    /// means unthrottled and returns `None`.
+    pub fn new(bytes_per_sec: u64) -> Option<Arc<Self>> {
+        if bytes_per_sec == 0 {
+            return None;
+        }
+
+        Some(Arc::new(Self {
+            bytes_per_sec,
+            state: Mutex::new(BucketState {
+                // Start with a full bucket so small fetches aren't delayed.
+                available: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }))
+    }
+
+    /// The configured rate in bytes per second.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Create a throttle from `scmstore.max-download-bytes-per-sec`. Unset or
+    /// 0 disables throttling.
+    pub fn from_config(config: &dyn Config) -> Result<Option<Arc<Self>>> {
+        let bytes_per_sec: ByteCount =
+            config.get_or_default("scmstore", "max-download-bytes-per-sec")?;
+        Ok(Self::new(bytes_per_sec.value()))
+    }
+
+    /// Debit `bytes` from the bucket, returning how long the caller should
+    /// wait before reading more data. The wait is zero while the bucket has
+    /// tokens; the bucket holds at most one second's worth of bytes.
+    pub fn acquire(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock();
+
+        let now = Instant::now();
+        let refill =
+            now.duration_since(state.last_refill).as_secs_f64() * self.bytes_per_sec as f64;
+        state.available = (state.available + refill).min(self.bytes_per_sec as f64);
+        state.last_refill = now;
+
+        state.available -= bytes as f64;
+        if state.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.available / self.bytes_per_sec as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_zero_rate_is_disabled() {
+        assert!(NetworkThrottle::new(0).is_none());
+    }
+
+    #[test]
+    fn test_burst_then_delay() {
+        let throttle = NetworkThrottle::new(1000).unwrap();
+
+        // The initial bucket covers one second of traffic.
+        assert_eq!(throttle.acquire(1000), Duration::ZERO);
+
+        // Another second's worth has to wait for refills.
+        let wait = throttle.acquire(1000);
+        assert!(wait > Duration::from_millis(500));
+        assert!(wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config: BTreeMap<&str, &str> = Default::default();
+        assert!(NetworkThrottle::from_config(&config).unwrap().is_none());
+
+        let config: BTreeMap<&str, &str> = vec![("scmstore.max-download-bytes-per-sec", "1MB")]
+            .into_iter()
+            .collect();
+        let throttle = NetworkThrottle::from_config(&config).unwrap().unwrap();
+        assert_eq!(throttle.bytes_per_sec, 1 << 20);
+    }
+}
@@ -7,12 +7,13 @@
 
 use std::io::ErrorKind;
 use std::io::Write;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::bail;
 use anyhow::Result;
 use configmodel::Config;
-use configmodel::ConfigExt;
 use edenapi::Stats;
 use fn_error_context::context;
 use fs_err::read_to_string;
@@ -23,15 +24,29 @@ use tracing::Span;
 use util::path::create_dir;
 use util::path::create_shared_dir;
 use util::path::create_shared_dir_all;
+use util::path::expand_path_strict;
 
 fn get_config_cache_path(config: &dyn Config) -> Result<Option<PathBuf>> {
-    let reponame = match config.get_nonempty("remotefilelog", "reponame") {
-        Some(name) => name,
-        None => return Ok(None),
+    get_config_cache_path_for_repo(config, None)
+}
+
+fn get_config_cache_path_for_repo(
+    config: &dyn Config,
+    repo_name: Option<&str>,
+) -> Result<Option<PathBuf>> {
+    let reponame = match repo_name {
+        Some(name) => name.to_string(),
+        None => match config.get_nonempty("remotefilelog", "reponame") {
+            Some(name) => name.to_string(),
+            None => return Ok(None),
+        },
     };
 
-    let mut path: PathBuf = match config.get_nonempty_opt("remotefilelog", "cachepath")? {
-        Some(path) => path,
+    // Expand `~` and environment variable references eagerly so that a
+    // reference to an unset variable is reported instead of silently
+    // creating a literal `$VAR` directory.
+    let mut path: PathBuf = match config.get_nonempty("remotefilelog", "cachepath") {
+        Some(path) => expand_path_strict(&path)?,
         None => return Ok(None),
     };
 
@@ -42,17 +57,46 @@ fn get_config_cache_path(config: &dyn Config) -> Result<Option<PathBuf>> {
     Ok(Some(path))
 }
 
+/// Check that a store suffix stays under the directory it is joined to.
+/// An absolute suffix or one containing `..` components would silently
+/// place the store outside the local or cache root.
+fn check_suffix(suffix: impl AsRef<Path>) -> Result<()> {
+    let suffix = suffix.as_ref();
+    if suffix.is_absolute() {
+        bail!("store suffix {:?} must not be an absolute path", suffix);
+    }
+    if suffix
+        .components()
+        .any(|component| matches!(component, Component::ParentDir))
+    {
+        bail!("store suffix {:?} must not contain '..' components", suffix);
+    }
+    Ok(())
+}
+
 #[context("get_cache_path")]
 pub fn get_cache_path(
     config: &dyn Config,
     suffix: &Option<impl AsRef<Path>>,
 ) -> Result<Option<PathBuf>> {
-    let mut path = match get_config_cache_path(config) {
+    get_cache_path_for_repo(config, suffix, None)
+}
+
+/// Like `get_cache_path`, but with `repo_name` overriding
+/// `remotefilelog.reponame` as the repo component of the cache path.
+#[context("get_cache_path_for_repo")]
+pub fn get_cache_path_for_repo(
+    config: &dyn Config,
+    suffix: &Option<impl AsRef<Path>>,
+    repo_name: Option<&str>,
+) -> Result<Option<PathBuf>> {
+    let mut path = match get_config_cache_path_for_repo(config, repo_name) {
         Ok(Some(path)) => path,
         res => return res,
     };
 
     if let Some(ref suffix) = suffix {
+        check_suffix(suffix)?;
         path.push(suffix);
         create_shared_dir(&path)?;
     }
@@ -61,10 +105,15 @@ pub fn get_cache_path(
 }
 
 #[context("get_local_path")]
-pub fn get_local_path(mut path: PathBuf, suffix: &Option<impl AsRef<Path>>) -> Result<PathBuf> {
+pub fn get_local_path(path: PathBuf, suffix: &Option<impl AsRef<Path>>) -> Result<PathBuf> {
+    let mut path = match path.to_str() {
+        Some(path) => expand_path_strict(path)?,
+        None => path,
+    };
     create_dir(&path)?;
 
     if let Some(ref suffix) = suffix {
+        check_suffix(suffix)?;
         path.push(suffix);
         create_dir(&path)?;
     }
@@ -167,11 +216,22 @@ pub fn get_lfs_blobs_path(store_path: impl AsRef<Path>) -> Result<PathBuf> {
 
 pub const RUN_ONCE_FILENAME: &str = "runoncemarker";
 pub fn check_run_once(store_path: impl AsRef<Path>, key: &str, cutoff: HgTime) -> bool {
+    check_run_once_marker(store_path, RUN_ONCE_FILENAME, key, cutoff)
+}
+
+/// Same as `check_run_once`, but with an explicit marker file name, so that
+/// independent mechanisms (e.g. scoped cache busters) don't share state.
+pub fn check_run_once_marker(
+    store_path: impl AsRef<Path>,
+    marker_filename: &str,
+    key: &str,
+    cutoff: HgTime,
+) -> bool {
     if HgTime::now() > Some(cutoff) {
         return false;
     }
 
-    let marker_path = store_path.as_ref().join(RUN_ONCE_FILENAME);
+    let marker_path = store_path.as_ref().join(marker_filename);
     let line = format!("\n{}\n", key);
     let marked = match read_to_string(&marker_path) {
         Ok(contents) => contents.contains(&line),
@@ -214,3 +274,40 @@ pub fn record_edenapi_stats(span: &Span, stats: &Stats) {
     let size = stats.downloaded as f64 / 1024.0 / 1024.0;
     span.record("download_speed", format!("{:.2}", size / time).as_str());
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_get_local_path_suffix() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        // Plain relative suffixes stay under the root.
+        let path = get_local_path(dir.path().to_path_buf(), &Some("manifests"))?;
+        assert_eq!(path, dir.path().join("manifests"));
+        assert!(path.is_dir());
+
+        // Nested relative suffixes are safe (only the final component is
+        // created, so the parent must already exist).
+        std::fs::create_dir(dir.path().join("foo"))?;
+        let path = get_local_path(dir.path().to_path_buf(), &Some("foo/bar"))?;
+        assert_eq!(path, dir.path().join("foo").join("bar"));
+        assert!(path.is_dir());
+
+        // Suffixes that would escape the root are rejected.
+        assert!(get_local_path(dir.path().to_path_buf(), &Some("../escape")).is_err());
+        assert!(get_local_path(dir.path().to_path_buf(), &Some("foo/../../escape")).is_err());
+        assert!(
+            get_local_path(
+                dir.path().to_path_buf(),
+                &Some(dir.path().join("absolute"))
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -30,6 +30,17 @@ impl PathLock {
         Ok(PathLock { file })
     }
 
+    /// Try to take an exclusive lock on `path` without blocking. Returns
+    /// `Ok(None)` if another process already holds the lock.
+    pub fn try_exclusive<P: AsRef<Path>>(path: P) -> io::Result<Option<Self>> {
+        let file = open(path.as_ref(), "wc").io_context("lock file")?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(PathLock { file })),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err).path_context("error locking file", path.as_ref()),
+        }
+    }
+
     pub fn as_file(&self) -> &File {
         &self.file
     }
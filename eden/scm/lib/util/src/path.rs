@@ -555,6 +555,17 @@ pub fn expand_path(path: impl AsRef<str>) -> PathBuf {
     expand_path_impl(path.as_ref(), |k| env::var(k).ok(), dirs::home_dir)
 }
 
+/// Like `expand_path`, but return an error if the path references an
+/// environment variable that is not set, instead of leaving the reference
+/// in the resulting path.
+///
+/// This is intended for paths that are about to be created on disk (such
+/// as cache directories), where silently treating `$MISSING` as a literal
+/// directory name is more confusing than an error.
+pub fn expand_path_strict(path: impl AsRef<str>) -> anyhow::Result<PathBuf> {
+    expand_path_strict_impl(path.as_ref(), |k| env::var(k).ok(), dirs::home_dir)
+}
+
 /// Same as `expand_path` but explicitly takes closures for environment variable
 /// and home directory lookup for the sake of testability.
 fn expand_path_impl<E, H>(path: &str, getenv: E, homedir: H) -> PathBuf
@@ -562,46 +573,70 @@ where
     E: FnMut(&str) -> Option<String>,
     H: FnOnce() -> Option<PathBuf>,
 {
-    // The shellexpand crate does not expand Windows environment variables
-    // like `%PROGRAMDATA%`. We'd like to expand them too. So let's do some
-    // pre-processing.
-    //
-    // XXX: Doing this preprocessing has the unfortunate side-effect that
-    // if an environment variable fails to expand on Windows, the resulting
-    // string will contain a UNIX-style environment variable reference.
-    //
-    // e.g., "/foo/%MISSING%/bar" will expand to "/foo/${MISSING}/bar"
-    //
-    // The current approach is good enough for now, but likely needs to
-    // be improved later for correctness.
-    let path = {
-        let mut new_path = String::new();
-        let mut is_starting = true;
-        for ch in path.chars() {
-            if ch == '%' {
-                if is_starting {
-                    new_path.push_str("${");
-                } else {
-                    new_path.push('}');
-                }
-                is_starting = !is_starting;
-            } else if cfg!(windows) && ch == '/' {
-                // Only on Windows, change "/" to "\" automatically.
-                // This makes sure "%include /foo" works as expected.
-                new_path.push('\\')
-            } else {
-                new_path.push(ch);
-            }
-        }
-        new_path
-    };
-
+    let path = preprocess_windows_vars(path);
     let path = shellexpand::env_with_context_no_errors(&path, getenv);
     shellexpand::tilde_with_context(&path, homedir)
         .as_ref()
         .into()
 }
 
+/// Same as `expand_path_strict` but explicitly takes closures for environment
+/// variable and home directory lookup for the sake of testability.
+fn expand_path_strict_impl<E, H>(path: &str, mut getenv: E, homedir: H) -> anyhow::Result<PathBuf>
+where
+    E: FnMut(&str) -> Option<String>,
+    H: FnOnce() -> Option<PathBuf>,
+{
+    let preprocessed = preprocess_windows_vars(path);
+    let expanded = shellexpand::env_with_context(&preprocessed, |k: &str| {
+        getenv(k).map(Some).ok_or(())
+    })
+    .map_err(|err| {
+        anyhow::anyhow!(
+            "environment variable {:?} referenced by path {:?} is not set",
+            err.var_name,
+            path,
+        )
+    })?;
+    Ok(shellexpand::tilde_with_context(&expanded, homedir)
+        .as_ref()
+        .into())
+}
+
+/// The shellexpand crate does not expand Windows environment variables
+/// like `%PROGRAMDATA%`. We'd like to expand them too. So let's do some
+/// pre-processing.
+///
+/// XXX: Doing this preprocessing has the unfortunate side-effect that
+/// if an environment variable fails to expand on Windows, the resulting
+/// string will contain a UNIX-style environment variable reference.
+///
+/// e.g., "/foo/%MISSING%/bar" will expand to "/foo/${MISSING}/bar"
+///
+/// The current approach is good enough for now, but likely needs to
+/// be improved later for correctness.
+fn preprocess_windows_vars(path: &str) -> String {
+    let mut new_path = String::new();
+    let mut is_starting = true;
+    for ch in path.chars() {
+        if ch == '%' {
+            if is_starting {
+                new_path.push_str("${");
+            } else {
+                new_path.push('}');
+            }
+            is_starting = !is_starting;
+        } else if cfg!(windows) && ch == '/' {
+            // Only on Windows, change "/" to "\" automatically.
+            // This makes sure "%include /foo" works as expected.
+            new_path.push('\\')
+        } else {
+            new_path.push(ch);
+        }
+    }
+    new_path
+}
+
 /// Return a relative [`PathBuf`] to the path from the base path.
 pub fn relativize(base: &Path, path: &Path) -> PathBuf {
     let mut base_iter = base.iter();
@@ -1040,6 +1075,31 @@ mod tests {
         assert_eq!(expand_path_impl(path, getenv, homedir), expected);
     }
 
+    #[test]
+    fn test_strict_path_expansion() {
+        fn getenv(key: &str) -> Option<String> {
+            match key {
+                "foo" => Some("~/a".into()),
+                "bar" => Some("b".into()),
+                _ => None,
+            }
+        }
+
+        fn homedir() -> Option<PathBuf> {
+            Some(PathBuf::from("/home/user"))
+        }
+
+        assert_eq!(
+            expand_path_strict_impl("$foo/${bar}", getenv, homedir).unwrap(),
+            PathBuf::from("/home/user/a/b"),
+        );
+
+        let err = expand_path_strict_impl("$foo/$baz", getenv, homedir).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("\"baz\""), "unexpected error: {}", msg);
+        assert!(msg.contains("$foo/$baz"), "unexpected error: {}", msg);
+    }
+
     #[test]
     fn test_create_shared_dir_all() -> Result<()> {
         test_create_dir_all_fn(&|path| create_shared_dir_all(path), 0o42775)
@@ -798,9 +798,20 @@ py_class!(pub class filescmstore |py| {
             .iter(py)
             .map(|tuple| from_tuple_to_key(py, &tuple))
             .collect::<PyResult<Vec<Key>>>()?;
-        py.allow_threads(|| FileStore::prefetch(store, keys)).map_pyerr(py)?;
-
-        Ok(Python::None(py))
+        let result = py.allow_threads(|| FileStore::prefetch(store, keys)).map_pyerr(py)?;
+
+        // Report when scmstore.max-prefetch-size split the request so the
+        // caller can print a hint; None when the prefetch ran as one fetch.
+        match result.split {
+            Some(split) => {
+                let hint = PyDict::new(py);
+                hint.set_item(py, "keys", split.keys)?;
+                hint.set_item(py, "chunks", split.chunks)?;
+                hint.set_item(py, "max_prefetch_size", split.max_prefetch_size)?;
+                Ok(hint.into_object())
+            }
+            None => Ok(Python::None(py)),
+        }
     }
 
     def markforrefresh(&self) -> PyResult<PyNone> {